@@ -1,31 +1,306 @@
-use crate::{keymap, partition, wifi};
+use crate::{console, keymap, partition, wifi};
+use crate::console::Script;
+use crate::keymap::{Grouping, Keymap, KeymapBackup, LineReader, TrialOutcome};
+use crate::keymap_hints::{KeymapHints, KeymapSuggestion};
+use crate::filesystem::Filesystem;
+use crate::firmware::detect_firmware_mode;
+use crate::size::SizeSpec;
+use crate::smart;
+use crate::disk;
+use crate::hibernate::{self, ResumeDevice};
+use crate::raid::{self, RaidConfig, RaidLevel};
+use crate::reinstall::{self, ExistingInstall};
+use crate::secret::{self, WIFI_PASSWORD_ENV};
+use crate::target::Target;
+use crate::wifi::{EnterpriseNetwork, Network};
+use std::path::Path;
+use crate::wipe::{self, WipeMode};
 use crate::common::{CommandResult, SetupError};
 use std::io::{self, Write};
+use std::time::{Duration, Instant};
+
+/// Keymaps the picker lists one by one; more are summarised per group
+const PICKER_PAGE: usize = 30;
 
 pub fn list_keymaps() -> CommandResult<()> {
     let keymaps = keymap::available_keymaps()?;
-    println!("Available keymaps:");
-    for km in keymaps {
-        println!("  {}", km);
+    println!("Available keymaps by language:");
+    for (language, group) in keymap::group_keymaps(&keymaps, Grouping::Language) {
+        let names: Vec<&str> = group.iter().map(|k| k.name.as_str()).collect();
+        println!("  {:<8} {}", language, names.join(", "));
     }
     Ok(())
 }
 
-pub fn set_keymap(map: &str) -> CommandResult<()> {
+/// Matches numbered in group order, or a per-group count when too many.
+/// Returns the numbered keymaps.
+fn print_keymap_matches<'a>(matches: &[&'a Keymap], grouping: Grouping) -> Vec<&'a Keymap> {
+    let groups = keymap::group_keymaps(matches.iter().copied(), grouping);
+    if matches.len() > PICKER_PAGE {
+        println!("{} keymaps match; type part of a name, a language or a layout to narrow:", matches.len());
+        let counts: Vec<String> = groups.iter().map(|(key, group)| format!("{} ({})", key, group.len())).collect();
+        for line in counts.chunks(8) {
+            println!("  {}", line.join("  "));
+        }
+        return Vec::new();
+    }
+
+    let mut numbered = Vec::new();
+    for (key, group) in groups {
+        println!("  [{}]", key);
+        for keymap in group {
+            numbered.push(keymap);
+            println!("  {:>3}) {:<24} {} {}", numbered.len(), keymap.name, keymap.family, keymap.arch);
+        }
+    }
+    numbered
+}
+
+/// Interactive keymap choice. Each search narrows the previous one;
+/// before the first search the suggestions can be picked by number.
+fn prompt_keymap(keymaps: &[Keymap], suggested: &[KeymapSuggestion]) -> CommandResult<String> {
+    let mut grouping = Grouping::Language;
+    let mut query = String::new();
+    loop {
+        let matches = keymap::search_keymaps(keymaps, &query);
+        if !query.is_empty() {
+            println!("\nSearch: {}", query);
+        }
+        let mut numbered = print_keymap_matches(&matches, grouping);
+        if query.is_empty() && !suggested.is_empty() {
+            println!("Suggested:");
+            numbered = suggested
+                .iter()
+                .filter_map(|s| keymaps.iter().find(|k| k.name == s.keymap))
+                .collect();
+            for (i, (keymap, suggestion)) in numbered.iter().zip(suggested).enumerate() {
+                println!("  {:>3}) {:<24} from the {}", i + 1, keymap.name, suggestion.source);
+            }
+        }
+        let input = prompt_input_default(
+            "Search, number to pick, '-' to clear, ':language' or ':family' to regroup (empty to cancel): ",
+            "",
+        )?;
+
+        match input.as_str() {
+            "" if numbered.len() == 1 => return Ok(numbered[0].name.clone()),
+            "" => return Err(SetupError::InvalidInput("Keymap selection cancelled".to_string())),
+            "-" => query.clear(),
+            ":language" => grouping = Grouping::Language,
+            ":family" => grouping = Grouping::Family,
+            _ => {
+                if let Some(index) = input.parse::<usize>().ok().filter(|n| (1..=numbered.len()).contains(n)) {
+                    return Ok(numbered[index - 1].name.clone());
+                }
+                if keymaps.iter().any(|k| k.name == input) {
+                    return Ok(input);
+                }
+                let narrowed = format!("{} {}", query, input);
+                if keymap::search_keymaps(keymaps, &narrowed).is_empty() {
+                    let suggestions = keymap::suggest_keymaps(keymaps, &input);
+                    if suggestions.is_empty() {
+                        println!("No keymap matches '{}'", input);
+                    } else {
+                        println!("No keymap matches '{}'. Did you mean: {}?", input, suggestions.join(", "));
+                    }
+                } else {
+                    query = narrowed.trim().to_string();
+                }
+            }
+        }
+    }
+}
+
+/// Search the installed keymaps interactively and load the chosen one
+pub fn pick_keymap(target: Option<&Path>, trial: Option<Duration>) -> CommandResult<()> {
+    let keymaps = keymap::available_keymaps()?;
+    let suggested = KeymapHints::gather(target).suggestions(&keymaps);
+    let choice = prompt_keymap(&keymaps, &suggested)?;
+    set_keymap(&choice, target, trial)
+}
+
+/// Ask for the trial text until it is typed or `timeout` runs out, then
+/// bring `backup` back unless it was typed
+fn confirm_keymap(map: &str, backup: &KeymapBackup, timeout: Duration) -> CommandResult<()> {
+    println!(
+        "Type \"{}\" and press Enter within {} seconds to keep '{}'",
+        keymap::TRIAL_TEXT,
+        timeout.as_secs(),
+        map
+    );
+    let deadline = Instant::now() + timeout;
+    let input = LineReader::stdin();
+    let reason = loop {
+        print!("> ");
+        io::stdout().flush()?;
+        let remaining = deadline.saturating_duration_since(Instant::now());
+        match input.await_confirmation(keymap::TRIAL_TEXT, remaining) {
+            TrialOutcome::Confirmed => {
+                println!("Keymap '{}' confirmed", map);
+                return Ok(());
+            }
+            TrialOutcome::Mismatch(typed) if Instant::now() < deadline => {
+                println!("Got \"{}\", try again", typed);
+            }
+            TrialOutcome::Mismatch(_) => break "the text did not match",
+            TrialOutcome::TimedOut => break "no confirmation in time",
+        }
+    };
+
+    backup.restore()?;
+    Err(SetupError::InvalidInput(format!(
+        "Keymap '{}' not kept: {}; the previous keymap is restored",
+        map, reason
+    )))
+}
+
+/// Load `map` now and, with a target, make it the installed system's
+/// keymap. "auto" takes the most likely keymap from the system's hints.
+/// With a `trial` timeout the keymap is only kept once the user proves
+/// they can type with it.
+pub fn set_keymap(map: &str, target: Option<&Path>, trial: Option<Duration>) -> CommandResult<()> {
+    let suggestion;
+    let map = if map == "auto" {
+        suggestion = KeymapHints::gather(target)
+            .suggestions(&keymap::available_keymaps()?)
+            .into_iter()
+            .next()
+            .ok_or_else(|| {
+                SetupError::InvalidInput(
+                    "No keymap hint in the kernel command line, locale, time zone or firmware; name a keymap".to_string(),
+                )
+            })?;
+        println!("Using keymap '{}' from the {}", suggestion.keymap, suggestion.source);
+        suggestion.keymap.as_str()
+    } else {
+        map
+    };
+    let backup = trial.map(|_| KeymapBackup::save()).transpose()?;
     keymap::set_keymap(map)?;
     println!("Keymap set to '{}'", map);
+    if let (Some(timeout), Some(backup)) = (trial, &backup) {
+        confirm_keymap(map, backup, timeout)?;
+    }
+
+    let Some(root) = target else {
+        return Ok(());
+    };
+    match keymap::configure_keymap(&Target::new(root), map)? {
+        Some(xkb) if xkb.variant.is_empty() => println!("Installed keymap '{}', X11 layout '{}'", map, xkb.layout),
+        Some(xkb) => println!("Installed keymap '{}', X11 layout '{}' ({})", map, xkb.layout, xkb.variant),
+        None => println!("Installed keymap '{}'; no matching X11 layout is known, set one with localectl", map),
+    }
+    Ok(())
+}
+
+/// Apply console `font` now and, with a target, install it there. "auto"
+/// picks the Terminus size for the screen, covering `script`.
+pub fn set_console_font(font: &str, script: Script, target: Option<&Path>) -> CommandResult<()> {
+    let font = if font == "auto" { console::choose_font(script)? } else { font.to_string() };
+    console::set_font(&font)?;
+    println!("Console font set to '{}'", font);
+
+    if let Some(root) = target {
+        console::configure_font(&Target::new(root), &font)?;
+        println!("Installed console font '{}'", font);
+    }
+    Ok(())
+}
+
+/// Ask for the script to cover and offer the font fitting the screen
+pub fn pick_console_font(target: Option<&Path>) -> CommandResult<()> {
+    match console::fb_resolution() {
+        Some((width, height)) => println!(
+            "Screen is {}x{}, Terminus {} px fits about 60 rows",
+            width,
+            height,
+            console::terminus_size(height)
+        ),
+        None => println!("Screen resolution unknown, assuming 16 px fonts"),
+    }
+    for (i, script) in Script::ALL.iter().enumerate() {
+        println!("  {}) {}", i + 1, script);
+    }
+    let choice = prompt_number("Script to display (default 1): ", 1)? as usize;
+    let script = *Script::ALL
+        .get(choice.wrapping_sub(1))
+        .ok_or_else(|| SetupError::InvalidInput(format!("No script number {}", choice)))?;
+
+    let suggested = console::choose_font(script)?;
+    let font = prompt_input_default(&format!("Font (default {}): ", suggested), &suggested)?;
+    set_console_font(&font, script, target)
+}
+
+pub fn list_wifi_devices() -> CommandResult<()> {
+    let devices = wifi::list_devices()?;
+    println!("Wireless devices:");
+    for device in devices {
+        println!(
+            "  {:<12} {:<8} {:<18} {:<8} {}",
+            device.name,
+            device.adapter,
+            device.address,
+            device.mode,
+            if device.powered { "on" } else { "off" }
+        );
+    }
     Ok(())
 }
 
-pub fn list_wifi_networks() -> CommandResult<()> {
-    let networks = wifi::list_networks()?;
+pub fn list_wifi_networks(device: Option<&str>) -> CommandResult<()> {
+    let networks = wifi::list_networks_on(device)?;
     println!("Available WiFi networks:");
     println!("{}", networks);
     Ok(())
 }
 
-pub fn connect_wifi(ssid: &str, password: Option<&str>) -> CommandResult<()> {
-    let result = wifi::connect_network(ssid, password)?;
+/// WiFi password from `file`, the ASENOS_WIFI_PASSWORD variable or the
+/// `ssid:password` spec, in that order. Without any, a masked prompt asks
+/// for it if `ask` is set.
+pub fn wifi_password(inline: Option<&str>, file: Option<&Path>, ask: bool) -> CommandResult<Option<String>> {
+    if inline.is_some() {
+        println!(
+            "Warning: a password in --wifi-connect shows up in ps and shell history; \
+             use --wifi-password-file, {} or --wifi-ask-password instead",
+            WIFI_PASSWORD_ENV
+        );
+    }
+    if let Some(file) = file {
+        return secret::read_secret_file(file).map(Some);
+    }
+    if let Some(password) = secret::take_env_secret(WIFI_PASSWORD_ENV) {
+        return Ok(Some(password));
+    }
+    if let Some(password) = inline {
+        return Ok(Some(password.to_string()));
+    }
+    if ask {
+        return secret::prompt_password("WiFi password: ").map(Some);
+    }
+    Ok(None)
+}
+
+pub fn connect_wifi(ssid: &str, password: Option<&str>, device: Option<&str>, replace: bool) -> CommandResult<()> {
+    let result = wifi::connect_network_on(ssid, password, device, replace)?;
+    println!("WiFi connection result:");
+    println!("{}", result);
+    Ok(())
+}
+
+pub fn connect_wifi_network(network: &Network, device: Option<&str>, replace: bool) -> CommandResult<()> {
+    let result = wifi::connect_configured_on(network, device, replace)?;
+    println!("Saved {} profile for '{}'", network.security, network.ssid);
+    println!("WiFi connection result:");
+    println!("{}", result);
+    Ok(())
+}
+
+pub fn connect_wifi_enterprise(network: &EnterpriseNetwork, device: Option<&str>, replace: bool) -> CommandResult<()> {
+    if network.ca_cert.is_none() {
+        println!("Warning: no CA certificate given, any server claiming to be '{}' is trusted", network.ssid);
+    }
+    let result = wifi::connect_enterprise_on(network, device, replace)?;
+    println!("Saved {} 802.1X profile for '{}'", network.method, network.ssid);
     println!("WiFi connection result:");
     println!("{}", result);
     Ok(())
@@ -35,39 +310,122 @@ pub fn list_disks() -> CommandResult<()> {
     let disks = partition::list_disks()?;
     println!("Available disks:");
     println!("{}", disks);
+    print_disk_health();
     Ok(())
 }
 
-pub fn partition_disk_interactive() -> CommandResult<()> {
+/// SMART summary and warnings for every disk, skipped without smartctl
+fn print_disk_health() {
+    for name in disk::whole_disks_in(Path::new("/")) {
+        let device = format!("/dev/{}", name);
+        if let Ok(Some(health)) = smart::disk_health(&device) {
+            println!("{}: SMART {}", device, health.summary());
+            for warning in health.warnings() {
+                println!("  Warning: {}", warning);
+            }
+        }
+    }
+}
+
+/// Show the SMART problems of `disk` and ask for an explicit override if it
+/// is failing. Returns whether the user chose to install on it anyway.
+fn prompt_failing_disk(disk: &str) -> CommandResult<bool> {
+    let Some(health) = smart::disk_health(disk)? else {
+        return Ok(false);
+    };
+    if !health.is_failing() {
+        return Ok(false);
+    }
+
+    println!("{} reports SMART failures:", disk);
+    for warning in health.warnings() {
+        println!("  {}", warning);
+    }
+    println!("Data installed on this disk may be lost at any time.");
+    let answer = prompt_input_default("Type 'failing' to install on it anyway: ", "")?;
+    if answer != "failing" {
+        return Err(SetupError::InvalidInput("Partitioning cancelled".to_string()));
+    }
+    Ok(true)
+}
+
+pub fn partition_disk_interactive(settle_timeout: Duration, target: Option<&Path>) -> CommandResult<()> {
     println!("=== Asenos Partition Wizard ===");
     
     // Show available disks
     let disks = partition::list_disks()?;
     println!("Available disks:\n{}", disks);
+    print_disk_health();
     
     // Get user input
     let disk = prompt_input("Disk (e.g., /dev/sda): ")?;
-    let boot_size_mb = prompt_number("Boot size MB (default 512): ", 512)?;
-    let swap_size_mb = prompt_number("Swap size MB (default 2048): ", 2048)?;
+    let allow_failing_disk = prompt_failing_disk(disk.trim())?;
+
+    // Offer to keep /home when the disk already holds Asenos
+    if let Ok(existing) = reinstall::detect(disk.trim()) {
+        print_existing_install(&existing);
+        if prompt_bool("Reinstall and keep /home? (y/n, default y): ", true)? {
+            return reinstall_interactive(disk.trim(), &existing, settle_timeout, allow_failing_disk, target);
+        }
+    }
+
+    println!("Sizes accept units and percentages, e.g. 512MiB, 4G, 20% or rest");
+    let boot_size = prompt_size("Boot size (default 512MiB): ", SizeSpec::mib(512))?;
+    let swap_size = prompt_size("Swap size (default 2GiB): ", SizeSpec::mib(2048))?;
+    let root_size = prompt_size("Root size (default rest): ", SizeSpec::Rest)?;
     let use_gpt = prompt_bool("Use GPT? (y/n, default y): ", true)?;
-    let filesystem = prompt_input_default("Filesystem (ext4/btrfs/xfs, default ext4): ", "ext4")?;
+    let choices = Filesystem::ROOT_CHOICES.map(|f| f.name()).join("/");
+    let filesystem = prompt_input_default(&format!("Filesystem ({}, default ext4): ", choices), "ext4")?
+        .parse::<Filesystem>()?;
+    let use_free_space = prompt_bool("Install alongside existing systems in free space? (y/n, default n): ", false)?;
+    // Hibernation is set up in the installed system, so it needs one
+    let hibernate = target.is_some() && prompt_bool("Enable hibernation? (y/n, default n): ", false)?;
     
-    let config = partition::PartitionConfig::new(
+    let mut config = partition::PartitionConfig::new(
         disk.trim().to_string(),
-        boot_size_mb,
-        swap_size_mb,
+        boot_size,
+        swap_size,
         use_gpt,
         filesystem,
-    );
+    )
+    .with_firmware(detect_firmware_mode())
+    .with_root_size(root_size)
+    .with_settle_timeout(settle_timeout)
+    .with_allow_failing_disk(allow_failing_disk);
+    if hibernate {
+        config = config.with_hibernation(hibernate::mem_total_mib()?);
+    }
     
     // Validate and create
     config.validate()?;
+
+    println!("Firmware: {}", config.firmware);
+    let warnings = config.warnings();
+    for warning in &warnings {
+        println!("Warning: {}", warning);
+    }
+    if !warnings.is_empty() && !prompt_bool("Continue anyway? (y/n, default n): ", false)? {
+        return Err(SetupError::InvalidInput("Partitioning cancelled".to_string()));
+    }
+
+    if use_free_space {
+        let region = prompt_free_region(&config)?;
+        config = config.with_mode(partition::InstallMode::FreeSpace(Some(region)));
+        println!("\nCreating partitions on {} in {}-{} MiB, keeping existing partitions...",
+            config.disk, region.start_mib, region.end_mib);
+    } else {
+        let wipe_mode = prompt_wipe_mode(&config.disk)?;
+        config = config.with_wipe(wipe_mode);
+        println!("\nCreating partitions on {} with {} table...", 
+            config.disk, if config.use_gpt { "GPT" } else { "MBR" });
+    }
     
-    println!("\nCreating partitions on {} with {} table...", 
-        config.disk, if config.use_gpt { "GPT" } else { "MBR" });
-    
-    partition::create_partitions(&config)?;
+    let volumes = partition::create_partitions(&config)?;
     println!("Partitions created successfully!");
+    print_volumes(&volumes);
+    if let Some(root) = target {
+        configure_target(root, &config, &volumes)?;
+    }
     
     // Show result
     if let Ok(info) = partition::get_partition_info(&config.disk) {
@@ -77,14 +435,272 @@ pub fn partition_disk_interactive() -> CommandResult<()> {
     Ok(())
 }
 
-pub fn partition_disk_config(config_str: &str) -> CommandResult<()> {
-    let config = partition::PartitionConfig::from_string(config_str)?;
-    
-    println!("Creating partitions on {} with {} table...", 
-        config.disk, if config.use_gpt { "GPT" } else { "MBR" });
+/// Interactive reinstall over the Asenos install `existing`
+fn reinstall_interactive(
+    disk: &str,
+    existing: &ExistingInstall,
+    settle_timeout: Duration,
+    allow_failing_disk: bool,
+    target: Option<&Path>,
+) -> CommandResult<()> {
+    let choices = Filesystem::ROOT_CHOICES.map(|f| f.name()).join("/");
+    let default = if existing.home_shares_root() { "btrfs" } else { "ext4" };
+    let filesystem = prompt_input_default(&format!("Root filesystem ({}, default {}): ", choices, default), default)?
+        .parse::<Filesystem>()?;
+    let hibernate = target.is_some() && prompt_bool("Enable hibernation? (y/n, default n): ", false)?;
+
+    // The partitions are kept, so the config describes them as they are
+    let use_gpt = partition::read_table(disk)?.label() == "gpt";
+    let kept_mib = |device: Option<&str>| {
+        device.map(|d| reinstall::partition_mib_in(Path::new("/"), d)).transpose().map(Option::unwrap_or_default)
+    };
+    let boot_mib = kept_mib(existing.esp.as_deref())?;
+    let swap_mib = kept_mib(existing.swap.as_deref())?;
+
+    let mut config = partition::PartitionConfig::new(disk.to_string(), SizeSpec::mib(boot_mib), SizeSpec::mib(swap_mib), use_gpt, filesystem)
+        .with_mode(partition::InstallMode::Reinstall)
+        .with_firmware(detect_firmware_mode())
+        .with_settle_timeout(settle_timeout)
+        .with_allow_failing_disk(allow_failing_disk);
+    if hibernate {
+        config = config.with_hibernation(hibernate::mem_total_mib()?);
+    }
+    config.validate()?;
+    if let Some(ram_mib) = config.hibernate_ram_mib {
+        reinstall::check_hibernation_swap_in(Path::new("/"), existing, ram_mib)?;
+    }
+
+    if !prompt_bool(&format!("Reformat root on {}? (y/n, default n): ", existing.root), false)? {
+        return Err(SetupError::InvalidInput("Reinstall cancelled".to_string()));
+    }
+
+    let volumes = partition::create_partitions(&config)?;
+    println!("Reinstall prepared successfully!");
+    print_volumes(&volumes);
+    if let Some(root) = target {
+        configure_target(root, &config, &volumes)?;
+    }
+    recreate_users(target, existing)
+}
+
+/// Ask how to clear the disk and have the user confirm the chosen wipe
+fn prompt_wipe_mode(disk: &str) -> CommandResult<WipeMode> {
+    println!("Clear the disk first?");
+    for (i, mode) in WipeMode::ALL.iter().enumerate() {
+        println!("  {}) {} - {}", i + 1, mode, mode.description());
+    }
+
+    let choice = prompt_number("Wipe mode (default 1): ", 1)?;
+    let mode = WipeMode::ALL.get((choice as usize).wrapping_sub(1))
+        .copied()
+        .ok_or_else(|| SetupError::InvalidInput("Invalid wipe mode".to_string()))?;
+    if mode == WipeMode::None {
+        return Ok(mode);
+    }
+
+    let plan = wipe::plan_wipe(disk, mode)?;
+    let answer = prompt_input_default(&plan.confirm_prompt(), "")?;
+    plan.check_confirmation(&answer)?;
+    Ok(mode)
+}
+
+/// Write fstab, I/O scheduler, TRIM and hibernation settings into the
+/// installed system
+fn configure_target(root: &Path, config: &partition::PartitionConfig, volumes: &[partition::Volume]) -> CommandResult<()> {
+    let target = Target::new(root);
+    let media = disk::storage_media(&config.disk);
+    target.configure_storage(volumes, media)?;
+    println!("Configured {} for {} storage", root.display(), media);
+    if media.supports_trim() {
+        println!("Enabled fstrim.timer");
+    }
+
+    // Arrays must be assembled before resume looks for the swap device
+    if volumes.iter().any(|v| v.device.starts_with("/dev/md/")) {
+        raid::configure_raid(&target, &raid::scan_arrays()?)?;
+        println!("Wrote mdadm.conf and added the mdadm_udev hook");
+    }
+
+    if config.hibernate_ram_mib.is_some() {
+        let resume = ResumeDevice::from_volumes(volumes)?;
+        hibernate::configure_hibernation(&target, &resume)?;
+        println!("Hibernation enabled: {}", resume.kernel_params().join(" "));
+    }
+    Ok(())
+}
+
+/// Show what a reinstall keeps
+fn print_existing_install(existing: &ExistingInstall) {
+    println!("Found Asenos on {}", existing.root);
+    match &existing.home_subvolume {
+        Some(subvolume) => println!("  /home: subvolume {} of {} (kept)", subvolume, existing.home),
+        None => println!("  /home: {} {} (kept)", existing.home, existing.home_filesystem),
+    }
+    if let Some(esp) = &existing.esp {
+        println!("  ESP: {}{}", esp, if existing.reformat_esp { "" } else { " (shared, kept)" });
+    }
+    let users: Vec<String> = existing.users.iter().map(|u| format!("{} ({})", u.name, u.uid)).collect();
+    if !users.is_empty() {
+        println!("  Users: {}", users.join(", "));
+    }
+}
+
+/// Recreate the old accounts in the target so they own the kept /home
+fn recreate_users(target: Option<&Path>, existing: &ExistingInstall) -> CommandResult<()> {
+    match target {
+        Some(root) => {
+            reinstall::recreate_users(&Target::new(root), &existing.users)?;
+            println!("Recreated {} user account(s)", existing.users.len());
+        }
+        None if !existing.users.is_empty() => {
+            println!("Warning: user accounts were not recreated; pass --target to keep their UIDs");
+        }
+        None => {}
+    }
+    Ok(())
+}
+
+fn print_volumes(volumes: &[partition::Volume]) {
+    for volume in volumes {
+        let uuid = volume.format.fs_uuid().map(|u| format!(" UUID={}", u)).unwrap_or_default();
+        let state = if volume.formatted { "" } else { " (kept)" };
+        println!("  {}: {} {}{}{}", volume.device, volume.format.filesystem, volume.format.label, uuid, state);
+    }
+}
+
+/// Show the free regions that fit the install and let the user pick one
+fn prompt_free_region(config: &partition::PartitionConfig) -> CommandResult<partition::FreeRegion> {
+    let table = partition::read_table(&config.disk)?;
+    let layout = partition::layout_from_table(&table);
+    let boot = config.boot_layout();
+    let existing_boot = layout.boot_partition(boot);
+    let regions = layout.free_regions(config.required_free_mib(existing_boot.is_some()));
+
+    if regions.is_empty() {
+        return Err(SetupError::InvalidInput(format!(
+            "No unallocated region on {} is large enough for the install",
+            config.disk
+        )));
+    }
+
+    let systems = table.detected_systems();
+    if !systems.is_empty() {
+        println!("Existing systems on {}: {}", config.disk, systems.join(", "));
+    }
+
+    match (existing_boot, boot) {
+        (Some(part), _) => println!("Existing boot partition {} will be reused", part.number),
+        (None, partition::BootLayout::Esp) => println!("No EFI system partition found, a new one will be created"),
+        (None, partition::BootLayout::BiosGrub) => println!("No bios_grub partition found, a new one will be created"),
+        (None, partition::BootLayout::None) => {}
+    }
+
+    println!("Free regions:");
+    for (i, region) in regions.iter().enumerate() {
+        println!("  {}) {}-{} MiB ({} MiB)", i + 1, region.start_mib, region.end_mib, region.size_mib());
+    }
+
+    let choice = prompt_number("Region (default 1): ", 1)?;
+    regions.get((choice as usize).wrapping_sub(1))
+        .copied()
+        .ok_or_else(|| SetupError::InvalidInput("Invalid region".to_string()))
+}
+
+/// Options of a non-interactive install besides the configuration string
+#[derive(Debug, Clone, Default)]
+pub struct PartitionOptions<'a> {
+    pub settle_timeout: Duration,
+    pub wipe: WipeMode,
+    /// Confirmation word for the wipe
+    pub confirm_wipe: Option<&'a str>,
+    pub allow_failing_disk: bool,
+    /// Root of the installed system to configure afterwards
+    pub target: Option<&'a Path>,
+    pub hibernate: bool,
+    /// Further disks mirroring the configured one
+    pub raid_disks: Vec<String>,
+    pub raid_level: RaidLevel,
+    /// Disk to hold /home on its own
+    pub home_disk: Option<String>,
+}
+
+/// Partition from a config string. A wipe other than `none` only runs when
+/// `options.confirm_wipe` is the mode's confirmation word.
+pub fn partition_disk_config(config_str: &str, options: &PartitionOptions) -> CommandResult<()> {
+    let wipe_mode = options.wipe;
+    let mut config = partition::PartitionConfig::from_string(config_str)?
+        .with_firmware(detect_firmware_mode())
+        .with_settle_timeout(options.settle_timeout)
+        .with_wipe(wipe_mode)
+        .with_allow_failing_disk(options.allow_failing_disk);
+    if options.hibernate {
+        config = config.with_hibernation(hibernate::mem_total_mib()?);
+    }
+    config.validate()?;
+    let raid = (!options.raid_disks.is_empty()).then(|| {
+        RaidConfig::new(config.clone(), options.raid_disks.clone(), options.raid_level)
+            .with_home_disk(options.home_disk.clone())
+    });
+    if let Some(raid) = &raid {
+        raid.validate()?;
+    }
+    let existing = match config.mode {
+        partition::InstallMode::Reinstall => {
+            if options.home_disk.is_some() {
+                return Err(SetupError::InvalidInput("A reinstall keeps the existing /home".to_string()));
+            }
+            let existing = reinstall::detect(&config.disk)?;
+            print_existing_install(&existing);
+            Some(existing)
+        }
+        _ => None,
+    };
+    let disks = raid.as_ref().map_or_else(|| vec![config.disk.clone()], |r| r.disks.clone());
+    let all_disks: Vec<&String> = disks.iter().chain(&options.home_disk).collect();
+
+    if wipe_mode != WipeMode::None {
+        for disk in &all_disks {
+            let plan = wipe::plan_wipe(disk, wipe_mode)?;
+            println!("Wipe: {} of {}, {}", wipe_mode, disk, wipe::format_estimate(plan.estimate));
+            plan.check_confirmation(options.confirm_wipe.unwrap_or(""))?;
+        }
+    }
+
+    for warning in config.warnings() {
+        println!("Warning: {}", warning);
+    }
+    for disk in &all_disks {
+        if let Ok(Some(health)) = smart::disk_health(disk) {
+            for warning in health.warnings() {
+                println!("Warning: SMART: {}: {}", disk, warning);
+            }
+        }
+    }
     
-    partition::create_partitions(&config)?;
+    let volumes = if let Some(raid) = &raid {
+        println!("Creating {} across {}...", raid.level, raid.disks.join(", "));
+        raid::create_raid(raid)?
+    } else {
+        match config.mode {
+            partition::InstallMode::WipeDisk => println!("Creating partitions on {} with {} table...", 
+                config.disk, if config.use_gpt { "GPT" } else { "MBR" }),
+            partition::InstallMode::FreeSpace(_) => println!("Creating partitions in free space on {}...", config.disk),
+            partition::InstallMode::Reinstall => println!("Reinstalling on {}...", config.disk),
+        }
+        let mut volumes = partition::create_partitions(&config)?;
+        if let Some(home) = &options.home_disk {
+            volumes.push(partition::create_home_partition(&config, home)?);
+        }
+        volumes
+    };
     println!("Partitions created successfully!");
+    print_volumes(&volumes);
+    if let Some(root) = options.target {
+        configure_target(root, &config, &volumes)?;
+    }
+    if let Some(existing) = &existing {
+        recreate_users(options.target, existing)?;
+    }
     
     // Show result
     if let Ok(info) = partition::get_partition_info(&config.disk) {
@@ -137,6 +753,20 @@ fn prompt_number(prompt: &str, default: u32) -> CommandResult<u32> {
     }
 }
 
+fn prompt_size(prompt: &str, default: SizeSpec) -> CommandResult<SizeSpec> {
+    print!("{}", prompt);
+    io::stdout().flush()?;
+    let mut input = String::new();
+    io::stdin().read_line(&mut input)?;
+    let trimmed = input.trim();
+    
+    if trimmed.is_empty() {
+        Ok(default)
+    } else {
+        trimmed.parse()
+    }
+}
+
 fn prompt_bool(prompt: &str, default: bool) -> CommandResult<bool> {
     print!("{}", prompt);
     io::stdout().flush()?;
@@ -1,3 +1,4 @@
+use crate::secret::redact;
 use std::io::Write;
 use std::process::{Command, Stdio};
 use thiserror::Error;
@@ -14,10 +15,23 @@ pub enum SetupError {
     System(String),
 }
 
+impl SetupError {
+    /// The same error with `secret` masked in its message
+    pub fn redact(self, secret: &str) -> Self {
+        match self {
+            SetupError::CommandFailed(msg) => SetupError::CommandFailed(redact(&msg, secret)),
+            SetupError::InvalidInput(msg) => SetupError::InvalidInput(redact(&msg, secret)),
+            SetupError::System(msg) => SetupError::System(redact(&msg, secret)),
+            SetupError::Io(e) => SetupError::Io(e),
+        }
+    }
+}
+
 pub type CommandResult<T> = Result<T, SetupError>;
 
 /// Execute a system command with optional stdin input
-/// Returns stdout/stderr combined on success, or SetupError on failure
+/// Returns stdout/stderr combined on success, or SetupError on failure.
+/// Programs may echo their input, so it is masked in the error message.
 pub fn run_command(args: &[&str], input: Option<&str>) -> CommandResult<String> {
     if args.is_empty() {
         return Err(SetupError::InvalidInput("No command provided".to_string()));
@@ -53,7 +67,11 @@ pub fn run_command(args: &[&str], input: Option<&str>) -> CommandResult<String>
     if output.status.success() {
         Ok(combined)
     } else {
-        Err(SetupError::CommandFailed(format!("Command {} failed: {}", args[0], combined)))
+        let error = SetupError::CommandFailed(format!("Command {} failed: {}", args[0], combined));
+        Err(match input {
+            Some(s) => error.redact(s.trim_end()),
+            None => error,
+        })
     }
 }
 
@@ -84,6 +102,15 @@ mod tests {
         assert!(result.unwrap().contains("hello"));
     }
 
+    #[test]
+    fn test_run_command_masks_input_on_failure() {
+        let result = run_command(&["sh", "-c", "cat; exit 1"], Some("hunter22"));
+        match result {
+            Err(SetupError::CommandFailed(msg)) => assert!(!msg.contains("hunter22"), "{}", msg),
+            other => panic!("Expected CommandFailed, got {:?}", other),
+        }
+    }
+
     #[test]
     fn test_run_command_empty_args() {
         let result = run_command(&[], None);
@@ -0,0 +1,202 @@
+use crate::common::{run_command, CommandResult, SetupError};
+use crate::target::Target;
+use std::fmt;
+use std::fs;
+use std::path::Path;
+use std::str::FromStr;
+
+/// Console fonts relative to the filesystem root
+pub const CONSOLE_FONTS_DIR: &str = "usr/share/kbd/consolefonts";
+
+/// Heights of the Terminus console fonts in pixels
+pub const TERMINUS_SIZES: [u32; 9] = [12, 14, 16, 18, 20, 22, 24, 28, 32];
+
+/// Rows of text the chosen font size aims for
+const TARGET_ROWS: u32 = 60;
+
+/// File suffixes setfont accepts after a font name
+const FONT_SUFFIXES: [&str; 4] = [".psfu.gz", ".psf.gz", ".psfu", ".psf"];
+
+/// Writing system the console font has to cover
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Script {
+    Latin,
+    Greek,
+    Cyrillic,
+    Arabic,
+    Hebrew,
+    Ethiopic,
+}
+
+impl Script {
+    pub const ALL: [Script; 6] =
+        [Script::Latin, Script::Greek, Script::Cyrillic, Script::Arabic, Script::Hebrew, Script::Ethiopic];
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            Script::Latin => "latin",
+            Script::Greek => "greek",
+            Script::Cyrillic => "cyrillic",
+            Script::Arabic => "arabic",
+            Script::Hebrew => "hebrew",
+            Script::Ethiopic => "ethiopic",
+        }
+    }
+
+    /// Fonts covering the script at roughly `size` pixels, preferred first.
+    /// Terminus covers Latin, Greek and Cyrillic at every size; the other
+    /// scripts only come in the sizes kbd ships.
+    pub fn fonts(&self, size: u32) -> Vec<String> {
+        match self {
+            Script::Latin | Script::Greek | Script::Cyrillic => {
+                vec![format!("ter-v{}n", size), format!("ter-v{}b", size)]
+            }
+            Script::Arabic | Script::Hebrew => {
+                let sizes = if size >= 19 { ["19", "16", "14"] } else { ["16", "14", "19"] };
+                sizes.iter().map(|s| format!("LatArCyrHeb-{}", s)).collect()
+            }
+            Script::Ethiopic => ["16", "14", "12"].iter().map(|s| format!("Agafari-{}", s)).collect(),
+        }
+    }
+}
+
+impl fmt::Display for Script {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.name())
+    }
+}
+
+impl FromStr for Script {
+    type Err = SetupError;
+
+    fn from_str(s: &str) -> CommandResult<Self> {
+        Script::ALL
+            .into_iter()
+            .find(|script| script.name().eq_ignore_ascii_case(s.trim()))
+            .ok_or_else(|| {
+                let names: Vec<&str> = Script::ALL.iter().map(Script::name).collect();
+                SetupError::InvalidInput(format!("Unknown script '{}', expected one of: {}", s, names.join(", ")))
+            })
+    }
+}
+
+/// Framebuffer width and height from `sys/class/graphics/fb0` under `root`
+pub fn fb_resolution_in(root: &Path) -> Option<(u32, u32)> {
+    let size = fs::read_to_string(root.join("sys/class/graphics/fb0/virtual_size")).ok()?;
+    let (width, height) = size.trim().split_once(',')?;
+    Some((width.parse().ok()?, height.parse().ok()?))
+}
+
+/// Framebuffer resolution of this machine
+pub fn fb_resolution() -> Option<(u32, u32)> {
+    fb_resolution_in(Path::new("/"))
+}
+
+/// Largest Terminus size that still leaves about 60 rows, 16 at least so
+/// low resolutions keep the usual console look
+pub fn terminus_size(height: u32) -> u32 {
+    TERMINUS_SIZES
+        .iter()
+        .copied()
+        .filter(|size| *size >= 16 && size * TARGET_ROWS <= height)
+        .max()
+        .unwrap_or(16)
+}
+
+/// Console fonts installed under `root`, by the name setfont takes
+pub fn available_fonts_in(root: &Path) -> Vec<String> {
+    let Ok(entries) = fs::read_dir(root.join(CONSOLE_FONTS_DIR)) else {
+        return Vec::new();
+    };
+    let mut fonts: Vec<String> = entries
+        .flatten()
+        .filter_map(|entry| {
+            let file_name = entry.file_name().to_string_lossy().to_string();
+            FONT_SUFFIXES.iter().find_map(|suffix| file_name.strip_suffix(suffix)).map(str::to_string)
+        })
+        .collect();
+    fonts.sort();
+    fonts.dedup();
+    fonts
+}
+
+/// Font for `script` suiting a screen `height` pixels high, among the
+/// fonts installed under `root`. Without a known height 16 px is assumed.
+pub fn choose_font_in(root: &Path, script: Script, height: Option<u32>) -> CommandResult<String> {
+    let size = height.map(terminus_size).unwrap_or(16);
+    let installed = available_fonts_in(root);
+    script
+        .fonts(size)
+        .into_iter()
+        .find(|font| installed.contains(font))
+        .ok_or_else(|| {
+            SetupError::System(format!(
+                "No {} console font for {} px installed in {}",
+                script,
+                size,
+                root.join(CONSOLE_FONTS_DIR).display()
+            ))
+        })
+}
+
+/// Font for `script` on this machine's screen
+pub fn choose_font(script: Script) -> CommandResult<String> {
+    choose_font_in(Path::new("/"), script, fb_resolution().map(|(_, height)| height))
+}
+
+/// Refuse fonts that are not installed under `root`
+pub fn check_font_in(root: &Path, font: &str) -> CommandResult<()> {
+    if font.trim().is_empty() {
+        return Err(SetupError::InvalidInput("Font cannot be empty".to_string()));
+    }
+    if !available_fonts_in(root).iter().any(|f| f == font) {
+        return Err(SetupError::InvalidInput(format!(
+            "Console font '{}' is not installed in {}",
+            font,
+            root.join(CONSOLE_FONTS_DIR).display()
+        )));
+    }
+    Ok(())
+}
+
+/// Switch the live console to `font`
+pub fn set_font(font: &str) -> CommandResult<()> {
+    check_font_in(Path::new("/"), font)?;
+    run_command(&["setfont", font], None)?;
+    Ok(())
+}
+
+/// Make `font` the installed system's console font, also in the initramfs
+/// so early prompts use it
+pub fn configure_font(target: &Target, font: &str) -> CommandResult<()> {
+    check_font_in(&target.root, font)?;
+    let systemd = target.initramfs_hooks()?.iter().any(|h| h == "systemd");
+    target.set_vconsole("FONT", font)?;
+
+    let before = ["block", "sd-encrypt", "encrypt", "filesystems"];
+    if systemd {
+        target.add_initramfs_hook("sd-vconsole", &before)
+    } else {
+        target.add_initramfs_hook("consolefont", &before)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_terminus_size_by_height() {
+        assert_eq!(terminus_size(768), 16);
+        assert_eq!(terminus_size(1080), 18);
+        assert_eq!(terminus_size(1440), 24);
+        assert_eq!(terminus_size(2160), 32);
+    }
+
+    #[test]
+    fn test_script_names() {
+        assert_eq!("Cyrillic".parse::<Script>().unwrap(), Script::Cyrillic);
+        let result = "klingon".parse::<Script>();
+        assert!(matches!(result, Err(SetupError::InvalidInput(msg)) if msg.contains("latin, greek")));
+    }
+}
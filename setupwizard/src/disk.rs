@@ -0,0 +1,439 @@
+use crate::common::{command_exists, run_command, CommandResult, SetupError};
+use std::fmt;
+use std::fs;
+use std::path::Path;
+use std::thread;
+use std::time::{Duration, Instant};
+
+/// Where archiso mounts the medium the live system booted from
+pub const ARCHISO_BOOT_MOUNT: &str = "/run/archiso/bootmnt";
+
+/// Label prefix of the Asenos ISO filesystem (see iso/profiledef.sh)
+pub const ISO_LABEL_PREFIX: &str = "ASENOS_";
+
+/// How long to wait for partition device nodes after re-reading a table
+pub const DEFAULT_SETTLE_TIMEOUT: Duration = Duration::from_secs(10);
+
+const SETTLE_POLL_INTERVAL: Duration = Duration::from_millis(50);
+
+/// Largest disk an MBR partition table can address with 512-byte sectors
+pub const MBR_MAX_BYTES: u64 = 2 * 1024 * 1024 * 1024 * 1024;
+
+/// Kernel name of a block device path, e.g. "sda" for "/dev/sda" or for
+/// a /dev/disk/by-id symlink pointing at it.
+/// `root` is the filesystem root the path is resolved in.
+pub fn block_name_in(root: &Path, device: &str) -> String {
+    let path = root.join(device.trim_start_matches('/'));
+    fs::canonicalize(&path)
+        .ok()
+        .as_deref()
+        .and_then(Path::file_name)
+        .or_else(|| Path::new(device).file_name())
+        .map(|n| n.to_string_lossy().into_owned())
+        .unwrap_or_default()
+}
+
+/// Whether `name` is a whole disk rather than a partition
+pub fn is_whole_disk_in(root: &Path, name: &str) -> bool {
+    let dir = root.join("sys/class/block").join(name);
+    dir.exists() && !dir.join("partition").exists()
+}
+
+/// Kernel names of the partitions of disk `name`
+pub fn disk_partitions_in(root: &Path, name: &str) -> Vec<String> {
+    let dir = root.join("sys/class/block").join(name);
+    let mut partitions: Vec<String> = fs::read_dir(&dir)
+        .map(|entries| {
+            entries
+                .flatten()
+                .filter(|e| e.path().join("partition").exists())
+                .map(|e| e.file_name().to_string_lossy().into_owned())
+                .collect()
+        })
+        .unwrap_or_default();
+    partitions.sort();
+    partitions
+}
+
+/// Kernel names of the disks an install can target, skipping loop, RAM,
+/// zram and optical devices
+pub fn whole_disks_in(root: &Path) -> Vec<String> {
+    let mut disks: Vec<String> = fs::read_dir(root.join("sys/block"))
+        .map(|entries| {
+            entries
+                .flatten()
+                .map(|e| e.file_name().to_string_lossy().into_owned())
+                .filter(|name| !["loop", "ram", "zram", "sr", "fd"].iter().any(|p| name.starts_with(p)))
+                .collect()
+        })
+        .unwrap_or_default();
+    disks.sort();
+    disks
+}
+
+/// Device node of partition `number` on disk `name`, found by reading the
+/// `partition` attribute of each of the disk's partitions in sysfs
+pub fn partition_node_in(root: &Path, name: &str, number: u32) -> Option<String> {
+    let dir = root.join("sys/class/block").join(name);
+    disk_partitions_in(root, name)
+        .into_iter()
+        .find(|part| {
+            fs::read_to_string(dir.join(part).join("partition"))
+                .ok()
+                .and_then(|n| n.trim().parse::<u32>().ok())
+                == Some(number)
+        })
+        .map(|part| format!("/dev/{}", part))
+}
+
+/// Device node of partition `number` on `disk` as the kernel reports it
+pub fn partition_node(disk: &str, number: u32) -> CommandResult<String> {
+    let root = Path::new("/");
+    let name = block_name_in(root, disk);
+    partition_node_in(root, &name, number).ok_or_else(|| {
+        SetupError::System(format!("Partition {} of {} not found in sysfs", number, disk))
+    })
+}
+
+/// Device nodes of partitions `numbers` on disk `name`, in the same order.
+/// Waits up to `timeout` for the kernel to list each partition in sysfs and
+/// for udev to create its node.
+pub fn wait_for_partitions_in(
+    root: &Path,
+    name: &str,
+    numbers: &[u32],
+    timeout: Duration,
+) -> CommandResult<Vec<String>> {
+    let started = Instant::now();
+
+    loop {
+        let nodes: Vec<Option<String>> = numbers
+            .iter()
+            .map(|n| partition_node_in(root, name, *n))
+            .collect();
+        let ready = |node: &Option<String>| {
+            node.as_ref().is_some_and(|node| root.join(node.trim_start_matches('/')).exists())
+        };
+
+        if nodes.iter().all(ready) {
+            return Ok(nodes.into_iter().flatten().collect());
+        }
+
+        if started.elapsed() >= timeout {
+            let missing: Vec<String> = numbers
+                .iter()
+                .zip(&nodes)
+                .filter(|(_, node)| !ready(node))
+                .map(|(n, node)| node.clone().unwrap_or_else(|| format!("partition {} of /dev/{}", n, name)))
+                .collect();
+            return Err(SetupError::System(format!(
+                "Timed out after {:.1}s waiting for device nodes: {}",
+                timeout.as_secs_f64(),
+                missing.join(", ")
+            )));
+        }
+
+        thread::sleep(SETTLE_POLL_INTERVAL);
+    }
+}
+
+/// Have the kernel re-read the partition table of `disk`, then wait until
+/// the nodes of partitions `numbers` exist
+pub fn reread_partitions(disk: &str, numbers: &[u32], timeout: Duration) -> CommandResult<Vec<String>> {
+    run_command(&["partprobe", disk], None)?;
+
+    // Settling only shortens the polling below, so its own timeout is not fatal
+    if command_exists("udevadm") {
+        let timeout_arg = format!("--timeout={}", timeout.as_secs().max(1));
+        let _ = run_command(&["udevadm", "settle", &timeout_arg], None);
+    }
+
+    let root = Path::new("/");
+    wait_for_partitions_in(root, &block_name_in(root, disk), numbers, timeout)
+}
+
+/// Disk that partition `name` belongs to, or `name` itself for whole disks
+pub fn parent_disk_in(root: &Path, name: &str) -> Option<String> {
+    if is_whole_disk_in(root, name) {
+        return Some(name.to_string());
+    }
+
+    fs::read_dir(root.join("sys/class/block"))
+        .ok()?
+        .flatten()
+        .map(|e| e.file_name().to_string_lossy().into_owned())
+        .find(|disk| is_whole_disk_in(root, disk) && disk_partitions_in(root, disk).iter().any(|p| p == name))
+}
+
+/// Size of block device `name` in bytes
+pub fn disk_size_bytes_in(root: &Path, name: &str) -> CommandResult<u64> {
+    let sectors = fs::read_to_string(root.join("sys/class/block").join(name).join("size"))
+        .map_err(|_| SetupError::InvalidInput(format!("{} is not a block device", name)))?;
+    let sectors = sectors.trim().parse::<u64>()
+        .map_err(|_| SetupError::System(format!("Invalid size reported for {}", name)))?;
+
+    // sysfs always counts 512-byte sectors, whatever the logical sector size
+    Ok(sectors * 512)
+}
+
+/// Size of a block device in bytes
+pub fn disk_size_bytes(disk: &str) -> CommandResult<u64> {
+    let root = Path::new("/");
+    disk_size_bytes_in(root, &block_name_in(root, disk))
+}
+
+/// Logical sector size of disk `name`, the unit its partition table
+/// counts in
+pub fn logical_block_size_in(root: &Path, name: &str) -> Option<u64> {
+    fs::read_to_string(root.join("sys/class/block").join(name).join("queue/logical_block_size"))
+        .ok()
+        .and_then(|v| v.trim().parse().ok())
+        .filter(|size: &u64| size.is_power_of_two() && *size >= 512)
+}
+
+/// Whether disk `name` reports itself as rotational; unknown devices count as rotational
+pub fn is_rotational_in(root: &Path, name: &str) -> bool {
+    fs::read_to_string(root.join("sys/class/block").join(name).join("queue/rotational"))
+        .map(|v| v.trim() != "0")
+        .unwrap_or(true)
+}
+
+/// Largest discard request disk `name` accepts, 0 when it cannot discard
+pub fn discard_max_bytes_in(root: &Path, name: &str) -> u64 {
+    fs::read_to_string(root.join("sys/class/block").join(name).join("queue/discard_max_bytes"))
+        .ok()
+        .and_then(|v| v.trim().parse().ok())
+        .unwrap_or(0)
+}
+
+/// Smallest unit disk `name` can discard, 0 when it cannot discard
+pub fn discard_granularity_in(root: &Path, name: &str) -> u64 {
+    fs::read_to_string(root.join("sys/class/block").join(name).join("queue/discard_granularity"))
+        .ok()
+        .and_then(|v| v.trim().parse().ok())
+        .unwrap_or(0)
+}
+
+/// Kind of storage behind a disk, as far as tuning is concerned
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum StorageMedia {
+    Rotational,
+    /// SATA SSD, NVMe, eMMC or any other non-rotational disk
+    SolidState { trim: bool },
+}
+
+impl StorageMedia {
+    pub fn is_solid_state(&self) -> bool {
+        matches!(self, StorageMedia::SolidState { .. })
+    }
+
+    /// Whether periodic TRIM is worth enabling
+    pub fn supports_trim(&self) -> bool {
+        matches!(self, StorageMedia::SolidState { trim: true })
+    }
+}
+
+impl fmt::Display for StorageMedia {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            StorageMedia::Rotational => write!(f, "rotational"),
+            StorageMedia::SolidState { trim: true } => write!(f, "solid-state with TRIM"),
+            StorageMedia::SolidState { trim: false } => write!(f, "solid-state without TRIM"),
+        }
+    }
+}
+
+/// Storage media of disk `name` from its sysfs queue attributes
+pub fn storage_media_in(root: &Path, name: &str) -> StorageMedia {
+    if is_rotational_in(root, name) {
+        return StorageMedia::Rotational;
+    }
+    StorageMedia::SolidState {
+        trim: discard_granularity_in(root, name) > 0 && discard_max_bytes_in(root, name) > 0,
+    }
+}
+
+/// Storage media of a disk
+pub fn storage_media(disk: &str) -> StorageMedia {
+    let root = Path::new("/");
+    storage_media_in(root, &block_name_in(root, disk))
+}
+
+/// Mounted devices as (device, mount point) pairs from proc/mounts
+pub fn mounted_devices_in(root: &Path) -> Vec<(String, String)> {
+    fs::read_to_string(root.join("proc/mounts"))
+        .unwrap_or_default()
+        .lines()
+        .filter_map(|line| {
+            let mut fields = line.split_whitespace();
+            let device = fields.next()?;
+            let mount_point = fields.next()?;
+            device.starts_with("/dev/").then(|| (device.to_string(), unescape_mount(mount_point)))
+        })
+        .collect()
+}
+
+/// Undo the octal escaping proc/mounts applies to spaces and tabs
+fn unescape_mount(path: &str) -> String {
+    path.replace("\\040", " ").replace("\\011", "\t").replace("\\134", "\\")
+}
+
+/// Devices in use as swap from proc/swaps
+pub fn active_swaps_in(root: &Path) -> Vec<String> {
+    fs::read_to_string(root.join("proc/swaps"))
+        .unwrap_or_default()
+        .lines()
+        .skip(1)
+        .filter_map(|line| line.split_whitespace().next())
+        .filter(|device| device.starts_with("/dev/"))
+        .map(str::to_string)
+        .collect()
+}
+
+/// Devices stacked on top of `name`, such as LUKS or LVM mappings
+pub fn holders_in(root: &Path, name: &str) -> Vec<String> {
+    fs::read_dir(root.join("sys/class/block").join(name).join("holders"))
+        .map(|entries| entries.flatten().map(|e| e.file_name().to_string_lossy().into_owned()).collect())
+        .unwrap_or_default()
+}
+
+/// Whether `label` is an ISO label (ASENOS_YYYYMM) rather than one of the
+/// ASENOS_* labels the installer gives to partitions
+pub fn is_iso_label(label: &str) -> bool {
+    label
+        .strip_prefix(ISO_LABEL_PREFIX)
+        .is_some_and(|date| date.len() == 6 && date.chars().all(|c| c.is_ascii_digit()))
+}
+
+/// Disk holding the medium the live ISO booted from, found through the
+/// archiso boot mount, the ISO label or the archisosearchuuid parameter
+pub fn live_medium_disk_in(root: &Path) -> Option<String> {
+    let by_mount = mounted_devices_in(root)
+        .into_iter()
+        .find(|(_, mount_point)| mount_point == ARCHISO_BOOT_MOUNT)
+        .map(|(device, _)| block_name_in(root, &device));
+
+    let by_label = || {
+        fs::read_dir(root.join("dev/disk/by-label"))
+            .ok()?
+            .flatten()
+            .find(|e| is_iso_label(&e.file_name().to_string_lossy()))
+            .map(|e| block_name_in(root, &format!("/dev/disk/by-label/{}", e.file_name().to_string_lossy())))
+    };
+
+    let by_uuid = || {
+        let cmdline = fs::read_to_string(root.join("proc/cmdline")).ok()?;
+        let uuid = cmdline
+            .split_whitespace()
+            .find_map(|arg| arg.strip_prefix("archisosearchuuid="))?;
+        let link = format!("/dev/disk/by-uuid/{}", uuid);
+        root.join(link.trim_start_matches('/')).exists().then(|| block_name_in(root, &link))
+    };
+
+    by_mount
+        .or_else(by_label)
+        .or_else(by_uuid)
+        .and_then(|name| parent_disk_in(root, &name))
+}
+
+/// Refuse a target disk that is unsafe to partition: a partition instead of
+/// a whole disk, a disk with mounted, swapped-on or stacked partitions,
+/// or the medium the live system is running from
+pub fn check_target_disk_in(root: &Path, disk: &str) -> CommandResult<()> {
+    let name = block_name_in(root, disk);
+
+    if !root.join("sys/class/block").join(&name).exists() {
+        return Err(SetupError::InvalidInput(format!("{} is not a block device", disk)));
+    }
+
+    if !is_whole_disk_in(root, &name) {
+        let parent = parent_disk_in(root, &name).unwrap_or_else(|| "its disk".to_string());
+        return Err(SetupError::InvalidInput(format!(
+            "{} is a partition; select the whole disk ({}) instead", disk, parent
+        )));
+    }
+
+    if live_medium_disk_in(root).as_deref() == Some(name.as_str()) {
+        return Err(SetupError::InvalidInput(format!(
+            "{} holds the running live system and cannot be partitioned", disk
+        )));
+    }
+
+    let mut devices = disk_partitions_in(root, &name);
+    devices.push(name.clone());
+
+    for (device, mount_point) in mounted_devices_in(root) {
+        if devices.contains(&block_name_in(root, &device)) {
+            return Err(SetupError::InvalidInput(format!(
+                "{} is mounted at {}; unmount it first", device, mount_point
+            )));
+        }
+    }
+
+    for device in active_swaps_in(root) {
+        if devices.contains(&block_name_in(root, &device)) {
+            return Err(SetupError::InvalidInput(format!(
+                "{} is in use as swap; run swapoff first", device
+            )));
+        }
+    }
+
+    for device in &devices {
+        if let Some(holder) = holders_in(root, device).first() {
+            return Err(SetupError::InvalidInput(format!(
+                "{} is in use by {}; close it first", device, holder
+            )));
+        }
+    }
+
+    Ok(())
+}
+
+/// Refuse a target disk that is unsafe to partition
+pub fn check_target_disk(disk: &str) -> CommandResult<()> {
+    check_target_disk_in(Path::new("/"), disk)
+}
+
+/// Non-fatal problems with partitioning `disk` using the given table type
+pub fn disk_warnings_in(root: &Path, disk: &str, use_gpt: bool) -> Vec<String> {
+    let mut warnings = Vec::new();
+
+    let name = block_name_in(root, disk);
+    if let Ok(size) = disk_size_bytes_in(root, &name) {
+        if !use_gpt && size > MBR_MAX_BYTES {
+            warnings.push(format!(
+                "{} is larger than 2 TiB; an MBR table cannot use the space beyond that, use GPT",
+                disk
+            ));
+        }
+    }
+
+    warnings
+}
+
+/// Non-fatal problems with partitioning `disk` using the given table type
+pub fn disk_warnings(disk: &str, use_gpt: bool) -> Vec<String> {
+    disk_warnings_in(Path::new("/"), disk, use_gpt)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_unescape_mount() {
+        assert_eq!(unescape_mount("/run/media/My\\040Disk"), "/run/media/My Disk");
+    }
+
+    #[test]
+    fn test_active_swaps_skips_header_and_files() {
+        let dir = tempfile::tempdir().unwrap();
+        fs::create_dir_all(dir.path().join("proc")).unwrap();
+        fs::write(
+            dir.path().join("proc/swaps"),
+            "Filename\tType\tSize\tUsed\tPriority\n/dev/sda2 partition 2097148 0 -2\n/swapfile file 1024 0 -3\n",
+        ).unwrap();
+
+        assert_eq!(active_swaps_in(dir.path()), vec!["/dev/sda2".to_string()]);
+    }
+}
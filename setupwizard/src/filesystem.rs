@@ -0,0 +1,346 @@
+use crate::common::{run_command, CommandResult, SetupError};
+use crate::disk::StorageMedia;
+use std::fmt;
+use std::fs;
+use std::str::FromStr;
+
+/// Filesystem label of the installed root filesystem
+pub const ROOT_LABEL: &str = "ASENOS_ROOT";
+/// Filesystem label of an ESP created by the installer
+pub const ESP_LABEL: &str = "ASENOS_ESP";
+/// Label of the installed swap area
+pub const SWAP_LABEL: &str = "ASENOS_SWAP";
+/// Filesystem label of a /home partition on its own disk
+pub const HOME_LABEL: &str = "ASENOS_HOME";
+
+/// Filesystems the ISO ships mkfs tools for
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Filesystem {
+    Ext4,
+    Btrfs,
+    Xfs,
+    F2fs,
+    Bcachefs,
+    Jfs,
+    Exfat,
+    Vfat,
+    Swap,
+}
+
+impl Filesystem {
+    /// Filesystems offered for the root partition; bcachefs only where
+    /// /boot is on the ESP, since GRUB cannot read it
+    pub const ROOT_CHOICES: [Filesystem; 5] = [
+        Filesystem::Ext4,
+        Filesystem::Btrfs,
+        Filesystem::Xfs,
+        Filesystem::F2fs,
+        Filesystem::Bcachefs,
+    ];
+
+    /// Name as used by mount and fstab
+    pub fn name(&self) -> &'static str {
+        match self {
+            Filesystem::Ext4 => "ext4",
+            Filesystem::Btrfs => "btrfs",
+            Filesystem::Xfs => "xfs",
+            Filesystem::F2fs => "f2fs",
+            Filesystem::Bcachefs => "bcachefs",
+            Filesystem::Jfs => "jfs",
+            Filesystem::Exfat => "exfat",
+            Filesystem::Vfat => "vfat",
+            Filesystem::Swap => "swap",
+        }
+    }
+
+    /// Whether a Linux root filesystem can live on it
+    pub fn is_root_capable(&self) -> bool {
+        Self::ROOT_CHOICES.contains(self)
+    }
+
+    /// Program that creates the filesystem
+    pub fn mkfs_tool(&self) -> &'static str {
+        match self {
+            Filesystem::Ext4 => "mkfs.ext4",
+            Filesystem::Btrfs => "mkfs.btrfs",
+            Filesystem::Xfs => "mkfs.xfs",
+            Filesystem::F2fs => "mkfs.f2fs",
+            Filesystem::Bcachefs => "bcachefs",
+            Filesystem::Jfs => "mkfs.jfs",
+            Filesystem::Exfat => "mkfs.exfat",
+            Filesystem::Vfat => "mkfs.fat",
+            Filesystem::Swap => "mkswap",
+        }
+    }
+
+    /// Longest label the filesystem stores
+    pub fn max_label_len(&self) -> usize {
+        match self {
+            Filesystem::Xfs => 12,
+            Filesystem::Exfat | Filesystem::Vfat => 11,
+            Filesystem::Ext4 | Filesystem::Jfs | Filesystem::Swap => 16,
+            Filesystem::Bcachefs => 32,
+            Filesystem::Btrfs => 255,
+            Filesystem::F2fs => 512,
+        }
+    }
+
+    /// Whether mkfs can be told which UUID to use
+    pub fn supports_uuid(&self) -> bool {
+        !matches!(self, Filesystem::Jfs | Filesystem::Exfat)
+    }
+
+    /// mkfs features enabled unless the caller picks its own
+    pub fn default_features(&self) -> &'static [&'static str] {
+        match self {
+            // Checksums and the attributes transparent compression needs
+            Filesystem::F2fs => &["extra_attr", "inode_checksum", "sb_checksum", "compression"],
+            _ => &[],
+        }
+    }
+
+    /// Options for the fstab entry of the installed system on `media`.
+    /// Flash skips atime writes; rotational disks keep relatime, which
+    /// batches them.
+    pub fn mount_options(&self, media: StorageMedia) -> String {
+        let ssd = media.is_solid_state();
+        let atime = if ssd { "noatime" } else { "relatime" };
+
+        match self {
+            Filesystem::Ext4 | Filesystem::Xfs | Filesystem::Jfs | Filesystem::Bcachefs => {
+                format!("defaults,{}", atime)
+            }
+            Filesystem::Btrfs if ssd && media.supports_trim() => {
+                "defaults,noatime,compress=zstd,ssd,discard=async".to_string()
+            }
+            Filesystem::Btrfs if ssd => "defaults,noatime,compress=zstd,ssd".to_string(),
+            Filesystem::Btrfs => "defaults,relatime,compress=zstd,autodefrag".to_string(),
+            Filesystem::F2fs => {
+                "defaults,noatime,compress_algorithm=zstd,compress_chksum,atgc,gc_merge,lazytime".to_string()
+            }
+            Filesystem::Exfat | Filesystem::Vfat => "defaults,umask=0077".to_string(),
+            Filesystem::Swap if media.supports_trim() => "defaults,discard=once".to_string(),
+            Filesystem::Swap => "defaults".to_string(),
+        }
+    }
+}
+
+impl FromStr for Filesystem {
+    type Err = SetupError;
+
+    fn from_str(s: &str) -> CommandResult<Self> {
+        match s.trim().to_ascii_lowercase().as_str() {
+            "ext4" => Ok(Filesystem::Ext4),
+            "btrfs" => Ok(Filesystem::Btrfs),
+            "xfs" => Ok(Filesystem::Xfs),
+            "f2fs" => Ok(Filesystem::F2fs),
+            "bcachefs" => Ok(Filesystem::Bcachefs),
+            "jfs" => Ok(Filesystem::Jfs),
+            "exfat" => Ok(Filesystem::Exfat),
+            "vfat" | "fat32" => Ok(Filesystem::Vfat),
+            "swap" => Ok(Filesystem::Swap),
+            _ => Err(SetupError::InvalidInput(format!(
+                "Filesystem '{}' is not supported. Valid root filesystems: {}",
+                s,
+                Self::ROOT_CHOICES.map(|f| f.name()).join(", ")
+            ))),
+        }
+    }
+}
+
+impl fmt::Display for Filesystem {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.name())
+    }
+}
+
+/// Random UUID from the kernel
+pub fn random_uuid() -> CommandResult<String> {
+    Ok(fs::read_to_string("/proc/sys/kernel/random/uuid")?.trim().to_string())
+}
+
+/// First eight hex digits of `uuid`, the part a FAT volume ID keeps
+fn fat_volume_id(uuid: &str) -> String {
+    uuid.chars().filter(char::is_ascii_hexdigit).take(8).collect()
+}
+
+/// How to create one filesystem
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct FormatSpec {
+    pub filesystem: Filesystem,
+    pub label: String,
+    /// UUID to assign; FAT uses its first 8 hex digits as the volume ID
+    pub uuid: Option<String>,
+    pub features: Vec<String>,
+}
+
+impl FormatSpec {
+    /// Spec with the filesystem's default features and no fixed UUID
+    pub fn new(filesystem: Filesystem, label: &str) -> Self {
+        Self {
+            filesystem,
+            label: label.to_string(),
+            uuid: None,
+            features: filesystem.default_features().iter().map(|f| f.to_string()).collect(),
+        }
+    }
+
+    pub fn with_uuid(mut self, uuid: String) -> Self {
+        self.uuid = Some(uuid);
+        self
+    }
+
+    pub fn with_features(mut self, features: Vec<String>) -> Self {
+        self.features = features;
+        self
+    }
+
+    /// UUID as blkid reports it after formatting, for fstab and the kernel
+    /// command line. FAT only stores the 32-bit volume ID, shown as
+    /// "1234-ABCD".
+    pub fn fs_uuid(&self) -> Option<String> {
+        let uuid = self.uuid.as_deref()?;
+        match self.filesystem {
+            Filesystem::Vfat if fat_volume_id(uuid).len() == 8 => {
+                let id = fat_volume_id(uuid);
+                Some(format!("{}-{}", &id[..4], &id[4..]).to_uppercase())
+            }
+            _ => Some(uuid.to_string()),
+        }
+    }
+
+    /// Reject labels the filesystem would truncate or refuse
+    pub fn validate(&self) -> CommandResult<()> {
+        if self.label.len() > self.filesystem.max_label_len() {
+            return Err(SetupError::InvalidInput(format!(
+                "Label '{}' is longer than the {} characters {} allows",
+                self.label,
+                self.filesystem.max_label_len(),
+                self.filesystem
+            )));
+        }
+        if self.uuid.is_some() && !self.filesystem.supports_uuid() {
+            return Err(SetupError::InvalidInput(format!(
+                "{} does not support choosing the UUID", self.filesystem
+            )));
+        }
+        if let (Filesystem::Vfat, Some(uuid)) = (self.filesystem, &self.uuid) {
+            if fat_volume_id(uuid).len() < 8 {
+                return Err(SetupError::InvalidInput(format!("'{}' has no 32-bit FAT volume ID", uuid)));
+            }
+        }
+        Ok(())
+    }
+
+    /// Full mkfs command line for `device`
+    pub fn mkfs_command(&self, device: &str) -> Vec<String> {
+        let mut args: Vec<String> = Vec::new();
+        let mut push = |items: &[&str]| args.extend(items.iter().map(|s| s.to_string()));
+        let label = self.label.as_str();
+        let uuid = self.uuid.as_deref();
+        let features = self.features.join(",");
+
+        match self.filesystem {
+            Filesystem::Ext4 => {
+                push(&["mkfs.ext4", "-F", "-L", label]);
+                if let Some(uuid) = uuid {
+                    push(&["-U", uuid]);
+                }
+                if !features.is_empty() {
+                    push(&["-O", &features]);
+                }
+            }
+            Filesystem::Btrfs => {
+                push(&["mkfs.btrfs", "-f", "-L", label]);
+                if let Some(uuid) = uuid {
+                    push(&["-U", uuid]);
+                }
+                if !features.is_empty() {
+                    push(&["-O", &features]);
+                }
+            }
+            Filesystem::Xfs => {
+                push(&["mkfs.xfs", "-f", "-L", label]);
+                if let Some(uuid) = uuid {
+                    push(&["-m", &format!("uuid={}", uuid)]);
+                }
+                for feature in &self.features {
+                    push(&["-m", feature]);
+                }
+            }
+            Filesystem::F2fs => {
+                push(&["mkfs.f2fs", "-f", "-l", label]);
+                if let Some(uuid) = uuid {
+                    push(&["-U", uuid]);
+                }
+                if !features.is_empty() {
+                    push(&["-O", &features]);
+                }
+            }
+            Filesystem::Bcachefs => {
+                push(&["bcachefs", "format", "-f", "-L", label]);
+                if let Some(uuid) = uuid {
+                    push(&[&format!("--uuid={}", uuid)]);
+                }
+                for feature in &self.features {
+                    push(&[&format!("--{}", feature)]);
+                }
+            }
+            Filesystem::Jfs => push(&["mkfs.jfs", "-q", "-L", label]),
+            Filesystem::Exfat => push(&["mkfs.exfat", "-L", label]),
+            Filesystem::Vfat => {
+                push(&["mkfs.fat", "-F32", "-n", label]);
+                if let Some(uuid) = uuid {
+                    push(&["-i", &fat_volume_id(uuid)]);
+                }
+            }
+            Filesystem::Swap => {
+                push(&["mkswap", "-L", label]);
+                if let Some(uuid) = uuid {
+                    push(&["-U", uuid]);
+                }
+            }
+        }
+
+        args.push(device.to_string());
+        args
+    }
+
+    /// Create the filesystem on `device`
+    pub fn format(&self, device: &str) -> CommandResult<()> {
+        self.validate()?;
+        let command = self.mkfs_command(device);
+        let args: Vec<&str> = command.iter().map(String::as_str).collect();
+        run_command(&args, None)?;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_round_trip() {
+        for fs in Filesystem::ROOT_CHOICES {
+            assert_eq!(fs.name().parse::<Filesystem>().unwrap(), fs);
+        }
+        assert_eq!("FAT32".parse::<Filesystem>().unwrap(), Filesystem::Vfat);
+    }
+
+    #[test]
+    fn test_vfat_volume_id_from_uuid() {
+        let spec = FormatSpec::new(Filesystem::Vfat, ESP_LABEL)
+            .with_uuid("1234abcd-0000-4000-8000-000000000000".to_string());
+        assert_eq!(
+            spec.mkfs_command("/dev/sda1"),
+            vec!["mkfs.fat", "-F32", "-n", "ASENOS_ESP", "-i", "1234abcd", "/dev/sda1"]
+        );
+        assert_eq!(spec.fs_uuid().as_deref(), Some("1234-ABCD"));
+
+        // A kept ESP already has the short form, which survives reformatting
+        let kept = FormatSpec::new(Filesystem::Vfat, ESP_LABEL).with_uuid("1234-ABCD".to_string());
+        assert_eq!(kept.mkfs_command("/dev/sda1")[5], "1234ABCD");
+        assert_eq!(kept.fs_uuid().as_deref(), Some("1234-ABCD"));
+    }
+}
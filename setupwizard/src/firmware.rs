@@ -0,0 +1,70 @@
+use std::fmt;
+use std::fs;
+use std::path::Path;
+
+/// How the running machine was booted
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum FirmwareMode {
+    /// UEFI firmware with its platform bitness (32 or 64)
+    Uefi { bits: u32 },
+    /// Legacy BIOS or UEFI CSM
+    Bios,
+}
+
+impl FirmwareMode {
+    pub fn is_uefi(&self) -> bool {
+        matches!(self, FirmwareMode::Uefi { .. })
+    }
+}
+
+impl fmt::Display for FirmwareMode {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            FirmwareMode::Uefi { bits } => write!(f, "UEFI ({}-bit)", bits),
+            FirmwareMode::Bios => write!(f, "BIOS"),
+        }
+    }
+}
+
+/// Detect the firmware mode of the running system
+pub fn detect_firmware_mode() -> FirmwareMode {
+    detect_firmware_mode_in(Path::new("/sys"))
+}
+
+/// Detect the firmware mode from a sysfs tree rooted at `sys_root`
+pub fn detect_firmware_mode_in(sys_root: &Path) -> FirmwareMode {
+    let efi = sys_root.join("firmware/efi");
+    if !efi.is_dir() {
+        return FirmwareMode::Bios;
+    }
+
+    // fw_platform_size is missing on older kernels, which only booted 64-bit UEFI
+    let bits = fs::read_to_string(efi.join("fw_platform_size"))
+        .ok()
+        .and_then(|s| s.trim().parse().ok())
+        .unwrap_or(64);
+
+    FirmwareMode::Uefi { bits }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_detect_bios_without_efi_dir() {
+        let dir = tempfile::tempdir().unwrap();
+        assert_eq!(detect_firmware_mode_in(dir.path()), FirmwareMode::Bios);
+    }
+
+    #[test]
+    fn test_detect_uefi_bitness() {
+        let dir = tempfile::tempdir().unwrap();
+        let efi = dir.path().join("firmware/efi");
+        fs::create_dir_all(&efi).unwrap();
+        assert_eq!(detect_firmware_mode_in(dir.path()), FirmwareMode::Uefi { bits: 64 });
+
+        fs::write(efi.join("fw_platform_size"), "32\n").unwrap();
+        assert_eq!(detect_firmware_mode_in(dir.path()), FirmwareMode::Uefi { bits: 32 });
+    }
+}
@@ -0,0 +1,82 @@
+use crate::common::{CommandResult, SetupError};
+use crate::partition::{PartitionRole, Volume};
+use crate::target::Target;
+use std::fs;
+use std::path::Path;
+
+/// Total RAM in MiB according to `proc/meminfo` under `root`
+pub fn mem_total_mib_in(root: &Path) -> CommandResult<u64> {
+    let meminfo = fs::read_to_string(root.join("proc/meminfo"))?;
+    meminfo
+        .lines()
+        .find_map(|line| line.strip_prefix("MemTotal:"))
+        .and_then(|value| value.trim().trim_end_matches("kB").trim().parse::<u64>().ok())
+        .map(|kib| kib.div_ceil(1024))
+        .ok_or_else(|| SetupError::System("No MemTotal in /proc/meminfo".to_string()))
+}
+
+/// Total RAM of this machine in MiB
+pub fn mem_total_mib() -> CommandResult<u64> {
+    mem_total_mib_in(Path::new("/"))
+}
+
+/// Refuse swap that cannot hold a hibernation image of all of RAM
+pub fn check_swap_size(swap_mib: u64, ram_mib: u64) -> CommandResult<()> {
+    if swap_mib < ram_mib {
+        return Err(SetupError::InvalidInput(format!(
+            "Swap of {} MiB is smaller than the {} MiB of RAM; hibernation needs at least as much swap as RAM",
+            swap_mib, ram_mib
+        )));
+    }
+    Ok(())
+}
+
+/// Where the kernel finds the hibernation image
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ResumeDevice {
+    /// Swap partition with this UUID
+    Partition { uuid: String },
+}
+
+impl ResumeDevice {
+    /// Resume from the swap partition among the installer's volumes
+    pub fn from_volumes(volumes: &[Volume]) -> CommandResult<Self> {
+        let swap = volumes
+            .iter()
+            .find(|v| v.role == PartitionRole::Swap)
+            .ok_or_else(|| SetupError::InvalidInput("Hibernation needs a swap partition".to_string()))?;
+        let uuid = swap.format.uuid.clone().ok_or_else(|| {
+            SetupError::System(format!("Swap partition {} has no known UUID", swap.device))
+        })?;
+        Ok(ResumeDevice::Partition { uuid })
+    }
+
+    /// Kernel parameters pointing at the image
+    pub fn kernel_params(&self) -> Vec<String> {
+        match self {
+            ResumeDevice::Partition { uuid } => vec![format!("resume=UUID={}", uuid)],
+        }
+    }
+}
+
+/// Make the installed system resume from `resume`: the resume hook for
+/// busybox initramfs images and the kernel parameters
+pub fn configure_hibernation(target: &Target, resume: &ResumeDevice) -> CommandResult<()> {
+    // The systemd hook resumes on its own
+    if !target.initramfs_hooks()?.iter().any(|h| h == "systemd") {
+        target.add_initramfs_hook("resume", &["filesystems", "fsck"])?;
+    }
+    target.set_kernel_params(&resume.kernel_params())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_swap_must_hold_ram() {
+        assert!(check_swap_size(16384, 15936).is_ok());
+        let result = check_swap_size(2048, 15936);
+        assert!(matches!(result, Err(SetupError::InvalidInput(msg)) if msg.contains("15936 MiB of RAM")));
+    }
+}
@@ -1,60 +1,574 @@
-use crate::common::{run_command, CommandResult, SetupError, command_exists};
+use crate::common::{run_command, CommandResult, SetupError};
+use crate::target::Target;
+use flate2::read::GzDecoder;
+use std::collections::{BTreeMap, HashSet};
+use std::fmt;
+use std::fs;
+use std::cell::Cell;
+use std::io::{self, Read, Write};
+use std::path::{Path, PathBuf};
+use std::sync::mpsc::{self, Receiver, RecvTimeoutError, Sender};
+use std::thread;
+use std::time::Duration;
 
-/// Get list of available system keymaps
-pub fn available_keymaps() -> CommandResult<Vec<String>> {
-    // Try localectl first (systemd systems)
-    if command_exists("localectl") {
-        if let Ok(output) = run_command(&["localectl", "list-keymaps"], None) {
-            let keymaps: Vec<String> = output
-                .lines()
-                .map(|line| line.trim().to_string())
-                .filter(|line| !line.is_empty())
-                .collect();
-            
-            if !keymaps.is_empty() {
-                return Ok(keymaps);
-            }
+/// Console keymaps relative to the filesystem root
+pub const KEYMAPS_DIR: &str = "usr/share/kbd/keymaps";
+
+/// X11 keyboard configuration, also what localed reports to Wayland desktops
+pub const X11_KEYBOARD_CONF: &str = "etc/X11/xorg.conf.d/00-keyboard.conf";
+
+/// Console keymaps and the XKB layout and variant typing the same
+/// characters, after systemd's kbd-model-map
+const XKB_LAYOUTS: &[(&str, &str, &str)] = &[
+    ("be-latin1", "be", ""),
+    ("bg_bds-utf8", "bg", ""),
+    ("br-abnt2", "br", ""),
+    ("colemak", "us", "colemak"),
+    ("croat", "hr", ""),
+    ("cz-lat2", "cz", "qwerty"),
+    ("cz-qwertz", "cz", ""),
+    ("de", "de", ""),
+    ("de-latin1", "de", ""),
+    ("de-latin1-nodeadkeys", "de", "nodeadkeys"),
+    ("de_CH-latin1", "ch", "de_nodeadkeys"),
+    ("dk", "dk", ""),
+    ("dk-latin1", "dk", ""),
+    ("dvorak", "us", "dvorak"),
+    ("dvorak-programmer", "us", "dvp"),
+    ("es", "es", ""),
+    ("et", "ee", ""),
+    ("fi", "fi", ""),
+    ("fr", "fr", ""),
+    ("fr-bepo", "fr", "bepo"),
+    ("fr-latin1", "fr", ""),
+    ("fr-latin9", "fr", "latin9"),
+    ("fr-pc", "fr", ""),
+    ("fr_CH", "ch", "fr"),
+    ("fr_CH-latin1", "ch", "fr"),
+    ("gr", "gr", ""),
+    ("hu", "hu", ""),
+    ("il", "il", ""),
+    ("is-latin1", "is", ""),
+    ("it", "it", ""),
+    ("jp106", "jp", ""),
+    ("la-latin1", "latam", ""),
+    ("lt", "lt", ""),
+    ("mk-utf", "mk", ""),
+    ("nl", "nl", ""),
+    ("no", "no", ""),
+    ("no-latin1", "no", ""),
+    ("pl", "pl", ""),
+    ("pl2", "pl", ""),
+    ("pt-latin1", "pt", ""),
+    ("ro", "ro", ""),
+    ("ru", "ru", ""),
+    ("se-lat6", "se", ""),
+    ("sg", "ch", ""),
+    ("sg-latin1", "ch", ""),
+    ("sk-qwerty", "sk", "qwerty"),
+    ("sk-qwertz", "sk", ""),
+    ("slovene", "si", ""),
+    ("sr-cy", "rs", ""),
+    ("sv-latin1", "se", ""),
+    ("trq", "tr", ""),
+    ("ua", "ua", ""),
+    ("uk", "gb", ""),
+    ("us", "us", ""),
+    ("us-acentos", "us", "intl"),
+];
+
+/// What the user types to prove a trial keymap works: letters that move
+/// between qwerty, qwertz and azerty, a digit and shifted symbols
+pub const TRIAL_TEXT: &str = "quiz zebra wham @42";
+
+/// How long a trial keymap waits for the test text
+pub const DEFAULT_TRIAL_TIMEOUT: Duration = Duration::from_secs(20);
+
+/// Compression suffixes loadkeys accepts after `.map`
+const COMPRESSED_SUFFIXES: [&str; 4] = [".gz", ".bz2", ".xz", ".zst"];
+
+/// Vendor prefixes in front of the language of a keymap name
+const VENDOR_PREFIXES: [&str; 7] = ["mac", "amiga", "atari", "sun", "sunt4", "sunt5", "sunt6"];
+
+/// At most this many "did you mean" suggestions are offered
+pub const MAX_SUGGESTIONS: usize = 5;
+
+/// How deep include chains are followed before giving up
+const MAX_INCLUDE_DEPTH: usize = 8;
+
+/// Physical arrangement of the letter keys
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub enum LayoutFamily {
+    Qwerty,
+    Qwertz,
+    Azerty,
+    Dvorak,
+    Colemak,
+    /// Neither the directory nor the includes tell
+    Unknown,
+}
+
+impl LayoutFamily {
+    /// Family named by a kbd directory or a `<family>-layout` include
+    pub fn from_name(name: &str) -> Option<Self> {
+        match name {
+            "qwerty" => Some(LayoutFamily::Qwerty),
+            "qwertz" => Some(LayoutFamily::Qwertz),
+            "azerty" => Some(LayoutFamily::Azerty),
+            "dvorak" => Some(LayoutFamily::Dvorak),
+            "colemak" => Some(LayoutFamily::Colemak),
+            _ => None,
+        }
+    }
+
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            LayoutFamily::Qwerty => "qwerty",
+            LayoutFamily::Qwertz => "qwertz",
+            LayoutFamily::Azerty => "azerty",
+            LayoutFamily::Dvorak => "dvorak",
+            LayoutFamily::Colemak => "colemak",
+            LayoutFamily::Unknown => "other",
+        }
+    }
+}
+
+impl fmt::Display for LayoutFamily {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
+
+/// A keymap file loadkeys can load by name
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Keymap {
+    /// Name without `.map` and compression suffix, e.g. "de-latin1"
+    pub name: String,
+    pub family: LayoutFamily,
+    /// Top-level directory under the keymaps root, e.g. "i386" or "mac"
+    pub arch: String,
+    pub path: PathBuf,
+    pub compressed: bool,
+}
+
+impl Keymap {
+    /// Language or country code the name starts with, e.g. "de" for
+    /// "de-latin1" and "fr" for "mac-fr"
+    pub fn language(&self) -> &str {
+        let mut parts = self.name.split(['-', '_']).filter(|p| !p.is_empty());
+        let first = parts.next().unwrap_or(&self.name);
+        if VENDOR_PREFIXES.contains(&first) {
+            parts.next().unwrap_or(first)
+        } else {
+            first
+        }
+    }
+}
+
+/// How the keymap picker groups its entries
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Grouping {
+    Language,
+    Family,
+}
+
+/// Keymaps grouped by language or layout family, groups and entries sorted
+pub fn group_keymaps<'a>(
+    keymaps: impl IntoIterator<Item = &'a Keymap>,
+    grouping: Grouping,
+) -> Vec<(String, Vec<&'a Keymap>)> {
+    let mut groups: BTreeMap<String, Vec<&'a Keymap>> = BTreeMap::new();
+    for keymap in keymaps {
+        let key = match grouping {
+            Grouping::Language => keymap.language().to_string(),
+            Grouping::Family => keymap.family.to_string(),
+        };
+        groups.entry(key).or_default().push(keymap);
+    }
+    groups.into_iter().collect()
+}
+
+/// Keymaps whose name contains every word of `query`, or whose language or
+/// layout family equals one of them, ignoring case
+pub fn search_keymaps<'a>(keymaps: &'a [Keymap], query: &str) -> Vec<&'a Keymap> {
+    let words: Vec<String> = query.split_whitespace().map(str::to_lowercase).collect();
+    keymaps
+        .iter()
+        .filter(|keymap| {
+            let name = keymap.name.to_lowercase();
+            words.iter().all(|word| {
+                name.contains(word.as_str())
+                    || keymap.language().eq_ignore_ascii_case(word)
+                    || keymap.family.as_str() == word
+            })
+        })
+        .collect()
+}
+
+/// Levenshtein distance between two names
+pub fn edit_distance(a: &str, b: &str) -> usize {
+    let b: Vec<char> = b.chars().collect();
+    let mut previous: Vec<usize> = (0..=b.len()).collect();
+    for (i, ca) in a.chars().enumerate() {
+        let mut current = vec![i + 1];
+        for (j, cb) in b.iter().enumerate() {
+            let substitution = previous[j] + usize::from(ca != *cb);
+            current.push(substitution.min(previous[j + 1] + 1).min(current[j] + 1));
         }
+        previous = current;
     }
+    previous[b.len()]
+}
 
-    // Fallback: scan filesystem
-    let find_cmd = concat!(
-        "find /usr/share/kbd/keymaps -type f ",
-        r#"\( -name '*.map.gz' -o -name '*.map' \) "#,
-        r#"-printf '%f\n' 2>/dev/null | "#,
-        r#"sed -E 's/\.(map|map\.gz)$//' | "#,
-        "sort -u"
-    );
-    
-    let output = run_command(&["sh", "-c", find_cmd], None)?;
-    let keymaps: Vec<String> = output
-        .lines()
-        .map(|line| line.trim().to_string())
-        .filter(|line| !line.is_empty())
+/// Names close to a mistyped `keymap`, nearest first. Typos within a third
+/// of the name's length count, as do names that merely differ in case or
+/// extend it.
+pub fn suggest_keymaps(keymaps: &[Keymap], keymap: &str) -> Vec<String> {
+    let wanted = keymap.trim().to_lowercase();
+    if wanted.is_empty() {
+        return Vec::new();
+    }
+    let limit = (wanted.chars().count() / 3).max(1);
+    let mut scored: Vec<(usize, &str)> = keymaps
+        .iter()
+        .filter_map(|k| {
+            let name = k.name.to_lowercase();
+            let distance = if name.starts_with(&wanted) { 0 } else { edit_distance(&wanted, &name) };
+            (distance <= limit).then_some((distance, k.name.as_str()))
+        })
         .collect();
+    scored.sort();
+    scored.into_iter().take(MAX_SUGGESTIONS).map(|(_, name)| name.to_string()).collect()
+}
+
+/// Keymap name and compression of a file name, None for non-keymaps
+fn keymap_file_name(file_name: &str) -> Option<(&str, bool)> {
+    if let Some(name) = file_name.strip_suffix(".map") {
+        return Some((name, false));
+    }
+    COMPRESSED_SUFFIXES
+        .iter()
+        .find_map(|suffix| file_name.strip_suffix(suffix)?.strip_suffix(".map"))
+        .map(|name| (name, true))
+}
+
+/// Read a keymap or include file, decompressing gzip. Other compressions
+/// yield None since following their includes is only a refinement.
+fn read_keymap_file(path: &Path) -> Option<String> {
+    let name = path.file_name()?.to_string_lossy();
+    if name.ends_with(".gz") {
+        let mut text = String::new();
+        GzDecoder::new(fs::File::open(path).ok()?).read_to_string(&mut text).ok()?;
+        return Some(text);
+    }
+    if COMPRESSED_SUFFIXES.iter().any(|suffix| name.ends_with(suffix)) {
+        return None;
+    }
+    fs::read_to_string(path).ok()
+}
+
+/// Names from `include "..."` lines, ignoring `#` and `!` comments
+pub fn parse_includes(text: &str) -> Vec<String> {
+    text.lines()
+        .map(|line| line.split(['#', '!']).next().unwrap_or("").trim())
+        .filter_map(|line| line.strip_prefix("include"))
+        .filter_map(|rest| {
+            let rest = rest.trim();
+            rest.strip_prefix('"')?.split('"').next().map(str::to_string)
+        })
+        .filter(|name| !name.is_empty())
+        .collect()
+}
+
+/// Locate an include the way loadkeys does: next to the including file,
+/// then in the `include` directories of its tree, with or without the
+/// usual suffixes and compression
+fn resolve_include(keymaps: &Path, arch: &str, from: &Path, name: &str) -> Option<PathBuf> {
+    let mut dirs = Vec::new();
+    if let Some(parent) = from.parent() {
+        dirs.push(parent.to_path_buf());
+        dirs.push(parent.join("include"));
+        if let Some(grandparent) = parent.parent() {
+            dirs.push(grandparent.join("include"));
+        }
+    }
+    dirs.push(keymaps.join(arch).join("include"));
+    dirs.push(keymaps.join("include"));
+
+    let bases = [name.to_string(), format!("{}.inc", name), format!("{}.map", name)];
+    dirs.iter()
+        .flat_map(|dir| bases.iter().map(move |base| dir.join(base)))
+        .flat_map(|path| {
+            std::iter::once(path.clone()).chain(
+                COMPRESSED_SUFFIXES
+                    .iter()
+                    .map(move |suffix| PathBuf::from(format!("{}{}", path.display(), suffix))),
+            )
+        })
+        .find(|path| path.is_file())
+}
+
+/// Family from a `<family>-layout` include anywhere in the include chain
+fn family_from_includes(keymaps: &Path, arch: &str, path: &Path) -> LayoutFamily {
+    let mut visited = HashSet::new();
+    let mut pending = vec![(path.to_path_buf(), 0)];
+    while let Some((file, depth)) = pending.pop() {
+        if depth > MAX_INCLUDE_DEPTH || !visited.insert(file.clone()) {
+            continue;
+        }
+        let Some(text) = read_keymap_file(&file) else { continue };
+        for include in parse_includes(&text) {
+            let family = include.strip_suffix("-layout").and_then(LayoutFamily::from_name);
+            if let Some(family) = family {
+                return family;
+            }
+            if let Some(next) = resolve_include(keymaps, arch, &file, &include) {
+                pending.push((next, depth + 1));
+            }
+        }
+    }
+    LayoutFamily::Unknown
+}
+
+/// Collect keymap files below `dir`, skipping include directories
+fn walk_keymaps(keymaps: &Path, dir: &Path, found: &mut Vec<Keymap>) {
+    let Ok(entries) = fs::read_dir(dir) else { return };
+    let mut entries: Vec<_> = entries.flatten().map(|e| e.path()).collect();
+    entries.sort();
+    for path in entries {
+        let file_name = path.file_name().map(|n| n.to_string_lossy().to_string()).unwrap_or_default();
+        if path.is_dir() {
+            if file_name != "include" {
+                walk_keymaps(keymaps, &path, found);
+            }
+            continue;
+        }
+        let Some((name, compressed)) = keymap_file_name(&file_name) else { continue };
+        let Ok(relative) = path.strip_prefix(keymaps) else { continue };
+        let components: Vec<String> =
+            relative.iter().map(|c| c.to_string_lossy().to_string()).collect();
+        // Maps directly under the keymaps root have no architecture
+        let arch = if components.len() > 1 { components[0].clone() } else { String::new() };
+        let family = components[..components.len() - 1]
+            .iter()
+            .find_map(|dir| LayoutFamily::from_name(dir))
+            .unwrap_or_else(|| family_from_includes(keymaps, &arch, &path));
+        found.push(Keymap { name: name.to_string(), family, arch, path: path.clone(), compressed });
+    }
+}
+
+/// Keymaps installed under `root`, sorted by name. A name present for
+/// several architectures is listed once, preferring i386 like loadkeys.
+pub fn available_keymaps_in(root: &Path) -> CommandResult<Vec<Keymap>> {
+    let keymaps = root.join(KEYMAPS_DIR);
+    let mut found = Vec::new();
+    walk_keymaps(&keymaps, &keymaps, &mut found);
+    found.sort_by(|a, b| {
+        a.name
+            .cmp(&b.name)
+            .then_with(|| (a.arch != "i386").cmp(&(b.arch != "i386")))
+            .then_with(|| a.arch.cmp(&b.arch))
+    });
+    found.dedup_by(|later, first| later.name == first.name);
 
-    if keymaps.is_empty() {
-        Err(SetupError::System("No keymaps found on system".to_string()))
+    if found.is_empty() {
+        Err(SetupError::System(format!("No keymaps found in {}", keymaps.display())))
     } else {
-        Ok(keymaps)
+        Ok(found)
     }
 }
 
-/// Set system keymap using loadkeys
-pub fn set_keymap(keymap: &str) -> CommandResult<()> {
+/// Get list of available system keymaps
+pub fn available_keymaps() -> CommandResult<Vec<Keymap>> {
+    available_keymaps_in(Path::new("/"))
+}
+
+/// Look up an installed keymap by its exact name under `root`
+pub fn find_keymap_in(root: &Path, keymap: &str) -> CommandResult<Keymap> {
     if keymap.trim().is_empty() {
         return Err(SetupError::InvalidInput("Keymap cannot be empty".to_string()));
     }
 
-    let available = available_keymaps()?;
-    if !available.contains(&keymap.to_string()) {
-        return Err(SetupError::InvalidInput(format!(
-            "Unknown keymap '{}'. Available: {}",
-            keymap,
-            available.join(", ")
-        )));
+    let available = available_keymaps_in(root)?;
+    available.iter().find(|k| k.name == keymap).cloned().ok_or_else(|| {
+        let suggestions = suggest_keymaps(&available, keymap);
+        if suggestions.is_empty() {
+            SetupError::InvalidInput(format!(
+                "Unknown keymap '{}'. Use --list-keymaps or --pick-keymap to find one",
+                keymap
+            ))
+        } else {
+            SetupError::InvalidInput(format!("Unknown keymap '{}'. Did you mean: {}?", keymap, suggestions.join(", ")))
+        }
+    })
+}
+
+/// XKB layout and variant for the X11 and Wayland sessions
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct XkbLayout {
+    pub layout: String,
+    /// Empty for the layout's default variant
+    pub variant: String,
+}
+
+/// XKB equivalent of console `keymap`, None if the table has no match
+pub fn xkb_layout(keymap: &str) -> Option<XkbLayout> {
+    XKB_LAYOUTS.iter().find(|(name, _, _)| *name == keymap).map(|(_, layout, variant)| XkbLayout {
+        layout: layout.to_string(),
+        variant: variant.to_string(),
+    })
+}
+
+/// 00-keyboard.conf in the format localectl writes
+pub fn x11_keyboard_conf(xkb: &XkbLayout) -> String {
+    let mut lines = vec![
+        "# Written by the Asenos installer".to_string(),
+        "Section \"InputClass\"".to_string(),
+        "        Identifier \"system-keyboard\"".to_string(),
+        "        MatchIsKeyboard \"on\"".to_string(),
+        format!("        Option \"XkbLayout\" \"{}\"", xkb.layout),
+    ];
+    if !xkb.variant.is_empty() {
+        lines.push(format!("        Option \"XkbVariant\" \"{}\"", xkb.variant));
+    }
+    lines.push("EndSection".to_string());
+    lines.join("\n") + "\n"
+}
+
+/// Make `keymap` the installed system's console keymap, in the initramfs
+/// too so LUKS passphrases are typed with it, and the matching graphical
+/// layout when the table knows one. Returns that layout.
+pub fn configure_keymap(target: &Target, keymap: &str) -> CommandResult<Option<XkbLayout>> {
+    let systemd = target.initramfs_hooks()?.iter().any(|h| h == "systemd");
+    target.set_vconsole("KEYMAP", keymap)?;
+
+    // Before any hook that may ask for a passphrase
+    let before = ["consolefont", "block", "sd-encrypt", "encrypt", "filesystems"];
+    if systemd {
+        target.add_initramfs_hook("sd-vconsole", &before)?;
+    } else {
+        target.add_initramfs_hook("keymap", &before)?;
+    }
+
+    let xkb = xkb_layout(keymap);
+    if let Some(xkb) = &xkb {
+        target.write_file(X11_KEYBOARD_CONF, &x11_keyboard_conf(xkb))?;
+    }
+    Ok(xkb)
+}
+
+/// The console keymap in effect before a trial, to go back to
+#[derive(Debug)]
+pub enum KeymapBackup {
+    /// Full table saved by dumpkeys in a private file, removed on drop
+    Dump(tempfile::TempPath),
+    /// Keymap name from vconsole.conf, or the kernel's "us"
+    Named(String),
+}
+
+impl KeymapBackup {
+    /// Save the current table, or remember the configured keymap name under
+    /// `root` when dumpkeys cannot read the console
+    pub fn save_in(root: &Path) -> CommandResult<Self> {
+        if let Ok(dump) = run_command(&["dumpkeys"], None) {
+            let mut file = tempfile::Builder::new().prefix("asenos-keymap-").suffix(".map").tempfile()?;
+            file.write_all(dump.as_bytes())?;
+            return Ok(KeymapBackup::Dump(file.into_temp_path()));
+        }
+        let name = fs::read_to_string(root.join(crate::target::VCONSOLE_CONF))
+            .ok()
+            .and_then(|conf| {
+                conf.lines()
+                    .find_map(|line| line.trim().strip_prefix("KEYMAP="))
+                    .map(|value| value.trim_matches('"').to_string())
+            })
+            .filter(|name| !name.is_empty())
+            .unwrap_or_else(|| "us".to_string());
+        Ok(KeymapBackup::Named(name))
+    }
+
+    pub fn save() -> CommandResult<Self> {
+        Self::save_in(Path::new("/"))
     }
 
+    /// Load the saved keymap again
+    pub fn restore(&self) -> CommandResult<()> {
+        match self {
+            KeymapBackup::Dump(path) => run_command(&["loadkeys", &path.to_string_lossy()], None)?,
+            KeymapBackup::Named(name) => run_command(&["loadkeys", name], None)?,
+        };
+        Ok(())
+    }
+}
+
+/// How a keymap trial ended
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum TrialOutcome {
+    Confirmed,
+    /// The user typed this instead of the test text
+    Mismatch(String),
+    /// Nothing arrived in time, or the input was closed
+    TimedOut,
+}
+
+/// Wait up to `timeout` for a line equal to `expected`
+pub fn await_confirmation(input: &Receiver<String>, expected: &str, timeout: Duration) -> TrialOutcome {
+    match input.recv_timeout(timeout) {
+        Ok(line) if line.trim_end_matches(['\r', '\n']) == expected => TrialOutcome::Confirmed,
+        Ok(line) => TrialOutcome::Mismatch(line.trim_end_matches(['\r', '\n']).to_string()),
+        Err(RecvTimeoutError::Timeout) | Err(RecvTimeoutError::Disconnected) => TrialOutcome::TimedOut,
+    }
+}
+
+/// Lines read on one thread so waiting for them can time out. A line is
+/// only read once asked for, so nothing typed after the last wait is
+/// taken from later prompts.
+pub struct LineReader {
+    requests: Sender<()>,
+    lines: Receiver<String>,
+    /// A line was asked for and has not arrived yet
+    pending: Cell<bool>,
+}
+
+impl LineReader {
+    /// Read with `read_line`, which returns 0 at the end of the input
+    pub fn spawn(mut read_line: impl FnMut(&mut String) -> io::Result<usize> + Send + 'static) -> Self {
+        let (requests, asked) = mpsc::channel::<()>();
+        let (sender, lines) = mpsc::channel();
+        thread::spawn(move || {
+            for () in asked {
+                let mut line = String::new();
+                match read_line(&mut line) {
+                    Ok(n) if n > 0 && sender.send(line).is_ok() => {}
+                    _ => break,
+                }
+            }
+        });
+        LineReader { requests, lines, pending: Cell::new(false) }
+    }
+
+    /// Lines of stdin
+    pub fn stdin() -> Self {
+        Self::spawn(|line| io::stdin().read_line(line))
+    }
+
+    /// Wait up to `timeout` for the next line to equal `expected`. A line
+    /// still being typed when this times out is what the next wait gets.
+    pub fn await_confirmation(&self, expected: &str, timeout: Duration) -> TrialOutcome {
+        if !self.pending.replace(true) {
+            let _ = self.requests.send(());
+        }
+        let outcome = await_confirmation(&self.lines, expected, timeout);
+        if outcome != TrialOutcome::TimedOut {
+            self.pending.set(false);
+        }
+        outcome
+    }
+}
+
+/// Set system keymap using loadkeys
+pub fn set_keymap(keymap: &str) -> CommandResult<()> {
+    find_keymap_in(Path::new("/"), keymap)?;
     run_command(&["loadkeys", keymap], None)?;
     Ok(())
 }
@@ -67,9 +581,9 @@ mod tests {
     fn test_available_keymaps() {
         let result = available_keymaps();
         // Should either succeed or fail gracefully
-        match result {
-            Ok(keymaps) => assert!(!keymaps.is_empty()),
-            Err(_) => {} // System might not have keymaps available in test environment
+        // System might not have keymaps available in test environment
+        if let Ok(keymaps) = result {
+            assert!(!keymaps.is_empty());
         }
     }
 
@@ -85,4 +599,25 @@ mod tests {
         let result = set_keymap("invalid_keymap_xyz123");
         assert!(result.is_err());
     }
+
+    #[test]
+    fn test_keymap_file_names() {
+        assert_eq!(keymap_file_name("us.map.gz"), Some(("us", true)));
+        assert_eq!(keymap_file_name("de-latin1.map"), Some(("de-latin1", false)));
+        assert_eq!(keymap_file_name("euro.inc.gz"), None);
+    }
+
+    #[test]
+    fn test_edit_distance() {
+        assert_eq!(edit_distance("de-latin1", "de-latin1"), 0);
+        assert_eq!(edit_distance("de-latni1", "de-latin1"), 2);
+        assert_eq!(edit_distance("uk", "us"), 1);
+        assert_eq!(edit_distance("", "fr"), 2);
+    }
+
+    #[test]
+    fn test_parse_includes() {
+        let text = "# comment\ninclude \"qwerty-layout\"\n  include \"linux-with-alt-and-altgr\" ! trailing\n! include \"hidden\"\nkeycode 1 = Escape\n";
+        assert_eq!(parse_includes(text), vec!["qwerty-layout", "linux-with-alt-and-altgr"]);
+    }
 }
@@ -0,0 +1,292 @@
+use crate::keymap::Keymap;
+use std::fmt;
+use std::fs;
+use std::path::Path;
+
+/// EFI variable with the firmware's language as an RFC 4646 tag
+const PLATFORM_LANG_VAR: &str = "sys/firmware/efi/efivars/PlatformLang-8be4df61-93ca-11d2-aa0d-00e098032b8c";
+
+/// Countries of time zones, as shipped with tzdata
+const ZONE_TAB: &str = "usr/share/zoneinfo/zone.tab";
+
+/// Locales whose territory types differently from the language alone
+const LOCALE_KEYMAPS: &[(&str, &str)] = &[
+    ("de_CH", "de_CH-latin1"),
+    ("en_GB", "uk"),
+    ("en_IE", "uk"),
+    ("fr_BE", "be-latin1"),
+    ("fr_CA", "cf"),
+    ("fr_CH", "fr_CH"),
+    ("nl_BE", "be-latin1"),
+    ("pt_BR", "br-abnt2"),
+];
+
+/// Keymap for each language code
+const LANGUAGE_KEYMAPS: &[(&str, &str)] = &[
+    ("bg", "bg_bds-utf8"),
+    ("cs", "cz-qwertz"),
+    ("da", "dk-latin1"),
+    ("de", "de-latin1"),
+    ("el", "gr"),
+    ("en", "us"),
+    ("es", "es"),
+    ("et", "et"),
+    ("fi", "fi"),
+    ("fr", "fr"),
+    ("he", "il"),
+    ("hr", "croat"),
+    ("hu", "hu"),
+    ("is", "is-latin1"),
+    ("it", "it"),
+    ("ja", "jp106"),
+    ("lt", "lt"),
+    ("mk", "mk-utf"),
+    ("nb", "no-latin1"),
+    ("nl", "nl"),
+    ("nn", "no-latin1"),
+    ("pl", "pl2"),
+    ("pt", "pt-latin1"),
+    ("ro", "ro"),
+    ("ru", "ru"),
+    ("sk", "sk-qwertz"),
+    ("sl", "slovene"),
+    ("sr", "sr-cy"),
+    ("sv", "sv-latin1"),
+    ("tr", "trq"),
+    ("uk", "ua"),
+];
+
+/// Keymap for each ISO 3166 country, for hints without a language
+const COUNTRY_KEYMAPS: &[(&str, &str)] = &[
+    ("AT", "de-latin1"),
+    ("AU", "us"),
+    ("BE", "be-latin1"),
+    ("BG", "bg_bds-utf8"),
+    ("BR", "br-abnt2"),
+    ("CA", "us"),
+    ("CH", "de_CH-latin1"),
+    ("CZ", "cz-qwertz"),
+    ("DE", "de-latin1"),
+    ("DK", "dk-latin1"),
+    ("EE", "et"),
+    ("ES", "es"),
+    ("FI", "fi"),
+    ("FR", "fr"),
+    ("GB", "uk"),
+    ("GR", "gr"),
+    ("HR", "croat"),
+    ("HU", "hu"),
+    ("IE", "uk"),
+    ("IL", "il"),
+    ("IS", "is-latin1"),
+    ("IT", "it"),
+    ("JP", "jp106"),
+    ("LT", "lt"),
+    ("NL", "us"),
+    ("NO", "no-latin1"),
+    ("NZ", "us"),
+    ("PL", "pl2"),
+    ("PT", "pt-latin1"),
+    ("RO", "ro"),
+    ("RS", "sr-cy"),
+    ("RU", "ru"),
+    ("SE", "sv-latin1"),
+    ("SI", "slovene"),
+    ("SK", "sk-qwertz"),
+    ("TR", "trq"),
+    ("UA", "ua"),
+    ("US", "us"),
+];
+
+fn lookup(table: &[(&str, &'static str)], key: &str) -> Option<&'static str> {
+    table.iter().find(|(k, _)| *k == key).map(|(_, keymap)| *keymap)
+}
+
+/// Where a keymap suggestion comes from, most trusted first
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum HintSource {
+    /// `vconsole.keymap=` on the kernel command line
+    KernelCmdline,
+    Locale(String),
+    Timezone(String),
+    /// The firmware's PlatformLang, e.g. "de-DE"
+    Firmware(String),
+}
+
+impl fmt::Display for HintSource {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            HintSource::KernelCmdline => write!(f, "kernel command line"),
+            HintSource::Locale(locale) => write!(f, "locale {}", locale),
+            HintSource::Timezone(zone) => write!(f, "time zone {}", zone),
+            HintSource::Firmware(lang) => write!(f, "firmware language {}", lang),
+        }
+    }
+}
+
+/// A proposed keymap and why
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct KeymapSuggestion {
+    pub keymap: String,
+    pub source: HintSource,
+}
+
+/// What the system tells about the user's keyboard
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct KeymapHints {
+    /// Keymap from `vconsole.keymap=` or `rd.vconsole.keymap=`
+    pub cmdline_keymap: Option<String>,
+    /// Locale such as "de_DE.UTF-8"
+    pub locale: Option<String>,
+    /// Time zone such as "Europe/Paris"
+    pub timezone: Option<String>,
+    /// Country of the time zone according to zone.tab
+    pub timezone_country: Option<String>,
+    /// PlatformLang such as "fr-FR"
+    pub platform_lang: Option<String>,
+}
+
+impl KeymapHints {
+    /// Read the hints of the system mounted at `root`: proc/cmdline,
+    /// etc/locale.conf, etc/localtime and the PlatformLang EFI variable
+    pub fn read_in(root: &Path) -> Self {
+        let timezone = timezone_in(root);
+        Self {
+            cmdline_keymap: fs::read_to_string(root.join("proc/cmdline"))
+                .ok()
+                .and_then(|cmdline| cmdline_keymap(&cmdline)),
+            locale: fs::read_to_string(root.join("etc/locale.conf")).ok().and_then(|conf| locale_lang(&conf)),
+            timezone_country: timezone.as_deref().and_then(|zone| zone_country_in(root, zone)),
+            timezone,
+            platform_lang: fs::read(root.join(PLATFORM_LANG_VAR)).ok().and_then(|var| parse_platform_lang(&var)),
+        }
+    }
+
+    /// Hints of the running system, with the locale and time zone already
+    /// chosen for the installed system at `target` taking precedence
+    pub fn gather(target: Option<&Path>) -> Self {
+        let mut hints = Self::read_in(Path::new("/"));
+        if let Some(target) = target {
+            let chosen = Self::read_in(target);
+            if chosen.locale.is_some() {
+                hints.locale = chosen.locale;
+            }
+            if chosen.timezone.is_some() {
+                hints.timezone = chosen.timezone;
+                hints.timezone_country = chosen.timezone_country;
+            }
+        }
+        hints
+    }
+
+    /// Keymaps the hints point to, most likely first, without duplicates.
+    /// Only keymaps in `available` are proposed.
+    pub fn suggestions(&self, available: &[Keymap]) -> Vec<KeymapSuggestion> {
+        let mut candidates: Vec<(String, HintSource)> = Vec::new();
+        if let Some(keymap) = &self.cmdline_keymap {
+            candidates.push((keymap.clone(), HintSource::KernelCmdline));
+        }
+        if let Some(locale) = &self.locale {
+            if let Some(keymap) = locale_keymap(locale) {
+                candidates.push((keymap.to_string(), HintSource::Locale(locale.clone())));
+            }
+        }
+        if let (Some(zone), Some(country)) = (&self.timezone, &self.timezone_country) {
+            if let Some(keymap) = lookup(COUNTRY_KEYMAPS, country) {
+                candidates.push((keymap.to_string(), HintSource::Timezone(zone.clone())));
+            }
+        }
+        if let Some(lang) = &self.platform_lang {
+            if let Some(keymap) = locale_keymap(&lang.replace('-', "_")) {
+                candidates.push((keymap.to_string(), HintSource::Firmware(lang.clone())));
+            }
+        }
+
+        let mut suggestions: Vec<KeymapSuggestion> = Vec::new();
+        for (keymap, source) in candidates {
+            let installed = available.iter().any(|k| k.name == keymap);
+            if installed && !suggestions.iter().any(|s| s.keymap == keymap) {
+                suggestions.push(KeymapSuggestion { keymap, source });
+            }
+        }
+        suggestions
+    }
+}
+
+/// Keymap from `vconsole.keymap=`, or the initramfs-only `rd.vconsole.keymap=`
+pub fn cmdline_keymap(cmdline: &str) -> Option<String> {
+    let value = |prefix: &str| {
+        cmdline
+            .split_whitespace()
+            .filter_map(|param| param.strip_prefix(prefix))
+            .next_back()
+            .filter(|v| !v.is_empty())
+            .map(str::to_string)
+    };
+    value("vconsole.keymap=").or_else(|| value("rd.vconsole.keymap="))
+}
+
+/// LANG from a locale.conf
+pub fn locale_lang(conf: &str) -> Option<String> {
+    conf.lines()
+        .find_map(|line| line.trim().strip_prefix("LANG="))
+        .map(|value| value.trim_matches('"').to_string())
+        .filter(|value| !value.is_empty())
+}
+
+/// Keymap for a locale like "de_DE.UTF-8" or "pt_BR", by territory where
+/// it matters and otherwise by language. C and POSIX have none.
+pub fn locale_keymap(locale: &str) -> Option<&'static str> {
+    let base = locale.split(['.', '@']).next().unwrap_or(locale);
+    let language = base.split('_').next().unwrap_or(base);
+    lookup(LOCALE_KEYMAPS, base).or_else(|| lookup(LANGUAGE_KEYMAPS, language))
+}
+
+/// Time zone `etc/localtime` links to, None for UTC
+pub fn timezone_in(root: &Path) -> Option<String> {
+    let link = fs::read_link(root.join("etc/localtime")).ok()?;
+    let link = link.to_string_lossy();
+    let zone = link.split_once("zoneinfo/")?.1;
+    let generic = zone == "UTC" || zone.starts_with("Etc/");
+    (!generic).then(|| zone.to_string())
+}
+
+/// Country code of `zone` from zone.tab under `root`
+pub fn zone_country_in(root: &Path, zone: &str) -> Option<String> {
+    let table = fs::read_to_string(root.join(ZONE_TAB)).ok()?;
+    table
+        .lines()
+        .filter(|line| !line.starts_with('#'))
+        .map(|line| line.split('\t').collect::<Vec<_>>())
+        .find(|fields| fields.len() > 2 && fields[2] == zone)
+        .map(|fields| fields[0].to_string())
+}
+
+/// Language tag from the raw PlatformLang efivar: four attribute bytes,
+/// then a NUL-terminated ASCII string
+pub fn parse_platform_lang(var: &[u8]) -> Option<String> {
+    let value = var.get(4..)?;
+    let end = value.iter().position(|b| *b == 0).unwrap_or(value.len());
+    let lang = std::str::from_utf8(&value[..end]).ok()?.trim();
+    (!lang.is_empty()).then(|| lang.to_string())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_locale_keymap() {
+        assert_eq!(locale_keymap("de_DE.UTF-8"), Some("de-latin1"));
+        assert_eq!(locale_keymap("de_CH.UTF-8"), Some("de_CH-latin1"));
+        assert_eq!(locale_keymap("sr_RS@latin"), Some("sr-cy"));
+        assert_eq!(locale_keymap("C.UTF-8"), None);
+    }
+
+    #[test]
+    fn test_cmdline_keymap() {
+        assert_eq!(cmdline_keymap("root=UUID=x vconsole.keymap=fr quiet"), Some("fr".to_string()));
+        assert_eq!(cmdline_keymap("rd.vconsole.keymap=de vconsole.font=ter-v16n"), Some("de".to_string()));
+        assert_eq!(cmdline_keymap("vconsole.keymap= quiet"), None);
+    }
+}
@@ -2,16 +2,36 @@
 //! 
 //! A system setup wizard for Asenos Linux providing functionality for:
 //! - Keymap configuration
+//! - Console fonts
 //! - WiFi network management  
 //! - Disk partitioning
+//! - Hibernation setup
 //! - Basic system configuration
 
 pub mod common;
+pub mod console;
+pub mod disk;
+pub mod filesystem;
+pub mod firmware;
+pub mod hibernate;
 pub mod keymap;
+pub mod keymap_hints;
 pub mod partition;
+pub mod parttable;
+pub mod raid;
+pub mod reinstall;
+pub mod secret;
+pub mod size;
+pub mod smart;
+pub mod target;
+pub mod wipe;
 pub mod wifi;
 pub mod cli_funcs;
 
 // Re-export commonly used types
 pub use partition::PartitionConfig;
 pub use common::{CommandResult, SetupError};
+pub use filesystem::Filesystem;
+pub use firmware::FirmwareMode;
+pub use size::SizeSpec;
+pub use wipe::WipeMode;
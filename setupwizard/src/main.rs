@@ -1,6 +1,14 @@
 use clap::Parser;
-use setupwizard::cli_funcs;
+use setupwizard::cli_funcs::{self, PartitionOptions};
+use setupwizard::console::Script;
+use setupwizard::disk::DEFAULT_SETTLE_TIMEOUT;
+use setupwizard::keymap::DEFAULT_TRIAL_TIMEOUT;
+use setupwizard::raid::RaidLevel;
+use setupwizard::wifi::{self, EapMethod, EnterpriseNetwork, Network, Phase2Method, Security};
+use setupwizard::WipeMode;
+use std::path::PathBuf;
 use std::process;
+use std::time::Duration;
 
 #[derive(Parser)]
 #[command(name = "setupwizard")]
@@ -11,18 +19,110 @@ struct Cli {
     #[arg(long)]
     list_keymaps: bool,
 
-    /// Set system keymap (e.g., "us", "uk", "de")
+    /// Set system keymap (e.g., "us", "uk", "de"), or "auto" for the one
+    /// the kernel command line, locale, time zone or firmware suggests
     #[arg(long)]
     keymap: Option<String>,
 
+    /// Search the keymaps interactively and set the chosen one
+    #[arg(long, conflicts_with = "keymap")]
+    pick_keymap: bool,
+
+    /// Keep the keymap from --keymap or --pick-keymap only after a test
+    /// text is typed with it, else restore the previous one
+    #[arg(long)]
+    try_keymap: bool,
+
+    /// Seconds --try-keymap waits for the test text
+    #[arg(long, value_name = "SECONDS", default_value_t = DEFAULT_TRIAL_TIMEOUT.as_secs())]
+    trial_timeout: u64,
+
+    /// Set the console font by name, or "auto" for the Terminus size
+    /// that suits the screen
+    #[arg(long, value_name = "FONT")]
+    console_font: Option<String>,
+
+    /// Script the "auto" console font must cover: latin, greek, cyrillic,
+    /// arabic, hebrew or ethiopic
+    #[arg(long, value_name = "SCRIPT", default_value = "latin")]
+    console_script: Script,
+
+    /// Choose the console font interactively
+    #[arg(long, conflicts_with = "console_font")]
+    pick_console_font: bool,
+
     /// List available WiFi networks
     #[arg(long)]
     wifi_list: bool,
 
-    /// Connect to WiFi network (format: "ssid" or "ssid:password")
+    /// Connect to WiFi network (format: "ssid" or "ssid:password"; write
+    /// a colon in the SSID as "\:" and a backslash as "\\"). A password
+    /// given here is visible in ps; prefer --wifi-password-file, the
+    /// ASENOS_WIFI_PASSWORD variable or --wifi-ask-password.
     #[arg(long)]
     wifi_connect: Option<String>,
 
+    /// Read the --wifi-connect password from the first line of FILE
+    #[arg(long, value_name = "FILE", requires = "wifi_connect")]
+    wifi_password_file: Option<PathBuf>,
+
+    /// Ask for the --wifi-connect password without echoing it
+    #[arg(long, requires = "wifi_connect", conflicts_with = "wifi_password_file")]
+    wifi_ask_password: bool,
+
+    /// Overwrite a profile iwd already has saved for the --wifi-connect network
+    #[arg(long, requires = "wifi_connect")]
+    wifi_replace_profile: bool,
+
+    /// The --wifi-connect network does not broadcast its SSID
+    #[arg(long, requires = "wifi_connect")]
+    wifi_hidden: bool,
+
+    /// Security of the --wifi-connect network: open, psk or sae. Without
+    /// it iwd uses what the scan reports.
+    #[arg(long, value_name = "TYPE", requires = "wifi_connect")]
+    wifi_security: Option<Security>,
+
+    /// Connect --wifi-connect as a WPA2/WPA3-Enterprise network with this
+    /// EAP method: peap, ttls or tls. The password after the SSID is the
+    /// inner password, or the client key passphrase for tls.
+    #[arg(long, value_name = "METHOD", requires_all = ["wifi_connect", "wifi_identity"], conflicts_with = "wifi_security")]
+    wifi_eap: Option<EapMethod>,
+
+    /// 802.1X user name, e.g. "jdoe@example.org"
+    #[arg(long, value_name = "NAME", requires = "wifi_eap")]
+    wifi_identity: Option<String>,
+
+    /// 802.1X outer identity sent before the tunnel is up
+    #[arg(long, value_name = "NAME", requires = "wifi_eap")]
+    wifi_anonymous_identity: Option<String>,
+
+    /// CA certificate that signed the 802.1X server's certificate
+    #[arg(long, value_name = "FILE", requires = "wifi_eap")]
+    wifi_ca_cert: Option<PathBuf>,
+
+    /// Inner method for peap and ttls: mschapv2 (default), gtc, md5, and
+    /// for ttls also pap, chap or mschap
+    #[arg(long, value_name = "METHOD", requires = "wifi_eap")]
+    wifi_phase2: Option<Phase2Method>,
+
+    /// Client certificate for --wifi-eap tls
+    #[arg(long, value_name = "FILE", requires = "wifi_eap")]
+    wifi_client_cert: Option<PathBuf>,
+
+    /// Client key for --wifi-eap tls
+    #[arg(long, value_name = "FILE", requires = "wifi_eap")]
+    wifi_client_key: Option<PathBuf>,
+
+    /// List wireless devices with adapter, address, mode and power state
+    #[arg(long)]
+    wifi_devices: bool,
+
+    /// Wireless device for --wifi-list and --wifi-connect, needed when
+    /// there are several; it is powered on and set to station mode
+    #[arg(long, value_name = "DEVICE")]
+    wifi_device: Option<String>,
+
     /// List available storage devices
     #[arg(long)]
     list_disks: bool,
@@ -32,10 +132,56 @@ struct Cli {
     partition_disk: bool,
 
     /// Create partitions with configuration string
-    /// Format: disk:boot_size:swap_size:gpt/msdos:filesystem
-    /// Example: /dev/sda:512:2048:gpt:ext4
+    /// Format: disk:boot_size:swap_size:gpt/msdos:filesystem[:wipe/free/reinstall[:root_size]]
+    /// Sizes are MiB or take units and percentages: 512MiB, 4G, 20%, rest
+    /// Filesystems: ext4, btrfs, xfs, f2fs, bcachefs (UEFI only)
+    /// Example: /dev/sda:512MiB:4G:gpt:ext4
+    /// "free" installs into the largest unallocated region and keeps existing partitions
+    /// "reinstall" reformats root and the ESP of an existing Asenos and keeps /home
     #[arg(long)]
     partition_config: Option<String>,
+
+    /// Seconds to wait for new partition device nodes to appear
+    #[arg(long, value_name = "SECONDS", default_value_t = DEFAULT_SETTLE_TIMEOUT.as_secs())]
+    settle_timeout: u64,
+
+    /// Clear the disk before a whole-disk install with --partition-config:
+    /// none, signatures, discard, secure-erase or random
+    #[arg(long, value_name = "MODE", default_value = "none")]
+    wipe: WipeMode,
+
+    /// Confirm --wipe by repeating the mode's confirmation word
+    /// (wipe, discard, erase or overwrite)
+    #[arg(long, value_name = "WORD")]
+    confirm_wipe: Option<String>,
+
+    /// Install with --partition-config even if the disk reports SMART failures
+    #[arg(long)]
+    allow_failing_disk: bool,
+
+    /// Root of the installed system; after partitioning, fstab, I/O
+    /// scheduler rules and periodic TRIM are configured there, and the
+    /// chosen keymap and console font are installed there
+    #[arg(long, value_name = "DIR")]
+    target: Option<PathBuf>,
+
+    /// Set up hibernation into the swap partition in --target; the swap
+    /// size must be at least the installed RAM
+    #[arg(long, requires = "target")]
+    hibernate: bool,
+
+    /// Further disks for a RAID install with --partition-config, which
+    /// get the same layout as its disk (comma-separated)
+    #[arg(long, value_name = "DISKS", value_delimiter = ',')]
+    raid_disks: Vec<String>,
+
+    /// How root is mirrored across the RAID disks: raid1, raid10 or btrfs-raid1
+    #[arg(long, value_name = "LEVEL", default_value = "raid1")]
+    raid_level: RaidLevel,
+
+    /// Put /home on this disk of its own with --partition-config
+    #[arg(long, value_name = "DISK")]
+    home_disk: Option<String>,
 }
 
 fn main() {
@@ -52,17 +198,59 @@ fn run_cli(cli: &Cli) -> Result<(), Box<dyn std::error::Error>> {
         cli_funcs::list_keymaps()?;
     }
 
+    let trial = cli.try_keymap.then(|| Duration::from_secs(cli.trial_timeout));
     if let Some(keymap) = &cli.keymap {
-        cli_funcs::set_keymap(keymap)?;
+        cli_funcs::set_keymap(keymap, cli.target.as_deref(), trial)?;
+    }
+
+    if cli.pick_keymap {
+        cli_funcs::pick_keymap(cli.target.as_deref(), trial)?;
     }
     
+    if let Some(font) = &cli.console_font {
+        cli_funcs::set_console_font(font, cli.console_script, cli.target.as_deref())?;
+    }
+
+    if cli.pick_console_font {
+        cli_funcs::pick_console_font(cli.target.as_deref())?;
+    }
+    
+    if cli.wifi_devices {
+        cli_funcs::list_wifi_devices()?;
+    }
+
     if cli.wifi_list {
-        cli_funcs::list_wifi_networks()?;
+        cli_funcs::list_wifi_networks(cli.wifi_device.as_deref())?;
     }
 
     if let Some(wifi_config) = &cli.wifi_connect {
-        let (ssid, password) = parse_wifi_config(wifi_config);
-        cli_funcs::connect_wifi(ssid, password)?;
+        let (ssid, inline) = parse_wifi_config(wifi_config);
+        // Prompt on our own when the network surely takes a password
+        let needs_password = matches!(cli.wifi_security, Some(Security::Psk | Security::Sae))
+            || matches!(cli.wifi_eap, Some(EapMethod::Peap | EapMethod::Ttls));
+        let password = cli_funcs::wifi_password(
+            inline,
+            cli.wifi_password_file.as_deref(),
+            cli.wifi_ask_password || needs_password,
+        )?;
+        let password = password.as_deref();
+        if let Some(method) = cli.wifi_eap {
+            let network = EnterpriseNetwork::new(&ssid, method, cli.wifi_identity.as_deref().unwrap_or_default())
+                .with_anonymous_identity(cli.wifi_anonymous_identity.as_deref())
+                .with_ca_cert(cli.wifi_ca_cert.as_deref())
+                .with_phase2(cli.wifi_phase2)
+                .with_password(password)
+                .with_client_cert(cli.wifi_client_cert.as_deref(), cli.wifi_client_key.as_deref())
+                .with_hidden(cli.wifi_hidden);
+            cli_funcs::connect_wifi_enterprise(&network, cli.wifi_device.as_deref(), cli.wifi_replace_profile)?;
+        } else if cli.wifi_hidden || cli.wifi_security.is_some() {
+            // Hidden networks are not scanned, so the type has to be known
+            let security = cli.wifi_security.unwrap_or(if password.is_some() { Security::Psk } else { Security::Open });
+            let network = Network::new(&ssid, security, password).with_hidden(cli.wifi_hidden);
+            cli_funcs::connect_wifi_network(&network, cli.wifi_device.as_deref(), cli.wifi_replace_profile)?;
+        } else {
+            cli_funcs::connect_wifi(&ssid, password, cli.wifi_device.as_deref(), cli.wifi_replace_profile)?;
+        }
     }
 
     if cli.list_disks {
@@ -70,23 +258,31 @@ fn run_cli(cli: &Cli) -> Result<(), Box<dyn std::error::Error>> {
     }
 
     if cli.partition_disk {
-        cli_funcs::partition_disk_interactive()?;
+        cli_funcs::partition_disk_interactive(Duration::from_secs(cli.settle_timeout), cli.target.as_deref())?;
     }
 
     if let Some(config_str) = &cli.partition_config {
-        cli_funcs::partition_disk_config(config_str)?;
+        let options = PartitionOptions {
+            settle_timeout: Duration::from_secs(cli.settle_timeout),
+            wipe: cli.wipe,
+            confirm_wipe: cli.confirm_wipe.as_deref(),
+            allow_failing_disk: cli.allow_failing_disk,
+            target: cli.target.as_deref(),
+            hibernate: cli.hibernate,
+            raid_disks: cli.raid_disks.clone(),
+            raid_level: cli.raid_level,
+            home_disk: cli.home_disk.clone(),
+        };
+        cli_funcs::partition_disk_config(config_str, &options)?;
     }
 
     Ok(())
 }
 
-/// Parse WiFi connection string in format "ssid" or "ssid:password"
-fn parse_wifi_config(config: &str) -> (&str, Option<&str>) {
-    match config.splitn(2, ':').collect::<Vec<&str>>().as_slice() {
-        [ssid] => (ssid, None),
-        [ssid, password] => (ssid, Some(password)),
-        _ => (config, None), // Fallback
-    }
+/// Parse WiFi connection string in format "ssid" or "ssid:password"; a
+/// colon in the SSID is written as "\:"
+fn parse_wifi_config(config: &str) -> (String, Option<&str>) {
+    wifi::parse_connect_spec(config)
 }
 
 #[cfg(test)]
@@ -113,4 +309,11 @@ mod tests {
         assert_eq!(ssid, "MyWiFi");
         assert_eq!(password, Some("pass:word"));
     }
+
+    #[test]
+    fn test_parse_wifi_config_with_escaped_colon_in_ssid() {
+        let (ssid, password) = parse_wifi_config(r"Cafe\:Guest \\o/:pass:word");
+        assert_eq!(ssid, r"Cafe:Guest \o/");
+        assert_eq!(password, Some("pass:word"));
+    }
 }
@@ -1,49 +1,319 @@
 use crate::common::{run_command, CommandResult, SetupError};
-use std::path::Path;
+use crate::disk::{
+    block_name_in, check_target_disk, disk_size_bytes, disk_warnings, reread_partitions, storage_media,
+    StorageMedia, DEFAULT_SETTLE_TIMEOUT,
+};
+use crate::filesystem::{random_uuid, Filesystem, FormatSpec, ESP_LABEL, HOME_LABEL, ROOT_LABEL, SWAP_LABEL};
+use crate::firmware::FirmwareMode;
+use crate::hibernate;
+use crate::parttable::{self, read_partition_table_from, PartitionTable};
+use crate::reinstall;
+use crate::size::{resolve_sizes, SizeSpec, MIB};
+use crate::smart::check_disk_health;
+use crate::wipe::{plan_wipe, WipeMode};
+use std::io::Write;
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+
+/// Smallest root partition accepted when installing into free space
+pub const MIN_ROOT_SIZE_MB: u64 = 8192;
+
+/// How the target disk is prepared before formatting
+#[derive(Debug, Clone, PartialEq)]
+pub enum InstallMode {
+    /// Write a new partition table over the whole disk
+    WipeDisk,
+    /// Create partitions inside unallocated space, keeping existing ones.
+    /// `None` picks the largest region that fits the install.
+    FreeSpace(Option<FreeRegion>),
+    /// Reformat root and the ESP of an existing Asenos install, keeping
+    /// its partitions, swap and /home
+    Reinstall,
+}
+
+/// Kind of boot partition the firmware and partition table call for
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum BootLayout {
+    /// FAT32 EFI system partition for UEFI boot
+    Esp,
+    /// Small unformatted partition GRUB embeds into on GPT disks under BIOS
+    BiosGrub,
+    /// No boot partition; GRUB lives in the MBR gap and root carries the boot flag
+    None,
+}
+
+/// Size of the bios_grub partition
+pub const BIOS_GRUB_SIZE_MB: u64 = 1;
+
+/// Unallocated region of a disk, in MiB from the start of the disk
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct FreeRegion {
+    pub start_mib: u64,
+    pub end_mib: u64,
+}
+
+impl FreeRegion {
+    pub fn size_mib(&self) -> u64 {
+        self.end_mib.saturating_sub(self.start_mib)
+    }
+}
+
+/// Partition already present in a disk's partition table
+#[derive(Debug, Clone, PartialEq)]
+pub struct ExistingPartition {
+    pub number: u32,
+    pub start_mib: u64,
+    pub end_mib: u64,
+    pub filesystem: String,
+    pub name: String,
+    pub flags: Vec<String>,
+}
+
+impl ExistingPartition {
+    /// Whether this is an EFI system partition that can be reused
+    pub fn is_esp(&self) -> bool {
+        self.has_flag("esp")
+    }
+
+    pub fn has_flag(&self, flag: &str) -> bool {
+        self.flags.iter().any(|f| f == flag)
+    }
+}
+
+/// Partition table and free space of a disk, read from its partition table
+#[derive(Debug, Clone, PartialEq)]
+pub struct DiskLayout {
+    pub table: String,
+    pub size_mib: u64,
+    pub partitions: Vec<ExistingPartition>,
+    pub free: Vec<FreeRegion>,
+}
+
+impl DiskLayout {
+    /// First EFI system partition on the disk, if any
+    pub fn esp(&self) -> Option<&ExistingPartition> {
+        self.partitions.iter().find(|p| p.is_esp())
+    }
+
+    /// Existing boot partition of the given layout that can be reused
+    pub fn boot_partition(&self, boot: BootLayout) -> Option<&ExistingPartition> {
+        match boot {
+            BootLayout::Esp => self.esp(),
+            BootLayout::BiosGrub => self.partitions.iter().find(|p| p.has_flag("bios_grub")),
+            BootLayout::None => None,
+        }
+    }
+
+    /// Free regions of at least `min_mib`, largest first
+    pub fn free_regions(&self, min_mib: u64) -> Vec<FreeRegion> {
+        let mut regions: Vec<FreeRegion> = self.free
+            .iter()
+            .copied()
+            .filter(|r| r.size_mib() >= min_mib)
+            .collect();
+        regions.sort_by_key(|r| std::cmp::Reverse(r.size_mib()));
+        regions
+    }
+}
+
+/// Partition sizes in whole MiB after resolving a config against real space
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct PartitionSizes {
+    /// Size of the boot partition to create, 0 when none is created
+    pub boot_mib: u64,
+    pub swap_mib: u64,
+    pub root_mib: u64,
+}
 
 #[derive(Debug, Clone)]
 pub struct PartitionConfig {
     pub disk: String,
-    pub boot_size_mb: u32,
-    pub swap_size_mb: u32,
+    pub boot_size: SizeSpec,
+    pub swap_size: SizeSpec,
+    pub root_size: SizeSpec,
     pub use_gpt: bool,
-    pub filesystem: String,
+    /// Root filesystem
+    pub filesystem: Filesystem,
+    pub mode: InstallMode,
+    pub firmware: FirmwareMode,
+    /// How long to wait for new partition nodes to appear
+    pub settle_timeout: Duration,
+    /// How to clear the disk before a whole-disk install
+    pub wipe: WipeMode,
+    /// Install even if the disk reports SMART failures
+    pub allow_failing_disk: bool,
+    /// RAM in MiB the swap partition must hold to hibernate, if enabled
+    pub hibernate_ram_mib: Option<u64>,
+    /// Partitions that become md RAID members, typed as Linux RAID
+    pub md_members: Vec<PartitionRole>,
 }
 
 impl PartitionConfig {
-    pub fn new(disk: String, boot_size_mb: u32, swap_size_mb: u32, use_gpt: bool, filesystem: String) -> Self {
+    /// Create a config; plain numbers for the sizes are taken as MiB
+    pub fn new(
+        disk: String,
+        boot_size: impl Into<SizeSpec>,
+        swap_size: impl Into<SizeSpec>,
+        use_gpt: bool,
+        filesystem: Filesystem,
+    ) -> Self {
         Self {
             disk,
-            boot_size_mb,
-            swap_size_mb,
+            boot_size: boot_size.into(),
+            swap_size: swap_size.into(),
+            root_size: SizeSpec::Rest,
             use_gpt,
             filesystem,
+            mode: InstallMode::WipeDisk,
+            // Detecting the firmware is left to the caller so configs do
+            // not depend on how the host booted
+            firmware: FirmwareMode::Uefi { bits: 64 },
+            settle_timeout: DEFAULT_SETTLE_TIMEOUT,
+            wipe: WipeMode::None,
+            allow_failing_disk: false,
+            hibernate_ram_mib: None,
+            md_members: Vec::new(),
         }
     }
 
-    /// Parse configuration from string format: "disk:boot_size:swap_size:gpt/msdos:filesystem"
+    /// Set the root partition size, which defaults to the rest of the space
+    pub fn with_root_size(mut self, root_size: SizeSpec) -> Self {
+        self.root_size = root_size;
+        self
+    }
+
+    /// Set the firmware mode, 64-bit UEFI unless given
+    pub fn with_firmware(mut self, firmware: FirmwareMode) -> Self {
+        self.firmware = firmware;
+        self
+    }
+
+    /// Set how long to wait for partition device nodes after partitioning
+    pub fn with_settle_timeout(mut self, timeout: Duration) -> Self {
+        self.settle_timeout = timeout;
+        self
+    }
+
+    /// Clear the disk before partitioning. The caller is responsible for
+    /// having the user confirm the wipe.
+    pub fn with_wipe(mut self, wipe: WipeMode) -> Self {
+        self.wipe = wipe;
+        self
+    }
+
+    /// Allow installing to a disk whose SMART health check fails
+    pub fn with_allow_failing_disk(mut self, allow: bool) -> Self {
+        self.allow_failing_disk = allow;
+        self
+    }
+
+    /// Size the swap partition for hibernating a machine with `ram_mib` of RAM
+    pub fn with_hibernation(mut self, ram_mib: u64) -> Self {
+        self.hibernate_ram_mib = Some(ram_mib);
+        self
+    }
+
+    /// Type the partitions in `roles` as md RAID members
+    pub fn with_md_members(mut self, roles: Vec<PartitionRole>) -> Self {
+        self.md_members = roles;
+        self
+    }
+
+    /// Boot partition needed for this firmware and partition table
+    pub fn boot_layout(&self) -> BootLayout {
+        match (self.firmware, self.use_gpt) {
+            (FirmwareMode::Uefi { .. }, _) => BootLayout::Esp,
+            (FirmwareMode::Bios, true) => BootLayout::BiosGrub,
+            (FirmwareMode::Bios, false) => BootLayout::None,
+        }
+    }
+
+    /// Combinations of firmware and partition table that may not boot
+    pub fn firmware_warnings(&self) -> Vec<String> {
+        let mut warnings = Vec::new();
+
+        if let FirmwareMode::Uefi { bits } = self.firmware {
+            if !self.use_gpt {
+                warnings.push(
+                    "Booted in UEFI mode but an MBR table was requested; \
+                     many firmwares only boot UEFI from GPT disks".to_string()
+                );
+            }
+            if bits == 32 {
+                warnings.push(
+                    "32-bit UEFI firmware detected; the bootloader must be \
+                     installed for i386-efi".to_string()
+                );
+            }
+        }
+
+        warnings
+    }
+
+    /// Everything worth confirming before partitioning: firmware mismatches
+    /// and problems with the target disk
+    pub fn warnings(&self) -> Vec<String> {
+        let mut warnings = self.firmware_warnings();
+        if Path::new(&self.disk).exists() {
+            warnings.extend(disk_warnings(&self.disk, self.use_gpt));
+
+            if self.filesystem == Filesystem::F2fs && storage_media(&self.disk) == StorageMedia::Rotational {
+                warnings.push(format!(
+                    "{} is a rotational disk; f2fs is designed for flash storage", self.disk
+                ));
+            }
+        }
+        warnings
+    }
+
+    /// Set how the disk is prepared
+    pub fn with_mode(mut self, mode: InstallMode) -> Self {
+        self.mode = mode;
+        self
+    }
+
+    /// Parse configuration from string format:
+    /// "disk:boot_size:swap_size:gpt/msdos:filesystem[:wipe/free/reinstall[:root_size]]"
+    /// Sizes accept units and percentages, e.g. "512MiB", "1G", "20%" or "rest".
     pub fn from_string(config_str: &str) -> CommandResult<Self> {
         let parts: Vec<&str> = config_str.split(':').collect();
         
-        if parts.len() != 5 {
+        if !(5..=7).contains(&parts.len()) {
             return Err(SetupError::InvalidInput(
-                "Format: disk:boot_size:swap_size:gpt/msdos:filesystem".to_string()
+                "Format: disk:boot_size:swap_size:gpt/msdos:filesystem[:wipe/free/reinstall[:root_size]]".to_string()
             ));
         }
         
-        let boot_size_mb = parts[1].parse::<u32>()
-            .map_err(|_| SetupError::InvalidInput("Invalid boot size".to_string()))?;
-        let swap_size_mb = parts[2].parse::<u32>()
-            .map_err(|_| SetupError::InvalidInput("Invalid swap size".to_string()))?;
+        let boot_size = parts[1].parse::<SizeSpec>()
+            .map_err(|e| SetupError::InvalidInput(format!("Invalid boot size: {}", e)))?;
+        let swap_size = parts[2].parse::<SizeSpec>()
+            .map_err(|e| SetupError::InvalidInput(format!("Invalid swap size: {}", e)))?;
+        let root_size = match parts.get(6) {
+            Some(size) => size.parse::<SizeSpec>()
+                .map_err(|e| SetupError::InvalidInput(format!("Invalid root size: {}", e)))?,
+            None => SizeSpec::Rest,
+        };
         let use_gpt = parts[3] == "gpt";
+        let mode = match parts.get(5).copied() {
+            None | Some("wipe") => InstallMode::WipeDisk,
+            Some("free") => InstallMode::FreeSpace(None),
+            Some("reinstall") => InstallMode::Reinstall,
+            Some(other) => {
+                return Err(SetupError::InvalidInput(format!(
+                    "Unknown install mode '{}', expected wipe, free or reinstall", other
+                )));
+            }
+        };
         
         let config = Self::new(
             parts[0].to_string(),
-            boot_size_mb,
-            swap_size_mb,
+            boot_size,
+            swap_size,
             use_gpt,
-            parts[4].to_string(),
-        );
+            parts[4].parse::<Filesystem>()?,
+        )
+        .with_root_size(root_size)
+        .with_mode(mode);
         
         config.validate()?;
         Ok(config)
@@ -55,110 +325,799 @@ impl PartitionConfig {
             return Err(SetupError::InvalidInput("Disk path must start with /dev/".to_string()));
         }
 
-        if !Path::new(&self.disk).exists() {
-            return Err(SetupError::InvalidInput(format!("Disk {} does not exist", self.disk)));
+        // Relative sizes are checked once they are resolved against the disk
+        if self.boot_size == SizeSpec::Rest || self.swap_size == SizeSpec::Rest {
+            return Err(SetupError::InvalidInput(
+                "Boot size and Swap size must not be 'rest'".to_string()
+            ));
+        }
+        // A reinstall keeps the partitions at the sizes they have
+        if self.mode != InstallMode::Reinstall {
+            check_size_bounds(self.boot_size.fixed_mib(), self.swap_size.fixed_mib())?;
         }
 
-        if !(100..=2048).contains(&self.boot_size_mb) {
-            return Err(SetupError::InvalidInput("Boot size must be 100-2048 MB".to_string()));
+        if !self.filesystem.is_root_capable() {
+            return Err(SetupError::InvalidInput(format!(
+                "Filesystem {} cannot hold the root filesystem. Valid: {}",
+                self.filesystem,
+                Filesystem::ROOT_CHOICES.map(|f| f.name()).join(", ")
+            )));
         }
 
-        if self.swap_size_mb < 512 {
-            return Err(SetupError::InvalidInput("Swap size must be at least 512 MB".to_string()));
+        // Without an ESP, /boot is a directory on root and GRUB has to read it
+        if self.filesystem == Filesystem::Bcachefs && self.boot_layout() != BootLayout::Esp {
+            return Err(SetupError::InvalidInput(
+                "GRUB cannot read bcachefs; a bcachefs root needs UEFI boot with /boot on the ESP".to_string()
+            ));
+        }
+
+        if self.firmware == FirmwareMode::Bios && matches!(self.mode, InstallMode::FreeSpace(_)) && !self.use_gpt {
+            // Legacy boot from MBR needs GRUB in the gap before the first
+            // partition, which another installed system already owns
+            return Err(SetupError::InvalidInput(
+                "Installing next to another system on an MBR disk requires UEFI boot".to_string()
+            ));
+        }
+
+        if self.wipe != WipeMode::None && self.mode != InstallMode::WipeDisk {
+            return Err(SetupError::InvalidInput(format!(
+                "The {} wipe would destroy the existing systems; it needs a whole-disk install",
+                self.wipe
+            )));
         }
 
-        if !["ext4", "btrfs", "xfs"].contains(&self.filesystem.as_str()) {
-            return Err(SetupError::InvalidInput("Filesystem must be ext4, btrfs, or xfs".to_string()));
+        if !Path::new(&self.disk).exists() {
+            return Err(SetupError::InvalidInput(format!("Disk {} does not exist", self.disk)));
+        }
+
+        // Refuse partitions, busy disks and the live medium before
+        // anything is written
+        check_target_disk(&self.disk)?;
+
+        // Free-space installs are resolved against their region instead
+        if self.mode == InstallMode::WipeDisk {
+            self.resolve_sizes(usable_disk_mib(disk_size_bytes(&self.disk)?), false)?;
         }
 
         Ok(())
     }
 
-    /// Get partition device names
-    pub fn get_partition_names(&self) -> (String, String, String) {
-        let prefix = if self.disk.contains("nvme") || self.disk.contains("mmc") {
-            format!("{}p", self.disk)
-        } else {
-            self.disk.clone()
+    /// Resolve the configured sizes against `available_mib` of space,
+    /// leaving out the boot partition when an existing one is reused
+    pub fn resolve_sizes(&self, available_mib: u64, reuse_boot: bool) -> CommandResult<PartitionSizes> {
+        let boot = if reuse_boot { BootLayout::None } else { self.boot_layout() };
+
+        let sizes = match boot {
+            BootLayout::Esp => {
+                let sizes = resolve_sizes(&[self.boot_size, self.swap_size, self.root_size], available_mib)?;
+                PartitionSizes { boot_mib: sizes[0], swap_mib: sizes[1], root_mib: sizes[2] }
+            }
+            BootLayout::BiosGrub | BootLayout::None => {
+                let boot_mib = if boot == BootLayout::BiosGrub { BIOS_GRUB_SIZE_MB } else { 0 };
+                let sizes = resolve_sizes(
+                    &[self.swap_size, self.root_size],
+                    available_mib.saturating_sub(boot_mib),
+                )?;
+                PartitionSizes { boot_mib, swap_mib: sizes[0], root_mib: sizes[1] }
+            }
         };
-        
+
+        if boot == BootLayout::Esp {
+            check_size_bounds(Some(sizes.boot_mib), Some(sizes.swap_mib))?;
+        } else {
+            check_size_bounds(None, Some(sizes.swap_mib))?;
+        }
+        if let Some(ram_mib) = self.hibernate_ram_mib {
+            hibernate::check_swap_size(sizes.swap_mib, ram_mib)?;
+        }
+        if sizes.root_mib < MIN_ROOT_SIZE_MB {
+            return Err(SetupError::InvalidInput(format!(
+                "Root size must be at least {} MiB, got {} MiB",
+                MIN_ROOT_SIZE_MB, sizes.root_mib
+            )));
+        }
+
+        Ok(sizes)
+    }
+
+    /// Size in MiB of the boot partition this config creates, if known
+    /// without resolving against a disk
+    pub fn boot_partition_mib(&self) -> u64 {
+        match self.boot_layout() {
+            BootLayout::Esp => self.boot_size.fixed_mib().unwrap_or(0),
+            BootLayout::BiosGrub => BIOS_GRUB_SIZE_MB,
+            BootLayout::None => 0,
+        }
+    }
+
+    /// Lower bound in MiB for a free region to hold the install.
+    /// Relative sizes count as zero; the boot partition is only counted
+    /// when no existing one can be reused.
+    pub fn required_free_mib(&self, reuse_boot: bool) -> u64 {
+        let boot = if reuse_boot { 0 } else { self.boot_partition_mib() };
+        boot + self.swap_size.fixed_mib().unwrap_or(0) + self.root_size.fixed_mib().unwrap_or(MIN_ROOT_SIZE_MB)
+    }
+
+    /// Check that a free-space install fits the disk's existing layout
+    /// and return the region to install into
+    pub fn check_free_space(&self, layout: &DiskLayout) -> CommandResult<FreeRegion> {
+        let expected = if self.use_gpt { "gpt" } else { "msdos" };
+        if layout.table != expected {
+            return Err(SetupError::InvalidInput(format!(
+                "Disk {} has a {} partition table, not {}",
+                self.disk, layout.table, expected
+            )));
+        }
+
+        let reuse_boot = layout.boot_partition(self.boot_layout()).is_some();
+        if !self.use_gpt {
+            let new_partitions = if reuse_boot { 2 } else { 3 };
+            if layout.partitions.len() + new_partitions > 4 {
+                return Err(SetupError::InvalidInput(format!(
+                    "MBR disk {} has no room for {} more primary partitions",
+                    self.disk, new_partitions
+                )));
+            }
+        }
+
+        let required = self.required_free_mib(reuse_boot);
+        match &self.mode {
+            InstallMode::FreeSpace(Some(region)) => {
+                let still_free = layout.free.iter().any(|r| {
+                    r.start_mib <= region.start_mib && region.end_mib <= r.end_mib
+                });
+                if !still_free {
+                    return Err(SetupError::InvalidInput(format!(
+                        "Region {}-{} MiB is not unallocated on {}",
+                        region.start_mib, region.end_mib, self.disk
+                    )));
+                }
+                if region.size_mib() < required {
+                    return Err(SetupError::InvalidInput(format!(
+                        "Region {}-{} MiB is too small: {} MiB required",
+                        region.start_mib, region.end_mib, required
+                    )));
+                }
+                self.resolve_sizes(region.size_mib(), reuse_boot)?;
+                Ok(*region)
+            }
+            _ => {
+                let region = layout.free_regions(required).first().copied().ok_or_else(|| {
+                    SetupError::InvalidInput(format!(
+                        "No unallocated region of at least {} MiB on {}",
+                        required, self.disk
+                    ))
+                })?;
+                self.resolve_sizes(region.size_mib(), reuse_boot)?;
+                Ok(region)
+            }
+        }
+    }
+
+    /// Device names of the boot partition, if any, swap and root that a
+    /// whole-disk install creates
+    pub fn get_partition_names(&self) -> (Option<String>, String, String) {
+        let numbers = PartitionNumbers::wipe(self);
         (
-            format!("{}1", prefix), // boot
-            format!("{}2", prefix), // swap
-            format!("{}3", prefix), // root
+            numbers.boot.map(|n| partition_path(&self.disk, n)),
+            partition_path(&self.disk, numbers.swap),
+            partition_path(&self.disk, numbers.root),
         )
     }
 }
 
+/// Check the boot and swap sizes that are known, in MiB
+fn check_size_bounds(boot_mib: Option<u64>, swap_mib: Option<u64>) -> CommandResult<()> {
+    if let Some(boot_mib) = boot_mib {
+        if !(100..=2048).contains(&boot_mib) {
+            return Err(SetupError::InvalidInput(format!(
+                "Boot size must be 100-2048 MiB, got {} MiB", boot_mib
+            )));
+        }
+    }
+
+    if let Some(swap_mib) = swap_mib {
+        if swap_mib < 512 {
+            return Err(SetupError::InvalidInput(format!(
+                "Swap size must be at least 512 MiB, got {} MiB", swap_mib
+            )));
+        }
+    }
+
+    Ok(())
+}
+
+/// MiB available for partitions on a whole disk of `disk_bytes`, keeping
+/// the first MiB for alignment and the last for the backup GPT
+pub fn usable_disk_mib(disk_bytes: u64) -> u64 {
+    (disk_bytes / MIB).saturating_sub(2)
+}
+
+/// Expected device node for partition `number` of `disk`, before the
+/// partition exists. Follows the kernel's naming rule: a "p" separator
+/// when the disk name ends in a digit (nvme0n1p1, loop0p1, md0p1).
+/// Symlinks such as /dev/disk/by-id are resolved to the kernel name.
+/// Use `disk::partition_node` once the partition has been created.
+pub fn partition_path(disk: &str, number: u32) -> String {
+    let name = block_name_in(Path::new("/"), disk);
+    if name.ends_with(|c: char| c.is_ascii_digit()) {
+        format!("/dev/{}p{}", name, number)
+    } else {
+        format!("/dev/{}{}", name, number)
+    }
+}
+
+/// Build a layout from a partition table read in-process.
+/// Partitions keep their full extent in MiB; free regions are shrunk
+/// to whole MiB so new partitions stay aligned.
+pub fn layout_from_table(table: &PartitionTable) -> DiskLayout {
+    let sector_size = table.sector_size();
+
+    DiskLayout {
+        table: table.label().to_string(),
+        size_mib: parttable::to_mib(table.total_sectors() * sector_size, false),
+        partitions: table
+            .partitions()
+            .into_iter()
+            .map(|p| ExistingPartition {
+                number: p.number,
+                start_mib: parttable::to_mib(p.first_lba * sector_size, false),
+                end_mib: parttable::to_mib((p.last_lba + 1) * sector_size, true),
+                filesystem: String::new(),
+                name: p.name,
+                flags: p.flags,
+            })
+            .collect(),
+        free: table
+            .free_ranges()
+            .into_iter()
+            .map(|(start, end)| FreeRegion {
+                start_mib: parttable::to_mib(start, true),
+                end_mib: parttable::to_mib(end, false),
+            })
+            .filter(|r| r.end_mib > r.start_mib)
+            .collect(),
+    }
+}
+
+/// Read the partition table of `disk`
+pub fn read_table(disk: &str) -> CommandResult<PartitionTable> {
+    read_partition_table_from(Path::new(disk))?
+        .ok_or_else(|| SetupError::InvalidInput(format!("{} has no partition table", disk)))
+}
+
+/// Read the partition table and free space of a disk
+pub fn read_disk_layout(disk: &str) -> CommandResult<DiskLayout> {
+    Ok(layout_from_table(&read_table(disk)?))
+}
+
 /// List available block devices
 pub fn list_disks() -> CommandResult<String> {
     run_command(&["lsblk", "-o", "NAME,SIZE,TYPE,MOUNTPOINT"], None)
 }
 
-/// Create partitions according to configuration
-pub fn create_partitions(config: &PartitionConfig) -> CommandResult<()> {
+/// Device nodes used by the install
+#[derive(Debug, Clone, PartialEq)]
+pub struct InstallPartitions {
+    /// ESP or bios_grub partition, absent for BIOS boot from MBR
+    pub boot: Option<String>,
+    pub swap: String,
+    pub root: String,
+    /// Whether the boot partition is a new ESP that must be formatted
+    pub format_boot: bool,
+}
+
+/// Partition of the new install and the filesystem on it
+#[derive(Debug, Clone, PartialEq)]
+pub struct Volume {
+    pub role: PartitionRole,
+    pub device: String,
+    pub format: FormatSpec,
+    /// False for an existing ESP that is reused as it is
+    pub formatted: bool,
+    /// btrfs subvolume mounted instead of the top level
+    pub subvolume: Option<String>,
+}
+
+/// Partition numbers used by the install, before mapping to device nodes
+#[derive(Debug, Clone, Copy, PartialEq)]
+struct PartitionNumbers {
+    boot: Option<u32>,
+    swap: u32,
+    root: u32,
+    format_boot: bool,
+}
+
+impl PartitionNumbers {
+    /// Numbers a whole-disk install creates
+    fn wipe(config: &PartitionConfig) -> Self {
+        match config.boot_layout() {
+            BootLayout::None => PartitionNumbers { boot: None, swap: 1, root: 2, format_boot: false },
+            boot => PartitionNumbers {
+                boot: Some(1),
+                swap: 2,
+                root: 3,
+                format_boot: boot == BootLayout::Esp,
+            },
+        }
+    }
+
+    /// Numbers in boot, swap, root order
+    fn all(&self) -> Vec<u32> {
+        self.boot.into_iter().chain([self.swap, self.root]).collect()
+    }
+
+    /// Re-read the table of `disk` and map the numbers to their device nodes
+    /// once they exist
+    fn settle(self, disk: &str, timeout: Duration) -> CommandResult<InstallPartitions> {
+        let numbers = self.all();
+        let nodes = reread_partitions(disk, &numbers, timeout)?;
+        self.to_partitions(disk, |_, n| {
+            numbers
+                .iter()
+                .position(|m| *m == n)
+                .map(|i| nodes[i].clone())
+                .ok_or_else(|| SetupError::System(format!("Partition {} was not waited for", n)))
+        })
+    }
+
+    /// Map numbers to device nodes with `node`
+    fn to_partitions(
+        self,
+        disk: &str,
+        node: impl Fn(&str, u32) -> CommandResult<String>,
+    ) -> CommandResult<InstallPartitions> {
+        Ok(InstallPartitions {
+            boot: self.boot.map(|n| node(disk, n)).transpose()?,
+            swap: node(disk, self.swap)?,
+            root: node(disk, self.root)?,
+            format_boot: self.format_boot,
+        })
+    }
+}
+
+/// Role of a partition the installer creates
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PartitionRole {
+    Esp,
+    BiosGrub,
+    Swap,
+    Root,
+    /// /home on a disk of its own
+    Home,
+}
+
+impl PartitionRole {
+    /// sfdisk type: a GPT type GUID or an MBR type byte.
+    /// Root uses the x86-64 root GUID from the Discoverable Partitions Specification.
+    pub fn type_code(&self, use_gpt: bool) -> &'static str {
+        match (self, use_gpt) {
+            (PartitionRole::Esp, true) => parttable::GUID_ESP,
+            (PartitionRole::BiosGrub, true) => parttable::GUID_BIOS_BOOT,
+            (PartitionRole::Swap, true) => parttable::GUID_LINUX_SWAP,
+            (PartitionRole::Root, true) => parttable::GUID_LINUX_ROOT_X86_64,
+            (PartitionRole::Home, true) => parttable::GUID_LINUX_HOME,
+            (PartitionRole::Esp, false) => "ef",
+            (PartitionRole::Swap, false) => "82",
+            (PartitionRole::Root | PartitionRole::BiosGrub | PartitionRole::Home, false) => "83",
+        }
+    }
+
+    /// GPT partition name
+    pub fn label(&self) -> &'static str {
+        match self {
+            PartitionRole::Esp => ESP_LABEL,
+            PartitionRole::BiosGrub => "ASENOS_BIOS",
+            PartitionRole::Swap => SWAP_LABEL,
+            PartitionRole::Root => ROOT_LABEL,
+            PartitionRole::Home => HOME_LABEL,
+        }
+    }
+}
+
+/// Partition to create, in MiB from the start of the disk
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct PlannedPartition {
+    pub role: PartitionRole,
+    pub start_mib: u64,
+    pub size_mib: u64,
+    /// MBR active flag, used when BIOS boots straight from root
+    pub bootable: bool,
+}
+
+/// Every partition of an install, written to the disk in a single sfdisk run
+#[derive(Debug, Clone, PartialEq)]
+pub struct PartitionPlan {
+    pub disk: String,
+    pub use_gpt: bool,
+    /// Replace the whole table rather than appending to it
+    pub new_table: bool,
+    pub partitions: Vec<PlannedPartition>,
+    /// Roles whose partitions become md RAID members
+    pub md_members: Vec<PartitionRole>,
+}
+
+impl PartitionPlan {
+    /// Lay out the partitions back to back from `start_mib`.
+    /// A boot partition is only planned when `sizes.boot_mib` is non-zero.
+    pub fn new(config: &PartitionConfig, sizes: &PartitionSizes, start_mib: u64) -> Self {
+        let boot = config.boot_layout();
+        let mut partitions = Vec::new();
+        let mut start = start_mib;
+
+        if sizes.boot_mib > 0 {
+            let role = if boot == BootLayout::BiosGrub { PartitionRole::BiosGrub } else { PartitionRole::Esp };
+            partitions.push(PlannedPartition { role, start_mib: start, size_mib: sizes.boot_mib, bootable: false });
+            start += sizes.boot_mib;
+        }
+
+        partitions.push(PlannedPartition {
+            role: PartitionRole::Swap,
+            start_mib: start,
+            size_mib: sizes.swap_mib,
+            bootable: false,
+        });
+        start += sizes.swap_mib;
+
+        partitions.push(PlannedPartition {
+            role: PartitionRole::Root,
+            start_mib: start,
+            size_mib: sizes.root_mib,
+            bootable: !config.use_gpt && boot == BootLayout::None,
+        });
+
+        Self {
+            disk: config.disk.clone(),
+            use_gpt: config.use_gpt,
+            new_table: config.mode == InstallMode::WipeDisk,
+            partitions,
+            md_members: config.md_members.clone(),
+        }
+    }
+
+    /// sfdisk type of the planned partition with `role`
+    pub fn type_code(&self, role: PartitionRole) -> &'static str {
+        match (self.md_members.contains(&role), self.use_gpt) {
+            (true, true) => parttable::GUID_LINUX_RAID,
+            (true, false) => "fd",
+            (false, _) => role.type_code(self.use_gpt),
+        }
+    }
+
+    /// Start offset of the planned partition with `role`
+    pub fn start_of(&self, role: PartitionRole) -> Option<u64> {
+        self.partitions.iter().find(|p| p.role == role).map(|p| p.start_mib)
+    }
+
+    /// Render the plan as an sfdisk script
+    pub fn to_sfdisk_script(&self) -> String {
+        let mut script = String::new();
+        if self.new_table {
+            script.push_str(if self.use_gpt { "label: gpt\n" } else { "label: dos\n" });
+        }
+
+        for part in &self.partitions {
+            script.push_str(&format!(
+                "start={}MiB, size={}MiB, type={}",
+                part.start_mib,
+                part.size_mib,
+                self.type_code(part.role)
+            ));
+            if self.use_gpt {
+                script.push_str(&format!(", name=\"{}\"", part.role.label()));
+            }
+            if part.bootable {
+                script.push_str(", bootable");
+            }
+            script.push('\n');
+        }
+
+        script
+    }
+
+    /// Check that every planned partition is in `table` with the planned
+    /// start, size and type
+    pub fn verify(&self, table: &PartitionTable) -> CommandResult<()> {
+        let sector_size = table.sector_size();
+        let partitions = table.partitions();
+
+        for planned in &self.partitions {
+            let first_lba = planned.start_mib * MIB / sector_size;
+            let sectors = planned.size_mib * MIB / sector_size;
+            let found = partitions.iter().find(|p| p.first_lba == first_lba).ok_or_else(|| {
+                SetupError::System(format!(
+                    "Partition table of {} has no partition at {} MiB after writing",
+                    self.disk, planned.start_mib
+                ))
+            })?;
+
+            // A corrupt entry may end before it starts
+            if found.last_lba.checked_sub(found.first_lba).map(|n| n + 1) != Some(sectors) {
+                return Err(SetupError::System(format!(
+                    "Partition {} of {} is not the planned {} MiB",
+                    found.number, self.disk, planned.size_mib
+                )));
+            }
+            if !found.type_code.eq_ignore_ascii_case(self.type_code(planned.role)) {
+                return Err(SetupError::System(format!(
+                    "Partition {} of {} has type {} instead of {}",
+                    found.number, self.disk, found.type_code, self.type_code(planned.role)
+                )));
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Write the whole plan to the disk in one operation
+    pub fn apply(&self) -> CommandResult<()> {
+        let script = self.to_sfdisk_script();
+        if self.new_table {
+            run_command(&["sfdisk", &self.disk], Some(&script))?;
+        } else {
+            run_command(&["sfdisk", "--append", &self.disk], Some(&script))?;
+        }
+        Ok(())
+    }
+}
+
+/// Partition table saved before partitioning so a failed install can be undone
+#[derive(Debug, Clone)]
+pub struct TableBackup {
+    pub disk: String,
+    /// `sfdisk --dump` output, `None` when the disk had no partition table
+    pub dump: Option<String>,
+    /// Copy of the dump kept for manual recovery
+    pub copy: Option<PathBuf>,
+}
+
+impl TableBackup {
+    /// Dump the current table and keep a copy, readable by root only, in
+    /// the temp directory for manual recovery
+    pub fn save(disk: &str) -> CommandResult<Self> {
+        let dump = match run_command(&["sfdisk", "--dump", disk], None) {
+            Ok(dump) => Some(dump),
+            Err(SetupError::CommandFailed(msg)) if msg.contains("does not contain a recognized partition table") => None,
+            Err(e) => return Err(e),
+        };
+
+        let copy = match &dump {
+            Some(dump) => {
+                let name = Path::new(disk).file_name().unwrap_or_default().to_string_lossy();
+                // A fresh file with a random name, so nothing planted in the
+                // temp directory is followed
+                let mut file = tempfile::Builder::new()
+                    .prefix(&format!("asenos-{}-table-", name))
+                    .suffix(".sfdisk")
+                    .tempfile()?;
+                file.write_all(dump.as_bytes())?;
+                let (_, path) = file.keep().map_err(|e| SetupError::Io(e.error))?;
+                Some(path)
+            }
+            None => None,
+        };
+
+        Ok(Self { disk: disk.to_string(), dump, copy })
+    }
+
+    /// Put the saved table back, or clear the new one if there was none
+    pub fn restore(&self) -> CommandResult<()> {
+        let restored = match &self.dump {
+            Some(dump) => run_command(&["sfdisk", "--no-reread", &self.disk], Some(dump)),
+            None => run_command(&["wipefs", "--all", &self.disk], None),
+        };
+        if let Err(e) = restored {
+            return Err(match &self.copy {
+                Some(copy) => SetupError::System(format!("{} (the old table is saved in {})", e, copy.display())),
+                None => e,
+            });
+        }
+        let _ = run_command(&["partprobe", &self.disk], None);
+        Ok(())
+    }
+}
+
+/// Create partitions according to configuration.
+/// The previous partition table is restored if writing the new one or
+/// formatting fails.
+pub fn create_partitions(config: &PartitionConfig) -> CommandResult<Vec<Volume>> {
     config.validate()?;
-    
-    let table_type = if config.use_gpt { "gpt" } else { "msdos" };
-    
-    // Create partition table
-    run_command(&["parted", "-s", &config.disk, "mklabel", table_type], None)?;
-    
-    // Calculate partition boundaries
-    let boot_end = config.boot_size_mb;
-    let swap_end = boot_end + config.swap_size_mb;
-    
-    // Create boot partition
-    run_command(&[
-        "parted", "-s", &config.disk, "mkpart", "primary", "fat32", 
-        "1MiB", &format!("{}MiB", boot_end)
-    ], None)?;
-    
-    // Create swap partition  
-    run_command(&[
-        "parted", "-s", &config.disk, "mkpart", "primary", "linux-swap",
-        &format!("{}MiB", boot_end), &format!("{}MiB", swap_end)
-    ], None)?;
-    
-    // Create root partition
-    run_command(&[
-        "parted", "-s", &config.disk, "mkpart", "primary", &config.filesystem,
-        &format!("{}MiB", swap_end), "100%"
-    ], None)?;
-    
-    // Set boot flag
-    let flag = if config.use_gpt { "esp" } else { "boot" };
-    run_command(&["parted", "-s", &config.disk, "set", "1", flag, "on"], None)?;
-    
-    // Update kernel partition table
-    run_command(&["partprobe", &config.disk], None)?;
-    std::thread::sleep(std::time::Duration::from_millis(1000));
-    
-    // Format partitions
-    format_partitions(config)?;
-    
-    Ok(())
+    check_disk_health(&config.disk, config.allow_failing_disk)?;
+
+    // The partition table stays as it is
+    if config.mode == InstallMode::Reinstall {
+        return reinstall::reinstall(config, &reinstall::detect(&config.disk)?);
+    }
+
+    // Nothing is left to restore once the old data is wiped, so the backup
+    // below only covers the new table
+    if config.wipe != WipeMode::None {
+        plan_wipe(&config.disk, config.wipe)?.run()?;
+    }
+
+    let backup = TableBackup::save(&config.disk)?;
+
+    let result = match config.mode {
+        InstallMode::WipeDisk => create_partitions_wipe(config),
+        InstallMode::FreeSpace(_) => create_partitions_free(config),
+        InstallMode::Reinstall => unreachable!("reinstalls keep the partition table"),
+    }
+    .and_then(|partitions| format_partitions(config, &partitions));
+
+    result.map_err(|e| match backup.restore() {
+        Ok(()) => e,
+        Err(restore_err) => SetupError::System(format!(
+            "{}; restoring the previous partition table also failed: {}",
+            e, restore_err
+        )),
+    })
 }
 
-/// Format created partitions
-fn format_partitions(config: &PartitionConfig) -> CommandResult<()> {
-    let (boot_part, swap_part, root_part) = config.get_partition_names();
-    
-    // Format boot as FAT32
-    run_command(&["mkfs.fat", "-F32", &boot_part], None)?;
-    
-    // Format swap
-    run_command(&["mkswap", &swap_part], None)?;
-    
-    // Format root with chosen filesystem
-    match config.filesystem.as_str() {
-        "ext4" => run_command(&["mkfs.ext4", "-F", &root_part], None)?,
-        "btrfs" => run_command(&["mkfs.btrfs", "-f", &root_part], None)?,
-        "xfs" => run_command(&["mkfs.xfs", "-f", &root_part], None)?,
-        _ => unreachable!(), // Already validated
+/// Replace the partition table of the whole disk
+fn create_partitions_wipe(config: &PartitionConfig) -> CommandResult<InstallPartitions> {
+    let sizes = config.resolve_sizes(usable_disk_mib(disk_size_bytes(&config.disk)?), false)?;
+    create_partitions_sized(config, &sizes)
+}
+
+/// Replace the partition table of the whole disk with partitions of
+/// `sizes`, which may be smaller than the disk allows
+pub(crate) fn create_partitions_sized(config: &PartitionConfig, sizes: &PartitionSizes) -> CommandResult<InstallPartitions> {
+    // Partitions start at 1 MiB for alignment
+    let plan = PartitionPlan::new(config, sizes, 1);
+    plan.apply()?;
+    plan.verify(&read_table(&config.disk)?)?;
+
+    PartitionNumbers::wipe(config).settle(&config.disk, config.settle_timeout)
+}
+
+/// Give `disk` a new table with a single /home partition formatted with
+/// the root filesystem of `config`, which also supplies the table type,
+/// wipe mode and timeouts. The old table is restored if anything fails.
+pub fn create_home_partition(config: &PartitionConfig, disk: &str) -> CommandResult<Volume> {
+    check_target_disk(disk)?;
+    check_disk_health(disk, config.allow_failing_disk)?;
+    if config.wipe != WipeMode::None {
+        plan_wipe(disk, config.wipe)?.run()?;
+    }
+
+    let backup = TableBackup::save(disk)?;
+    let result = home_plan(config, disk).and_then(|plan| {
+        plan.apply()?;
+        plan.verify(&read_table(disk)?)?;
+        let device = reread_partitions(disk, &[1], config.settle_timeout)?.remove(0);
+
+        let mut format = FormatSpec::new(config.filesystem, HOME_LABEL);
+        if config.filesystem.supports_uuid() {
+            format = format.with_uuid(random_uuid()?);
+        }
+        format.format(&device)?;
+        Ok(Volume { role: PartitionRole::Home, device, format, formatted: true, subvolume: None })
+    });
+
+    result.map_err(|e| match backup.restore() {
+        Ok(()) => e,
+        Err(restore_err) => SetupError::System(format!(
+            "{}; restoring the previous partition table also failed: {}",
+            e, restore_err
+        )),
+    })
+}
+
+/// Plan filling `disk` with one /home partition
+pub fn home_plan(config: &PartitionConfig, disk: &str) -> CommandResult<PartitionPlan> {
+    let size_mib = usable_disk_mib(disk_size_bytes(disk)?);
+    Ok(PartitionPlan {
+        disk: disk.to_string(),
+        use_gpt: config.use_gpt,
+        new_table: true,
+        partitions: vec![PlannedPartition { role: PartitionRole::Home, start_mib: 1, size_mib, bootable: false }],
+        md_members: Vec::new(),
+    })
+}
+
+/// Device nodes a whole-disk install is expected to create
+pub fn wipe_partition_names(config: &PartitionConfig) -> InstallPartitions {
+    PartitionNumbers::wipe(config)
+        .to_partitions(&config.disk, |disk, n| Ok(partition_path(disk, n)))
+        .expect("predicting partition names cannot fail")
+}
+
+/// Add partitions inside a free region, keeping the existing table
+fn create_partitions_free(config: &PartitionConfig) -> CommandResult<InstallPartitions> {
+    let layout = read_disk_layout(&config.disk)?;
+    let region = config.check_free_space(&layout)?;
+    let boot = config.boot_layout();
+    let existing_boot = layout.boot_partition(boot).map(|p| p.number);
+    let sizes = config.resolve_sizes(region.size_mib(), existing_boot.is_some())?;
+
+    let plan = PartitionPlan::new(config, &sizes, region.start_mib);
+    plan.apply()?;
+
+    // Match the new partitions back to their numbers by start offset
+    let table = read_table(&config.disk)?;
+    plan.verify(&table)?;
+    let updated = layout_from_table(&table);
+    let number_of = |role| {
+        plan.start_of(role)
+            .ok_or_else(|| SetupError::System("Partition missing from plan".to_string()))
+            .and_then(|start| partition_number_at(&updated, start))
     };
-    
-    Ok(())
+    let boot_number = match (existing_boot, boot) {
+        (Some(number), _) => Some(number),
+        (None, BootLayout::None) => None,
+        (None, BootLayout::Esp) => Some(number_of(PartitionRole::Esp)?),
+        (None, BootLayout::BiosGrub) => Some(number_of(PartitionRole::BiosGrub)?),
+    };
+    let swap_number = number_of(PartitionRole::Swap)?;
+    let root_number = number_of(PartitionRole::Root)?;
+
+    PartitionNumbers {
+        boot: boot_number,
+        swap: swap_number,
+        root: root_number,
+        format_boot: existing_boot.is_none() && boot == BootLayout::Esp,
+    }
+    .settle(&config.disk, config.settle_timeout)
+}
+
+/// Number of the partition starting at `start_mib`
+fn partition_number_at(layout: &DiskLayout, start_mib: u64) -> CommandResult<u32> {
+    layout.partitions
+        .iter()
+        .find(|p| p.start_mib == start_mib)
+        .map(|p| p.number)
+        .ok_or_else(|| SetupError::System(format!("No partition found at {} MiB", start_mib)))
+}
+
+/// Filesystems to create on the new partitions, each with a fresh UUID.
+/// A reused ESP is listed unformatted; bios_grub holds no filesystem.
+pub fn format_plan(config: &PartitionConfig, partitions: &InstallPartitions) -> CommandResult<Vec<Volume>> {
+    format_plan_in(Path::new("/"), config, partitions)
+}
+
+/// Plan like `format_plan`, looking up the UUID of a reused ESP under `root`
+pub fn format_plan_in(root: &Path, config: &PartitionConfig, partitions: &InstallPartitions) -> CommandResult<Vec<Volume>> {
+    let mut volumes = Vec::new();
+
+    if let (BootLayout::Esp, Some(boot)) = (config.boot_layout(), &partitions.boot) {
+        volumes.push(if partitions.format_boot {
+            let format = FormatSpec::new(Filesystem::Vfat, ESP_LABEL).with_uuid(random_uuid()?);
+            Volume { role: PartitionRole::Esp, device: boot.clone(), format, formatted: true, subvolume: None }
+        } else {
+            // A reused ESP keeps whatever label and UUID it already has;
+            // the UUID lets fstab find it whatever order disks appear in
+            let uuid = reinstall::uuid_of_in(root, boot).ok_or_else(|| {
+                SetupError::System(format!("Cannot find the filesystem UUID of the ESP on {}", boot))
+            })?;
+            let format = FormatSpec::new(Filesystem::Vfat, "").with_uuid(uuid);
+            Volume { role: PartitionRole::Esp, device: boot.clone(), format, formatted: false, subvolume: None }
+        });
+    }
+
+    volumes.push(Volume {
+        role: PartitionRole::Swap,
+        device: partitions.swap.clone(),
+        format: FormatSpec::new(Filesystem::Swap, SWAP_LABEL).with_uuid(random_uuid()?),
+        formatted: true,
+        subvolume: None,
+    });
+
+    let mut root = FormatSpec::new(config.filesystem, ROOT_LABEL);
+    if config.filesystem.supports_uuid() {
+        root = root.with_uuid(random_uuid()?);
+    }
+    volumes.push(Volume { role: PartitionRole::Root, device: partitions.root.clone(), format: root, formatted: true, subvolume: None });
+
+    Ok(volumes)
+}
+
+/// Format created partitions
+fn format_partitions(config: &PartitionConfig, partitions: &InstallPartitions) -> CommandResult<Vec<Volume>> {
+    let volumes = format_plan(config, partitions)?;
+    for volume in volumes.iter().filter(|v| v.formatted) {
+        volume.format.format(&volume.device)?;
+    }
+    Ok(volumes)
 }
 
 /// Get partition information after creation
@@ -177,14 +1136,14 @@ mod tests {
             512,
             2048,
             true,
-            "ext4".to_string()
+            Filesystem::Ext4
         );
 
         assert_eq!(config.disk, "/dev/sda");
-        assert_eq!(config.boot_size_mb, 512);
-        assert_eq!(config.swap_size_mb, 2048);
+        assert_eq!(config.boot_size, SizeSpec::mib(512));
+        assert_eq!(config.swap_size, SizeSpec::mib(2048));
         assert!(config.use_gpt);
-        assert_eq!(config.filesystem, "ext4");
+        assert_eq!(config.filesystem, Filesystem::Ext4);
     }
 
     #[test]
@@ -219,17 +1178,17 @@ mod tests {
         // Test creating config object (before validation)
         let config = PartitionConfig::new(
             parts[0].to_string(),
-            parts[1].parse().unwrap(),
-            parts[2].parse().unwrap(),
+            parts[1].parse::<u32>().unwrap(),
+            parts[2].parse::<u32>().unwrap(),
             parts[3] == "gpt",
-            parts[4].to_string(),
+            parts[4].parse::<Filesystem>().unwrap(),
         );
         
         assert_eq!(config.disk, "/dev/sdz999");
-        assert_eq!(config.boot_size_mb, 512);
-        assert_eq!(config.swap_size_mb, 2048);
+        assert_eq!(config.boot_size, SizeSpec::mib(512));
+        assert_eq!(config.swap_size, SizeSpec::mib(2048));
         assert!(config.use_gpt);
-        assert_eq!(config.filesystem, "ext4");
+        assert_eq!(config.filesystem, Filesystem::Ext4);
     }
 
     #[test]
@@ -247,7 +1206,7 @@ mod tests {
             50, // Too small
             2048,
             true,
-            "ext4".to_string()
+            Filesystem::Ext4
         );
 
         assert!(config.validate().is_err());
@@ -255,12 +1214,14 @@ mod tests {
 
     #[test]
     fn test_validate_config_invalid_filesystem() {
+        assert!("invalid".parse::<Filesystem>().is_err());
+
         let config = PartitionConfig::new(
             "/dev/sda".to_string(),
             512,
             2048,
             true,
-            "invalid".to_string()
+            Filesystem::Vfat
         );
 
         assert!(config.validate().is_err());
@@ -273,11 +1234,11 @@ mod tests {
             512,
             2048,
             true,
-            "ext4".to_string()
+            Filesystem::Ext4
         );
 
         let (boot, swap, root) = config.get_partition_names();
-        assert_eq!(boot, "/dev/nvme0n1p1");
+        assert_eq!(boot.as_deref(), Some("/dev/nvme0n1p1"));
         assert_eq!(swap, "/dev/nvme0n1p2");
         assert_eq!(root, "/dev/nvme0n1p3");
     }
@@ -289,12 +1250,45 @@ mod tests {
             512,
             2048,
             true,
-            "ext4".to_string()
+            Filesystem::Ext4
         );
 
         let (boot, swap, root) = config.get_partition_names();
-        assert_eq!(boot, "/dev/sda1");
+        assert_eq!(boot.as_deref(), Some("/dev/sda1"));
         assert_eq!(swap, "/dev/sda2");
         assert_eq!(root, "/dev/sda3");
     }
+
+    #[test]
+    fn test_required_free_mib_reuses_esp() {
+        let config = PartitionConfig::new(
+            "/dev/sda".to_string(),
+            512,
+            2048,
+            true,
+            Filesystem::Ext4
+        ).with_firmware(FirmwareMode::Uefi { bits: 64 });
+
+        assert_eq!(config.required_free_mib(true), 2048 + MIN_ROOT_SIZE_MB);
+        assert_eq!(config.required_free_mib(false), 512 + 2048 + MIN_ROOT_SIZE_MB);
+    }
+
+    #[test]
+    fn test_boot_layout_follows_firmware() {
+        let config = PartitionConfig::new(
+            "/dev/sda".to_string(),
+            512,
+            2048,
+            true,
+            Filesystem::Ext4
+        );
+
+        let uefi = config.clone().with_firmware(FirmwareMode::Uefi { bits: 64 });
+        assert_eq!(uefi.boot_layout(), BootLayout::Esp);
+        assert!(uefi.firmware_warnings().is_empty());
+
+        let bios = config.with_firmware(FirmwareMode::Bios);
+        assert_eq!(bios.boot_layout(), BootLayout::BiosGrub);
+        assert_eq!(bios.boot_partition_mib(), BIOS_GRUB_SIZE_MB);
+    }
 }
@@ -0,0 +1,588 @@
+use crate::common::{CommandResult, SetupError};
+use crate::disk::{block_name_in, logical_block_size_in};
+use crate::size::MIB;
+use std::fmt;
+use std::fs::File;
+use std::io::{Read, Seek, SeekFrom};
+use std::os::unix::fs::FileTypeExt;
+use std::path::Path;
+use std::str::FromStr;
+
+/// Sector sizes tried when reading an image whose sector size is unknown
+pub const SECTOR_SIZES: [u64; 2] = [512, 4096];
+
+const GPT_SIGNATURE: &[u8; 8] = b"EFI PART";
+const MBR_PROTECTIVE: u8 = 0xee;
+const MBR_EXTENDED: [u8; 3] = [0x05, 0x0f, 0x85];
+
+pub const GUID_ESP: &str = "C12A7328-F81F-11D2-BA4B-00A0C93EC93B";
+pub const GUID_BIOS_BOOT: &str = "21686148-6449-6E6F-744E-656564454649";
+pub const GUID_LINUX_SWAP: &str = "0657FD6D-A4AB-43C4-84E5-0933C84B4F4F";
+pub const GUID_LINUX_FS: &str = "0FC63DAF-8483-4772-8E79-3D69D8477DE4";
+pub const GUID_LINUX_ROOT_X86_64: &str = "4F68BCE3-E8CD-4DB1-96E7-FBCAF984B709";
+pub const GUID_LINUX_HOME: &str = "933AC7E1-2EB4-4F13-B844-0E14E2AEF915";
+pub const GUID_LINUX_RAID: &str = "A19D880F-05FC-4D3B-A006-743F0F84911E";
+pub const GUID_MS_BASIC_DATA: &str = "EBD0A0A2-B9E5-4433-87C0-68B6B72699C7";
+pub const GUID_MS_RESERVED: &str = "E3C9E316-0B5C-4DB8-817D-F92DF00215AE";
+pub const GUID_MS_RECOVERY: &str = "DE94BBA4-06D1-4D40-A16A-BFD50179D6AC";
+pub const GUID_APPLE_APFS: &str = "7C3457EF-0000-11AA-AA11-00306543ECAC";
+
+/// CRC-32 (IEEE 802.3) as used by GPT headers and entry arrays
+pub fn crc32(data: &[u8]) -> u32 {
+    let mut crc = 0xffff_ffffu32;
+    for byte in data {
+        crc ^= u32::from(*byte);
+        for _ in 0..8 {
+            let mask = (crc & 1).wrapping_neg();
+            crc = (crc >> 1) ^ (0xedb8_8320 & mask);
+        }
+    }
+    !crc
+}
+
+/// GPT GUID in its on-disk mixed-endian byte order
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Default)]
+pub struct Guid(pub [u8; 16]);
+
+impl Guid {
+    pub fn is_zero(&self) -> bool {
+        self.0 == [0; 16]
+    }
+}
+
+impl FromStr for Guid {
+    type Err = SetupError;
+
+    fn from_str(s: &str) -> CommandResult<Self> {
+        let hex: String = s.chars().filter(|c| *c != '-').collect();
+        if hex.len() != 32 || s.len() != 36 {
+            return Err(SetupError::InvalidInput(format!("Invalid GUID '{}'", s)));
+        }
+
+        let mut text = [0u8; 16];
+        for (i, byte) in text.iter_mut().enumerate() {
+            *byte = u8::from_str_radix(&hex[i * 2..i * 2 + 2], 16)
+                .map_err(|_| SetupError::InvalidInput(format!("Invalid GUID '{}'", s)))?;
+        }
+
+        // The first three fields are stored little-endian
+        let mut bytes = text;
+        bytes[0..4].reverse();
+        bytes[4..6].reverse();
+        bytes[6..8].reverse();
+        Ok(Guid(bytes))
+    }
+}
+
+impl fmt::Display for Guid {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let b = &self.0;
+        write!(
+            f,
+            "{:02X}{:02X}{:02X}{:02X}-{:02X}{:02X}-{:02X}{:02X}-{:02X}{:02X}-{:02X}{:02X}{:02X}{:02X}{:02X}{:02X}",
+            b[3], b[2], b[1], b[0], b[5], b[4], b[7], b[6],
+            b[8], b[9], b[10], b[11], b[12], b[13], b[14], b[15]
+        )
+    }
+}
+
+/// Partition from an MBR, including logical partitions of an extended one
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct MbrEntry {
+    /// 1-4 for primary partitions, 5 and up for logical ones
+    pub number: u32,
+    pub bootable: bool,
+    pub type_id: u8,
+    pub first_lba: u64,
+    pub sectors: u64,
+}
+
+/// GPT header fields, from either the primary or the backup copy
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct GptHeader {
+    pub current_lba: u64,
+    pub backup_lba: u64,
+    pub first_usable_lba: u64,
+    pub last_usable_lba: u64,
+    pub disk_guid: Guid,
+    pub entries_lba: u64,
+    pub entry_count: u32,
+    pub entry_size: u32,
+    pub entries_crc: u32,
+}
+
+/// Used entry of a GPT partition array
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct GptEntry {
+    /// 1-based index in the entry array, as the kernel numbers partitions
+    pub number: u32,
+    pub type_guid: Guid,
+    pub unique_guid: Guid,
+    pub first_lba: u64,
+    pub last_lba: u64,
+    pub attributes: u64,
+    pub name: String,
+}
+
+impl GptEntry {
+    /// Attribute bit 2, which legacy BIOS bootloaders look for
+    pub fn legacy_bootable(&self) -> bool {
+        self.attributes & (1 << 2) != 0
+    }
+}
+
+/// Which GPT header copies passed their checks
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct GptHealth {
+    pub protective_mbr: bool,
+    pub primary_valid: bool,
+    pub backup_valid: bool,
+}
+
+/// Partition table read from a disk or image
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum PartitionTable {
+    Mbr {
+        sector_size: u64,
+        total_sectors: u64,
+        entries: Vec<MbrEntry>,
+    },
+    Gpt {
+        sector_size: u64,
+        total_sectors: u64,
+        header: GptHeader,
+        entries: Vec<GptEntry>,
+        health: GptHealth,
+    },
+}
+
+/// Partition reduced to what the installer needs from either table type
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct TablePartition {
+    pub number: u32,
+    pub first_lba: u64,
+    /// Inclusive
+    pub last_lba: u64,
+    /// GPT type GUID or two-digit MBR type
+    pub type_code: String,
+    pub name: String,
+    pub flags: Vec<String>,
+}
+
+impl PartitionTable {
+    pub fn sector_size(&self) -> u64 {
+        match self {
+            PartitionTable::Mbr { sector_size, .. } | PartitionTable::Gpt { sector_size, .. } => *sector_size,
+        }
+    }
+
+    pub fn total_sectors(&self) -> u64 {
+        match self {
+            PartitionTable::Mbr { total_sectors, .. } | PartitionTable::Gpt { total_sectors, .. } => *total_sectors,
+        }
+    }
+
+    /// Table type using parted's names, "gpt" or "msdos"
+    pub fn label(&self) -> &'static str {
+        match self {
+            PartitionTable::Mbr { .. } => "msdos",
+            PartitionTable::Gpt { .. } => "gpt",
+        }
+    }
+
+    /// All partitions in on-disk order, with flags named like parted's
+    pub fn partitions(&self) -> Vec<TablePartition> {
+        let mut partitions: Vec<TablePartition> = match self {
+            PartitionTable::Mbr { entries, .. } => entries
+                .iter()
+                .filter(|e| !MBR_EXTENDED.contains(&e.type_id))
+                .map(|e| {
+                    let mut flags = Vec::new();
+                    if e.bootable {
+                        flags.push("boot".to_string());
+                    }
+                    match e.type_id {
+                        0xef => flags.push("esp".to_string()),
+                        0x82 => flags.push("swap".to_string()),
+                        0xfd => flags.push("raid".to_string()),
+                        _ => {}
+                    }
+                    TablePartition {
+                        number: e.number,
+                        first_lba: e.first_lba,
+                        last_lba: e.first_lba + e.sectors - 1,
+                        type_code: format!("{:02x}", e.type_id),
+                        name: String::new(),
+                        flags,
+                    }
+                })
+                .collect(),
+            PartitionTable::Gpt { entries, .. } => entries
+                .iter()
+                .map(|e| {
+                    let type_code = e.type_guid.to_string();
+                    let mut flags = Vec::new();
+                    match type_code.as_str() {
+                        GUID_ESP => flags.extend(["boot".to_string(), "esp".to_string()]),
+                        GUID_BIOS_BOOT => flags.push("bios_grub".to_string()),
+                        GUID_LINUX_SWAP => flags.push("swap".to_string()),
+                        GUID_LINUX_RAID => flags.push("raid".to_string()),
+                        GUID_MS_BASIC_DATA => flags.push("msftdata".to_string()),
+                        GUID_MS_RESERVED => flags.push("msftres".to_string()),
+                        _ => {}
+                    }
+                    if e.legacy_bootable() {
+                        flags.push("legacy_boot".to_string());
+                    }
+                    TablePartition {
+                        number: e.number,
+                        first_lba: e.first_lba,
+                        last_lba: e.last_lba,
+                        type_code,
+                        name: e.name.clone(),
+                        flags,
+                    }
+                })
+                .collect(),
+        };
+        partitions.sort_by_key(|p| p.first_lba);
+        partitions
+    }
+
+    /// First and last LBA partitions may occupy
+    pub fn usable_range(&self) -> (u64, u64) {
+        match self {
+            PartitionTable::Mbr { total_sectors, .. } => (1, total_sectors.saturating_sub(1)),
+            PartitionTable::Gpt { header, .. } => (header.first_usable_lba, header.last_usable_lba),
+        }
+    }
+
+    /// Unallocated space in bytes as (start, end) pairs, end exclusive
+    pub fn free_ranges(&self) -> Vec<(u64, u64)> {
+        let sector_size = self.sector_size();
+        let (first, last) = self.usable_range();
+        let mut ranges = Vec::new();
+        let mut cursor = first;
+
+        for part in self.partitions() {
+            if part.first_lba > cursor {
+                ranges.push((cursor * sector_size, part.first_lba * sector_size));
+            }
+            cursor = cursor.max(part.last_lba + 1);
+        }
+        if last + 1 > cursor {
+            ranges.push((cursor * sector_size, (last + 1) * sector_size));
+        }
+
+        // Logical partitions live inside the extended one, which is not
+        // free; space before and after it still is
+        if let PartitionTable::Mbr { entries, .. } = self {
+            for ext in entries.iter().filter(|e| MBR_EXTENDED.contains(&e.type_id)) {
+                let (ext_start, ext_end) = (ext.first_lba * sector_size, (ext.first_lba + ext.sectors) * sector_size);
+                ranges = ranges
+                    .into_iter()
+                    .flat_map(|(start, end)| [(start, end.min(ext_start)), (start.max(ext_end), end)])
+                    .filter(|(start, end)| start < end)
+                    .collect();
+            }
+        }
+
+        ranges
+    }
+
+    /// Operating systems recognised from the partition types present
+    pub fn detected_systems(&self) -> Vec<&'static str> {
+        let partitions = self.partitions();
+        let has = |codes: &[&str]| partitions.iter().any(|p| codes.contains(&p.type_code.as_str()));
+
+        let mut systems = Vec::new();
+        if has(&[GUID_MS_BASIC_DATA, GUID_MS_RESERVED, GUID_MS_RECOVERY, "07", "0b", "0c", "27"]) {
+            systems.push("Windows");
+        }
+        if has(&[GUID_LINUX_FS, GUID_LINUX_ROOT_X86_64, GUID_LINUX_HOME, GUID_LINUX_RAID, "83", "fd"]) {
+            systems.push("Linux");
+        }
+        if has(&[GUID_APPLE_APFS, "af"]) {
+            systems.push("macOS");
+        }
+        systems
+    }
+}
+
+/// Read `len` bytes at byte `offset`
+fn read_at<R: Read + Seek>(dev: &mut R, offset: u64, len: usize) -> CommandResult<Vec<u8>> {
+    let mut buf = vec![0u8; len];
+    dev.seek(SeekFrom::Start(offset))?;
+    dev.read_exact(&mut buf)?;
+    Ok(buf)
+}
+
+fn le_u32(buf: &[u8], at: usize) -> u32 {
+    u32::from_le_bytes(buf[at..at + 4].try_into().unwrap())
+}
+
+fn le_u64(buf: &[u8], at: usize) -> u64 {
+    u64::from_le_bytes(buf[at..at + 8].try_into().unwrap())
+}
+
+fn guid_at(buf: &[u8], at: usize) -> Guid {
+    Guid(buf[at..at + 16].try_into().unwrap())
+}
+
+/// The four primary entries of an MBR sector, skipping empty slots
+fn parse_mbr_entries(sector: &[u8]) -> Vec<MbrEntry> {
+    (0..4)
+        .filter_map(|i| {
+            let e = &sector[446 + i * 16..446 + (i + 1) * 16];
+            let type_id = e[4];
+            let sectors = u64::from(le_u32(e, 12));
+            (type_id != 0 && sectors != 0).then(|| MbrEntry {
+                number: i as u32 + 1,
+                bootable: e[0] == 0x80,
+                type_id,
+                first_lba: u64::from(le_u32(e, 8)),
+                sectors,
+            })
+        })
+        .collect()
+}
+
+/// Follow the EBR chain of an extended partition
+fn read_logical_partitions<R: Read + Seek>(
+    dev: &mut R,
+    sector_size: u64,
+    extended_lba: u64,
+) -> CommandResult<Vec<MbrEntry>> {
+    let mut logical = Vec::new();
+    let mut ebr_lba = extended_lba;
+
+    // Bound the walk so a looping chain cannot hang the installer
+    for number in 5..=128u32 {
+        let sector = read_at(dev, ebr_lba * sector_size, 512)?;
+        if sector[510..512] != [0x55, 0xaa] {
+            break;
+        }
+
+        let entries = parse_mbr_entries(&sector);
+        if let Some(first) = entries.first() {
+            logical.push(MbrEntry {
+                number,
+                first_lba: ebr_lba + first.first_lba,
+                ..first.clone()
+            });
+        }
+        match entries.get(1) {
+            Some(next) if MBR_EXTENDED.contains(&next.type_id) => ebr_lba = extended_lba + next.first_lba,
+            _ => break,
+        }
+    }
+
+    Ok(logical)
+}
+
+/// Read and check a GPT header and its entry array at `lba` of a disk with
+/// `total_sectors`. Headers are taken as corrupt when their fields cannot
+/// describe a table on this disk, CRC or not.
+fn read_gpt_at<R: Read + Seek>(
+    dev: &mut R,
+    sector_size: u64,
+    total_sectors: u64,
+    lba: u64,
+) -> CommandResult<Option<(GptHeader, Vec<GptEntry>)>> {
+    let Some(offset) = lba.checked_mul(sector_size) else {
+        return Ok(None);
+    };
+    let buf = read_at(dev, offset, sector_size as usize)?;
+    if &buf[0..8] != GPT_SIGNATURE {
+        return Ok(None);
+    }
+
+    let header_size = le_u32(&buf, 12) as usize;
+    if !(92..=sector_size as usize).contains(&header_size) {
+        return Ok(None);
+    }
+    let mut header_bytes = buf[..header_size].to_vec();
+    header_bytes[16..20].fill(0);
+    if crc32(&header_bytes) != le_u32(&buf, 16) {
+        return Ok(None);
+    }
+
+    let header = GptHeader {
+        current_lba: le_u64(&buf, 24),
+        backup_lba: le_u64(&buf, 32),
+        first_usable_lba: le_u64(&buf, 40),
+        last_usable_lba: le_u64(&buf, 48),
+        disk_guid: guid_at(&buf, 56),
+        entries_lba: le_u64(&buf, 72),
+        entry_count: le_u32(&buf, 80),
+        entry_size: le_u32(&buf, 84),
+        entries_crc: le_u32(&buf, 88),
+    };
+    // Entries are multiples of 128 bytes and never span sectors, which
+    // also bounds the array read below to 1024 sectors
+    let entry_size = u64::from(header.entry_size);
+    if header.current_lba != lba
+        || entry_size < 128
+        || entry_size % 128 != 0
+        || entry_size > sector_size
+        || header.entry_count > 1024
+    {
+        return Ok(None);
+    }
+
+    let array_len = u64::from(header.entry_count) * entry_size;
+    let array_end = header
+        .entries_lba
+        .checked_mul(sector_size)
+        .and_then(|start| start.checked_add(array_len).map(|end| (start, end)));
+    let Some((array_start, array_end)) = array_end else {
+        return Ok(None);
+    };
+    if array_end > total_sectors.saturating_mul(sector_size) {
+        return Ok(None);
+    }
+    let array = read_at(dev, array_start, array_len as usize)?;
+    if crc32(&array) != header.entries_crc {
+        return Ok(None);
+    }
+
+    let entries = array
+        .chunks(header.entry_size as usize)
+        .enumerate()
+        .filter_map(|(i, e)| {
+            let type_guid = guid_at(e, 0);
+            if type_guid.is_zero() {
+                return None;
+            }
+            let name_units: Vec<u16> = e[56..128]
+                .chunks(2)
+                .map(|c| u16::from_le_bytes([c[0], c[1]]))
+                .take_while(|u| *u != 0)
+                .collect();
+            Some(GptEntry {
+                number: i as u32 + 1,
+                type_guid,
+                unique_guid: guid_at(e, 16),
+                first_lba: le_u64(e, 32),
+                last_lba: le_u64(e, 40),
+                attributes: le_u64(e, 48),
+                name: String::from_utf16_lossy(&name_units),
+            })
+        })
+        .collect::<Vec<GptEntry>>();
+
+    // Entries must not end before they start, nor beyond byte offsets a
+    // u64 holds
+    let end_overflows = |e: &GptEntry| e.last_lba.checked_add(1).and_then(|n| n.checked_mul(sector_size)).is_none();
+    if entries.iter().any(|e| e.last_lba < e.first_lba || end_overflows(e)) {
+        return Ok(None);
+    }
+
+    Ok(Some((header, entries)))
+}
+
+/// Read the partition table of a disk or image of `total_bytes`.
+/// Returns `None` when there is no MBR signature at all.
+pub fn read_partition_table<R: Read + Seek>(
+    dev: &mut R,
+    sector_size: u64,
+    total_bytes: u64,
+) -> CommandResult<Option<PartitionTable>> {
+    let total_sectors = total_bytes / sector_size;
+    if total_sectors < 2 {
+        return Ok(None);
+    }
+
+    let mbr = read_at(dev, 0, 512)?;
+    if mbr[510..512] != [0x55, 0xaa] {
+        return Ok(None);
+    }
+    let primary = parse_mbr_entries(&mbr);
+
+    if !primary.iter().any(|e| e.type_id == MBR_PROTECTIVE) {
+        let mut entries = primary.clone();
+        for ext in primary.iter().filter(|e| MBR_EXTENDED.contains(&e.type_id)) {
+            entries.extend(read_logical_partitions(dev, sector_size, ext.first_lba)?);
+        }
+        return Ok(Some(PartitionTable::Mbr { sector_size, total_sectors, entries }));
+    }
+
+    let primary_gpt = read_gpt_at(dev, sector_size, total_sectors, 1)?;
+    let backup_lba = primary_gpt
+        .as_ref()
+        .map(|(h, _)| h.backup_lba)
+        .unwrap_or(total_sectors - 1);
+    let backup_gpt = if backup_lba < total_sectors {
+        read_gpt_at(dev, sector_size, total_sectors, backup_lba)?
+    } else {
+        None
+    };
+
+    let health = GptHealth {
+        protective_mbr: true,
+        primary_valid: primary_gpt.is_some(),
+        backup_valid: backup_gpt.is_some(),
+    };
+
+    match primary_gpt.or(backup_gpt) {
+        Some((header, entries)) => Ok(Some(PartitionTable::Gpt {
+            sector_size,
+            total_sectors,
+            header,
+            entries,
+            health,
+        })),
+        None => Err(SetupError::System(
+            "Protective MBR found but both GPT headers are corrupt".to_string()
+        )),
+    }
+}
+
+/// Read the partition table of a file or block device. Block devices use
+/// the logical sector size the kernel reports, since an MBR does not record
+/// it; images are tried with the common sector sizes in turn.
+pub fn read_partition_table_from(path: &Path) -> CommandResult<Option<PartitionTable>> {
+    let mut file = File::open(path)?;
+    let total_bytes = file.seek(SeekFrom::End(0))?;
+
+    if file.metadata()?.file_type().is_block_device() {
+        let root = Path::new("/");
+        if let Some(sector_size) = logical_block_size_in(root, &block_name_in(root, &path.to_string_lossy())) {
+            return read_partition_table(&mut file, sector_size, total_bytes);
+        }
+    }
+
+    let mut fallback = None;
+    for sector_size in SECTOR_SIZES {
+        match read_partition_table(&mut file, sector_size, total_bytes) {
+            Ok(Some(table @ PartitionTable::Gpt { .. })) => return Ok(Some(table)),
+            // A protective MBR read with the wrong sector size looks corrupt
+            Err(e) => fallback = fallback.or(Some(Err(e))),
+            Ok(other) => fallback = fallback.or(Some(Ok(other))),
+        }
+    }
+
+    fallback.unwrap_or(Ok(None))
+}
+
+/// Byte offset rounded to whole MiB, up or down
+pub fn to_mib(bytes: u64, round_up: bool) -> u64 {
+    if round_up { bytes.div_ceil(MIB) } else { bytes / MIB }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_crc32_known_value() {
+        assert_eq!(crc32(b"123456789"), 0xcbf4_3926);
+    }
+
+    #[test]
+    fn test_guid_round_trip() {
+        let guid: Guid = GUID_ESP.parse().unwrap();
+        assert_eq!(guid.0[0], 0x28);
+        assert_eq!(guid.to_string(), GUID_ESP);
+    }
+}
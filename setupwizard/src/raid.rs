@@ -0,0 +1,378 @@
+use crate::common::{run_command, CommandResult, SetupError};
+use crate::disk::{check_target_disk, disk_size_bytes};
+use crate::filesystem::{Filesystem, FormatSpec};
+use crate::partition::{
+    create_home_partition, create_partitions_sized, format_plan, usable_disk_mib, BootLayout, InstallMode,
+    InstallPartitions, PartitionConfig, PartitionRole, PartitionSizes, TableBackup, Volume,
+};
+use crate::smart::check_disk_health;
+use crate::target::Target;
+use crate::wipe::{plan_wipe, WipeMode};
+use std::fmt;
+use std::str::FromStr;
+
+/// md array names; mdadm creates them as /dev/md/<name>
+pub const ESP_ARRAY: &str = "asenos-esp";
+pub const SWAP_ARRAY: &str = "asenos-swap";
+pub const ROOT_ARRAY: &str = "asenos-root";
+
+/// How root is made redundant across the disks
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum RaidLevel {
+    /// mdadm RAID1 mirror
+    #[default]
+    Raid1,
+    /// mdadm RAID10, striped mirrors
+    Raid10,
+    /// btrfs raid1 profile for data and metadata, without md for root
+    BtrfsRaid1,
+}
+
+impl RaidLevel {
+    pub const ALL: [RaidLevel; 3] = [RaidLevel::Raid1, RaidLevel::Raid10, RaidLevel::BtrfsRaid1];
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            RaidLevel::Raid1 => "raid1",
+            RaidLevel::Raid10 => "raid10",
+            RaidLevel::BtrfsRaid1 => "btrfs-raid1",
+        }
+    }
+
+    /// Fewest disks the level is worth using with
+    pub fn min_disks(&self) -> usize {
+        match self {
+            RaidLevel::Raid10 => 4,
+            RaidLevel::Raid1 | RaidLevel::BtrfsRaid1 => 2,
+        }
+    }
+
+    /// mdadm level of the swap array, and of the root array unless btrfs
+    /// mirrors root itself
+    pub fn md_level(&self) -> &'static str {
+        match self {
+            RaidLevel::Raid10 => "10",
+            RaidLevel::Raid1 | RaidLevel::BtrfsRaid1 => "1",
+        }
+    }
+}
+
+impl FromStr for RaidLevel {
+    type Err = SetupError;
+
+    fn from_str(s: &str) -> CommandResult<Self> {
+        RaidLevel::ALL
+            .into_iter()
+            .find(|level| level.name().eq_ignore_ascii_case(s.trim()))
+            .ok_or_else(|| {
+                SetupError::InvalidInput(format!(
+                    "Unknown RAID level '{}', expected one of: {}",
+                    s,
+                    RaidLevel::ALL.map(|l| l.name()).join(", ")
+                ))
+            })
+    }
+}
+
+impl fmt::Display for RaidLevel {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.name())
+    }
+}
+
+/// md array to create from one partition of every disk
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct MdArray {
+    pub name: &'static str,
+    pub level: &'static str,
+    /// Superblock version. 1.0 sits at the end of each member, so firmware
+    /// reads a mirrored ESP as a plain FAT partition.
+    pub metadata: &'static str,
+    pub members: Vec<String>,
+}
+
+impl MdArray {
+    pub fn device(&self) -> String {
+        format!("/dev/md/{}", self.name)
+    }
+
+    /// mdadm command creating the array. The homehost "any" lets the
+    /// installed system assemble it under the same name as the live ISO.
+    pub fn create_command(&self) -> Vec<String> {
+        let mut command: Vec<String> = vec![
+            "mdadm".to_string(),
+            "--create".to_string(),
+            self.device(),
+            "--run".to_string(),
+            "--homehost=any".to_string(),
+            format!("--level={}", self.level),
+            format!("--metadata={}", self.metadata),
+            format!("--raid-devices={}", self.members.len()),
+        ];
+        command.extend(self.members.iter().cloned());
+        command
+    }
+
+    pub fn create(&self) -> CommandResult<()> {
+        let command = self.create_command();
+        let args: Vec<&str> = command.iter().map(String::as_str).collect();
+        run_command(&args, None)?;
+        Ok(())
+    }
+}
+
+/// A whole-disk install spread over several identical disks
+#[derive(Debug, Clone)]
+pub struct RaidConfig {
+    /// Layout of every member; its disk is the first member
+    pub base: PartitionConfig,
+    /// All member disks, starting with `base.disk`
+    pub disks: Vec<String>,
+    pub level: RaidLevel,
+    /// Disk that holds /home on its own, outside the array
+    pub home_disk: Option<String>,
+}
+
+impl RaidConfig {
+    /// Mirror the layout of `base` onto `others`
+    pub fn new(base: PartitionConfig, others: Vec<String>, level: RaidLevel) -> Self {
+        let disks = std::iter::once(base.disk.clone()).chain(others).collect();
+        Self { base, disks, level, home_disk: None }
+    }
+
+    pub fn with_home_disk(mut self, disk: Option<String>) -> Self {
+        self.home_disk = disk;
+        self
+    }
+
+    /// Partition config of the member `disk`. ESPs keep their type even
+    /// when mirrored, since firmware looks for them by type.
+    pub fn member_config(&self, disk: &str) -> PartitionConfig {
+        let mut roles = vec![PartitionRole::Swap];
+        if self.level != RaidLevel::BtrfsRaid1 {
+            roles.push(PartitionRole::Root);
+        }
+
+        let mut config = self.base.clone().with_md_members(roles);
+        config.disk = disk.to_string();
+        config
+    }
+
+    pub fn validate(&self) -> CommandResult<()> {
+        if self.disks.len() < self.level.min_disks() {
+            return Err(SetupError::InvalidInput(format!(
+                "{} needs at least {} disks, got {}",
+                self.level,
+                self.level.min_disks(),
+                self.disks.len()
+            )));
+        }
+        for (i, disk) in self.disks.iter().enumerate() {
+            if self.disks[..i].contains(disk) {
+                return Err(SetupError::InvalidInput(format!("Disk {} is listed twice", disk)));
+            }
+        }
+        if self.base.mode != InstallMode::WipeDisk {
+            return Err(SetupError::InvalidInput("RAID installs need whole disks".to_string()));
+        }
+        if self.level == RaidLevel::BtrfsRaid1 && self.base.filesystem != Filesystem::Btrfs {
+            return Err(SetupError::InvalidInput(format!(
+                "{} needs a btrfs root, not {}",
+                self.level, self.base.filesystem
+            )));
+        }
+
+        for disk in &self.disks {
+            self.member_config(disk).validate()?;
+        }
+
+        if let Some(home) = &self.home_disk {
+            if self.disks.contains(home) {
+                return Err(SetupError::InvalidInput(format!(
+                    "Home disk {} is also a RAID member", home
+                )));
+            }
+            check_target_disk(home)?;
+        }
+
+        Ok(())
+    }
+
+    /// Sizes for every member, resolved against the smallest disk
+    pub fn sizes(&self) -> CommandResult<PartitionSizes> {
+        let mut smallest = u64::MAX;
+        for disk in &self.disks {
+            smallest = smallest.min(usable_disk_mib(disk_size_bytes(disk)?));
+        }
+        self.base.resolve_sizes(smallest, false)
+    }
+
+    /// Arrays to build from the partitions of every member
+    pub fn arrays(&self, members: &[InstallPartitions]) -> Vec<MdArray> {
+        let mut arrays = Vec::new();
+
+        let esps: Vec<String> = members.iter().filter_map(|m| m.boot.clone()).collect();
+        if self.base.boot_layout() == BootLayout::Esp && !esps.is_empty() {
+            arrays.push(MdArray { name: ESP_ARRAY, level: "1", metadata: "1.0", members: esps });
+        }
+        arrays.push(MdArray {
+            name: SWAP_ARRAY,
+            level: self.level.md_level(),
+            metadata: "1.2",
+            members: members.iter().map(|m| m.swap.clone()).collect(),
+        });
+        if self.level != RaidLevel::BtrfsRaid1 {
+            arrays.push(MdArray {
+                name: ROOT_ARRAY,
+                level: self.level.md_level(),
+                metadata: "1.2",
+                members: members.iter().map(|m| m.root.clone()).collect(),
+            });
+        }
+
+        arrays
+    }
+
+    /// Devices the filesystems go on once the arrays exist. A btrfs
+    /// mirror is addressed through its first member.
+    pub fn assembled(&self, members: &[InstallPartitions]) -> InstallPartitions {
+        let device = |name| format!("/dev/md/{}", name);
+        InstallPartitions {
+            boot: (self.base.boot_layout() == BootLayout::Esp).then(|| device(ESP_ARRAY)),
+            swap: device(SWAP_ARRAY),
+            root: match self.level {
+                RaidLevel::BtrfsRaid1 => members[0].root.clone(),
+                _ => device(ROOT_ARRAY),
+            },
+            format_boot: true,
+        }
+    }
+}
+
+/// mkfs.btrfs command mirroring data and metadata across `devices`
+pub fn btrfs_raid1_command(spec: &FormatSpec, devices: &[String]) -> Vec<String> {
+    let mut command = spec.mkfs_command(&devices[0]);
+    command.pop();
+    command.extend(["-d", "raid1", "-m", "raid1"].map(String::from));
+    command.extend(devices.iter().cloned());
+    command
+}
+
+/// Stop the installer's arrays so their members can be repartitioned
+fn stop_arrays() {
+    for name in [ESP_ARRAY, SWAP_ARRAY, ROOT_ARRAY] {
+        let _ = run_command(&["mdadm", "--stop", &format!("/dev/md/{}", name)], None);
+    }
+}
+
+/// Partition every member, build the arrays and create the filesystems.
+/// All partition tables are restored if any step fails.
+pub fn create_raid(config: &RaidConfig) -> CommandResult<Vec<Volume>> {
+    config.validate()?;
+    let members: Vec<PartitionConfig> = config.disks.iter().map(|d| config.member_config(d)).collect();
+
+    for member in &members {
+        check_disk_health(&member.disk, member.allow_failing_disk)?;
+        if member.wipe != WipeMode::None {
+            plan_wipe(&member.disk, member.wipe)?.run()?;
+        }
+    }
+    let backups = members
+        .iter()
+        .map(|m| TableBackup::save(&m.disk))
+        .collect::<CommandResult<Vec<_>>>()?;
+
+    let result = config
+        .sizes()
+        .and_then(|sizes| {
+            members
+                .iter()
+                .map(|m| create_partitions_sized(m, &sizes))
+                .collect::<CommandResult<Vec<_>>>()
+        })
+        .and_then(|partitions| build_arrays(config, &partitions));
+
+    let mut volumes = result.map_err(|e| {
+        stop_arrays();
+        let failed: Vec<String> = backups
+            .iter()
+            .filter_map(|b| b.restore().err().map(|err| format!("{}: {}", b.disk, err)))
+            .collect();
+        if failed.is_empty() {
+            e
+        } else {
+            SetupError::System(format!(
+                "{}; restoring the previous partition tables also failed: {}",
+                e,
+                failed.join(", ")
+            ))
+        }
+    })?;
+
+    if let Some(home) = &config.home_disk {
+        volumes.push(create_home_partition(&config.base, home)?);
+    }
+    Ok(volumes)
+}
+
+fn build_arrays(config: &RaidConfig, partitions: &[InstallPartitions]) -> CommandResult<Vec<Volume>> {
+    for array in config.arrays(partitions) {
+        array.create()?;
+    }
+
+    let volumes = format_plan(&config.base, &config.assembled(partitions))?;
+    for volume in &volumes {
+        if volume.role == PartitionRole::Root && config.level == RaidLevel::BtrfsRaid1 {
+            let roots: Vec<String> = partitions.iter().map(|p| p.root.clone()).collect();
+            let command = btrfs_raid1_command(&volume.format, &roots);
+            let args: Vec<&str> = command.iter().map(String::as_str).collect();
+            run_command(&args, None)?;
+        } else {
+            volume.format.format(&volume.device)?;
+        }
+    }
+    Ok(volumes)
+}
+
+/// mdadm.conf for the target from `mdadm --detail --scan` output
+pub fn mdadm_conf(scan: &str) -> String {
+    let mut conf = String::from("# Written by the Asenos installer\nMAILADDR root\n");
+    for line in scan.lines().filter(|l| l.starts_with("ARRAY ")) {
+        conf.push_str(line);
+        conf.push('\n');
+    }
+    conf
+}
+
+/// Record the arrays in the target and assemble them in the initramfs
+pub fn configure_raid(target: &Target, scan: &str) -> CommandResult<()> {
+    target.write_file("etc/mdadm.conf", &mdadm_conf(scan))?;
+    target.add_initramfs_hook("mdadm_udev", &["filesystems", "fsck"])
+}
+
+/// Current `mdadm --detail --scan` output
+pub fn scan_arrays() -> CommandResult<String> {
+    run_command(&["mdadm", "--detail", "--scan"], None)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_level_round_trip() {
+        for level in RaidLevel::ALL {
+            assert_eq!(level.name().parse::<RaidLevel>().unwrap(), level);
+        }
+        assert!("raid5".parse::<RaidLevel>().is_err());
+    }
+
+    #[test]
+    fn test_mdadm_conf_keeps_array_lines() {
+        let scan = "ARRAY /dev/md/asenos-root metadata=1.2 name=any:asenos-root UUID=1:2:3:4\n";
+        assert_eq!(
+            mdadm_conf(scan),
+            format!("# Written by the Asenos installer\nMAILADDR root\n{}", scan)
+        );
+    }
+}
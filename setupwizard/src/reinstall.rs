@@ -0,0 +1,503 @@
+use crate::common::{run_command, CommandResult, SetupError};
+use crate::disk::{block_name_in, disk_partitions_in, disk_size_bytes_in};
+use crate::filesystem::{random_uuid, Filesystem, FormatSpec, ESP_LABEL, ROOT_LABEL};
+use crate::hibernate;
+use crate::partition::{PartitionConfig, PartitionRole, Volume};
+use crate::size::MIB;
+use crate::target::Target;
+use std::fs;
+use std::ops::RangeInclusive;
+use std::path::Path;
+
+/// UIDs useradd hands out to regular accounts
+pub const USER_UIDS: RangeInclusive<u32> = 1000..=60000;
+
+/// One line of an fstab
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct FstabEntry {
+    pub source: String,
+    pub mount_point: String,
+    pub fstype: String,
+    pub options: Vec<String>,
+}
+
+impl FstabEntry {
+    /// btrfs subvolume from the subvol= option, without the leading slash
+    pub fn subvolume(&self) -> Option<&str> {
+        self.options
+            .iter()
+            .find_map(|o| o.strip_prefix("subvol="))
+            .map(|s| s.trim_start_matches('/'))
+            .filter(|s| !s.is_empty())
+    }
+}
+
+/// Entries of an fstab, skipping comments and malformed lines
+pub fn parse_fstab(contents: &str) -> Vec<FstabEntry> {
+    contents
+        .lines()
+        .map(str::trim)
+        .filter(|line| !line.is_empty() && !line.starts_with('#'))
+        .filter_map(|line| {
+            let fields: Vec<&str> = line.split_whitespace().collect();
+            if fields.len() < 3 {
+                return None;
+            }
+            Some(FstabEntry {
+                source: fields[0].to_string(),
+                mount_point: fields[1].to_string(),
+                fstype: fields[2].to_string(),
+                options: fields.get(3).unwrap_or(&"defaults").split(',').map(str::to_string).collect(),
+            })
+        })
+        .collect()
+}
+
+/// Device node an fstab source such as `UUID=...` refers to, found
+/// through udev's /dev/disk links under `root`
+pub fn resolve_source_in(root: &Path, source: &str) -> Option<String> {
+    let link = match source.split_once('=') {
+        Some(("UUID", value)) => format!("/dev/disk/by-uuid/{}", value),
+        Some(("LABEL", value)) => format!("/dev/disk/by-label/{}", value),
+        Some(("PARTUUID", value)) => format!("/dev/disk/by-partuuid/{}", value),
+        Some(("PARTLABEL", value)) => format!("/dev/disk/by-partlabel/{}", value),
+        _ if source.starts_with("/dev/") => source.to_string(),
+        _ => return None,
+    };
+    if !root.join(link.trim_start_matches('/')).exists() {
+        return None;
+    }
+    Some(format!("/dev/{}", block_name_in(root, &link)))
+}
+
+/// UUID of the filesystem on `device`, from udev's by-uuid links
+pub fn uuid_of_in(root: &Path, device: &str) -> Option<String> {
+    let name = block_name_in(root, device);
+    fs::read_dir(root.join("dev/disk/by-uuid"))
+        .ok()?
+        .filter_map(|entry| entry.ok())
+        .map(|entry| entry.file_name().to_string_lossy().into_owned())
+        .find(|uuid| block_name_in(root, &format!("/dev/disk/by-uuid/{}", uuid)) == name)
+}
+
+/// Regular user account of the old system
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct UserAccount {
+    pub name: String,
+    pub uid: u32,
+    pub gid: u32,
+    /// Name of the primary group
+    pub group: String,
+    pub gecos: String,
+    pub home: String,
+    pub shell: String,
+    /// Supplementary groups
+    pub groups: Vec<String>,
+    /// Password hash from shadow, `None` when locked or unreadable
+    pub password_hash: Option<String>,
+}
+
+/// Regular accounts from passwd, group and shadow contents
+pub fn parse_users(passwd: &str, group: &str, shadow: &str) -> Vec<UserAccount> {
+    let groups: Vec<Vec<&str>> = group.lines().map(|l| l.split(':').collect()).filter(|f: &Vec<&str>| f.len() >= 4).collect();
+
+    passwd
+        .lines()
+        .filter_map(|line| {
+            let fields: Vec<&str> = line.split(':').collect();
+            if fields.len() < 7 {
+                return None;
+            }
+            let uid = fields[2].parse::<u32>().ok().filter(|uid| USER_UIDS.contains(uid))?;
+            let gid = fields[3].parse::<u32>().ok()?;
+            let name = fields[0];
+
+            let password_hash = shadow
+                .lines()
+                .map(|l| l.split(':').collect::<Vec<&str>>())
+                .find(|f| f.len() >= 2 && f[0] == name)
+                .map(|f| f[1].to_string())
+                .filter(|hash| hash.starts_with('$'));
+
+            Some(UserAccount {
+                name: name.to_string(),
+                uid,
+                gid,
+                group: groups
+                    .iter()
+                    .find(|g| g[2].parse() == Ok(gid))
+                    .map_or_else(|| name.to_string(), |g| g[0].to_string()),
+                gecos: fields[4].to_string(),
+                home: fields[5].to_string(),
+                shell: fields[6].to_string(),
+                groups: groups
+                    .iter()
+                    .filter(|g| g[3].split(',').any(|member| member == name))
+                    .map(|g| g[0].to_string())
+                    .collect(),
+                password_hash,
+            })
+        })
+        .collect()
+}
+
+/// What an existing Asenos install is made of and what a reinstall keeps
+#[derive(Debug, Clone, PartialEq)]
+pub struct ExistingInstall {
+    pub root: String,
+    pub esp: Option<String>,
+    /// Whether the ESP was created by the installer; a shared ESP, e.g.
+    /// with Windows, is reused rather than reformatted
+    pub reformat_esp: bool,
+    pub swap: Option<String>,
+    pub home: String,
+    pub home_filesystem: Filesystem,
+    /// btrfs subvolumes /home and root are mounted from
+    pub home_subvolume: Option<String>,
+    pub root_subvolume: Option<String>,
+    pub users: Vec<UserAccount>,
+}
+
+impl ExistingInstall {
+    /// Whether /home is a subvolume on the root filesystem, so a reinstall
+    /// recreates only the root subvolume
+    pub fn home_shares_root(&self) -> bool {
+        self.home == self.root
+    }
+}
+
+/// Root partition of an Asenos install on `disk`, found by its GPT name
+/// or its filesystem label
+pub fn find_old_root_in(root: &Path, disk: &str) -> CommandResult<String> {
+    let name = block_name_in(root, disk);
+    let partitions = disk_partitions_in(root, &name);
+
+    let by_name = partitions.iter().find(|part| {
+        fs::read_to_string(root.join("sys/class/block").join(part).join("uevent"))
+            .map(|uevent| uevent.lines().any(|l| l == format!("PARTNAME={}", ROOT_LABEL)))
+            .unwrap_or(false)
+    });
+    if let Some(part) = by_name {
+        return Ok(format!("/dev/{}", part));
+    }
+
+    match resolve_source_in(root, &format!("LABEL={}", ROOT_LABEL)) {
+        Some(device) if partitions.iter().any(|p| device == format!("/dev/{}", p)) => Ok(device),
+        _ => Err(SetupError::InvalidInput(format!("No Asenos installation found on {}", disk))),
+    }
+}
+
+/// Read the layout and users of the old system mounted at `old_root`,
+/// resolving devices under `root`
+pub fn inspect_old_root_in(root: &Path, root_device: &str, old_root: &Path) -> CommandResult<ExistingInstall> {
+    // A btrfs root may keep the system in a subvolume below the top level
+    let system = [old_root.to_path_buf(), old_root.join("@")]
+        .into_iter()
+        .find(|dir| dir.join("etc/fstab").exists())
+        .ok_or_else(|| SetupError::InvalidInput(format!("{} has no etc/fstab", root_device)))?;
+    let read = |file: &str| fs::read_to_string(system.join(file)).unwrap_or_default();
+
+    let entries = parse_fstab(&read("etc/fstab"));
+    let entry = |mount_point: &str| entries.iter().find(|e| e.mount_point == mount_point);
+    let resolve = |entry: &FstabEntry| {
+        resolve_source_in(root, &entry.source).ok_or_else(|| {
+            SetupError::System(format!("Cannot find {} of {}", entry.source, entry.mount_point))
+        })
+    };
+
+    let home = entry("/home").ok_or_else(|| {
+        SetupError::InvalidInput(format!(
+            "The system on {} keeps /home inside its root filesystem, so it cannot be preserved",
+            root_device
+        ))
+    })?;
+    let home_device = resolve(home)?;
+    let home_subvolume = home.subvolume().map(str::to_string);
+    if home_device == root_device && home_subvolume.is_none() {
+        return Err(SetupError::InvalidInput(format!(
+            "/home of the system on {} is not separate from root", root_device
+        )));
+    }
+
+    let root_subvolume = entry("/").and_then(|e| e.subvolume()).map(str::to_string);
+    if home_device == root_device && root_subvolume.is_none() {
+        // Only the root subvolume is recreated, which would leave the old
+        // system behind in the top level
+        return Err(SetupError::InvalidInput(format!(
+            "The system on {} lives in the top level of its btrfs filesystem next to /home, so it cannot be reinstalled",
+            root_device
+        )));
+    }
+
+    let esp = entries
+        .iter()
+        .find(|e| (e.mount_point == "/boot" || e.mount_point == "/efi") && e.fstype == "vfat")
+        .map(resolve)
+        .transpose()?;
+    let reformat_esp = esp.is_some() && resolve_source_in(root, &format!("LABEL={}", ESP_LABEL)) == esp;
+    let swap = entries.iter().find(|e| e.fstype == "swap").map(resolve).transpose()?;
+
+    Ok(ExistingInstall {
+        root: root_device.to_string(),
+        esp,
+        reformat_esp,
+        swap,
+        home: home_device,
+        home_filesystem: home.fstype.parse()?,
+        home_subvolume,
+        root_subvolume,
+        users: parse_users(&read("etc/passwd"), &read("etc/group"), &read("etc/shadow")),
+    })
+}
+
+/// Mount `device` with `options` on a fresh private directory while `f` runs
+fn with_mount<T>(device: &str, options: &str, f: impl FnOnce(&Path) -> CommandResult<T>) -> CommandResult<T> {
+    let scratch = tempfile::Builder::new()
+        .prefix(&format!("asenos-{}-", block_name_in(Path::new("/"), device)))
+        .tempdir()?;
+    let dir = scratch.path();
+    let mount_point = dir.to_string_lossy();
+    run_command(&["mount", "-o", options, device, &mount_point], None)?;
+
+    let result = f(dir);
+    let unmounted = run_command(&["umount", &mount_point], None);
+    let value = result?;
+    unmounted?;
+    Ok(value)
+}
+
+/// Find the Asenos install on `disk` and what a reinstall would keep
+pub fn detect(disk: &str) -> CommandResult<ExistingInstall> {
+    let root = Path::new("/");
+    let device = find_old_root_in(root, disk)?;
+    with_mount(&device, "ro", |old_root| inspect_old_root_in(root, &device, old_root))
+}
+
+/// Size in MiB of the kept partition `device`
+pub fn partition_mib_in(root: &Path, device: &str) -> CommandResult<u64> {
+    Ok(disk_size_bytes_in(root, &block_name_in(root, device))? / MIB)
+}
+
+/// Refuse hibernation unless `existing` keeps a swap partition that can
+/// hold all `ram_mib` of RAM
+pub fn check_hibernation_swap_in(root: &Path, existing: &ExistingInstall, ram_mib: u64) -> CommandResult<()> {
+    let swap = existing.swap.as_deref().ok_or_else(|| {
+        SetupError::InvalidInput(format!(
+            "Hibernation needs a swap partition, and the system on {} has none", existing.root
+        ))
+    })?;
+    hibernate::check_swap_size(partition_mib_in(root, swap)?, ram_mib)
+}
+
+/// Kept volume with its existing UUID. The old filesystems never got the
+/// installer's labels, so fstab needs the UUID to find them.
+fn kept(role: PartitionRole, device: &str, format: FormatSpec, subvolume: Option<String>) -> CommandResult<Volume> {
+    let uuid = uuid_of_in(Path::new("/"), device)
+        .ok_or_else(|| SetupError::System(format!("Cannot find the filesystem UUID of {}", device)))?;
+    Ok(Volume { role, device: device.to_string(), format: format.with_uuid(uuid), formatted: false, subvolume })
+}
+
+/// Reformat root and the installer's ESP of `existing`, keeping swap and
+/// /home. When /home is a subvolume next to root, only the root
+/// subvolume is recreated.
+pub fn reinstall(config: &PartitionConfig, existing: &ExistingInstall) -> CommandResult<Vec<Volume>> {
+    if existing.home_shares_root() && config.filesystem != Filesystem::Btrfs {
+        return Err(SetupError::InvalidInput(format!(
+            "/home is a subvolume of the btrfs root on {}; reinstall with btrfs to keep it",
+            existing.root
+        )));
+    }
+    // Swap is kept as it is, so check it before root is reformatted
+    if let Some(ram_mib) = config.hibernate_ram_mib {
+        check_hibernation_swap_in(Path::new("/"), existing, ram_mib)?;
+    }
+
+    // Find what is kept before anything is reformatted
+    let kept_esp = match &existing.esp {
+        Some(esp) if !existing.reformat_esp => Some(kept(PartitionRole::Esp, esp, FormatSpec::new(Filesystem::Vfat, ""), None)?),
+        _ => None,
+    };
+    let kept_swap = existing
+        .swap
+        .as_deref()
+        .map(|swap| kept(PartitionRole::Swap, swap, FormatSpec::new(Filesystem::Swap, ""), None))
+        .transpose()?;
+    let kept_home = kept(
+        PartitionRole::Home,
+        &existing.home,
+        FormatSpec::new(existing.home_filesystem, ""),
+        existing.home_subvolume.clone(),
+    )?;
+
+    let mut volumes = Vec::new();
+
+    match (&existing.esp, kept_esp) {
+        (_, Some(esp)) => volumes.push(esp),
+        (Some(esp), None) => {
+            let format = FormatSpec::new(Filesystem::Vfat, ESP_LABEL).with_uuid(random_uuid()?);
+            format.format(esp)?;
+            volumes.push(Volume { role: PartitionRole::Esp, device: esp.clone(), format, formatted: true, subvolume: None });
+        }
+        (None, None) => {}
+    }
+    volumes.extend(kept_swap);
+
+    if existing.home_shares_root() {
+        let subvolume = existing.root_subvolume.clone().ok_or_else(|| {
+            SetupError::InvalidInput(format!("Root on {} is not a btrfs subvolume", existing.root))
+        })?;
+        with_mount(&existing.root, "subvolid=5", |top| {
+            let path = top.join(&subvolume).to_string_lossy().into_owned();
+            if top.join(&subvolume).exists() {
+                run_command(&["btrfs", "subvolume", "delete", "--recursive", &path], None)?;
+            }
+            run_command(&["btrfs", "subvolume", "create", &path], None)?;
+            Ok(())
+        })?;
+        volumes.push(kept(PartitionRole::Root, &existing.root, FormatSpec::new(Filesystem::Btrfs, ROOT_LABEL), Some(subvolume))?);
+    } else {
+        let mut format = FormatSpec::new(config.filesystem, ROOT_LABEL);
+        if config.filesystem.supports_uuid() {
+            format = format.with_uuid(random_uuid()?);
+        }
+        format.format(&existing.root)?;
+        volumes.push(Volume { role: PartitionRole::Root, device: existing.root.clone(), format, formatted: true, subvolume: None });
+    }
+
+    volumes.push(kept_home);
+
+    Ok(volumes)
+}
+
+/// Group names defined in a group file
+fn group_names(group: &str) -> Vec<String> {
+    group.lines().filter_map(|l| l.split(':').next()).map(str::to_string).collect()
+}
+
+/// Refuse `users` whose primary group clashes with the group file of the
+/// new system: the same name with another GID, or the GID under another
+/// name. groupadd and useradd would fail halfway through otherwise.
+pub fn check_group_ids(group: &str, users: &[UserAccount]) -> CommandResult<()> {
+    let entries: Vec<(&str, &str)> = group
+        .lines()
+        .filter_map(|line| {
+            let mut fields = line.split(':');
+            Some((fields.next()?, fields.nth(1)?))
+        })
+        .collect();
+
+    for user in users {
+        let gid = user.gid.to_string();
+        if let Some((_, other)) = entries.iter().find(|(name, id)| *name == user.group && *id != gid) {
+            return Err(SetupError::InvalidInput(format!(
+                "Group '{}' of {} has GID {} in the new system instead of {}",
+                user.group, user.name, other, gid
+            )));
+        }
+        if let Some((other, _)) = entries.iter().find(|(name, id)| *id == gid && *name != user.group) {
+            return Err(SetupError::InvalidInput(format!(
+                "GID {} of group '{}' of {} already belongs to '{}' in the new system",
+                gid, user.group, user.name, other
+            )));
+        }
+    }
+    Ok(())
+}
+
+/// useradd command recreating `user` in the system at `root`. Groups
+/// the new system does not have yet are left out.
+pub fn useradd_command(root: &Path, user: &UserAccount, known_groups: &[String]) -> Vec<String> {
+    let mut command: Vec<String> = [
+        "useradd",
+        "--root",
+        &root.to_string_lossy(),
+        "--uid",
+        &user.uid.to_string(),
+        "--gid",
+        &user.gid.to_string(),
+        "--no-create-home",
+        "--home-dir",
+        &user.home,
+        "--shell",
+        &user.shell,
+        "--comment",
+        &user.gecos,
+    ]
+    .map(String::from)
+    .to_vec();
+
+    let groups: Vec<&str> = user
+        .groups
+        .iter()
+        .filter(|g| known_groups.contains(g))
+        .map(String::as_str)
+        .collect();
+    if !groups.is_empty() {
+        command.extend(["--groups".to_string(), groups.join(",")]);
+    }
+    command.push(user.name.clone());
+    command
+}
+
+/// shadow contents with the password hash of `name` replaced
+pub fn set_password_hash(shadow: &str, name: &str, hash: &str) -> String {
+    shadow
+        .lines()
+        .map(|line| {
+            let mut fields: Vec<&str> = line.split(':').collect();
+            if fields.len() >= 2 && fields[0] == name {
+                fields[1] = hash;
+            }
+            fields.join(":")
+        })
+        .collect::<Vec<_>>()
+        .join("\n")
+        + "\n"
+}
+
+/// Recreate `users` in the target with their old UIDs, GIDs and password
+/// hashes so the kept /home stays theirs
+pub fn recreate_users(target: &Target, users: &[UserAccount]) -> CommandResult<()> {
+    let root = target.root.to_string_lossy().into_owned();
+    check_group_ids(&fs::read_to_string(target.path("etc/group"))?, users)?;
+
+    for user in users {
+        let groups = group_names(&fs::read_to_string(target.path("etc/group"))?);
+        if !groups.contains(&user.group) {
+            run_command(&["groupadd", "--root", &root, "--gid", &user.gid.to_string(), &user.group], None)?;
+        }
+
+        let command = useradd_command(&target.root, user, &groups);
+        let args: Vec<&str> = command.iter().map(String::as_str).collect();
+        run_command(&args, None)?;
+
+        // Written to the file directly so the hash never shows up in a
+        // command line or error message
+        if let Some(hash) = &user.password_hash {
+            let shadow = fs::read_to_string(target.path("etc/shadow"))?;
+            target.write_file("etc/shadow", &set_password_hash(&shadow, &user.name, hash))?;
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_fstab_subvolume() {
+        let entries = parse_fstab("UUID=abc /home btrfs rw,noatime,subvol=/@home 0 0\n# comment\n");
+        assert_eq!(entries.len(), 1);
+        assert_eq!(entries[0].subvolume(), Some("@home"));
+    }
+
+    #[test]
+    fn test_set_password_hash() {
+        let shadow = "root:!*:19000::::::\nalice:!:19000:0:99999:7:::\n";
+        assert_eq!(
+            set_password_hash(shadow, "alice", "$6$salt$hash"),
+            "root:!*:19000::::::\nalice:$6$salt$hash:19000:0:99999:7:::\n"
+        );
+    }
+}
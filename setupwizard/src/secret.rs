@@ -0,0 +1,123 @@
+use crate::common::{run_command, CommandResult, SetupError};
+use std::fs;
+use std::io::{self, BufRead, IsTerminal, Write};
+use std::path::Path;
+
+/// Environment variable the WiFi password can be passed in
+pub const WIFI_PASSWORD_ENV: &str = "ASENOS_WIFI_PASSWORD";
+
+/// What secrets are replaced with in messages
+pub const REDACTED: &str = "********";
+
+/// `text` with every occurrence of `secret` replaced
+pub fn redact(text: &str, secret: &str) -> String {
+    if secret.is_empty() {
+        return text.to_string();
+    }
+    text.replace(secret, REDACTED)
+}
+
+/// First line of `path`, for passwords kept out of argv and shell history
+pub fn read_secret_file(path: &Path) -> CommandResult<String> {
+    let contents = fs::read_to_string(path)
+        .map_err(|e| SetupError::InvalidInput(format!("Cannot read password file {}: {}", path.display(), e)))?;
+    let secret = contents.lines().next().unwrap_or("").to_string();
+    if secret.is_empty() {
+        return Err(SetupError::InvalidInput(format!("Password file {} is empty", path.display())));
+    }
+    Ok(secret)
+}
+
+/// Value of environment variable `name`, removed afterwards so commands
+/// started later do not inherit it
+pub fn take_env_secret(name: &str) -> Option<String> {
+    let secret = std::env::var(name).ok().filter(|s| !s.is_empty());
+    std::env::remove_var(name);
+    secret
+}
+
+/// Read keystrokes until Enter, echoing one '*' per character. Backspace
+/// removes a whole character; Ctrl-C and Ctrl-D cancel.
+pub fn read_masked(input: impl BufRead, echo: &mut impl Write) -> CommandResult<String> {
+    let mut secret: Vec<u8> = Vec::new();
+    for byte in input.bytes() {
+        match byte? {
+            b'\r' | b'\n' => {
+                writeln!(echo)?;
+                return String::from_utf8(secret)
+                    .map_err(|_| SetupError::InvalidInput("Password is not valid UTF-8".to_string()));
+            }
+            0x03 | 0x04 => {
+                writeln!(echo)?;
+                return Err(SetupError::InvalidInput("Password entry cancelled".to_string()));
+            }
+            0x08 | 0x7f => {
+                // Drop continuation bytes, then the lead byte of the character
+                while secret.last().is_some_and(|b| b & 0xc0 == 0x80) {
+                    secret.pop();
+                }
+                if secret.pop().is_some() {
+                    write!(echo, "\x08 \x08")?;
+                }
+            }
+            byte => {
+                // One star per character, not per UTF-8 byte
+                if byte & 0xc0 != 0x80 {
+                    write!(echo, "*")?;
+                }
+                secret.push(byte);
+            }
+        }
+        echo.flush()?;
+    }
+    Err(SetupError::InvalidInput("Password entry ended without Enter".to_string()))
+}
+
+/// Puts the terminal back the way it was, also when reading fails
+struct TerminalState(String);
+
+impl Drop for TerminalState {
+    fn drop(&mut self) {
+        let _ = run_command(&["stty", &self.0], None);
+    }
+}
+
+/// Ask for a password on the terminal without showing it. Piped input is
+/// read as a plain line since nobody watches it.
+pub fn prompt_password(prompt: &str) -> CommandResult<String> {
+    print!("{}", prompt);
+    io::stdout().flush()?;
+
+    if !io::stdin().is_terminal() {
+        let mut line = String::new();
+        io::stdin().read_line(&mut line)?;
+        return Ok(line.trim_end_matches(['\r', '\n']).to_string());
+    }
+
+    let saved = TerminalState(run_command(&["stty", "-g"], None)?.trim().to_string());
+    run_command(&["stty", "-echo", "-icanon", "-isig", "min", "1"], None)?;
+    let secret = read_masked(io::stdin().lock(), &mut io::stdout());
+    drop(saved);
+    secret
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_redact() {
+        assert_eq!(redact("bad key hunter22 for hunter22", "hunter22"), "bad key ******** for ********");
+        assert_eq!(redact("nothing", ""), "nothing");
+    }
+
+    #[test]
+    fn test_read_masked_editing() {
+        let mut echo = Vec::new();
+        let secret = read_masked("pa\x7fassé\x7fe\r".as_bytes(), &mut echo).unwrap();
+        assert_eq!(secret, "passe");
+        let echoed = String::from_utf8(echo).unwrap();
+        assert!(!echoed.contains('p'));
+        assert_eq!(echoed.matches('*').count(), 7);
+    }
+}
@@ -0,0 +1,158 @@
+use crate::common::{CommandResult, SetupError};
+use std::fmt;
+use std::str::FromStr;
+
+pub const MIB: u64 = 1024 * 1024;
+
+/// Partition size as written in a profile or at a prompt
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SizeSpec {
+    /// Absolute size in bytes
+    Bytes(u64),
+    /// Percentage (1-100) of the space being partitioned
+    Percent(u8),
+    /// Whatever space is left after the other partitions
+    Rest,
+}
+
+impl SizeSpec {
+    pub fn mib(mib: u64) -> Self {
+        SizeSpec::Bytes(mib * MIB)
+    }
+
+    /// Absolute size rounded up to a whole MiB, if this is not relative
+    pub fn fixed_mib(&self) -> Option<u64> {
+        match self {
+            SizeSpec::Bytes(bytes) => Some(bytes.div_ceil(MIB)),
+            _ => None,
+        }
+    }
+}
+
+/// Bare numbers are MiB, matching the original profile format
+impl From<u32> for SizeSpec {
+    fn from(mib: u32) -> Self {
+        SizeSpec::mib(u64::from(mib))
+    }
+}
+
+impl FromStr for SizeSpec {
+    type Err = SetupError;
+
+    /// Parse values such as "512", "512MiB", "1G", "1.5GB", "20%" or "rest".
+    /// K/M/G/T and KiB/MiB/GiB/TiB are binary units, KB/MB/GB/TB are decimal.
+    fn from_str(s: &str) -> CommandResult<Self> {
+        let value = s.trim();
+        let invalid = || SetupError::InvalidInput(format!("Invalid size '{}'", value));
+
+        if value.eq_ignore_ascii_case("rest") {
+            return Ok(SizeSpec::Rest);
+        }
+
+        if let Some(percent) = value.strip_suffix('%') {
+            let percent = percent.trim().parse::<u8>().map_err(|_| invalid())?;
+            if !(1..=100).contains(&percent) {
+                return Err(SetupError::InvalidInput(format!(
+                    "Percentage must be 1-100, got '{}'", value
+                )));
+            }
+            return Ok(SizeSpec::Percent(percent));
+        }
+
+        let split = value
+            .find(|c: char| !(c.is_ascii_digit() || c == '.'))
+            .unwrap_or(value.len());
+        let (number, unit) = value.split_at(split);
+        let number = number.parse::<f64>().map_err(|_| invalid())?;
+        if !number.is_finite() || number < 0.0 {
+            return Err(invalid());
+        }
+
+        let multiplier: u64 = match unit.trim().to_ascii_lowercase().as_str() {
+            "" | "m" | "mib" => MIB,
+            "b" => 1,
+            "k" | "kib" => 1024,
+            "g" | "gib" => 1024 * MIB,
+            "t" | "tib" => 1024 * 1024 * MIB,
+            "kb" => 1000,
+            "mb" => 1000 * 1000,
+            "gb" => 1000 * 1000 * 1000,
+            "tb" => 1000 * 1000 * 1000 * 1000,
+            _ => return Err(SetupError::InvalidInput(format!("Unknown size unit in '{}'", value))),
+        };
+
+        Ok(SizeSpec::Bytes((number * multiplier as f64).round() as u64))
+    }
+}
+
+impl fmt::Display for SizeSpec {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            SizeSpec::Bytes(bytes) if bytes % (1024 * MIB) == 0 && *bytes > 0 => {
+                write!(f, "{}GiB", bytes / (1024 * MIB))
+            }
+            SizeSpec::Bytes(bytes) if bytes % MIB == 0 => write!(f, "{}MiB", bytes / MIB),
+            SizeSpec::Bytes(bytes) => write!(f, "{}B", bytes),
+            SizeSpec::Percent(percent) => write!(f, "{}%", percent),
+            SizeSpec::Rest => write!(f, "rest"),
+        }
+    }
+}
+
+/// Resolve sizes against `available_mib`, returning whole MiB per entry
+/// so every partition starts and ends on a MiB boundary.
+/// Percentages are of `available_mib`; at most one entry may be `Rest`.
+pub fn resolve_sizes(specs: &[SizeSpec], available_mib: u64) -> CommandResult<Vec<u64>> {
+    if specs.iter().filter(|s| **s == SizeSpec::Rest).count() > 1 {
+        return Err(SetupError::InvalidInput("Only one partition can use 'rest'".to_string()));
+    }
+
+    let mut sizes: Vec<Option<u64>> = specs
+        .iter()
+        .map(|spec| match spec {
+            SizeSpec::Bytes(_) => spec.fixed_mib(),
+            SizeSpec::Percent(percent) => Some(available_mib * u64::from(*percent) / 100),
+            SizeSpec::Rest => None,
+        })
+        .collect();
+
+    let used: u64 = sizes.iter().flatten().sum();
+    if used > available_mib {
+        return Err(SetupError::InvalidInput(format!(
+            "Requested sizes total {} MiB but only {} MiB are available",
+            used, available_mib
+        )));
+    }
+
+    if let Some(rest) = sizes.iter_mut().find(|s| s.is_none()) {
+        let remaining = available_mib - used;
+        if remaining == 0 {
+            return Err(SetupError::InvalidInput(
+                "No space left for the partition using 'rest'".to_string()
+            ));
+        }
+        *rest = Some(remaining);
+    }
+
+    Ok(sizes.into_iter().flatten().collect())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_units() {
+        assert_eq!("512".parse::<SizeSpec>().unwrap(), SizeSpec::mib(512));
+        assert_eq!("512MiB".parse::<SizeSpec>().unwrap(), SizeSpec::mib(512));
+        assert_eq!("1G".parse::<SizeSpec>().unwrap(), SizeSpec::mib(1024));
+        assert_eq!("20%".parse::<SizeSpec>().unwrap(), SizeSpec::Percent(20));
+        assert_eq!("REST".parse::<SizeSpec>().unwrap(), SizeSpec::Rest);
+    }
+
+    #[test]
+    fn test_resolve_rest() {
+        let sizes = resolve_sizes(&[SizeSpec::mib(512), SizeSpec::Percent(10), SizeSpec::Rest], 10000).unwrap();
+        assert_eq!(sizes, vec![512, 1000, 8488]);
+    }
+}
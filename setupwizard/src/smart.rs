@@ -0,0 +1,151 @@
+use crate::common::{command_exists, CommandResult, SetupError};
+use serde_json::Value;
+use std::process::Command;
+
+/// Percentage of rated NVMe endurance from which a drive counts as worn out
+pub const NVME_WEAR_WARNING_PERCENT: u64 = 90;
+
+/// smartctl exit status bits meaning it could not read the device at all
+const SMARTCTL_NO_DATA_BITS: i32 = 0b11;
+
+/// Health summary of one disk from `smartctl -j`
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct SmartHealth {
+    pub model: Option<String>,
+    /// Overall self-assessment; `None` when the drive did not report one
+    pub passed: Option<bool>,
+    pub reallocated_sectors: Option<u64>,
+    pub pending_sectors: Option<u64>,
+    pub nvme_percentage_used: Option<u64>,
+    pub nvme_media_errors: Option<u64>,
+    pub nvme_critical_warning: Option<u64>,
+    pub power_on_hours: Option<u64>,
+}
+
+impl SmartHealth {
+    /// Whether the drive itself reports that it is failing
+    pub fn is_failing(&self) -> bool {
+        self.passed == Some(false) || self.nvme_critical_warning.is_some_and(|w| w != 0)
+    }
+
+    /// Problems worth showing before installing to this disk
+    pub fn warnings(&self) -> Vec<String> {
+        let mut warnings = Vec::new();
+
+        if self.passed == Some(false) {
+            warnings.push("SMART overall health self-assessment FAILED".to_string());
+        }
+        if let Some(warning) = self.nvme_critical_warning.filter(|w| *w != 0) {
+            warnings.push(format!("NVMe critical warning flags set (0x{:02x})", warning));
+        }
+        if let Some(count) = self.reallocated_sectors.filter(|c| *c > 0) {
+            warnings.push(format!("{} reallocated sectors", count));
+        }
+        if let Some(count) = self.pending_sectors.filter(|c| *c > 0) {
+            warnings.push(format!("{} sectors pending reallocation", count));
+        }
+        if let Some(count) = self.nvme_media_errors.filter(|c| *c > 0) {
+            warnings.push(format!("{} media errors", count));
+        }
+        if let Some(used) = self.nvme_percentage_used.filter(|u| *u >= NVME_WEAR_WARNING_PERCENT) {
+            warnings.push(format!("{}% of rated endurance used", used));
+        }
+
+        warnings
+    }
+
+    /// One-line summary for the disk picker, e.g. "PASSED, 12034 h, 3% used"
+    pub fn summary(&self) -> String {
+        let mut parts = vec![match self.passed {
+            Some(true) => "PASSED".to_string(),
+            Some(false) => "FAILED".to_string(),
+            None => "status unknown".to_string(),
+        }];
+        if let Some(hours) = self.power_on_hours {
+            parts.push(format!("{} h", hours));
+        }
+        if let Some(used) = self.nvme_percentage_used {
+            parts.push(format!("{}% used", used));
+        }
+        parts.join(", ")
+    }
+}
+
+/// Raw value of ATA attribute `id` from the attribute table
+fn ata_attribute(json: &Value, id: u64) -> Option<u64> {
+    json["ata_smart_attributes"]["table"]
+        .as_array()?
+        .iter()
+        .find(|attr| attr["id"].as_u64() == Some(id))
+        .and_then(|attr| attr["raw"]["value"].as_u64())
+}
+
+/// Parse the JSON printed by `smartctl -j -H -A -i`
+pub fn parse_smartctl_json(output: &str) -> CommandResult<SmartHealth> {
+    let json: Value = serde_json::from_str(output)
+        .map_err(|e| SetupError::System(format!("Invalid smartctl output: {}", e)))?;
+    let nvme = &json["nvme_smart_health_information_log"];
+
+    Ok(SmartHealth {
+        model: json["model_name"].as_str().map(str::to_string),
+        passed: json["smart_status"]["passed"].as_bool(),
+        reallocated_sectors: ata_attribute(&json, 5),
+        pending_sectors: ata_attribute(&json, 197),
+        nvme_percentage_used: nvme["percentage_used"].as_u64(),
+        nvme_media_errors: nvme["media_errors"].as_u64(),
+        nvme_critical_warning: nvme["critical_warning"].as_u64(),
+        power_on_hours: json["power_on_time"]["hours"]
+            .as_u64()
+            .or_else(|| nvme["power_on_hours"].as_u64()),
+    })
+}
+
+/// Read the SMART health of `disk`. Returns `None` when smartctl is not
+/// installed or the device has no SMART data, as with USB bridges and VMs.
+pub fn disk_health(disk: &str) -> CommandResult<Option<SmartHealth>> {
+    if !command_exists("smartctl") {
+        return Ok(None);
+    }
+
+    // smartctl sets status bits for failing drives too, so the status only
+    // decides whether the JSON is worth reading
+    let output = Command::new("smartctl")
+        .args(["-j", "-H", "-A", "-i", disk])
+        .output()
+        .map_err(|e| SetupError::CommandFailed(format!("Failed to spawn smartctl: {}", e)))?;
+    if output.status.code().is_none_or(|code| code & SMARTCTL_NO_DATA_BITS != 0) {
+        return Ok(None);
+    }
+
+    parse_smartctl_json(&String::from_utf8_lossy(&output.stdout)).map(Some)
+}
+
+/// Refuse a disk that reports SMART failures unless `allow_failing` is set
+pub fn check_disk_health(disk: &str, allow_failing: bool) -> CommandResult<()> {
+    match disk_health(disk)? {
+        Some(health) if health.is_failing() && !allow_failing => Err(SetupError::InvalidInput(format!(
+            "{} reports SMART failures ({}); choose another disk or explicitly allow a failing disk",
+            disk,
+            health.warnings().join(", ")
+        ))),
+        _ => Ok(()),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_minimal_json() {
+        let health = parse_smartctl_json(r#"{"smart_status": {"passed": true}}"#).unwrap();
+        assert_eq!(health.passed, Some(true));
+        assert!(health.warnings().is_empty());
+        assert_eq!(health.summary(), "PASSED");
+    }
+
+    #[test]
+    fn test_parse_invalid_json() {
+        assert!(parse_smartctl_json("smartctl 7.4").is_err());
+    }
+}
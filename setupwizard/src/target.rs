@@ -0,0 +1,288 @@
+use crate::common::{CommandResult, SetupError};
+use crate::disk::StorageMedia;
+use crate::partition::{PartitionRole, Volume};
+use std::fs;
+use std::os::unix::fs::symlink;
+use std::path::{Path, PathBuf};
+
+/// Where the target's systemd units are installed
+const SYSTEMD_UNIT_DIR: &str = "/usr/lib/systemd/system";
+
+/// Initramfs configuration of the target
+const MKINITCPIO_CONF: &str = "etc/mkinitcpio.conf";
+/// Kernel command line used by kernel-install, UKIs and systemd-boot
+const KERNEL_CMDLINE: &str = "etc/kernel/cmdline";
+/// GRUB defaults, only updated when GRUB is installed
+const GRUB_DEFAULTS: &str = "etc/default/grub";
+
+/// Console keymap and font of the target
+pub const VCONSOLE_CONF: &str = "etc/vconsole.conf";
+
+/// I/O schedulers per device type, following the Arch Wiki recommendations:
+/// BFQ keeps hard disks responsive, SSDs and NVMe need little scheduling
+pub const IO_SCHEDULER_RULES: &str = "\
+# Written by the Asenos installer
+ACTION==\"add|change\", KERNEL==\"sd[a-z]*|mmcblk[0-9]*\", ATTR{queue/rotational}==\"1\", ATTR{queue/scheduler}=\"bfq\"
+ACTION==\"add|change\", KERNEL==\"sd[a-z]*|mmcblk[0-9]*\", ATTR{queue/rotational}==\"0\", ATTR{queue/scheduler}=\"mq-deadline\"
+ACTION==\"add|change\", KERNEL==\"nvme[0-9]*\", ATTR{queue/rotational}==\"0\", ATTR{queue/scheduler}=\"none\"
+";
+
+/// The installed system, mounted at `root`
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Target {
+    pub root: PathBuf,
+}
+
+impl Target {
+    pub fn new(root: impl Into<PathBuf>) -> Self {
+        Self { root: root.into() }
+    }
+
+    /// Path of `path` inside the target
+    pub fn path(&self, path: &str) -> PathBuf {
+        self.root.join(path.trim_start_matches('/'))
+    }
+
+    /// Write `contents` to `path` inside the target, creating directories
+    pub fn write_file(&self, path: &str, contents: &str) -> CommandResult<()> {
+        let path = self.path(path);
+        if let Some(parent) = path.parent() {
+            fs::create_dir_all(parent)?;
+        }
+        fs::write(path, contents)?;
+        Ok(())
+    }
+
+    /// Enable systemd `unit` for `wanted_by` the way `systemctl enable` does,
+    /// without needing systemd to run inside the target
+    pub fn enable_unit(&self, unit: &str, wanted_by: &str) -> CommandResult<()> {
+        let wants = self.path(&format!("etc/systemd/system/{}.wants", wanted_by));
+        fs::create_dir_all(&wants)?;
+
+        let link = wants.join(unit);
+        if link.symlink_metadata().is_ok() {
+            fs::remove_file(&link)?;
+        }
+        symlink(Path::new(SYSTEMD_UNIT_DIR).join(unit), link)?;
+        Ok(())
+    }
+
+    /// Whether `unit` is enabled for `wanted_by`
+    pub fn unit_enabled(&self, unit: &str, wanted_by: &str) -> bool {
+        self.path(&format!("etc/systemd/system/{}.wants/{}", wanted_by, unit))
+            .symlink_metadata()
+            .is_ok()
+    }
+
+    /// mkinitcpio hooks of the target, in order
+    pub fn initramfs_hooks(&self) -> CommandResult<Vec<String>> {
+        hooks(&self.read_mkinitcpio_conf()?)
+    }
+
+    /// Add mkinitcpio `hook` in front of the first of `before` the target
+    /// uses, or at the end. Nothing changes if the hook is already there.
+    pub fn add_initramfs_hook(&self, hook: &str, before: &[&str]) -> CommandResult<()> {
+        let config = add_hook(&self.read_mkinitcpio_conf()?, hook, before)?;
+        self.write_file(MKINITCPIO_CONF, &config)
+    }
+
+    fn read_mkinitcpio_conf(&self) -> CommandResult<String> {
+        fs::read_to_string(self.path(MKINITCPIO_CONF)).map_err(|_| {
+            SetupError::InvalidInput(format!(
+                "{} has no {}; install the base system first",
+                self.root.display(),
+                MKINITCPIO_CONF
+            ))
+        })
+    }
+
+    /// Set `key` in the target's vconsole.conf, keeping its other settings
+    pub fn set_vconsole(&self, key: &str, value: &str) -> CommandResult<()> {
+        let config = fs::read_to_string(self.path(VCONSOLE_CONF)).unwrap_or_default();
+        self.write_file(VCONSOLE_CONF, &set_shell_var(&config, key, value))
+    }
+
+    /// Set kernel parameters, replacing earlier values of the same names,
+    /// in the target's kernel command line and GRUB defaults if present
+    pub fn set_kernel_params(&self, params: &[String]) -> CommandResult<()> {
+        let cmdline = fs::read_to_string(self.path(KERNEL_CMDLINE)).unwrap_or_default();
+        self.write_file(KERNEL_CMDLINE, &(merge_kernel_params(&cmdline, params) + "\n"))?;
+
+        if let Ok(grub) = fs::read_to_string(self.path(GRUB_DEFAULTS)) {
+            self.write_file(GRUB_DEFAULTS, &set_grub_cmdline(&grub, params))?;
+        }
+        Ok(())
+    }
+
+    /// Write fstab, I/O scheduler rules and periodic TRIM for the new volumes
+    pub fn configure_storage(&self, volumes: &[Volume], media: StorageMedia) -> CommandResult<()> {
+        self.write_file("etc/fstab", &fstab(volumes, media))?;
+        self.write_file("etc/udev/rules.d/60-ioschedulers.rules", IO_SCHEDULER_RULES)?;
+
+        // Weekly batched TRIM for every filesystem; only btrfs also
+        // discards continuously, through its cheap discard=async
+        if media.supports_trim() {
+            self.enable_unit("fstrim.timer", "timers.target")?;
+        }
+
+        Ok(())
+    }
+}
+
+/// Index of the HOOKS line mkinitcpio uses, the last uncommented one
+fn hooks_line(config: &str) -> Option<usize> {
+    config
+        .lines()
+        .enumerate()
+        .filter(|(_, line)| line.trim_start().starts_with("HOOKS="))
+        .map(|(i, _)| i)
+        .last()
+}
+
+/// Hooks listed in a mkinitcpio.conf, e.g. `HOOKS=(base udev block filesystems)`
+pub fn hooks(config: &str) -> CommandResult<Vec<String>> {
+    let line = hooks_line(config)
+        .and_then(|i| config.lines().nth(i))
+        .ok_or_else(|| SetupError::System("mkinitcpio.conf has no HOOKS line".to_string()))?;
+    let list = line.trim().trim_start_matches("HOOKS=").trim_start_matches('(').trim_end_matches(')');
+    Ok(list.split_whitespace().map(str::to_string).collect())
+}
+
+/// mkinitcpio.conf with `hook` added in front of the first of `before`
+/// present, or at the end of the HOOKS array
+pub fn add_hook(config: &str, hook: &str, before: &[&str]) -> CommandResult<String> {
+    let mut list = hooks(config)?;
+    if list.iter().any(|h| h == hook) {
+        return Ok(config.to_string());
+    }
+    let position = before
+        .iter()
+        .find_map(|b| list.iter().position(|h| h == b))
+        .unwrap_or(list.len());
+    list.insert(position, hook.to_string());
+
+    let index = hooks_line(config).expect("hooks() found the HOOKS line");
+    let mut lines: Vec<String> = config.lines().map(str::to_string).collect();
+    lines[index] = format!("HOOKS=({})", list.join(" "));
+    Ok(lines.join("\n") + "\n")
+}
+
+/// `KEY=value` file such as vconsole.conf with `key` replaced or appended
+pub fn set_shell_var(config: &str, key: &str, value: &str) -> String {
+    let assignment = format!("{}={}", key, value);
+    let prefix = format!("{}=", key);
+    let mut lines: Vec<String> = config.lines().map(str::to_string).collect();
+    match lines.iter().position(|line| line.trim_start().starts_with(&prefix)) {
+        Some(i) => lines[i] = assignment,
+        None => lines.push(assignment),
+    }
+    lines.join("\n") + "\n"
+}
+
+/// Name of a kernel parameter, e.g. "resume" for "resume=UUID=..."
+fn param_name(param: &str) -> &str {
+    param.split('=').next().unwrap_or(param)
+}
+
+/// `cmdline` with `params` appended after dropping earlier values of the same names
+pub fn merge_kernel_params(cmdline: &str, params: &[String]) -> String {
+    let replaced: Vec<&str> = params.iter().map(|p| param_name(p)).collect();
+    cmdline
+        .split_whitespace()
+        .filter(|p| !replaced.contains(&param_name(p)))
+        .chain(params.iter().map(String::as_str))
+        .collect::<Vec<_>>()
+        .join(" ")
+}
+
+/// GRUB defaults with `params` merged into GRUB_CMDLINE_LINUX_DEFAULT
+fn set_grub_cmdline(grub: &str, params: &[String]) -> String {
+    const KEY: &str = "GRUB_CMDLINE_LINUX_DEFAULT=";
+    let mut lines: Vec<String> = grub.lines().map(str::to_string).collect();
+
+    match lines.iter().position(|line| line.starts_with(KEY)) {
+        Some(i) => {
+            let current = lines[i][KEY.len()..].trim_matches('"').to_string();
+            lines[i] = format!("{}\"{}\"", KEY, merge_kernel_params(&current, params));
+        }
+        None => lines.push(format!("{}\"{}\"", KEY, merge_kernel_params("", params))),
+    }
+    lines.join("\n") + "\n"
+}
+
+/// Mount point of a volume in `role`, "none" for swap
+fn mount_point(role: PartitionRole) -> Option<&'static str> {
+    match role {
+        PartitionRole::Root => Some("/"),
+        PartitionRole::Esp => Some("/boot"),
+        PartitionRole::Home => Some("/home"),
+        PartitionRole::Swap => Some("none"),
+        PartitionRole::BiosGrub => None,
+    }
+}
+
+/// fstab entries for `volumes`, root first. Volumes are referred to by
+/// UUID where the installer assigned one, else by label or device node.
+pub fn fstab(volumes: &[Volume], media: StorageMedia) -> String {
+    let mut lines = vec!["# Written by the Asenos installer".to_string()];
+
+    let mut ordered: Vec<&Volume> = volumes.iter().collect();
+    ordered.sort_by_key(|v| match v.role {
+        PartitionRole::Root => 0,
+        PartitionRole::Esp => 1,
+        _ => 2,
+    });
+
+    for volume in ordered {
+        let Some(mount_point) = mount_point(volume.role) else {
+            continue;
+        };
+        let format = &volume.format;
+        let source = match (format.fs_uuid(), format.label.is_empty()) {
+            (Some(uuid), _) => format!("UUID={}", uuid),
+            (None, false) => format!("LABEL={}", format.label),
+            (None, true) => volume.device.clone(),
+        };
+        let pass = match volume.role {
+            PartitionRole::Root => 1,
+            PartitionRole::Esp | PartitionRole::Home => 2,
+            _ => 0,
+        };
+        let mut options = format.filesystem.mount_options(media);
+        if let Some(subvolume) = &volume.subvolume {
+            options.push_str(&format!(",subvol=/{}", subvolume));
+        }
+        lines.push(format!(
+            "{}\t{}\t{}\t{}\t0 {}",
+            source,
+            mount_point,
+            format.filesystem.name(),
+            options,
+            pass
+        ));
+    }
+
+    lines.join("\n") + "\n"
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_target_path_is_inside_root() {
+        let target = Target::new("/mnt");
+        assert_eq!(target.path("/etc/fstab"), PathBuf::from("/mnt/etc/fstab"));
+        assert_eq!(target.path("etc/fstab"), PathBuf::from("/mnt/etc/fstab"));
+    }
+
+    #[test]
+    fn test_enable_unit_is_idempotent() {
+        let dir = tempfile::tempdir().unwrap();
+        let target = Target::new(dir.path());
+
+        target.enable_unit("fstrim.timer", "timers.target").unwrap();
+        target.enable_unit("fstrim.timer", "timers.target").unwrap();
+        assert!(target.unit_enabled("fstrim.timer", "timers.target"));
+    }
+}
@@ -1,63 +1,910 @@
 use crate::common::{run_command, CommandResult, SetupError, command_exists};
+use std::fmt;
+use std::fs;
+use std::io::Write;
+use std::os::unix::fs::{OpenOptionsExt, PermissionsExt};
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
 
-/// List available WiFi networks using iwctl
-pub fn list_networks() -> CommandResult<String> {
-    if !command_exists("iwctl") {
-        return Err(SetupError::System("iwctl not found - ensure iwd is installed".to_string()));
+/// Where iwd keeps its network profiles, relative to the filesystem root
+pub const IWD_STATE_DIR: &str = "var/lib/iwd";
+
+/// Longest SSID 802.11 allows, in bytes
+const MAX_SSID_LEN: usize = 32;
+
+/// How a network authenticates
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Security {
+    Open,
+    /// WPA2 or WPA3 with a passphrase of 8 to 63 characters
+    Psk,
+    /// WPA3-only SAE, which also takes short passwords
+    Sae,
+}
+
+impl Security {
+    pub const ALL: [Security; 3] = [Security::Open, Security::Psk, Security::Sae];
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            Security::Open => "open",
+            Security::Psk => "psk",
+            Security::Sae => "sae",
+        }
     }
 
-    let device = get_first_wireless_device()?;
-    
-    // Trigger scan (best effort)
-    let _ = run_command(&["iwctl", "station", &device, "scan"], None);
-    
-    // Get networks
-    let output = run_command(&["iwctl", "station", &device, "get-networks"], None)?;
-    Ok(output)
+    /// Extension of the iwd profile; iwd negotiates SAE itself for .psk
+    pub fn profile_extension(&self) -> &'static str {
+        match self {
+            Security::Open => "open",
+            Security::Psk | Security::Sae => "psk",
+        }
+    }
+
+    /// Refuse passphrases the network type cannot use
+    pub fn check_passphrase(&self, passphrase: Option<&str>) -> CommandResult<()> {
+        match (self, passphrase) {
+            (Security::Open, None) => Ok(()),
+            (Security::Open, Some(_)) => {
+                Err(SetupError::InvalidInput("Open networks take no passphrase".to_string()))
+            }
+            (_, None) | (_, Some("")) => Err(SetupError::InvalidInput(format!(
+                "{} networks need a passphrase",
+                self.name()
+            ))),
+            (_, Some(p)) if p.chars().any(char::is_control) => Err(SetupError::InvalidInput(
+                "Passphrase cannot contain control characters".to_string(),
+            )),
+            (Security::Psk, Some(p)) if !(8..=63).contains(&p.chars().count()) => Err(SetupError::InvalidInput(
+                "WPA passphrases have 8 to 63 characters".to_string(),
+            )),
+            _ => Ok(()),
+        }
+    }
 }
 
-/// Connect to WiFi network
-pub fn connect_network(ssid: &str, password: Option<&str>) -> CommandResult<String> {
+impl fmt::Display for Security {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.name())
+    }
+}
+
+impl FromStr for Security {
+    type Err = SetupError;
+
+    fn from_str(s: &str) -> CommandResult<Self> {
+        Security::ALL
+            .into_iter()
+            .find(|security| security.name().eq_ignore_ascii_case(s.trim()))
+            .ok_or_else(|| {
+                SetupError::InvalidInput(format!("Unknown security type '{}', expected one of: open, psk, sae", s))
+            })
+    }
+}
+
+/// A network to connect to with known security, hidden or not
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Network {
+    pub ssid: String,
+    pub security: Security,
+    pub passphrase: Option<String>,
+    /// Not broadcast, so iwd has to probe for it
+    pub hidden: bool,
+}
+
+impl Network {
+    pub fn new(ssid: &str, security: Security, passphrase: Option<&str>) -> Self {
+        Self { ssid: ssid.to_string(), security, passphrase: passphrase.map(str::to_string), hidden: false }
+    }
+
+    pub fn with_hidden(mut self, hidden: bool) -> Self {
+        self.hidden = hidden;
+        self
+    }
+
+    pub fn validate(&self) -> CommandResult<()> {
+        check_ssid(&self.ssid)?;
+        self.security.check_passphrase(self.passphrase.as_deref())
+    }
+
+    /// File name iwd looks the profile up by
+    pub fn profile_file_name(&self) -> String {
+        profile_file_name(&self.ssid, self.security)
+    }
+
+    /// Contents of the iwd profile
+    pub fn profile(&self) -> String {
+        let mut lines = Vec::new();
+        if let Some(passphrase) = &self.passphrase {
+            lines.push("[Security]".to_string());
+            lines.push(format!("Passphrase={}", escape_value(passphrase)));
+            lines.push(String::new());
+        }
+        lines.push("[Settings]".to_string());
+        lines.push("AutoConnect=true".to_string());
+        if self.hidden {
+            lines.push("Hidden=true".to_string());
+        }
+        lines.join("\n") + "\n"
+    }
+}
+
+/// `value` as iwd's settings parser reads it back: backslashes doubled
+/// and spaces at either end written as `\s`, since the parser trims them.
+/// Control characters are refused before profiles are written.
+fn escape_value(value: &str) -> String {
+    let start = value.len() - value.trim_start_matches(' ').len();
+    let end = value.trim_end_matches(' ').len().max(start);
+    let mut escaped = "\\s".repeat(start);
+    escaped.push_str(&value[start..end].replace('\\', "\\\\"));
+    escaped.push_str(&"\\s".repeat(value.len() - end));
+    escaped
+}
+
+/// SSIDs are 1 to 32 bytes; iwd cannot store ones with NUL bytes
+pub fn check_ssid(ssid: &str) -> CommandResult<()> {
     if ssid.trim().is_empty() {
         return Err(SetupError::InvalidInput("SSID cannot be empty".to_string()));
     }
+    if ssid.len() > MAX_SSID_LEN {
+        return Err(SetupError::InvalidInput(format!(
+            "SSID '{}' is {} bytes long, at most {} are allowed",
+            ssid,
+            ssid.len(),
+            MAX_SSID_LEN
+        )));
+    }
+    if ssid.contains('\0') {
+        return Err(SetupError::InvalidInput("SSID cannot contain NUL bytes".to_string()));
+    }
+    Ok(())
+}
+
+/// iwd profile name: the SSID itself if it only has letters, digits, spaces,
+/// '-' and '_', else '=' and the SSID bytes in hex
+pub fn profile_file_name(ssid: &str, security: Security) -> String {
+    profile_name(ssid, security.profile_extension())
+}
+
+fn profile_name(ssid: &str, extension: &str) -> String {
+    let plain = ssid.chars().all(|c| c.is_ascii_alphanumeric() || matches!(c, ' ' | '-' | '_'));
+    let stem = if plain {
+        ssid.to_string()
+    } else {
+        "=".to_string() + &ssid.bytes().map(|b| format!("{:02x}", b)).collect::<String>()
+    };
+    format!("{}.{}", stem, extension)
+}
+
+/// SSID and extension of an iwd profile file name
+pub fn parse_profile_file_name(file_name: &str) -> Option<(String, &str)> {
+    let (stem, extension) = file_name.rsplit_once('.')?;
+    let Some(hex) = stem.strip_prefix('=') else {
+        return Some((stem.to_string(), extension));
+    };
+    if hex.len() % 2 != 0 {
+        return None;
+    }
+    let bytes = (0..hex.len())
+        .step_by(2)
+        .map(|i| u8::from_str_radix(hex.get(i..i + 2)?, 16).ok())
+        .collect::<Option<Vec<u8>>>()?;
+    Some((String::from_utf8(bytes).ok()?, extension))
+}
+
+/// Store `network` as an iwd profile under `root`, readable by root only
+pub fn write_profile_in(root: &Path, network: &Network) -> CommandResult<PathBuf> {
+    network.validate()?;
+    store_profile(root, &network.profile_file_name(), &network.profile())
+}
+
+fn store_profile(root: &Path, file_name: &str, profile: &str) -> CommandResult<PathBuf> {
+    let dir = root.join(IWD_STATE_DIR);
+    fs::create_dir_all(&dir)?;
+    let path = dir.join(file_name);
+    let mut file = fs::OpenOptions::new().write(true).create(true).truncate(true).mode(0o600).open(&path)?;
+    // The mode above only applies to new files
+    file.set_permissions(fs::Permissions::from_mode(0o600))?;
+    file.write_all(profile.as_bytes())?;
+    Ok(path)
+}
+
+/// Outer EAP method of an 802.1X network
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum EapMethod {
+    Peap,
+    Ttls,
+    /// Client certificate instead of a password
+    Tls,
+}
+
+impl EapMethod {
+    pub const ALL: [EapMethod; 3] = [EapMethod::Peap, EapMethod::Ttls, EapMethod::Tls];
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            EapMethod::Peap => "peap",
+            EapMethod::Ttls => "ttls",
+            EapMethod::Tls => "tls",
+        }
+    }
+
+    /// Method name in iwd's EAP-* keys
+    fn iwd_name(&self) -> &'static str {
+        match self {
+            EapMethod::Peap => "PEAP",
+            EapMethod::Ttls => "TTLS",
+            EapMethod::Tls => "TLS",
+        }
+    }
+}
+
+impl fmt::Display for EapMethod {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.name())
+    }
+}
+
+impl FromStr for EapMethod {
+    type Err = SetupError;
+
+    fn from_str(s: &str) -> CommandResult<Self> {
+        EapMethod::ALL
+            .into_iter()
+            .find(|method| method.name().eq_ignore_ascii_case(s.trim()))
+            .ok_or_else(|| {
+                SetupError::InvalidInput(format!("Unknown EAP method '{}', expected one of: peap, ttls, tls", s))
+            })
+    }
+}
+
+/// Inner authentication of PEAP and TTLS
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Phase2Method {
+    Mschapv2,
+    Gtc,
+    Md5,
+    /// TTLS only, like the other non-EAP methods
+    Pap,
+    Chap,
+    Mschap,
+}
+
+impl Phase2Method {
+    pub const ALL: [Phase2Method; 6] = [
+        Phase2Method::Mschapv2,
+        Phase2Method::Gtc,
+        Phase2Method::Md5,
+        Phase2Method::Pap,
+        Phase2Method::Chap,
+        Phase2Method::Mschap,
+    ];
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            Phase2Method::Mschapv2 => "mschapv2",
+            Phase2Method::Gtc => "gtc",
+            Phase2Method::Md5 => "md5",
+            Phase2Method::Pap => "pap",
+            Phase2Method::Chap => "chap",
+            Phase2Method::Mschap => "mschap",
+        }
+    }
+
+    /// Value of EAP-<method>-Phase2-Method, None where the outer method
+    /// cannot carry it. TTLS tunnels the plain methods without EAP.
+    fn iwd_name(&self, outer: EapMethod) -> Option<&'static str> {
+        match (outer, self) {
+            (EapMethod::Tls, _) => None,
+            (_, Phase2Method::Gtc) => Some("GTC"),
+            (_, Phase2Method::Md5) => Some("MD5"),
+            (EapMethod::Peap, Phase2Method::Mschapv2) => Some("MSCHAPV2"),
+            (EapMethod::Peap, _) => None,
+            (EapMethod::Ttls, Phase2Method::Mschapv2) => Some("Tunneled-MSCHAPv2"),
+            (EapMethod::Ttls, Phase2Method::Pap) => Some("Tunneled-PAP"),
+            (EapMethod::Ttls, Phase2Method::Chap) => Some("Tunneled-CHAP"),
+            (EapMethod::Ttls, Phase2Method::Mschap) => Some("Tunneled-MSCHAP"),
+        }
+    }
+}
+
+impl fmt::Display for Phase2Method {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.name())
+    }
+}
+
+impl FromStr for Phase2Method {
+    type Err = SetupError;
+
+    fn from_str(s: &str) -> CommandResult<Self> {
+        Phase2Method::ALL
+            .into_iter()
+            .find(|method| method.name().eq_ignore_ascii_case(s.trim()))
+            .ok_or_else(|| {
+                SetupError::InvalidInput(format!(
+                    "Unknown phase 2 method '{}', expected one of: mschapv2, gtc, md5, pap, chap, mschap",
+                    s
+                ))
+            })
+    }
+}
+
+/// A WPA2/WPA3-Enterprise network
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct EnterpriseNetwork {
+    pub ssid: String,
+    pub method: EapMethod,
+    /// User name checked by the authentication server
+    pub identity: String,
+    /// Outer identity sent in the clear, e.g. "anonymous@example.org"
+    pub anonymous_identity: Option<String>,
+    /// CA that signed the server certificate; without it any server is trusted
+    pub ca_cert: Option<PathBuf>,
+    /// Inner method of PEAP and TTLS, MSCHAPv2 when None
+    pub phase2: Option<Phase2Method>,
+    /// Inner password, or the client key passphrase for TLS
+    pub password: Option<String>,
+    /// Client certificate and key for TLS
+    pub client_cert: Option<PathBuf>,
+    pub client_key: Option<PathBuf>,
+    pub hidden: bool,
+}
+
+impl EnterpriseNetwork {
+    pub fn new(ssid: &str, method: EapMethod, identity: &str) -> Self {
+        Self {
+            ssid: ssid.to_string(),
+            method,
+            identity: identity.to_string(),
+            anonymous_identity: None,
+            ca_cert: None,
+            phase2: None,
+            password: None,
+            client_cert: None,
+            client_key: None,
+            hidden: false,
+        }
+    }
+
+    pub fn with_anonymous_identity(mut self, identity: Option<&str>) -> Self {
+        self.anonymous_identity = identity.map(str::to_string);
+        self
+    }
+
+    pub fn with_ca_cert(mut self, path: Option<&Path>) -> Self {
+        self.ca_cert = path.map(Path::to_path_buf);
+        self
+    }
+
+    pub fn with_phase2(mut self, method: Option<Phase2Method>) -> Self {
+        self.phase2 = method;
+        self
+    }
+
+    pub fn with_password(mut self, password: Option<&str>) -> Self {
+        self.password = password.map(str::to_string);
+        self
+    }
+
+    pub fn with_client_cert(mut self, cert: Option<&Path>, key: Option<&Path>) -> Self {
+        self.client_cert = cert.map(Path::to_path_buf);
+        self.client_key = key.map(Path::to_path_buf);
+        self
+    }
+
+    pub fn with_hidden(mut self, hidden: bool) -> Self {
+        self.hidden = hidden;
+        self
+    }
 
+    fn phase2_method(&self) -> Phase2Method {
+        self.phase2.unwrap_or(Phase2Method::Mschapv2)
+    }
+
+    /// Check the fields the EAP method needs, and that every file it
+    /// names exists so iwd does not fail later without saying why
+    pub fn validate(&self) -> CommandResult<()> {
+        check_ssid(&self.ssid)?;
+        let values = [Some(&self.identity), self.anonymous_identity.as_ref(), self.password.as_ref()];
+        if values.iter().flatten().any(|v| v.chars().any(char::is_control)) {
+            return Err(SetupError::InvalidInput(
+                "Identities and passwords cannot contain control characters".to_string(),
+            ));
+        }
+        if self.identity.trim().is_empty() {
+            return Err(SetupError::InvalidInput("802.1X networks need an identity".to_string()));
+        }
+
+        match self.method {
+            EapMethod::Tls => {
+                if self.client_cert.is_none() || self.client_key.is_none() {
+                    return Err(SetupError::InvalidInput(
+                        "EAP-TLS needs a client certificate and key".to_string(),
+                    ));
+                }
+                if self.phase2.is_some() {
+                    return Err(SetupError::InvalidInput("EAP-TLS has no phase 2 method".to_string()));
+                }
+            }
+            EapMethod::Peap | EapMethod::Ttls => {
+                if self.phase2_method().iwd_name(self.method).is_none() {
+                    return Err(SetupError::InvalidInput(format!(
+                        "{} cannot use phase 2 method {}",
+                        self.method,
+                        self.phase2_method()
+                    )));
+                }
+                if self.password.as_deref().unwrap_or("").is_empty() {
+                    return Err(SetupError::InvalidInput(format!("{} needs a password", self.method)));
+                }
+            }
+        }
+
+        let files = [&self.ca_cert, &self.client_cert, &self.client_key];
+        for path in files.into_iter().flatten() {
+            if !path.is_absolute() || !path.is_file() {
+                return Err(SetupError::InvalidInput(format!(
+                    "{} is not an existing file given by absolute path",
+                    path.display()
+                )));
+            }
+        }
+        Ok(())
+    }
+
+    pub fn profile_file_name(&self) -> String {
+        profile_name(&self.ssid, "8021x")
+    }
+
+    /// Contents of the iwd .8021x profile
+    pub fn profile(&self) -> String {
+        let method = self.method.iwd_name();
+        let key = |name: &str| format!("EAP-{}-{}", method, name);
+        let mut lines = vec!["[Security]".to_string(), format!("EAP-Method={}", method)];
+
+        match self.method {
+            EapMethod::Tls => {
+                lines.push(format!("EAP-Identity={}", escape_value(&self.identity)));
+                if let Some(ca) = &self.ca_cert {
+                    lines.push(format!("{}={}", key("CACert"), ca.display()));
+                }
+                if let Some(cert) = &self.client_cert {
+                    lines.push(format!("{}={}", key("ClientCert"), cert.display()));
+                }
+                if let Some(client_key) = &self.client_key {
+                    lines.push(format!("{}={}", key("ClientKey"), client_key.display()));
+                }
+                if let Some(passphrase) = &self.password {
+                    lines.push(format!("{}={}", key("ClientKeyPassphrase"), escape_value(passphrase)));
+                }
+            }
+            EapMethod::Peap | EapMethod::Ttls => {
+                let outer = self.anonymous_identity.as_deref().unwrap_or(&self.identity);
+                lines.push(format!("EAP-Identity={}", escape_value(outer)));
+                if let Some(ca) = &self.ca_cert {
+                    lines.push(format!("{}={}", key("CACert"), ca.display()));
+                }
+                let phase2 = self.phase2_method().iwd_name(self.method).unwrap_or("MSCHAPV2");
+                lines.push(format!("{}={}", key("Phase2-Method"), phase2));
+                lines.push(format!("{}={}", key("Phase2-Identity"), escape_value(&self.identity)));
+                if let Some(password) = &self.password {
+                    lines.push(format!("{}={}", key("Phase2-Password"), escape_value(password)));
+                }
+            }
+        }
+
+        lines.push(String::new());
+        lines.push("[Settings]".to_string());
+        lines.push("AutoConnect=true".to_string());
+        if self.hidden {
+            lines.push("Hidden=true".to_string());
+        }
+        lines.join("\n") + "\n"
+    }
+}
+
+/// Store `network` as an iwd .8021x profile under `root`, readable by root only
+pub fn write_enterprise_profile_in(root: &Path, network: &EnterpriseNetwork) -> CommandResult<PathBuf> {
+    network.validate()?;
+    store_profile(root, &network.profile_file_name(), &network.profile())
+}
+
+/// Parse `ssid:password`, where `\:` and `\\` in the SSID stand for a
+/// literal colon and backslash. The password is taken as is.
+pub fn parse_connect_spec(spec: &str) -> (String, Option<&str>) {
+    let mut ssid = String::new();
+    let mut chars = spec.char_indices();
+    while let Some((i, c)) = chars.next() {
+        match c {
+            '\\' => match chars.next() {
+                Some((_, next)) => ssid.push(next),
+                None => ssid.push('\\'),
+            },
+            ':' => return (ssid, Some(&spec[i + 1..])),
+            _ => ssid.push(c),
+        }
+    }
+    (ssid, None)
+}
+
+/// What iwd uses a wireless device for
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum DeviceMode {
+    Station,
+    AccessPoint,
+    AdHoc,
+    Other(String),
+}
+
+impl DeviceMode {
+    /// Mode as iwctl prints it
+    pub fn from_iwd(mode: &str) -> Self {
+        match mode {
+            "station" => DeviceMode::Station,
+            "ap" => DeviceMode::AccessPoint,
+            "ad-hoc" => DeviceMode::AdHoc,
+            other => DeviceMode::Other(other.to_string()),
+        }
+    }
+}
+
+impl fmt::Display for DeviceMode {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            DeviceMode::Station => write!(f, "station"),
+            DeviceMode::AccessPoint => write!(f, "ap"),
+            DeviceMode::AdHoc => write!(f, "ad-hoc"),
+            DeviceMode::Other(mode) => write!(f, "{}", mode),
+        }
+    }
+}
+
+/// A wireless device known to iwd
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct WirelessDevice {
+    /// Interface name, e.g. "wlan0"
+    pub name: String,
+    /// Adapter it belongs to, e.g. "phy0"
+    pub adapter: String,
+    pub mode: DeviceMode,
+    pub powered: bool,
+    /// MAC address
+    pub address: String,
+}
+
+impl fmt::Display for WirelessDevice {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{} ({}, {}, {}, {})",
+            self.name,
+            self.adapter,
+            self.address,
+            self.mode,
+            if self.powered { "on" } else { "off" }
+        )
+    }
+}
+
+/// `text` without the ANSI colour sequences iwctl adds
+fn strip_ansi(text: &str) -> String {
+    let mut plain = String::with_capacity(text.len());
+    let mut chars = text.chars();
+    while let Some(c) = chars.next() {
+        if c == '\u{1b}' {
+            // CSI sequences end with a letter
+            for c in chars.by_ref() {
+                if c.is_ascii_alphabetic() {
+                    break;
+                }
+            }
+        } else {
+            plain.push(c);
+        }
+    }
+    plain
+}
+
+/// Devices in `iwctl device list` output. Columns are found by their
+/// headers since iwd releases order them differently.
+pub fn parse_device_list(output: &str) -> Vec<WirelessDevice> {
+    let plain = strip_ansi(output);
+    let mut columns: Option<Vec<String>> = None;
+    let mut devices = Vec::new();
+    for line in plain.lines() {
+        let words: Vec<&str> = line.split_whitespace().collect();
+        if words.is_empty() || words[0].starts_with('-') {
+            continue;
+        }
+        if words[0] == "Name" || words[0] == "Device" {
+            columns = Some(words.iter().map(|w| w.to_string()).collect());
+            continue;
+        }
+        let Some(columns) = &columns else { continue };
+        if words.len() != columns.len() {
+            continue;
+        }
+        let value = |header: &str| {
+            columns.iter().position(|c| c == header).map(|i| words[i].to_string()).unwrap_or_default()
+        };
+        let mode = if columns.iter().any(|c| c == "Mode") { value("Mode") } else { value("Type") };
+        devices.push(WirelessDevice {
+            name: words[0].to_string(),
+            adapter: value("Adapter"),
+            mode: DeviceMode::from_iwd(&mode),
+            powered: value("Powered") == "on",
+            address: value("Address"),
+        });
+    }
+    devices
+}
+
+fn require_iwctl() -> CommandResult<()> {
     if !command_exists("iwctl") {
         return Err(SetupError::System("iwctl not found - ensure iwd is installed".to_string()));
     }
+    Ok(())
+}
 
-    let device = get_first_wireless_device()?;
-    
-    // Trigger scan first
-    let _ = run_command(&["iwctl", "station", &device, "scan"], None);
-    
-    // Connect with optional password
-    let output = run_command(
-        &["iwctl", "station", &device, "connect", ssid], 
-        password
-    )?;
+/// Run iwctl for real, for the `*_with` functions outside of tests
+fn run_iwctl(args: &[&str]) -> CommandResult<String> {
+    run_command(args, None)
+}
+
+/// Wireless devices iwd manages
+pub fn list_devices() -> CommandResult<Vec<WirelessDevice>> {
+    require_iwctl()?;
+    list_devices_with(&run_iwctl)
+}
+
+/// Wireless devices, asking iwctl through `run`
+pub fn list_devices_with(run: &impl Fn(&[&str]) -> CommandResult<String>) -> CommandResult<Vec<WirelessDevice>> {
+    Ok(parse_device_list(&run(&["iwctl", "device", "list"])?))
+}
+
+/// The device called `wanted`, or the only device there is. Several
+/// devices without a choice are refused rather than guessed.
+pub fn select_device(devices: &[WirelessDevice], wanted: Option<&str>) -> CommandResult<WirelessDevice> {
+    let names = || devices.iter().map(ToString::to_string).collect::<Vec<_>>().join(", ");
+    match (wanted, devices) {
+        (_, []) => Err(SetupError::System("No wireless device found".to_string())),
+        (Some(wanted), _) => devices.iter().find(|d| d.name == wanted).cloned().ok_or_else(|| {
+            SetupError::InvalidInput(format!("No wireless device '{}'. Available: {}", wanted, names()))
+        }),
+        (None, [device]) => Ok(device.clone()),
+        (None, _) => Err(SetupError::InvalidInput(format!(
+            "Several wireless devices found: {}; choose one with --wifi-device",
+            names()
+        ))),
+    }
+}
+
+/// iwctl commands that power `device` on and put it in station mode
+pub fn preparation_commands(device: &WirelessDevice) -> Vec<Vec<String>> {
+    let command = |args: &[&str]| args.iter().map(|a| a.to_string()).collect::<Vec<_>>();
+    let mut commands = Vec::new();
+    if !device.powered {
+        // A device cannot be powered while its adapter is off
+        if !device.adapter.is_empty() {
+            commands.push(command(&["iwctl", "adapter", &device.adapter, "set-property", "Powered", "on"]));
+        }
+        commands.push(command(&["iwctl", "device", &device.name, "set-property", "Powered", "on"]));
+    }
+    if device.mode != DeviceMode::Station {
+        commands.push(command(&["iwctl", "device", &device.name, "set-property", "Mode", "station"]));
+    }
+    commands
+}
+
+/// Pick the device to use and make it ready to scan and connect
+pub fn prepare_device(wanted: Option<&str>) -> CommandResult<WirelessDevice> {
+    require_iwctl()?;
+    prepare_device_with(wanted, &run_iwctl)
+}
+
+/// Pick and prepare the device, running iwctl through `run`
+pub fn prepare_device_with(
+    wanted: Option<&str>,
+    run: &impl Fn(&[&str]) -> CommandResult<String>,
+) -> CommandResult<WirelessDevice> {
+    let device = select_device(&list_devices_with(run)?, wanted)?;
+    for command in preparation_commands(&device) {
+        let args: Vec<&str> = command.iter().map(String::as_str).collect();
+        run(&args)?;
+    }
+    Ok(device)
+}
+
+/// List available WiFi networks using iwctl
+pub fn list_networks() -> CommandResult<String> {
+    list_networks_on(None)
+}
+
+/// List the networks `device` sees, or the only device if None
+pub fn list_networks_on(device: Option<&str>) -> CommandResult<String> {
+    require_iwctl()?;
+    list_networks_with(device, &run_iwctl)
+}
+
+/// List the networks `device` sees, running iwctl through `run`
+pub fn list_networks_with(
+    device: Option<&str>,
+    run: &impl Fn(&[&str]) -> CommandResult<String>,
+) -> CommandResult<String> {
+    let device = prepare_device_with(device, run)?.name;
+
+    // Trigger scan (best effort)
+    let _ = run(&["iwctl", "station", &device, "scan"]);
     
+    // Get networks
+    let output = run(&["iwctl", "station", &device, "get-networks"])?;
     Ok(output)
 }
 
-/// Get the first available wireless device
-fn get_first_wireless_device() -> CommandResult<String> {
-    let output = run_command(&["iwctl", "device", "list"], None)?;
-    
-    for line in output.lines() {
-        let trimmed = line.trim();
-        if trimmed.is_empty() || 
-           trimmed.starts_with("Device") || 
-           trimmed.starts_with('-') {
-            continue;
+/// Connect to WiFi network
+pub fn connect_network(ssid: &str, password: Option<&str>) -> CommandResult<String> {
+    connect_network_on(ssid, password, None, false)
+}
+
+/// The iwd profile for `ssid` a password connect writes, if one is saved
+pub fn saved_profile_in(root: &Path, ssid: &str) -> Option<PathBuf> {
+    Some(root.join(IWD_STATE_DIR).join(profile_file_name(ssid, Security::Sae))).filter(|path| path.exists())
+}
+
+/// Connect to `network` through `device` using an iwd profile, so the
+/// security type is fixed and hidden networks are probed for. A saved
+/// profile for the network is only overwritten with `replace`.
+pub fn connect_configured_on(network: &Network, device: Option<&str>, replace: bool) -> CommandResult<String> {
+    require_iwctl()?;
+    connect_configured_in(Path::new("/"), network, device, replace, &run_iwctl)
+}
+
+/// Connect like `connect_configured_on`, keeping profiles under `root`
+/// and running iwctl through `run`
+pub fn connect_configured_in(
+    root: &Path,
+    network: &Network,
+    device: Option<&str>,
+    replace: bool,
+    run: &impl Fn(&[&str]) -> CommandResult<String>,
+) -> CommandResult<String> {
+    network.validate()?;
+    let (file_name, profile) = (network.profile_file_name(), network.profile());
+    connect_with_saved_profile(root, &file_name, &profile, replace, network.passphrase.as_deref(), || {
+        let device = prepare_device_with(device, run)?.name;
+        connect_with_profile(&device, &network.ssid, network.hidden, run)
+    })
+}
+
+/// Connect to an 802.1X network through `device` after writing its
+/// profile. A saved profile for the network is only overwritten with
+/// `replace`.
+pub fn connect_enterprise_on(network: &EnterpriseNetwork, device: Option<&str>, replace: bool) -> CommandResult<String> {
+    require_iwctl()?;
+    connect_enterprise_in(Path::new("/"), network, device, replace, &run_iwctl)
+}
+
+/// Connect like `connect_enterprise_on`, keeping profiles under `root`
+/// and running iwctl through `run`
+pub fn connect_enterprise_in(
+    root: &Path,
+    network: &EnterpriseNetwork,
+    device: Option<&str>,
+    replace: bool,
+    run: &impl Fn(&[&str]) -> CommandResult<String>,
+) -> CommandResult<String> {
+    network.validate()?;
+    let (file_name, profile) = (network.profile_file_name(), network.profile());
+    connect_with_saved_profile(root, &file_name, &profile, replace, network.password.as_deref(), || {
+        let device = prepare_device_with(device, run)?.name;
+        connect_with_profile(&device, &network.ssid, network.hidden, run)
+    })
+}
+
+/// Connect to `ssid` whose profile iwd already has
+fn connect_with_profile(
+    device: &str,
+    ssid: &str,
+    hidden: bool,
+    run: &impl Fn(&[&str]) -> CommandResult<String>,
+) -> CommandResult<String> {
+    let _ = run(&["iwctl", "station", device, "scan"]);
+    let action = if hidden { "connect-hidden" } else { "connect" };
+    run(&["iwctl", "station", device, action, ssid])
+}
+
+/// Save `profile` as `file_name` under `root`, then `connect` with it. A
+/// saved profile is only overwritten with `replace`. When connecting fails
+/// the old profile comes back, or the new one is removed, so iwd does not
+/// retry a wrong secret. `secret` is kept out of the error.
+fn connect_with_saved_profile(
+    root: &Path,
+    file_name: &str,
+    profile: &str,
+    replace: bool,
+    secret: Option<&str>,
+    connect: impl FnOnce() -> CommandResult<String>,
+) -> CommandResult<String> {
+    let path = root.join(IWD_STATE_DIR).join(file_name);
+    let previous = if path.exists() {
+        if !replace {
+            return Err(SetupError::InvalidInput(format!(
+                "A profile is already saved in {}; remove it or use --wifi-replace-profile",
+                path.display()
+            )));
         }
-        
-        if let Some(device) = trimmed.split_whitespace().next() {
-            return Ok(device.to_string());
+        Some(fs::read_to_string(&path)?)
+    } else {
+        None
+    };
+
+    store_profile(root, file_name, profile)?;
+    masked(connect(), secret).map_err(|e| {
+        let cleanup = match &previous {
+            Some(profile) => store_profile(root, file_name, profile).map(drop),
+            None => fs::remove_file(&path).map_err(SetupError::from),
+        };
+        match cleanup {
+            Ok(()) => e,
+            Err(cleanup_err) => SetupError::System(format!(
+                "{}; restoring the saved profile {} also failed: {}",
+                e,
+                path.display(),
+                cleanup_err
+            )),
         }
+    })
+}
+
+/// Keep `secret` out of the error, whatever iwctl printed
+fn masked(result: CommandResult<String>, secret: Option<&str>) -> CommandResult<String> {
+    match secret {
+        Some(secret) => result.map_err(|e| e.redact(secret)),
+        None => result,
     }
-    
-    Err(SetupError::System("No wireless device found".to_string()))
+}
+
+/// Connect to a network through `device`, or the only device if None. The
+/// password goes into a root-only iwd profile, never into iwctl's argv.
+/// A saved profile for the network is only overwritten with `replace`.
+pub fn connect_network_on(
+    ssid: &str,
+    password: Option<&str>,
+    device: Option<&str>,
+    replace: bool,
+) -> CommandResult<String> {
+    require_iwctl()?;
+    connect_network_in(Path::new("/"), ssid, password, device, replace, &run_iwctl)
+}
+
+/// Connect like `connect_network_on`, keeping profiles under `root` and
+/// running iwctl through `run`
+pub fn connect_network_in(
+    root: &Path,
+    ssid: &str,
+    password: Option<&str>,
+    device: Option<&str>,
+    replace: bool,
+    run: &impl Fn(&[&str]) -> CommandResult<String>,
+) -> CommandResult<String> {
+    check_ssid(ssid)?;
+    let password = password.filter(|p| !p.is_empty());
+
+    let Some(password) = password else {
+        let device = prepare_device_with(device, run)?.name;
+        return connect_with_profile(&device, ssid, false, run);
+    };
+
+    // iwd picks WPA2 or WPA3 from the scan, so only SAE's looser
+    // passphrase rules can be checked here
+    let network = Network::new(ssid, Security::Sae, Some(password));
+    network.validate()?;
+    let (file_name, profile) = (network.profile_file_name(), network.profile());
+    connect_with_saved_profile(root, &file_name, &profile, replace, Some(password), || {
+        let device = prepare_device_with(device, run)?.name;
+        connect_with_profile(&device, ssid, false, run)
+    })
 }
 
 #[cfg(test)]
@@ -79,26 +926,58 @@ mod tests {
     }
 
     #[test]
-    fn test_get_first_wireless_device_parsing() {
-        // Test the device parsing logic with mock data
-        let mock_output = "Device     Type    Mode    Powered\nwlan0      station on      on\n";
-        let lines: Vec<&str> = mock_output.lines().collect();
-        
-        let mut device = None;
-        for line in lines {
-            let trimmed = line.trim();
-            if trimmed.is_empty() || 
-               trimmed.starts_with("Device") || 
-               trimmed.starts_with('-') {
-                continue;
-            }
-            
-            if let Some(d) = trimmed.split_whitespace().next() {
-                device = Some(d.to_string());
-                break;
-            }
-        }
-        
-        assert_eq!(device, Some("wlan0".to_string()));
+    fn test_peap_profile() {
+        let network = EnterpriseNetwork::new("eduroam", EapMethod::Peap, "jdoe@example.org")
+            .with_anonymous_identity(Some("anonymous@example.org"))
+            .with_ca_cert(Some(Path::new("/etc/ssl/certs/ca.pem")))
+            .with_password(Some("s3cret=;#"));
+        assert_eq!(network.profile_file_name(), "eduroam.8021x");
+        assert_eq!(
+            network.profile(),
+            "[Security]\n\
+             EAP-Method=PEAP\n\
+             EAP-Identity=anonymous@example.org\n\
+             EAP-PEAP-CACert=/etc/ssl/certs/ca.pem\n\
+             EAP-PEAP-Phase2-Method=MSCHAPV2\n\
+             EAP-PEAP-Phase2-Identity=jdoe@example.org\n\
+             EAP-PEAP-Phase2-Password=s3cret=;#\n\
+             \n\
+             [Settings]\n\
+             AutoConnect=true\n"
+        );
+    }
+
+    #[test]
+    fn test_ttls_profile_tunnels_phase2() {
+        let network = EnterpriseNetwork::new("Corp WiFi", EapMethod::Ttls, "jdoe")
+            .with_phase2(Some(Phase2Method::Pap))
+            .with_password(Some("pw"))
+            .with_hidden(true);
+        let profile = network.profile();
+        // Without an anonymous identity the real one goes outside too
+        assert!(profile.contains("EAP-Identity=jdoe\n"));
+        assert!(profile.contains("EAP-TTLS-Phase2-Method=Tunneled-PAP\n"));
+        assert!(profile.contains("EAP-TTLS-Phase2-Password=pw\n"));
+        assert!(!profile.contains("CACert"));
+        assert!(profile.ends_with("Hidden=true\n"));
+    }
+
+    #[test]
+    fn test_device_list_parsing() {
+        // iwd 1.x prints the same columns without colours
+        let output = "\
+                                    Devices                                   *
+--------------------------------------------------------------------------------
+  Name                  Address               Powered     Adapter     Mode
+--------------------------------------------------------------------------------
+  wlp3s0                a0:88:b4:1c:2d:3e     on          phy0        station
+";
+        let devices = parse_device_list(output);
+        assert_eq!(devices.len(), 1);
+        assert_eq!(devices[0].name, "wlp3s0");
+        assert_eq!(devices[0].mode, DeviceMode::Station);
+        assert_eq!(devices[0].adapter, "phy0");
+        assert_eq!(devices[0].address, "a0:88:b4:1c:2d:3e");
+        assert!(devices[0].powered);
     }
 }
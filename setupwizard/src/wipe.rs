@@ -0,0 +1,335 @@
+use crate::common::{command_exists, run_command, CommandResult, SetupError};
+use crate::disk::{block_name_in, discard_max_bytes_in, disk_partitions_in, disk_size_bytes_in, is_rotational_in};
+use std::fmt;
+use std::path::Path;
+use std::str::FromStr;
+use std::time::Duration;
+
+/// Throughputs used for time estimates, in bytes per second
+const DISCARD_RATE: u64 = 50_000_000_000;
+const HDD_WRITE_RATE: u64 = 150_000_000;
+const SATA_SSD_WRITE_RATE: u64 = 400_000_000;
+const NVME_WRITE_RATE: u64 = 1_000_000_000;
+
+/// Temporary ATA password; SECURITY ERASE UNIT clears it again
+pub const ATA_ERASE_PASSWORD: &str = "asenos";
+
+/// How to clear a disk before it is partitioned
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum WipeMode {
+    /// Only replace the partition table
+    #[default]
+    None,
+    /// Remove filesystem, RAID and LVM signatures with wipefs
+    Signatures,
+    /// Discard every block with blkdiscard
+    Discard,
+    /// NVMe format or ATA SECURITY ERASE UNIT
+    SecureErase,
+    /// Overwrite the whole disk with random data
+    Random,
+}
+
+impl WipeMode {
+    pub const ALL: [WipeMode; 5] = [
+        WipeMode::None,
+        WipeMode::Signatures,
+        WipeMode::Discard,
+        WipeMode::SecureErase,
+        WipeMode::Random,
+    ];
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            WipeMode::None => "none",
+            WipeMode::Signatures => "signatures",
+            WipeMode::Discard => "discard",
+            WipeMode::SecureErase => "secure-erase",
+            WipeMode::Random => "random",
+        }
+    }
+
+    pub fn description(&self) -> &'static str {
+        match self {
+            WipeMode::None => "keep old data, only write a new partition table",
+            WipeMode::Signatures => "remove filesystem, RAID and LVM signatures",
+            WipeMode::Discard => "discard every block (SSDs only)",
+            WipeMode::SecureErase => "erase the disk with its built-in secure erase",
+            WipeMode::Random => "overwrite the whole disk with random data",
+        }
+    }
+
+    /// Word the user must type to start this wipe
+    pub fn confirmation_word(&self) -> Option<&'static str> {
+        match self {
+            WipeMode::None => None,
+            WipeMode::Signatures => Some("wipe"),
+            WipeMode::Discard => Some("discard"),
+            WipeMode::SecureErase => Some("erase"),
+            WipeMode::Random => Some("overwrite"),
+        }
+    }
+}
+
+impl FromStr for WipeMode {
+    type Err = SetupError;
+
+    fn from_str(s: &str) -> CommandResult<Self> {
+        let value = s.trim().to_ascii_lowercase();
+        Self::ALL.into_iter().find(|m| m.name() == value).ok_or_else(|| {
+            SetupError::InvalidInput(format!(
+                "Unknown wipe mode '{}'. Valid: {}",
+                s,
+                Self::ALL.map(|m| m.name()).join(", ")
+            ))
+        })
+    }
+}
+
+impl fmt::Display for WipeMode {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.name())
+    }
+}
+
+/// ATA security state as reported by `hdparm -I`
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct AtaSecurity {
+    pub supported: bool,
+    pub frozen: bool,
+    /// Time the drive itself estimates for SECURITY ERASE UNIT
+    pub erase_minutes: Option<u64>,
+}
+
+/// Parse the Security section of `hdparm -I` output
+pub fn parse_hdparm_security(output: &str) -> AtaSecurity {
+    let mut security = AtaSecurity::default();
+    let section = output
+        .lines()
+        .skip_while(|line| !line.starts_with("Security:"))
+        .skip(1)
+        .take_while(|line| line.starts_with('\t') || line.starts_with(' ') || line.is_empty());
+
+    for line in section {
+        let words: Vec<&str> = line.split_whitespace().collect();
+        match words.as_slice() {
+            ["supported"] => security.supported = true,
+            ["frozen"] => security.frozen = true,
+            [minutes, "for", "SECURITY", "ERASE", ..] => {
+                security.erase_minutes = minutes.trim_end_matches("min").parse().ok();
+            }
+            _ => {}
+        }
+    }
+
+    security
+}
+
+/// Human-readable form of a time estimate
+pub fn format_estimate(estimate: Duration) -> String {
+    let secs = estimate.as_secs().max(1);
+    match secs {
+        0..=89 => format!("about {} seconds", secs),
+        90..=5399 => format!("about {} minutes", (secs + 30) / 60),
+        _ => format!("about {} hours {} minutes", secs / 3600, (secs % 3600 + 30) / 60),
+    }
+}
+
+/// A wipe of one disk, checked against what the disk supports
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct WipePlan {
+    pub mode: WipeMode,
+    pub disk: String,
+    pub bytes: u64,
+    pub estimate: Duration,
+    commands: Vec<Vec<String>>,
+    /// Undo what the first command set up when a later one fails
+    recovery: Vec<Vec<String>>,
+}
+
+impl WipePlan {
+    /// Commands the wipe runs, in order
+    pub fn commands(&self) -> &[Vec<String>] {
+        &self.commands
+    }
+
+    /// Commands run when the wipe fails after its first command
+    pub fn recovery_commands(&self) -> &[Vec<String>] {
+        &self.recovery
+    }
+
+    /// Prompt naming the disk, the mode, the estimate and the word to type
+    pub fn confirm_prompt(&self) -> String {
+        format!(
+            "This will {} on {} ({} GiB), taking {}. Type '{}' to continue: ",
+            self.mode.description(),
+            self.disk,
+            self.bytes / (1024 * 1024 * 1024),
+            format_estimate(self.estimate),
+            self.mode.confirmation_word().unwrap_or("yes")
+        )
+    }
+
+    /// Accept `answer` only if it is this mode's confirmation word
+    pub fn check_confirmation(&self, answer: &str) -> CommandResult<()> {
+        match self.mode.confirmation_word() {
+            Some(word) if answer.trim() != word => Err(SetupError::InvalidInput(format!(
+                "{} wipe of {} not confirmed; type '{}' to confirm",
+                self.mode, self.disk, word
+            ))),
+            _ => Ok(()),
+        }
+    }
+
+    /// Run the wipe. A secure erase that fails once the drive has a
+    /// password has it disabled again, and the error names the password.
+    pub fn run(&self) -> CommandResult<()> {
+        for (i, command) in self.commands.iter().enumerate() {
+            if let Err(e) = run_args(command) {
+                if i == 0 || self.recovery.is_empty() {
+                    return Err(e);
+                }
+                return Err(self.recover(e));
+            }
+        }
+        Ok(())
+    }
+
+    fn recover(&self, error: SetupError) -> SetupError {
+        match self.recovery.iter().try_for_each(|c| run_args(c).map(drop)) {
+            Ok(()) => SetupError::System(format!(
+                "{}; the temporary ATA password '{}' of {} was removed again",
+                error, ATA_ERASE_PASSWORD, self.disk
+            )),
+            Err(disable_err) => SetupError::System(format!(
+                "{}; {} is left locked with ATA password '{}' and removing it failed ({}). \
+                 Unlock it with: hdparm --user-master u --security-disable {} {}",
+                error, self.disk, ATA_ERASE_PASSWORD, disable_err, ATA_ERASE_PASSWORD, self.disk
+            )),
+        }
+    }
+}
+
+fn command(args: &[&str]) -> Vec<String> {
+    args.iter().map(|s| s.to_string()).collect()
+}
+
+fn run_args(command: &[String]) -> CommandResult<String> {
+    let args: Vec<&str> = command.iter().map(String::as_str).collect();
+    run_command(&args, None)
+}
+
+/// Plan a wipe of `disk` from the sysfs tree under `root`.
+/// `ata` is the drive's security state, needed for secure erase of
+/// non-NVMe disks.
+pub fn plan_wipe_in(root: &Path, disk: &str, mode: WipeMode, ata: Option<AtaSecurity>) -> CommandResult<WipePlan> {
+    let name = block_name_in(root, disk);
+    let bytes = disk_size_bytes_in(root, &name)?;
+    let nvme = name.starts_with("nvme");
+    let write_rate = match (nvme, is_rotational_in(root, &name)) {
+        (true, _) => NVME_WRITE_RATE,
+        (false, true) => HDD_WRITE_RATE,
+        (false, false) => SATA_SSD_WRITE_RATE,
+    };
+    let at_rate = |rate: u64| Duration::from_secs(bytes / rate);
+
+    let mut recovery = Vec::new();
+    let (commands, estimate) = match mode {
+        WipeMode::None => (Vec::new(), Duration::ZERO),
+        WipeMode::Signatures => {
+            // Partitions first: their signatures are unreachable once the table is gone
+            let mut devices: Vec<String> = disk_partitions_in(root, &name)
+                .into_iter()
+                .map(|p| format!("/dev/{}", p))
+                .collect();
+            devices.push(disk.to_string());
+            let estimate = Duration::from_secs(devices.len() as u64);
+            (devices.iter().map(|d| command(&["wipefs", "--all", d])).collect(), estimate)
+        }
+        WipeMode::Discard => {
+            if discard_max_bytes_in(root, &name) == 0 {
+                return Err(SetupError::InvalidInput(format!("{} does not support discard", disk)));
+            }
+            let estimate = at_rate(DISCARD_RATE).max(Duration::from_secs(10));
+            (vec![command(&["blkdiscard", "--force", disk])], estimate)
+        }
+        WipeMode::SecureErase if nvme => (
+            vec![command(&["nvme", "format", disk, "--ses=1", "--force"])],
+            at_rate(NVME_WRITE_RATE),
+        ),
+        WipeMode::SecureErase => {
+            let ata = ata.unwrap_or_default();
+            if !ata.supported {
+                return Err(SetupError::InvalidInput(format!(
+                    "{} does not support ATA secure erase", disk
+                )));
+            }
+            if ata.frozen {
+                return Err(SetupError::InvalidInput(format!(
+                    "{} is security frozen; suspend and resume the machine to unfreeze it", disk
+                )));
+            }
+            let estimate = ata
+                .erase_minutes
+                .map(|m| Duration::from_secs(m * 60))
+                .unwrap_or_else(|| at_rate(write_rate));
+            recovery.push(command(&["hdparm", "--user-master", "u", "--security-disable", ATA_ERASE_PASSWORD, disk]));
+            (
+                vec![
+                    command(&["hdparm", "--user-master", "u", "--security-set-pass", ATA_ERASE_PASSWORD, disk]),
+                    command(&["hdparm", "--user-master", "u", "--security-erase", ATA_ERASE_PASSWORD, disk]),
+                ],
+                estimate,
+            )
+        }
+        WipeMode::Random => (vec![command(&["shred", "--iterations=1", disk])], at_rate(write_rate)),
+    };
+
+    Ok(WipePlan { mode, disk: disk.to_string(), bytes, estimate, commands, recovery })
+}
+
+/// Plan a wipe of `disk`, checking the tools the mode needs are present
+pub fn plan_wipe(disk: &str, mode: WipeMode) -> CommandResult<WipePlan> {
+    let root = Path::new("/");
+    let nvme = block_name_in(root, disk).starts_with("nvme");
+
+    let tool = match mode {
+        WipeMode::None => None,
+        WipeMode::Signatures => Some("wipefs"),
+        WipeMode::Discard => Some("blkdiscard"),
+        WipeMode::SecureErase if nvme => Some("nvme"),
+        WipeMode::SecureErase => Some("hdparm"),
+        WipeMode::Random => Some("shred"),
+    };
+    if let Some(tool) = tool.filter(|t| !command_exists(t)) {
+        return Err(SetupError::System(format!("{} wipe needs {}, which is not installed", mode, tool)));
+    }
+
+    let ata = if mode == WipeMode::SecureErase && !nvme {
+        Some(parse_hdparm_security(&run_command(&["hdparm", "-I", disk], None)?))
+    } else {
+        None
+    };
+
+    plan_wipe_in(root, disk, mode, ata)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_modes() {
+        for mode in WipeMode::ALL {
+            assert_eq!(mode.name().parse::<WipeMode>().unwrap(), mode);
+        }
+        assert!("zero".parse::<WipeMode>().is_err());
+    }
+
+    #[test]
+    fn test_format_estimate() {
+        assert_eq!(format_estimate(Duration::from_secs(3)), "about 3 seconds");
+        assert_eq!(format_estimate(Duration::from_secs(600)), "about 10 minutes");
+        assert_eq!(format_estimate(Duration::from_secs(3 * 3600 + 20 * 60)), "about 3 hours 20 minutes");
+    }
+}
@@ -8,7 +8,7 @@ mod cli_integration_tests {
     #[test]
     fn test_binary_compilation() {
         let output = Command::new("cargo")
-            .args(&["build"])
+            .args(["build"])
             .current_dir(".")
             .output()
             .expect("Failed to execute cargo build");
@@ -22,7 +22,7 @@ mod cli_integration_tests {
     #[test]
     fn test_cli_help_output() {
         let output = Command::new("cargo")
-            .args(&["run", "--", "--help"])
+            .args(["run", "--", "--help"])
             .current_dir(".")
             .output()
             .expect("Failed to execute command");
@@ -42,7 +42,7 @@ mod cli_integration_tests {
     #[test]
     fn test_version_output() {
         let output = Command::new("cargo")
-            .args(&["run", "--", "--version"])
+            .args(["run", "--", "--version"])
             .current_dir(".")
             .output()
             .expect("Failed to execute command");
@@ -54,7 +54,7 @@ mod cli_integration_tests {
     #[test]
     fn test_list_disks_command() {
         let output = Command::new("cargo")
-            .args(&["run", "--", "--list-disks"])
+            .args(["run", "--", "--list-disks"])
             .current_dir(".")
             .output()
             .expect("Failed to execute command");
@@ -166,11 +166,11 @@ mod cli_integration_tests {
     #[test]
     fn test_error_handling() {
         // Test that functions return proper error types
-        let result = cli_funcs::set_keymap("");
+        let result = cli_funcs::set_keymap("", None, None);
         assert!(result.is_err());
         matches!(result.unwrap_err(), SetupError::InvalidInput(_));
 
-        let result = cli_funcs::connect_wifi("", None);
+        let result = cli_funcs::connect_wifi("", None, None, false);
         assert!(result.is_err());
         matches!(result.unwrap_err(), SetupError::InvalidInput(_));
     }
@@ -179,7 +179,7 @@ mod cli_integration_tests {
     fn test_no_args_behavior() {
         // Test that running with no arguments doesn't crash
         let output = Command::new("cargo")
-            .args(&["run"])
+            .args(["run"])
             .current_dir(".")
             .output()
             .expect("Failed to execute command");
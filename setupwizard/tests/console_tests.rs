@@ -0,0 +1,84 @@
+use setupwizard::common::SetupError;
+use setupwizard::console::*;
+use setupwizard::keymap::configure_keymap;
+use setupwizard::target::{Target, VCONSOLE_CONF};
+use std::fs;
+use std::path::Path;
+
+#[cfg(test)]
+mod console_tests {
+    use super::*;
+
+    fn install_fonts(root: &Path, fonts: &[&str]) {
+        let dir = root.join(CONSOLE_FONTS_DIR);
+        fs::create_dir_all(&dir).unwrap();
+        for font in fonts {
+            fs::write(dir.join(font), "").unwrap();
+        }
+    }
+
+    #[test]
+    fn test_fb_resolution() {
+        let dir = tempfile::tempdir().unwrap();
+        assert_eq!(fb_resolution_in(dir.path()), None);
+
+        let fb0 = dir.path().join("sys/class/graphics/fb0");
+        fs::create_dir_all(&fb0).unwrap();
+        fs::write(fb0.join("virtual_size"), "3840,2160\n").unwrap();
+        assert_eq!(fb_resolution_in(dir.path()), Some((3840, 2160)));
+    }
+
+    #[test]
+    fn test_choose_font_for_screen_and_script() {
+        let dir = tempfile::tempdir().unwrap();
+        install_fonts(
+            dir.path(),
+            &["ter-v16n.psf.gz", "ter-v32b.psf.gz", "ter-v32n.psf.gz", "LatArCyrHeb-16.psfu.gz", "README.terminus"],
+        );
+
+        assert_eq!(choose_font_in(dir.path(), Script::Latin, Some(2160)).unwrap(), "ter-v32n");
+        assert_eq!(choose_font_in(dir.path(), Script::Cyrillic, None).unwrap(), "ter-v16n");
+        // kbd has no Arabic font that large, the biggest installed one is used
+        assert_eq!(choose_font_in(dir.path(), Script::Arabic, Some(2160)).unwrap(), "LatArCyrHeb-16");
+
+        let result = choose_font_in(dir.path(), Script::Greek, Some(1440));
+        assert!(matches!(result, Err(SetupError::System(msg)) if msg.contains("No greek console font for 24 px")));
+        let result = choose_font_in(dir.path(), Script::Ethiopic, None);
+        assert!(result.is_err());
+    }
+
+    #[test]
+    fn test_check_font() {
+        let dir = tempfile::tempdir().unwrap();
+        install_fonts(dir.path(), &["ter-v16n.psf.gz"]);
+        assert_eq!(available_fonts_in(dir.path()), vec!["ter-v16n"]);
+        assert!(check_font_in(dir.path(), "ter-v16n").is_ok());
+
+        let result = check_font_in(dir.path(), "ter-v16n.psf.gz");
+        assert!(matches!(result, Err(SetupError::InvalidInput(msg)) if msg.contains("not installed")));
+        let result = check_font_in(dir.path(), " ");
+        assert!(matches!(result, Err(SetupError::InvalidInput(msg)) if msg.contains("cannot be empty")));
+    }
+
+    #[test]
+    fn test_configure_font_in_target() {
+        let dir = tempfile::tempdir().unwrap();
+        let target = Target::new(dir.path());
+        target
+            .write_file("etc/mkinitcpio.conf", "HOOKS=(base udev autodetect modconf kms keyboard block filesystems fsck)\n")
+            .unwrap();
+        install_fonts(dir.path(), &["ter-v32n.psf.gz"]);
+
+        configure_font(&target, "ter-v32n").unwrap();
+        configure_keymap(&target, "us").unwrap();
+        assert_eq!(fs::read_to_string(target.path(VCONSOLE_CONF)).unwrap(), "FONT=ter-v32n\nKEYMAP=us\n");
+        assert_eq!(
+            target.initramfs_hooks().unwrap().join(" "),
+            "base udev autodetect modconf kms keyboard keymap consolefont block filesystems fsck"
+        );
+
+        // The installed system must have the font, not just the ISO
+        let result = configure_font(&target, "ter-v16n");
+        assert!(matches!(result, Err(SetupError::InvalidInput(msg)) if msg.contains("'ter-v16n' is not installed")));
+    }
+}
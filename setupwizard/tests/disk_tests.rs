@@ -0,0 +1,302 @@
+use setupwizard::common::SetupError;
+use setupwizard::disk::*;
+use std::fs;
+use std::path::Path;
+use std::time::Duration;
+
+#[cfg(test)]
+mod disk_tests {
+    use super::*;
+
+    /// Build a sysfs/procfs fixture with an internal disk sda (two
+    /// partitions) and the live USB stick sdb carrying the ISO
+    fn fixture(root: &Path) {
+        let block = root.join("sys/class/block");
+        for (disk, sectors, parts) in [("sda", "8589934592", vec!["sda1", "sda2"]), ("sdb", "31260672", vec!["sdb1"])] {
+            fs::create_dir_all(block.join(disk)).unwrap();
+            fs::write(block.join(disk).join("size"), sectors).unwrap();
+            for (i, part) in parts.iter().enumerate() {
+                // Real sysfs has partitions both nested in the disk and as top-level links
+                for dir in [block.join(disk).join(part), block.join(part)] {
+                    fs::create_dir_all(&dir).unwrap();
+                    fs::write(dir.join("partition"), (i + 1).to_string()).unwrap();
+                    fs::write(dir.join("size"), "2048").unwrap();
+                }
+            }
+        }
+        fs::create_dir_all(root.join("proc")).unwrap();
+        fs::write(root.join("proc/mounts"), "proc /proc proc rw 0 0\n").unwrap();
+        fs::write(root.join("proc/swaps"), "Filename Type Size Used Priority\n").unwrap();
+    }
+
+    #[test]
+    fn test_whole_disk_and_partitions() {
+        let dir = tempfile::tempdir().unwrap();
+        fixture(dir.path());
+
+        assert!(is_whole_disk_in(dir.path(), "sda"));
+        assert!(!is_whole_disk_in(dir.path(), "sda1"));
+        assert_eq!(disk_partitions_in(dir.path(), "sda"), vec!["sda1", "sda2"]);
+        assert_eq!(parent_disk_in(dir.path(), "sda2").as_deref(), Some("sda"));
+        assert_eq!(disk_size_bytes_in(dir.path(), "sda").unwrap(), 2 * MBR_MAX_BYTES);
+    }
+
+    #[test]
+    fn test_block_name_follows_symlinks() {
+        let dir = tempfile::tempdir().unwrap();
+        fs::create_dir_all(dir.path().join("dev/disk/by-id")).unwrap();
+        fs::write(dir.path().join("dev/nvme0n1"), "").unwrap();
+        std::os::unix::fs::symlink("../../nvme0n1", dir.path().join("dev/disk/by-id/nvme-Samsung_SSD")).unwrap();
+
+        assert_eq!(block_name_in(dir.path(), "/dev/disk/by-id/nvme-Samsung_SSD"), "nvme0n1");
+        assert_eq!(block_name_in(dir.path(), "/dev/sdq"), "sdq");
+    }
+
+    #[test]
+    fn test_check_target_disk_accepts_idle_disk() {
+        let dir = tempfile::tempdir().unwrap();
+        fixture(dir.path());
+
+        assert!(check_target_disk_in(dir.path(), "/dev/sda").is_ok());
+    }
+
+    #[test]
+    fn test_check_target_disk_refuses_partition() {
+        let dir = tempfile::tempdir().unwrap();
+        fixture(dir.path());
+
+        let result = check_target_disk_in(dir.path(), "/dev/sda1");
+        assert!(matches!(result, Err(SetupError::InvalidInput(msg)) if msg.contains("whole disk (sda)")));
+    }
+
+    #[test]
+    fn test_check_target_disk_refuses_unknown_device() {
+        let dir = tempfile::tempdir().unwrap();
+        fixture(dir.path());
+
+        let result = check_target_disk_in(dir.path(), "/dev/sdz");
+        assert!(matches!(result, Err(SetupError::InvalidInput(msg)) if msg.contains("not a block device")));
+    }
+
+    #[test]
+    fn test_check_target_disk_refuses_mounted_partition() {
+        let dir = tempfile::tempdir().unwrap();
+        fixture(dir.path());
+        fs::write(dir.path().join("proc/mounts"), "/dev/sda2 /mnt/data ext4 rw 0 0\n").unwrap();
+
+        let result = check_target_disk_in(dir.path(), "/dev/sda");
+        assert!(matches!(result, Err(SetupError::InvalidInput(msg)) if msg.contains("mounted at /mnt/data")));
+    }
+
+    #[test]
+    fn test_check_target_disk_refuses_active_swap() {
+        let dir = tempfile::tempdir().unwrap();
+        fixture(dir.path());
+        fs::write(
+            dir.path().join("proc/swaps"),
+            "Filename Type Size Used Priority\n/dev/sda1 partition 1024 0 -2\n",
+        ).unwrap();
+
+        let result = check_target_disk_in(dir.path(), "/dev/sda");
+        assert!(matches!(result, Err(SetupError::InvalidInput(msg)) if msg.contains("swap")));
+    }
+
+    #[test]
+    fn test_check_target_disk_refuses_held_partition() {
+        let dir = tempfile::tempdir().unwrap();
+        fixture(dir.path());
+        fs::create_dir_all(dir.path().join("sys/class/block/sda2/holders/dm-0")).unwrap();
+
+        let result = check_target_disk_in(dir.path(), "/dev/sda");
+        assert!(matches!(result, Err(SetupError::InvalidInput(msg)) if msg.contains("dm-0")));
+    }
+
+    #[test]
+    fn test_live_medium_by_boot_mount() {
+        let dir = tempfile::tempdir().unwrap();
+        fixture(dir.path());
+        fs::write(
+            dir.path().join("proc/mounts"),
+            format!("/dev/sdb1 {} iso9660 ro 0 0\n", ARCHISO_BOOT_MOUNT),
+        ).unwrap();
+
+        assert_eq!(live_medium_disk_in(dir.path()).as_deref(), Some("sdb"));
+        let result = check_target_disk_in(dir.path(), "/dev/sdb");
+        assert!(matches!(result, Err(SetupError::InvalidInput(msg)) if msg.contains("live system")));
+    }
+
+    #[test]
+    fn test_live_medium_by_label() {
+        let dir = tempfile::tempdir().unwrap();
+        fixture(dir.path());
+        fs::create_dir_all(dir.path().join("dev/disk/by-label")).unwrap();
+        fs::write(dir.path().join("dev/sdb1"), "").unwrap();
+        std::os::unix::fs::symlink("../../sdb1", dir.path().join("dev/disk/by-label/ASENOS_202610")).unwrap();
+
+        assert_eq!(live_medium_disk_in(dir.path()).as_deref(), Some("sdb"));
+    }
+
+    #[test]
+    fn test_live_medium_by_search_uuid() {
+        let dir = tempfile::tempdir().unwrap();
+        fixture(dir.path());
+        fs::write(dir.path().join("proc/cmdline"), "archisobasedir=asenos archisosearchuuid=2026-10-01-12-00-00-00\n").unwrap();
+        fs::create_dir_all(dir.path().join("dev/disk/by-uuid")).unwrap();
+        fs::write(dir.path().join("dev/sdb"), "").unwrap();
+        std::os::unix::fs::symlink("../../sdb", dir.path().join("dev/disk/by-uuid/2026-10-01-12-00-00-00")).unwrap();
+
+        assert_eq!(live_medium_disk_in(dir.path()).as_deref(), Some("sdb"));
+    }
+
+    #[test]
+    fn test_no_live_medium() {
+        let dir = tempfile::tempdir().unwrap();
+        fixture(dir.path());
+
+        assert_eq!(live_medium_disk_in(dir.path()), None);
+    }
+
+    #[test]
+    fn test_mbr_warning_on_large_disk() {
+        let dir = tempfile::tempdir().unwrap();
+        fixture(dir.path());
+
+        let warnings = disk_warnings_in(dir.path(), "/dev/sda", false);
+        assert_eq!(warnings.len(), 1);
+        assert!(warnings[0].contains("2 TiB"));
+
+        assert!(disk_warnings_in(dir.path(), "/dev/sda", true).is_empty());
+        assert!(disk_warnings_in(dir.path(), "/dev/sdb", false).is_empty());
+    }
+
+    #[test]
+    fn test_partition_node_from_sysfs() {
+        let dir = tempfile::tempdir().unwrap();
+        fixture(dir.path());
+
+        assert_eq!(partition_node_in(dir.path(), "sda", 2).as_deref(), Some("/dev/sda2"));
+        assert_eq!(partition_node_in(dir.path(), "sda", 3), None);
+    }
+
+    #[test]
+    fn test_partition_node_uses_partition_attribute() {
+        // Partition numbers need not follow directory names or be contiguous,
+        // as after installing into free space next to another system
+        let dir = tempfile::tempdir().unwrap();
+        let disk = dir.path().join("sys/class/block/loop0");
+        for (part, number) in [("loop0p1", "1"), ("loop0p5", "7")] {
+            fs::create_dir_all(disk.join(part)).unwrap();
+            fs::write(disk.join(part).join("partition"), format!("{}\n", number)).unwrap();
+        }
+
+        assert_eq!(partition_node_in(dir.path(), "loop0", 7).as_deref(), Some("/dev/loop0p5"));
+        assert_eq!(partition_node_in(dir.path(), "loop0", 5), None);
+    }
+
+    #[test]
+    fn test_wait_for_partitions_returns_nodes() {
+        let dir = tempfile::tempdir().unwrap();
+        fixture(dir.path());
+        fs::create_dir_all(dir.path().join("dev")).unwrap();
+        fs::write(dir.path().join("dev/sda1"), "").unwrap();
+        fs::write(dir.path().join("dev/sda2"), "").unwrap();
+
+        let nodes = wait_for_partitions_in(dir.path(), "sda", &[2, 1], Duration::ZERO).unwrap();
+        assert_eq!(nodes, vec!["/dev/sda2", "/dev/sda1"]);
+    }
+
+    #[test]
+    fn test_wait_for_partitions_sees_late_nodes() {
+        let dir = tempfile::tempdir().unwrap();
+        fixture(dir.path());
+        fs::create_dir_all(dir.path().join("dev")).unwrap();
+        fs::write(dir.path().join("dev/sda1"), "").unwrap();
+
+        let late = dir.path().join("dev/sda2");
+        let udev = std::thread::spawn(move || {
+            std::thread::sleep(Duration::from_millis(150));
+            fs::write(late, "").unwrap();
+        });
+
+        let nodes = wait_for_partitions_in(dir.path(), "sda", &[1, 2], Duration::from_secs(5)).unwrap();
+        assert_eq!(nodes, vec!["/dev/sda1", "/dev/sda2"]);
+        udev.join().unwrap();
+    }
+
+    #[test]
+    fn test_wait_for_partitions_timeout_names_missing_nodes() {
+        let dir = tempfile::tempdir().unwrap();
+        fixture(dir.path());
+        fs::create_dir_all(dir.path().join("dev")).unwrap();
+        fs::write(dir.path().join("dev/sda1"), "").unwrap();
+
+        let result = wait_for_partitions_in(dir.path(), "sda", &[1, 2, 3], Duration::from_millis(100));
+        assert!(matches!(result, Err(SetupError::System(msg))
+            if msg.contains("/dev/sda2") && msg.contains("partition 3 of /dev/sda") && !msg.contains("sda1")));
+    }
+
+    #[test]
+    fn test_installer_labels_are_not_the_live_medium() {
+        assert!(is_iso_label("ASENOS_202610"));
+        assert!(!is_iso_label("ASENOS_ROOT"));
+        assert!(!is_iso_label("ASENOS_ESP"));
+
+        let dir = tempfile::tempdir().unwrap();
+        fixture(dir.path());
+        fs::create_dir_all(dir.path().join("dev/disk/by-label")).unwrap();
+        fs::write(dir.path().join("dev/sda2"), "").unwrap();
+        std::os::unix::fs::symlink("../../sda2", dir.path().join("dev/disk/by-label/ASENOS_ROOT")).unwrap();
+
+        assert_eq!(live_medium_disk_in(dir.path()), None);
+        assert!(check_target_disk_in(dir.path(), "/dev/sda").is_ok());
+    }
+
+    #[test]
+    fn test_whole_disks_skip_virtual_devices() {
+        let dir = tempfile::tempdir().unwrap();
+        for name in ["sda", "nvme0n1", "loop0", "zram0", "sr0"] {
+            fs::create_dir_all(dir.path().join("sys/block").join(name)).unwrap();
+        }
+
+        assert_eq!(whole_disks_in(dir.path()), vec!["nvme0n1", "sda"]);
+    }
+
+    #[test]
+    fn test_storage_media_from_queue_attributes() {
+        let dir = tempfile::tempdir().unwrap();
+        let block = dir.path().join("sys/class/block");
+        for (name, rotational, granularity, max_bytes) in [
+            ("sda", "1", "0", "0"),
+            ("sdb", "0", "512", "2147450880"),
+            ("sdc", "0", "0", "0"),
+            ("nvme0n1", "0", "4096", "2199023255040"),
+        ] {
+            fs::create_dir_all(block.join(name).join("queue")).unwrap();
+            fs::write(block.join(name).join("queue/rotational"), rotational).unwrap();
+            fs::write(block.join(name).join("queue/discard_granularity"), granularity).unwrap();
+            fs::write(block.join(name).join("queue/discard_max_bytes"), max_bytes).unwrap();
+        }
+
+        assert_eq!(storage_media_in(dir.path(), "sda"), StorageMedia::Rotational);
+        assert_eq!(storage_media_in(dir.path(), "sdb"), StorageMedia::SolidState { trim: true });
+        assert_eq!(storage_media_in(dir.path(), "sdc"), StorageMedia::SolidState { trim: false });
+        assert!(storage_media_in(dir.path(), "nvme0n1").supports_trim());
+        // Without sysfs information the disk is treated as rotational
+        assert_eq!(storage_media_in(dir.path(), "sdz"), StorageMedia::Rotational);
+    }
+
+    #[test]
+    fn test_logical_block_size_from_queue() {
+        let dir = tempfile::tempdir().unwrap();
+        let block = dir.path().join("sys/class/block");
+        for (name, size) in [("sda", "512\n"), ("sdb", "4096\n"), ("sdc", "0\n")] {
+            fs::create_dir_all(block.join(name).join("queue")).unwrap();
+            fs::write(block.join(name).join("queue/logical_block_size"), size).unwrap();
+        }
+
+        assert_eq!(logical_block_size_in(dir.path(), "sda"), Some(512));
+        assert_eq!(logical_block_size_in(dir.path(), "sdb"), Some(4096));
+        assert_eq!(logical_block_size_in(dir.path(), "sdc"), None);
+        assert_eq!(logical_block_size_in(dir.path(), "sdz"), None);
+    }
+}
@@ -0,0 +1,160 @@
+use setupwizard::common::{command_exists, SetupError};
+use setupwizard::disk::StorageMedia;
+use setupwizard::filesystem::*;
+use setupwizard::partition::{format_plan, format_plan_in, InstallPartitions, PartitionConfig, PartitionRole};
+use setupwizard::target::fstab;
+use setupwizard::FirmwareMode;
+use std::fs;
+use std::os::unix::fs::symlink;
+
+#[cfg(test)]
+mod filesystem_tests {
+    use super::*;
+
+    const UUID: &str = "0f3c9a4e-5b6d-4e7f-8a9b-0c1d2e3f4a5b";
+
+    #[test]
+    fn test_root_choices() {
+        for name in ["ext4", "btrfs", "xfs", "f2fs", "bcachefs"] {
+            assert!(name.parse::<Filesystem>().unwrap().is_root_capable(), "{} should be a root choice", name);
+        }
+        for fs in [Filesystem::Jfs, Filesystem::Exfat, Filesystem::Vfat, Filesystem::Swap] {
+            assert!(!fs.is_root_capable());
+        }
+
+        let result = "ntfs".parse::<Filesystem>();
+        assert!(matches!(result, Err(SetupError::InvalidInput(msg)) if msg.contains("f2fs") && msg.contains("bcachefs")));
+    }
+
+    #[test]
+    fn test_mkfs_commands() {
+        let spec = |fs| FormatSpec::new(fs, ROOT_LABEL).with_uuid(UUID.to_string());
+
+        assert_eq!(
+            spec(Filesystem::Ext4).mkfs_command("/dev/sda3"),
+            vec!["mkfs.ext4", "-F", "-L", "ASENOS_ROOT", "-U", UUID, "/dev/sda3"]
+        );
+        assert_eq!(
+            spec(Filesystem::Xfs).mkfs_command("/dev/sda3"),
+            vec!["mkfs.xfs", "-f", "-L", "ASENOS_ROOT", "-m", &format!("uuid={}", UUID), "/dev/sda3"]
+        );
+        assert_eq!(
+            spec(Filesystem::F2fs).mkfs_command("/dev/sda3"),
+            vec![
+                "mkfs.f2fs", "-f", "-l", "ASENOS_ROOT", "-U", UUID,
+                "-O", "extra_attr,inode_checksum,sb_checksum,compression", "/dev/sda3",
+            ]
+        );
+        assert_eq!(
+            spec(Filesystem::Bcachefs).mkfs_command("/dev/sda3"),
+            vec!["bcachefs", "format", "-f", "-L", "ASENOS_ROOT", &format!("--uuid={}", UUID), "/dev/sda3"]
+        );
+        assert_eq!(
+            FormatSpec::new(Filesystem::Btrfs, ROOT_LABEL)
+                .with_features(vec!["block-group-tree".to_string()])
+                .mkfs_command("/dev/sda3"),
+            vec!["mkfs.btrfs", "-f", "-L", "ASENOS_ROOT", "-O", "block-group-tree", "/dev/sda3"]
+        );
+        assert_eq!(
+            FormatSpec::new(Filesystem::Swap, SWAP_LABEL).mkfs_command("/dev/sda2"),
+            vec!["mkswap", "-L", "ASENOS_SWAP", "/dev/sda2"]
+        );
+    }
+
+    #[test]
+    fn test_spec_validation() {
+        let long_label = FormatSpec::new(Filesystem::Xfs, "ASENOS_ROOTFS");
+        assert!(matches!(long_label.validate(), Err(SetupError::InvalidInput(msg)) if msg.contains("12 characters")));
+
+        let jfs_uuid = FormatSpec::new(Filesystem::Jfs, ROOT_LABEL).with_uuid(UUID.to_string());
+        assert!(matches!(jfs_uuid.validate(), Err(SetupError::InvalidInput(msg)) if msg.contains("UUID")));
+
+        for fs in Filesystem::ROOT_CHOICES {
+            assert!(FormatSpec::new(fs, ROOT_LABEL).validate().is_ok(), "{} should take the root label", fs);
+        }
+    }
+
+    #[test]
+    fn test_mount_options() {
+        let ssd = StorageMedia::SolidState { trim: true };
+        let hdd = StorageMedia::Rotational;
+
+        assert!(Filesystem::Btrfs.mount_options(ssd).contains("compress=zstd"));
+        assert!(Filesystem::F2fs.mount_options(ssd).contains("compress_algorithm=zstd"));
+        assert!(Filesystem::Vfat.mount_options(hdd).contains("umask=0077"));
+        assert!(Filesystem::ROOT_CHOICES.iter().all(|fs| fs.mount_options(ssd).contains("noatime")));
+        assert!(Filesystem::Ext4.mount_options(hdd).contains("relatime"));
+    }
+
+    #[test]
+    fn test_btrfs_discard_follows_trim_support() {
+        let trim = StorageMedia::SolidState { trim: true };
+        let no_trim = StorageMedia::SolidState { trim: false };
+
+        assert_eq!(Filesystem::Btrfs.mount_options(trim), "defaults,noatime,compress=zstd,ssd,discard=async");
+        assert!(!Filesystem::Btrfs.mount_options(no_trim).contains("discard"));
+        assert!(Filesystem::Btrfs.mount_options(StorageMedia::Rotational).contains("autodefrag"));
+        assert_eq!(Filesystem::Swap.mount_options(trim), "defaults,discard=once");
+        assert_eq!(Filesystem::Swap.mount_options(no_trim), "defaults");
+    }
+
+    #[test]
+    fn test_format_plan_labels_and_uuids() {
+        let partitions = InstallPartitions {
+            boot: Some("/dev/sda1".to_string()),
+            swap: "/dev/sda2".to_string(),
+            root: "/dev/sda3".to_string(),
+            format_boot: true,
+        };
+        let config = PartitionConfig::new("/dev/sda".to_string(), 512, 2048, true, Filesystem::F2fs)
+            .with_firmware(FirmwareMode::Uefi { bits: 64 });
+
+        let volumes = format_plan(&config, &partitions).unwrap();
+        let roles: Vec<PartitionRole> = volumes.iter().map(|v| v.role).collect();
+        assert_eq!(roles, vec![PartitionRole::Esp, PartitionRole::Swap, PartitionRole::Root]);
+        assert_eq!(volumes[0].format.label, ESP_LABEL);
+        assert_eq!(volumes[2].format.filesystem, Filesystem::F2fs);
+        assert_eq!(volumes[2].format.label, ROOT_LABEL);
+        assert!(volumes.iter().all(|v| v.formatted && v.format.uuid.as_ref().is_some_and(|u| u.len() == 36)));
+
+        // A reused ESP is reported but not reformatted, and BIOS installs have none
+        let root = tempfile::tempdir().unwrap();
+        fs::create_dir_all(root.path().join("dev/disk/by-uuid")).unwrap();
+        fs::write(root.path().join("dev/sda1"), "").unwrap();
+        symlink("../../sda1", root.path().join("dev/disk/by-uuid/1A2B-3C4D")).unwrap();
+        let reused = InstallPartitions { format_boot: false, ..partitions.clone() };
+        let volumes = format_plan_in(root.path(), &config, &reused).unwrap();
+        assert!(!volumes[0].formatted);
+        // fstab finds it by its volume ID rather than the device node
+        assert!(fstab(&volumes, StorageMedia::Rotational).contains("UUID=1A2B-3C4D\t/boot\tvfat\t"));
+
+        fs::remove_file(root.path().join("dev/disk/by-uuid/1A2B-3C4D")).unwrap();
+        assert!(format_plan_in(root.path(), &config, &reused).is_err());
+
+        let bios = config.with_firmware(FirmwareMode::Bios);
+        assert_eq!(format_plan(&bios, &partitions).unwrap().len(), 2);
+    }
+
+    #[test]
+    fn test_format_ext4_image() {
+        if !command_exists("mkfs.ext4") {
+            return;
+        }
+
+        let dir = tempfile::tempdir().unwrap();
+        let image = dir.path().join("root.img");
+        fs::File::create(&image).unwrap().set_len(64 * 1024 * 1024).unwrap();
+
+        FormatSpec::new(Filesystem::Ext4, ROOT_LABEL)
+            .with_uuid(UUID.to_string())
+            .format(&image.to_string_lossy())
+            .unwrap();
+
+        // The superblock starts at 1024 with the UUID at 0x68 and the label at 0x78
+        let data = fs::read(&image).unwrap();
+        let superblock = &data[1024..2048];
+        let uuid: String = superblock[0x68..0x78].iter().map(|b| format!("{:02x}", b)).collect();
+        assert_eq!(uuid, UUID.replace('-', ""));
+        assert_eq!(&superblock[0x78..0x78 + ROOT_LABEL.len()], ROOT_LABEL.as_bytes());
+    }
+}
@@ -0,0 +1,41 @@
+use setupwizard::firmware::{detect_firmware_mode, detect_firmware_mode_in, FirmwareMode};
+use std::fs;
+
+#[cfg(test)]
+mod firmware_tests {
+    use super::*;
+
+    #[test]
+    fn test_detect_firmware_mode_host() {
+        // Either mode is valid, but UEFI must report a known bitness
+        if let FirmwareMode::Uefi { bits } = detect_firmware_mode() {
+            assert!(bits == 32 || bits == 64, "Unexpected bitness: {}", bits);
+        }
+    }
+
+    #[test]
+    fn test_detect_firmware_mode_fixture() {
+        let dir = tempfile::tempdir().unwrap();
+        assert_eq!(detect_firmware_mode_in(dir.path()), FirmwareMode::Bios);
+
+        let efi = dir.path().join("firmware/efi");
+        fs::create_dir_all(&efi).unwrap();
+        fs::write(efi.join("fw_platform_size"), "64").unwrap();
+        assert!(detect_firmware_mode_in(dir.path()).is_uefi());
+    }
+
+    #[test]
+    fn test_invalid_platform_size_defaults_to_64() {
+        let dir = tempfile::tempdir().unwrap();
+        let efi = dir.path().join("firmware/efi");
+        fs::create_dir_all(&efi).unwrap();
+        fs::write(efi.join("fw_platform_size"), "garbage").unwrap();
+        assert_eq!(detect_firmware_mode_in(dir.path()), FirmwareMode::Uefi { bits: 64 });
+    }
+
+    #[test]
+    fn test_display() {
+        assert_eq!(FirmwareMode::Uefi { bits: 32 }.to_string(), "UEFI (32-bit)");
+        assert_eq!(FirmwareMode::Bios.to_string(), "BIOS");
+    }
+}
@@ -0,0 +1,108 @@
+use setupwizard::common::SetupError;
+use setupwizard::filesystem::{FormatSpec, SWAP_LABEL};
+use setupwizard::hibernate::*;
+use setupwizard::partition::{PartitionRole, Volume};
+use setupwizard::target::Target;
+use setupwizard::Filesystem;
+use std::fs;
+
+#[cfg(test)]
+mod hibernate_tests {
+    use super::*;
+
+    const SWAP_UUID: &str = "7a1b2c3d-4e5f-4a6b-8c7d-9e0f1a2b3c4d";
+
+    fn swap_volume(uuid: Option<&str>) -> Volume {
+        let mut format = FormatSpec::new(Filesystem::Swap, SWAP_LABEL);
+        if let Some(uuid) = uuid {
+            format = format.with_uuid(uuid.to_string());
+        }
+        Volume { role: PartitionRole::Swap, device: "/dev/sda2".to_string(), format, formatted: true, subvolume: None }
+    }
+
+    /// Target with a stock mkinitcpio.conf using `hooks`
+    fn target_with_hooks(root: &std::path::Path, hooks: &str) -> Target {
+        let target = Target::new(root);
+        target
+            .write_file(
+                "etc/mkinitcpio.conf",
+                &format!("MODULES=()\n# HOOKS=(base)\nHOOKS=({})\nCOMPRESSION=\"zstd\"\n", hooks),
+            )
+            .unwrap();
+        target
+    }
+
+    #[test]
+    fn test_mem_total_from_meminfo() {
+        let dir = tempfile::tempdir().unwrap();
+        fs::create_dir_all(dir.path().join("proc")).unwrap();
+        fs::write(
+            dir.path().join("proc/meminfo"),
+            "MemTotal:       16318268 kB\nMemFree:         9143360 kB\n",
+        )
+        .unwrap();
+
+        assert_eq!(mem_total_mib_in(dir.path()).unwrap(), 15936);
+    }
+
+    #[test]
+    fn test_resume_from_swap_partition() {
+        let resume = ResumeDevice::from_volumes(&[swap_volume(Some(SWAP_UUID))]).unwrap();
+        assert_eq!(resume.kernel_params(), vec![format!("resume=UUID={}", SWAP_UUID)]);
+
+        let result = ResumeDevice::from_volumes(&[]);
+        assert!(matches!(result, Err(SetupError::InvalidInput(msg)) if msg.contains("swap partition")));
+        assert!(ResumeDevice::from_volumes(&[swap_volume(None)]).is_err());
+    }
+
+    #[test]
+    fn test_configure_hibernation_adds_resume_hook_and_params() {
+        let dir = tempfile::tempdir().unwrap();
+        let target = target_with_hooks(dir.path(), "base udev autodetect block filesystems fsck");
+        target.write_file("etc/kernel/cmdline", "root=UUID=1234 rw resume=/dev/sdb2\n").unwrap();
+        target
+            .write_file("etc/default/grub", "GRUB_TIMEOUT=5\nGRUB_CMDLINE_LINUX_DEFAULT=\"loglevel=3 quiet\"\n")
+            .unwrap();
+
+        let resume = ResumeDevice::Partition { uuid: SWAP_UUID.to_string() };
+        configure_hibernation(&target, &resume).unwrap();
+        // Running it again changes nothing
+        configure_hibernation(&target, &resume).unwrap();
+
+        assert_eq!(
+            target.initramfs_hooks().unwrap(),
+            vec!["base", "udev", "autodetect", "block", "resume", "filesystems", "fsck"]
+        );
+        let config = fs::read_to_string(target.path("etc/mkinitcpio.conf")).unwrap();
+        assert!(config.contains("# HOOKS=(base)\n"));
+        assert!(config.contains("COMPRESSION=\"zstd\""));
+
+        let params = format!("resume=UUID={}", SWAP_UUID);
+        assert_eq!(
+            fs::read_to_string(target.path("etc/kernel/cmdline")).unwrap(),
+            format!("root=UUID=1234 rw {}\n", params)
+        );
+        let grub = fs::read_to_string(target.path("etc/default/grub")).unwrap();
+        assert!(grub.contains(&format!("GRUB_CMDLINE_LINUX_DEFAULT=\"loglevel=3 quiet {}\"", params)));
+        assert!(grub.starts_with("GRUB_TIMEOUT=5\n"));
+    }
+
+    #[test]
+    fn test_configure_hibernation_with_systemd_initramfs() {
+        let dir = tempfile::tempdir().unwrap();
+        let target = target_with_hooks(dir.path(), "base systemd autodetect block filesystems fsck");
+
+        configure_hibernation(&target, &ResumeDevice::Partition { uuid: SWAP_UUID.to_string() }).unwrap();
+
+        assert!(!target.initramfs_hooks().unwrap().contains(&"resume".to_string()));
+        assert!(fs::read_to_string(target.path("etc/kernel/cmdline")).unwrap().contains("resume=UUID="));
+        assert!(!target.path("etc/default/grub").exists());
+    }
+
+    #[test]
+    fn test_configure_hibernation_needs_base_system() {
+        let dir = tempfile::tempdir().unwrap();
+        let result = configure_hibernation(&Target::new(dir.path()), &ResumeDevice::Partition { uuid: SWAP_UUID.to_string() });
+        assert!(matches!(result, Err(SetupError::InvalidInput(msg)) if msg.contains("etc/mkinitcpio.conf")));
+    }
+}
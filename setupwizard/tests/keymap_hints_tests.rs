@@ -0,0 +1,116 @@
+use setupwizard::keymap::{Keymap, LayoutFamily};
+use setupwizard::keymap_hints::*;
+use std::fs;
+use std::os::unix::fs::symlink;
+use std::path::{Path, PathBuf};
+
+#[cfg(test)]
+mod keymap_hints_tests {
+    use super::*;
+
+    fn keymaps(names: &[&str]) -> Vec<Keymap> {
+        names
+            .iter()
+            .map(|name| Keymap {
+                name: name.to_string(),
+                family: LayoutFamily::Unknown,
+                arch: "i386".to_string(),
+                path: PathBuf::from(format!("/usr/share/kbd/keymaps/i386/{}.map.gz", name)),
+                compressed: true,
+            })
+            .collect()
+    }
+
+    /// PlatformLang as efivarfs returns it: attributes, then the tag
+    fn platform_lang(lang: &str) -> Vec<u8> {
+        let mut var = vec![0x07, 0x00, 0x00, 0x00];
+        var.extend_from_slice(lang.as_bytes());
+        var.push(0);
+        var
+    }
+
+    fn system(root: &Path, cmdline: &str, locale: Option<&str>, zone: Option<&str>, lang: Option<&str>) {
+        fs::create_dir_all(root.join("proc")).unwrap();
+        fs::write(root.join("proc/cmdline"), cmdline).unwrap();
+        fs::create_dir_all(root.join("etc")).unwrap();
+        fs::create_dir_all(root.join("usr/share/zoneinfo")).unwrap();
+        fs::write(
+            root.join("usr/share/zoneinfo/zone.tab"),
+            "# tz zone descriptions\nDE\t+5230+01322\tEurope/Berlin\tmost of Germany\nFR\t+4852+00220\tEurope/Paris\n",
+        )
+        .unwrap();
+        if let Some(locale) = locale {
+            fs::write(root.join("etc/locale.conf"), format!("LANG=\"{}\"\nLC_TIME=C\n", locale)).unwrap();
+        }
+        if let Some(zone) = zone {
+            symlink(format!("/usr/share/zoneinfo/{}", zone), root.join("etc/localtime")).unwrap();
+        }
+        if let Some(lang) = lang {
+            let vars = root.join("sys/firmware/efi/efivars");
+            fs::create_dir_all(&vars).unwrap();
+            fs::write(vars.join("PlatformLang-8be4df61-93ca-11d2-aa0d-00e098032b8c"), platform_lang(lang)).unwrap();
+        }
+    }
+
+    #[test]
+    fn test_read_hints() {
+        let dir = tempfile::tempdir().unwrap();
+        system(dir.path(), "initrd=x vconsole.keymap=fr-bepo\n", Some("de_DE.UTF-8"), Some("Europe/Paris"), Some("en-US"));
+
+        let hints = KeymapHints::read_in(dir.path());
+        assert_eq!(hints.cmdline_keymap.as_deref(), Some("fr-bepo"));
+        assert_eq!(hints.locale.as_deref(), Some("de_DE.UTF-8"));
+        assert_eq!(hints.timezone.as_deref(), Some("Europe/Paris"));
+        assert_eq!(hints.timezone_country.as_deref(), Some("FR"));
+        assert_eq!(hints.platform_lang.as_deref(), Some("en-US"));
+    }
+
+    #[test]
+    fn test_missing_hints() {
+        let dir = tempfile::tempdir().unwrap();
+        system(dir.path(), "quiet", None, Some("UTC"), None);
+        assert_eq!(KeymapHints::read_in(dir.path()), KeymapHints::default());
+    }
+
+    #[test]
+    fn test_suggestions_in_priority_order() {
+        let dir = tempfile::tempdir().unwrap();
+        system(dir.path(), "vconsole.keymap=fr-bepo", Some("de_DE.UTF-8"), Some("Europe/Paris"), Some("en-US"));
+        let available = keymaps(&["de-latin1", "fr", "fr-bepo", "us"]);
+
+        let suggestions = KeymapHints::read_in(dir.path()).suggestions(&available);
+        let found: Vec<(&str, String)> =
+            suggestions.iter().map(|s| (s.keymap.as_str(), s.source.to_string())).collect();
+        assert_eq!(
+            found,
+            vec![
+                ("fr-bepo", "kernel command line".to_string()),
+                ("de-latin1", "locale de_DE.UTF-8".to_string()),
+                ("fr", "time zone Europe/Paris".to_string()),
+                ("us", "firmware language en-US".to_string()),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_suggestions_skip_duplicates_and_missing_keymaps() {
+        let hints = KeymapHints {
+            cmdline_keymap: Some("no-such-map".to_string()),
+            locale: Some("de_AT.UTF-8".to_string()),
+            timezone: Some("Europe/Berlin".to_string()),
+            timezone_country: Some("DE".to_string()),
+            platform_lang: Some("de-DE".to_string()),
+        };
+        let suggestions = hints.suggestions(&keymaps(&["de-latin1", "us"]));
+        assert_eq!(suggestions.len(), 1);
+        assert_eq!(suggestions[0].keymap, "de-latin1");
+        assert_eq!(suggestions[0].source, HintSource::Locale("de_AT.UTF-8".to_string()));
+    }
+
+    #[test]
+    fn test_platform_lang_parsing() {
+        assert_eq!(parse_platform_lang(&platform_lang("fr-FR")).as_deref(), Some("fr-FR"));
+        assert_eq!(parse_platform_lang(&[7, 0, 0]), None);
+        assert_eq!(parse_platform_lang(&[7, 0, 0, 0, 0]), None);
+    }
+}
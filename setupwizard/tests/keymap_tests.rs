@@ -1,5 +1,18 @@
-use setupwizard::keymap::{available_keymaps, set_keymap};
+use setupwizard::keymap::{
+    available_keymaps, available_keymaps_in, find_keymap_in, group_keymaps, search_keymaps, set_keymap, suggest_keymaps,
+    await_confirmation, configure_keymap, xkb_layout, Grouping, KeymapBackup, LayoutFamily, LineReader, TrialOutcome, XkbLayout,
+    KEYMAPS_DIR, TRIAL_TEXT, X11_KEYBOARD_CONF,
+};
+use std::io::{BufRead, Cursor};
+use std::sync::{mpsc, Arc, Mutex};
+use std::time::Duration;
+use setupwizard::target::{Target, VCONSOLE_CONF};
 use setupwizard::common::SetupError;
+use flate2::write::GzEncoder;
+use flate2::Compression;
+use std::fs;
+use std::io::Write;
+use std::path::Path;
 
 #[cfg(test)]
 mod keymap_tests {
@@ -14,7 +27,7 @@ mod keymap_tests {
             Ok(keymaps) => {
                 assert!(!keymaps.is_empty(), "Should return some keymaps");
                 // Common keymaps that should be available
-                let has_common = keymaps.iter().any(|k| k == "us" || k == "uk" || k == "de");
+                let has_common = keymaps.iter().any(|k| k.name == "us" || k.name == "uk" || k.name == "de");
                 if !has_common {
                     println!("Warning: No common keymaps found. Available: {:?}", keymaps);
                 }
@@ -76,9 +89,9 @@ mod keymap_tests {
             Ok(keymaps) => {
                 if let Some(first_keymap) = keymaps.first() {
                     // Try to set a valid keymap
-                    let result = set_keymap(first_keymap);
+                    let result = set_keymap(&first_keymap.name);
                     match result {
-                        Ok(_) => println!("Successfully set keymap: {}", first_keymap),
+                        Ok(_) => println!("Successfully set keymap: {}", first_keymap.name),
                         Err(SetupError::CommandFailed(_)) => {
                             // This is expected if we don't have permission to change keymap
                             println!("Permission denied for keymap change (expected in tests)");
@@ -129,4 +142,245 @@ mod keymap_tests {
             );
         }
     }
+
+    fn write_map(root: &Path, relative: &str, text: &str) {
+        let path = root.join(KEYMAPS_DIR).join(relative);
+        fs::create_dir_all(path.parent().unwrap()).unwrap();
+        if relative.ends_with(".gz") {
+            let mut encoder = GzEncoder::new(fs::File::create(path).unwrap(), Compression::default());
+            encoder.write_all(text.as_bytes()).unwrap();
+            encoder.finish().unwrap();
+        } else {
+            fs::write(path, text).unwrap();
+        }
+    }
+
+    /// A slice of the kbd tree as shipped on the ISO
+    fn keymap_tree(root: &Path) {
+        write_map(root, "i386/qwerty/us.map.gz", "include \"qwerty-layout\"\ninclude \"linux-with-alt-and-altgr\"\n");
+        write_map(root, "i386/qwertz/de-latin1.map.gz", "include \"linux-with-alt-and-altgr\"\n");
+        write_map(root, "i386/azerty/fr.map.gz", "include \"azerty-layout\"\n");
+        write_map(root, "i386/dvorak/dvorak.map", "keymaps 0-2,4\n");
+        write_map(root, "i386/include/qwerty-layout.inc.gz", "keycode 16 = +q\n");
+        write_map(root, "i386/include/windowkeys.map.gz", "keycode 125 = Decr_Console\n");
+        // No family directory, the layout comes from a nested include
+        write_map(root, "mac/all/mac-fr.map.gz", "include \"mac-azerty-base\"\n");
+        write_map(root, "mac/include/mac-azerty-base.inc", "# French Mac layout\ninclude \"azerty-layout\"\n");
+        write_map(root, "mac/all/mac-us.map.gz", "include \"mac-us-base\"\n");
+        write_map(root, "sun/sunkeymap.map", "keycode 1 = Escape\n");
+        // Same name as the i386 map, loadkeys prefers i386
+        write_map(root, "atari/us.map", "keycode 1 = Escape\n");
+        write_map(root, "i386/qwerty/README", "not a keymap");
+    }
+
+    #[test]
+    fn test_keymaps_from_tree() {
+        let dir = tempfile::tempdir().unwrap();
+        keymap_tree(dir.path());
+
+        let keymaps = available_keymaps_in(dir.path()).unwrap();
+        let names: Vec<&str> = keymaps.iter().map(|k| k.name.as_str()).collect();
+        assert_eq!(names, vec!["de-latin1", "dvorak", "fr", "mac-fr", "mac-us", "sunkeymap", "us"]);
+
+        let by_name = |name: &str| keymaps.iter().find(|k| k.name == name).unwrap();
+        let us = by_name("us");
+        assert_eq!((us.family, us.arch.as_str(), us.compressed), (LayoutFamily::Qwerty, "i386", true));
+        assert_eq!(us.path, dir.path().join(KEYMAPS_DIR).join("i386/qwerty/us.map.gz"));
+        assert_eq!(by_name("de-latin1").family, LayoutFamily::Qwertz);
+        let dvorak = by_name("dvorak");
+        assert_eq!((dvorak.family, dvorak.compressed), (LayoutFamily::Dvorak, false));
+        assert_eq!(by_name("mac-fr").family, LayoutFamily::Azerty);
+        // The include does not exist, so nothing tells the layout
+        assert_eq!(by_name("mac-us").family, LayoutFamily::Unknown);
+        assert_eq!(by_name("sunkeymap").arch, "sun");
+    }
+
+    #[test]
+    fn test_keymaps_missing_tree() {
+        let dir = tempfile::tempdir().unwrap();
+        let result = available_keymaps_in(dir.path());
+        assert!(matches!(result, Err(SetupError::System(msg)) if msg.contains("No keymaps found")));
+    }
+
+    #[test]
+    fn test_find_keymap_in_tree() {
+        let dir = tempfile::tempdir().unwrap();
+        keymap_tree(dir.path());
+
+        assert_eq!(find_keymap_in(dir.path(), "fr").unwrap().family, LayoutFamily::Azerty);
+        // Include fragments are not loadable keymaps
+        let result = find_keymap_in(dir.path(), "windowkeys");
+        assert!(matches!(result, Err(SetupError::InvalidInput(msg)) if msg.contains("Unknown keymap 'windowkeys'")));
+    }
+
+    #[test]
+    fn test_unknown_keymap_suggests_close_names() {
+        let dir = tempfile::tempdir().unwrap();
+        keymap_tree(dir.path());
+
+        let result = find_keymap_in(dir.path(), "de-latni1");
+        assert!(matches!(result, Err(SetupError::InvalidInput(msg)) if msg.ends_with("Did you mean: de-latin1?")));
+        // Nothing is close, so no list of every keymap either
+        let result = find_keymap_in(dir.path(), "xxxxxxxxxx");
+        assert!(matches!(result, Err(SetupError::InvalidInput(msg)) if !msg.contains("Did you mean") && !msg.contains("sunkeymap")));
+    }
+
+    #[test]
+    fn test_suggestions_nearest_first() {
+        let dir = tempfile::tempdir().unwrap();
+        keymap_tree(dir.path());
+        let keymaps = available_keymaps_in(dir.path()).unwrap();
+
+        assert_eq!(suggest_keymaps(&keymaps, "US"), vec!["us"]);
+        assert_eq!(suggest_keymaps(&keymaps, "mac"), vec!["mac-fr", "mac-us"]);
+        assert_eq!(suggest_keymaps(&keymaps, "fx"), vec!["fr"]);
+        assert!(suggest_keymaps(&keymaps, "").is_empty());
+    }
+
+    #[test]
+    fn test_search_and_group_keymaps() {
+        let dir = tempfile::tempdir().unwrap();
+        keymap_tree(dir.path());
+        let keymaps = available_keymaps_in(dir.path()).unwrap();
+
+        let names = |found: Vec<&setupwizard::keymap::Keymap>| found.iter().map(|k| k.name.clone()).collect::<Vec<_>>();
+        assert_eq!(names(search_keymaps(&keymaps, "FR")), vec!["fr", "mac-fr"]);
+        assert_eq!(names(search_keymaps(&keymaps, "azerty mac")), vec!["mac-fr"]);
+        assert_eq!(names(search_keymaps(&keymaps, "")).len(), keymaps.len());
+
+        let languages: Vec<(String, usize)> = group_keymaps(&keymaps, Grouping::Language)
+            .into_iter()
+            .map(|(key, group)| (key, group.len()))
+            .collect();
+        assert_eq!(
+            languages,
+            vec![("de".to_string(), 1), ("dvorak".to_string(), 1), ("fr".to_string(), 2), ("sunkeymap".to_string(), 1), ("us".to_string(), 2)]
+        );
+        let families: Vec<String> = group_keymaps(&keymaps, Grouping::Family).into_iter().map(|(key, _)| key).collect();
+        assert_eq!(families, vec!["azerty", "dvorak", "other", "qwerty", "qwertz"]);
+    }
+
+    /// Target with an Arch default mkinitcpio.conf using `hooks`
+    fn target_with_hooks(dir: &Path, hooks: &str) -> Target {
+        let target = Target::new(dir);
+        target.write_file("etc/mkinitcpio.conf", &format!("MODULES=()\nHOOKS=({})\n", hooks)).unwrap();
+        target
+    }
+
+    #[test]
+    fn test_xkb_layout_table() {
+        let xkb = |layout: &str, variant: &str| Some(XkbLayout { layout: layout.to_string(), variant: variant.to_string() });
+        assert_eq!(xkb_layout("uk"), xkb("gb", ""));
+        assert_eq!(xkb_layout("de-latin1-nodeadkeys"), xkb("de", "nodeadkeys"));
+        assert_eq!(xkb_layout("fr_CH"), xkb("ch", "fr"));
+        assert_eq!(xkb_layout("sunkeymap"), None);
+    }
+
+    #[test]
+    fn test_configure_keymap_in_target() {
+        let dir = tempfile::tempdir().unwrap();
+        let target = target_with_hooks(dir.path(), "base udev autodetect microcode modconf kms keyboard block encrypt filesystems fsck");
+        target.write_file(VCONSOLE_CONF, "KEYMAP=us\nFONT=ter-v16n\n").unwrap();
+
+        let xkb = configure_keymap(&target, "de-latin1-nodeadkeys").unwrap();
+        assert_eq!(xkb.unwrap().layout, "de");
+        assert_eq!(
+            fs::read_to_string(target.path(VCONSOLE_CONF)).unwrap(),
+            "KEYMAP=de-latin1-nodeadkeys\nFONT=ter-v16n\n"
+        );
+        let x11 = fs::read_to_string(target.path(X11_KEYBOARD_CONF)).unwrap();
+        assert!(x11.contains("Option \"XkbLayout\" \"de\"\n"));
+        assert!(x11.contains("Option \"XkbVariant\" \"nodeadkeys\"\n"));
+        // The passphrase prompt of encrypt must see the keymap
+        assert_eq!(
+            target.initramfs_hooks().unwrap().join(" "),
+            "base udev autodetect microcode modconf kms keyboard keymap block encrypt filesystems fsck"
+        );
+    }
+
+    #[test]
+    fn test_configure_keymap_systemd_initramfs() {
+        let dir = tempfile::tempdir().unwrap();
+        let target = target_with_hooks(dir.path(), "base systemd autodetect keyboard sd-vconsole block sd-encrypt filesystems");
+
+        // Without a known X11 layout only the console is configured
+        assert_eq!(configure_keymap(&target, "sunkeymap").unwrap(), None);
+        assert!(!target.path(X11_KEYBOARD_CONF).exists());
+        assert_eq!(fs::read_to_string(target.path(VCONSOLE_CONF)).unwrap(), "KEYMAP=sunkeymap\n");
+        let hooks = target.initramfs_hooks().unwrap();
+        assert_eq!(hooks.iter().filter(|h| *h == "sd-vconsole").count(), 1);
+        assert!(!hooks.contains(&"keymap".to_string()));
+    }
+
+    #[test]
+    fn test_configure_keymap_needs_base_system() {
+        let dir = tempfile::tempdir().unwrap();
+        let result = configure_keymap(&Target::new(dir.path()), "us");
+        assert!(matches!(result, Err(SetupError::InvalidInput(msg)) if msg.contains("install the base system first")));
+        assert!(!dir.path().join(VCONSOLE_CONF).exists());
+    }
+
+    #[test]
+    fn test_trial_confirmation() {
+        let (sender, receiver) = mpsc::channel();
+        sender.send(format!("{}\r\n", TRIAL_TEXT)).unwrap();
+        assert_eq!(await_confirmation(&receiver, TRIAL_TEXT, Duration::from_secs(5)), TrialOutcome::Confirmed);
+
+        // With a broken layout the text comes out garbled
+        sender.send("qiuw wevra zham \"42\n".to_string()).unwrap();
+        assert_eq!(
+            await_confirmation(&receiver, TRIAL_TEXT, Duration::from_secs(5)),
+            TrialOutcome::Mismatch("qiuw wevra zham \"42".to_string())
+        );
+    }
+
+    #[test]
+    fn test_trial_times_out() {
+        let (sender, receiver) = mpsc::channel::<String>();
+        assert_eq!(await_confirmation(&receiver, TRIAL_TEXT, Duration::from_millis(20)), TrialOutcome::TimedOut);
+        // Closed input cannot confirm either
+        drop(sender);
+        assert_eq!(await_confirmation(&receiver, TRIAL_TEXT, Duration::from_secs(5)), TrialOutcome::TimedOut);
+    }
+
+    #[test]
+    fn test_line_reader_reads_only_when_asked() {
+        let input = Arc::new(Mutex::new(Cursor::new(format!("garbled\n{}\nnext prompt\n", TRIAL_TEXT))));
+        let shared = Arc::clone(&input);
+        let reader = LineReader::spawn(move |line| shared.lock().unwrap().read_line(line));
+
+        let timeout = Duration::from_secs(5);
+        assert_eq!(reader.await_confirmation(TRIAL_TEXT, timeout), TrialOutcome::Mismatch("garbled".to_string()));
+        assert_eq!(reader.await_confirmation(TRIAL_TEXT, timeout), TrialOutcome::Confirmed);
+        drop(reader);
+
+        // The line after the trial is left for the next prompt
+        let mut rest = String::new();
+        input.lock().unwrap().read_line(&mut rest).unwrap();
+        assert_eq!(rest, "next prompt\n");
+    }
+
+    #[test]
+    fn test_line_reader_end_of_input() {
+        let mut input = Cursor::new(String::new());
+        let reader = LineReader::spawn(move |line| input.read_line(line));
+        assert_eq!(reader.await_confirmation(TRIAL_TEXT, Duration::from_secs(5)), TrialOutcome::TimedOut);
+    }
+
+    #[test]
+    fn test_backup_falls_back_to_configured_keymap() {
+        let dir = tempfile::tempdir().unwrap();
+        let target = Target::new(dir.path());
+        target.write_file(VCONSOLE_CONF, "KEYMAP=\"de-latin1\"\n").unwrap();
+
+        // dumpkeys needs a console; without one the configured name is kept
+        match KeymapBackup::save_in(dir.path()).unwrap() {
+            KeymapBackup::Named(name) => assert_eq!(name, "de-latin1"),
+            KeymapBackup::Dump(path) => assert!(path.exists()),
+        }
+        let empty = tempfile::tempdir().unwrap();
+        if let KeymapBackup::Named(name) = KeymapBackup::save_in(empty.path()).unwrap() {
+            assert_eq!(name, "us");
+        }
+    }
 }
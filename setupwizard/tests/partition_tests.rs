@@ -1,5 +1,8 @@
 use setupwizard::partition::*;
 use setupwizard::common::SetupError;
+use setupwizard::filesystem::Filesystem;
+use setupwizard::firmware::FirmwareMode;
+use setupwizard::size::SizeSpec;
 
 #[cfg(test)]
 mod partition_tests {
@@ -12,14 +15,14 @@ mod partition_tests {
             512,
             2048,
             true,
-            "ext4".to_string(),
+            Filesystem::Ext4,
         );
 
         assert_eq!(config.disk, "/dev/sda");
-        assert_eq!(config.boot_size_mb, 512);
-        assert_eq!(config.swap_size_mb, 2048);
+        assert_eq!(config.boot_size, SizeSpec::mib(512));
+        assert_eq!(config.swap_size, SizeSpec::mib(2048));
         assert!(config.use_gpt);
-        assert_eq!(config.filesystem, "ext4");
+        assert_eq!(config.filesystem, Filesystem::Ext4);
     }
 
     #[test]
@@ -62,7 +65,7 @@ mod partition_tests {
             512,
             2048,
             true,
-            "ext4".to_string(),
+            Filesystem::Ext4,
         );
 
         let result = config.validate();
@@ -78,7 +81,7 @@ mod partition_tests {
             50,
             2048,
             true,
-            "ext4".to_string(),
+            Filesystem::Ext4,
         );
         assert!(config.validate().is_err());
 
@@ -88,7 +91,7 @@ mod partition_tests {
             3000,
             2048,
             true,
-            "ext4".to_string(),
+            Filesystem::Ext4,
         );
         assert!(config.validate().is_err());
 
@@ -98,7 +101,7 @@ mod partition_tests {
             512,
             2048,
             true,
-            "ext4".to_string(),
+            Filesystem::Ext4,
         );
         // This will fail because /dev/sda doesn't exist in test environment
         // but it tests the size validation logic
@@ -115,7 +118,7 @@ mod partition_tests {
             512,
             100, // Too small
             true,
-            "ext4".to_string(),
+            Filesystem::Ext4,
         );
 
         let result = config.validate();
@@ -127,18 +130,19 @@ mod partition_tests {
 
     #[test]
     fn test_validate_config_invalid_filesystem() {
-        let invalid_filesystems = vec!["fat32", "ntfs", "invalid", ""];
+        let invalid_filesystems = vec!["fat32", "exfat", "swap", "ntfs", "invalid", ""];
 
         for fs in invalid_filesystems {
-            let config = PartitionConfig::new(
-                "/dev/sda".to_string(),
-                512,
-                2048,
-                true,
-                fs.to_string(),
-            );
-
-            let result = config.validate();
+            let result = fs.parse::<Filesystem>().and_then(|filesystem| {
+                PartitionConfig::new(
+                    "/dev/sda".to_string(),
+                    512,
+                    2048,
+                    true,
+                    filesystem,
+                )
+                .validate()
+            });
             assert!(result.is_err(), "Should fail for filesystem: {}", fs);
             if let Err(SetupError::InvalidInput(msg)) = result {
                 assert!(msg.contains("Filesystem"));
@@ -153,11 +157,11 @@ mod partition_tests {
             512,
             2048,
             true,
-            "ext4".to_string(),
+            Filesystem::Ext4,
         );
 
         let (boot, swap, root) = config.get_partition_names();
-        assert_eq!(boot, "/dev/nvme0n1p1");
+        assert_eq!(boot.as_deref(), Some("/dev/nvme0n1p1"));
         assert_eq!(swap, "/dev/nvme0n1p2");
         assert_eq!(root, "/dev/nvme0n1p3");
     }
@@ -169,11 +173,11 @@ mod partition_tests {
             512,
             2048,
             true,
-            "ext4".to_string(),
+            Filesystem::Ext4,
         );
 
         let (boot, swap, root) = config.get_partition_names();
-        assert_eq!(boot, "/dev/mmcblk0p1");
+        assert_eq!(boot.as_deref(), Some("/dev/mmcblk0p1"));
         assert_eq!(swap, "/dev/mmcblk0p2");
         assert_eq!(root, "/dev/mmcblk0p3");
     }
@@ -185,15 +189,41 @@ mod partition_tests {
             512,
             2048,
             true,
-            "ext4".to_string(),
+            Filesystem::Ext4,
         );
 
         let (boot, swap, root) = config.get_partition_names();
-        assert_eq!(boot, "/dev/sda1");
+        assert_eq!(boot.as_deref(), Some("/dev/sda1"));
         assert_eq!(swap, "/dev/sda2");
         assert_eq!(root, "/dev/sda3");
     }
 
+    #[test]
+    fn test_bcachefs_root_needs_esp() {
+        let config = |firmware, use_gpt| {
+            PartitionConfig::new("/dev/sda".to_string(), 512, 2048, use_gpt, Filesystem::Bcachefs).with_firmware(firmware)
+        };
+
+        for (firmware, use_gpt) in [(FirmwareMode::Bios, true), (FirmwareMode::Bios, false)] {
+            let result = config(firmware, use_gpt).validate();
+            assert!(matches!(result, Err(SetupError::InvalidInput(msg)) if msg.contains("GRUB cannot read bcachefs")));
+        }
+        if let Err(SetupError::InvalidInput(msg)) = config(FirmwareMode::Uefi { bits: 64 }, true).validate() {
+            assert!(!msg.contains("bcachefs"), "Unexpected error: {}", msg);
+        }
+    }
+
+    #[test]
+    fn test_get_partition_names_bios_mbr() {
+        let config = PartitionConfig::new("/dev/sda".to_string(), 512, 2048, false, Filesystem::Ext4)
+            .with_firmware(FirmwareMode::Bios);
+
+        let (boot, swap, root) = config.get_partition_names();
+        assert_eq!(boot, None);
+        assert_eq!(swap, "/dev/sda1");
+        assert_eq!(root, "/dev/sda2");
+    }
+
     #[test]
     fn test_clone_and_debug() {
         let config = PartitionConfig::new(
@@ -201,13 +231,13 @@ mod partition_tests {
             512,
             2048,
             true,
-            "ext4".to_string(),
+            Filesystem::Ext4,
         );
 
         let cloned = config.clone();
         assert_eq!(config.disk, cloned.disk);
-        assert_eq!(config.boot_size_mb, cloned.boot_size_mb);
-        assert_eq!(config.swap_size_mb, cloned.swap_size_mb);
+        assert_eq!(config.boot_size, cloned.boot_size);
+        assert_eq!(config.swap_size, cloned.swap_size);
         assert_eq!(config.use_gpt, cloned.use_gpt);
         assert_eq!(config.filesystem, cloned.filesystem);
 
@@ -219,7 +249,7 @@ mod partition_tests {
 
     #[test]
     fn test_valid_filesystems() {
-        let valid_filesystems = vec!["ext4", "btrfs", "xfs"];
+        let valid_filesystems = vec!["ext4", "btrfs", "xfs", "f2fs", "bcachefs"];
 
         for fs in valid_filesystems {
             let config = PartitionConfig::new(
@@ -227,7 +257,7 @@ mod partition_tests {
                 512,
                 2048,
                 true,
-                fs.to_string(),
+                fs.parse::<Filesystem>().unwrap(),
             );
 
             // Will fail due to non-existent disk but filesystem validation should pass
@@ -237,4 +267,455 @@ mod partition_tests {
             }
         }
     }
+
+    #[test]
+    fn test_config_from_string_install_mode() {
+        // Parsing succeeds up to the disk existence check for both modes
+        for mode in ["wipe", "free"] {
+            let result = PartitionConfig::from_string(&format!("/dev/sdz999:512:2048:gpt:ext4:{}", mode));
+            if let Err(SetupError::InvalidInput(msg)) = result {
+                assert!(msg.contains("does not exist"), "Unexpected error for {}: {}", mode, msg);
+            }
+        }
+
+        let result = PartitionConfig::from_string("/dev/sdz999:512:2048:gpt:ext4:shrink");
+        if let Err(SetupError::InvalidInput(msg)) = result {
+            assert!(msg.contains("install mode"));
+        } else {
+            panic!("Expected unknown install mode to be rejected");
+        }
+    }
+
+    fn part(number: u32, start_mib: u64, end_mib: u64, name: &str, flags: &[&str]) -> ExistingPartition {
+        ExistingPartition {
+            number,
+            start_mib,
+            end_mib,
+            filesystem: String::new(),
+            name: name.to_string(),
+            flags: flags.iter().map(|f| f.to_string()).collect(),
+        }
+    }
+
+    /// Windows on a 100 GiB GPT disk with two free regions behind it
+    fn dual_boot_layout() -> DiskLayout {
+        DiskLayout {
+            table: "gpt".to_string(),
+            size_mib: 102400,
+            partitions: vec![
+                part(1, 1, 261, "EFI system partition", &["boot", "esp"]),
+                part(2, 261, 277, "Microsoft reserved partition", &["msftres"]),
+                part(3, 277, 50000, "Basic data partition", &["msftdata"]),
+            ],
+            free: vec![
+                FreeRegion { start_mib: 50000, end_mib: 60000 },
+                FreeRegion { start_mib: 60000, end_mib: 102400 },
+            ],
+        }
+    }
+
+    #[test]
+    fn test_free_regions_filtered_and_sorted() {
+        let layout = dual_boot_layout();
+
+        let regions = layout.free_regions(5000);
+        assert_eq!(regions[0], FreeRegion { start_mib: 60000, end_mib: 102400 });
+        assert_eq!(regions[1], FreeRegion { start_mib: 50000, end_mib: 60000 });
+
+        assert_eq!(layout.free_regions(20000).len(), 1);
+        assert!(layout.free_regions(50000).is_empty());
+    }
+
+    #[test]
+    fn test_check_free_space_picks_largest_region() {
+        let config = PartitionConfig::new(
+            "/dev/sda".to_string(),
+            512,
+            2048,
+            true,
+            Filesystem::Ext4,
+        )
+        .with_firmware(FirmwareMode::Uefi { bits: 64 })
+        .with_mode(InstallMode::FreeSpace(None));
+
+        let region = config.check_free_space(&dual_boot_layout()).unwrap();
+        assert_eq!(region, FreeRegion { start_mib: 60000, end_mib: 102400 });
+    }
+
+    #[test]
+    fn test_check_free_space_chosen_region() {
+        let layout = dual_boot_layout();
+        let chosen = FreeRegion { start_mib: 50000, end_mib: 60000 };
+        let config = PartitionConfig::new(
+            "/dev/sda".to_string(),
+            512,
+            1024,
+            true,
+            Filesystem::Ext4,
+        )
+        .with_firmware(FirmwareMode::Uefi { bits: 64 })
+        .with_mode(InstallMode::FreeSpace(Some(chosen)));
+        assert_eq!(config.check_free_space(&layout).unwrap(), chosen);
+
+        // Too small once swap grows
+        let config = config.clone();
+        let config = PartitionConfig { swap_size: SizeSpec::mib(4096), ..config };
+        assert!(config.check_free_space(&layout).is_err());
+
+        // Overlaps the Windows partition
+        let config = config.with_mode(InstallMode::FreeSpace(Some(FreeRegion { start_mib: 40000, end_mib: 60000 })));
+        assert!(config.check_free_space(&layout).is_err());
+    }
+
+    #[test]
+    fn test_check_free_space_table_mismatch() {
+        let config = PartitionConfig::new(
+            "/dev/sda".to_string(),
+            512,
+            2048,
+            false,
+            Filesystem::Ext4,
+        )
+        .with_firmware(FirmwareMode::Uefi { bits: 64 })
+        .with_mode(InstallMode::FreeSpace(None));
+
+        let result = config.check_free_space(&dual_boot_layout());
+        if let Err(SetupError::InvalidInput(msg)) = result {
+            assert!(msg.contains("gpt partition table"));
+        } else {
+            panic!("Expected table type mismatch to be rejected");
+        }
+    }
+
+    #[test]
+    fn test_partition_path() {
+        assert_eq!(partition_path("/dev/sda", 5), "/dev/sda5");
+        assert_eq!(partition_path("/dev/nvme0n1", 4), "/dev/nvme0n1p4");
+    }
+
+    #[test]
+    fn test_partition_path_digit_suffixed_disks() {
+        // Any disk name ending in a digit gets the "p" separator
+        assert_eq!(partition_path("/dev/loop7", 1), "/dev/loop7p1");
+        assert_eq!(partition_path("/dev/md0", 2), "/dev/md0p2");
+        assert_eq!(partition_path("/dev/nbd3", 3), "/dev/nbd3p3");
+        assert_eq!(partition_path("/dev/vdb", 1), "/dev/vdb1");
+        assert_eq!(partition_path("/dev/xvda", 2), "/dev/xvda2");
+    }
+
+    #[test]
+    fn test_wipe_layout_uefi_gpt() {
+        let config = PartitionConfig::new(
+            "/dev/sda".to_string(),
+            512,
+            2048,
+            true,
+            Filesystem::Ext4,
+        ).with_firmware(FirmwareMode::Uefi { bits: 64 });
+
+        let parts = wipe_partition_names(&config);
+        assert_eq!(config.boot_layout(), BootLayout::Esp);
+        assert_eq!(parts.boot.as_deref(), Some("/dev/sda1"));
+        assert_eq!(parts.root, "/dev/sda3");
+        assert!(parts.format_boot);
+    }
+
+    #[test]
+    fn test_wipe_layout_bios_gpt() {
+        let config = PartitionConfig::new(
+            "/dev/sda".to_string(),
+            512,
+            2048,
+            true,
+            Filesystem::Ext4,
+        ).with_firmware(FirmwareMode::Bios);
+
+        let parts = wipe_partition_names(&config);
+        assert_eq!(config.boot_layout(), BootLayout::BiosGrub);
+        assert_eq!(parts.boot.as_deref(), Some("/dev/sda1"));
+        assert!(!parts.format_boot, "bios_grub must not be formatted");
+        assert_eq!(config.required_free_mib(false), BIOS_GRUB_SIZE_MB + 2048 + MIN_ROOT_SIZE_MB);
+    }
+
+    #[test]
+    fn test_wipe_layout_bios_mbr() {
+        let config = PartitionConfig::new(
+            "/dev/sda".to_string(),
+            512,
+            2048,
+            false,
+            Filesystem::Ext4,
+        ).with_firmware(FirmwareMode::Bios);
+
+        let parts = wipe_partition_names(&config);
+        assert_eq!(config.boot_layout(), BootLayout::None);
+        assert_eq!(parts.boot, None);
+        assert_eq!(parts.swap, "/dev/sda1");
+        assert_eq!(parts.root, "/dev/sda2");
+    }
+
+    #[test]
+    fn test_firmware_warnings() {
+        let config = PartitionConfig::new(
+            "/dev/sda".to_string(),
+            512,
+            2048,
+            false,
+            Filesystem::Ext4,
+        );
+
+        let warnings = config.clone().with_firmware(FirmwareMode::Uefi { bits: 64 }).firmware_warnings();
+        assert_eq!(warnings.len(), 1);
+        assert!(warnings[0].contains("MBR"));
+
+        let warnings = config.clone().with_firmware(FirmwareMode::Uefi { bits: 32 }).firmware_warnings();
+        assert_eq!(warnings.len(), 2);
+
+        assert!(config.with_firmware(FirmwareMode::Bios).firmware_warnings().is_empty());
+    }
+
+    #[test]
+    fn test_validate_refuses_bios_mbr_free_space() {
+        let config = PartitionConfig::new(
+            "/dev/sda".to_string(),
+            512,
+            2048,
+            false,
+            Filesystem::Ext4,
+        )
+        .with_firmware(FirmwareMode::Bios)
+        .with_mode(InstallMode::FreeSpace(None));
+
+        let result = config.validate();
+        if let Err(SetupError::InvalidInput(msg)) = result {
+            assert!(msg.contains("requires UEFI"), "Unexpected error: {}", msg);
+        } else {
+            panic!("Expected BIOS free-space install on MBR to be refused");
+        }
+    }
+
+    #[test]
+    fn test_check_free_space_mbr_primary_limit() {
+        let layout = DiskLayout {
+            table: "msdos".to_string(),
+            size_mib: 100000,
+            partitions: vec![part(1, 1, 10000, "", &["boot"]), part(2, 10000, 20000, "", &[])],
+            free: vec![FreeRegion { start_mib: 20000, end_mib: 100000 }],
+        };
+        let config = PartitionConfig::new(
+            "/dev/sdb".to_string(),
+            512,
+            2048,
+            false,
+            Filesystem::Ext4,
+        )
+        .with_firmware(FirmwareMode::Uefi { bits: 64 })
+        .with_mode(InstallMode::FreeSpace(None));
+
+        let result = config.check_free_space(&layout);
+        assert!(matches!(result, Err(SetupError::InvalidInput(msg)) if msg.contains("primary partitions")));
+    }
+
+    #[test]
+    fn test_config_from_string_size_units() {
+        // Units parse; validation then stops at the missing disk
+        let result = PartitionConfig::from_string("/dev/sdz999:512MiB:4G:gpt:ext4:wipe:50%");
+        if let Err(SetupError::InvalidInput(msg)) = result {
+            assert!(msg.contains("does not exist"), "Unexpected error: {}", msg);
+        }
+
+        let result = PartitionConfig::from_string("/dev/sdz999:512MiB:4X:gpt:ext4");
+        assert!(matches!(result, Err(SetupError::InvalidInput(msg)) if msg.contains("swap size")));
+    }
+
+    #[test]
+    fn test_validate_rejects_rest_for_boot() {
+        let config = PartitionConfig::new(
+            "/dev/sda".to_string(),
+            SizeSpec::Rest,
+            2048,
+            true,
+            Filesystem::Ext4,
+        );
+
+        assert!(matches!(config.validate(), Err(SetupError::InvalidInput(msg)) if msg.contains("rest")));
+    }
+
+    #[test]
+    fn test_resolve_sizes_esp_layout() {
+        let config = PartitionConfig::new(
+            "/dev/sda".to_string(),
+            "1G".parse::<SizeSpec>().unwrap(),
+            "10%".parse::<SizeSpec>().unwrap(),
+            true,
+            Filesystem::Ext4,
+        ).with_firmware(FirmwareMode::Uefi { bits: 64 });
+
+        let sizes = config.resolve_sizes(100_000, false).unwrap();
+        assert_eq!(sizes, PartitionSizes { boot_mib: 1024, swap_mib: 10_000, root_mib: 88_976 });
+
+        // A reused ESP leaves its share to root
+        let sizes = config.resolve_sizes(100_000, true).unwrap();
+        assert_eq!(sizes.boot_mib, 0);
+        assert_eq!(sizes.root_mib, 90_000);
+    }
+
+    #[test]
+    fn test_resolve_sizes_bios_grub_layout() {
+        let config = PartitionConfig::new(
+            "/dev/sda".to_string(),
+            512,
+            4096,
+            true,
+            Filesystem::Ext4,
+        ).with_firmware(FirmwareMode::Bios);
+
+        let sizes = config.resolve_sizes(20_000, false).unwrap();
+        assert_eq!(sizes.boot_mib, BIOS_GRUB_SIZE_MB);
+        assert_eq!(sizes.boot_mib + sizes.swap_mib + sizes.root_mib, 20_000);
+    }
+
+    #[test]
+    fn test_resolve_sizes_hibernation_needs_swap_for_ram() {
+        let config = PartitionConfig::new(
+            "/dev/sda".to_string(),
+            512,
+            "10%".parse::<SizeSpec>().unwrap(),
+            true,
+            Filesystem::Ext4,
+        )
+        .with_firmware(FirmwareMode::Uefi { bits: 64 })
+        .with_hibernation(16_000);
+
+        // 10% of a 200 GiB disk holds the RAM, 10% of 100 GiB does not
+        assert!(config.resolve_sizes(204_800, false).is_ok());
+        let result = config.resolve_sizes(102_400, false);
+        assert!(matches!(result, Err(SetupError::InvalidInput(msg)) if msg.contains("16000 MiB of RAM")));
+    }
+
+    #[test]
+    fn test_resolve_sizes_exceeds_disk() {
+        let config = PartitionConfig::new(
+            "/dev/sda".to_string(),
+            512,
+            "16G".parse::<SizeSpec>().unwrap(),
+            true,
+            Filesystem::Ext4,
+        )
+        .with_firmware(FirmwareMode::Uefi { bits: 64 })
+        .with_root_size("20G".parse().unwrap());
+
+        let result = config.resolve_sizes(30_000, false);
+        assert!(matches!(result, Err(SetupError::InvalidInput(msg)) if msg.contains("only 30000 MiB")));
+    }
+
+    #[test]
+    fn test_resolve_sizes_percent_bounds() {
+        // 1% of a small disk is too little swap
+        let config = PartitionConfig::new(
+            "/dev/sda".to_string(),
+            512,
+            SizeSpec::Percent(1),
+            true,
+            Filesystem::Ext4,
+        ).with_firmware(FirmwareMode::Uefi { bits: 64 });
+
+        let result = config.resolve_sizes(20_000, false);
+        assert!(matches!(result, Err(SetupError::InvalidInput(msg)) if msg.contains("Swap size")));
+
+        // Root below the minimum
+        let result = config.resolve_sizes(9_000, false);
+        assert!(result.is_err());
+    }
+
+    #[test]
+    fn test_usable_disk_mib() {
+        assert_eq!(usable_disk_mib(0), 0);
+        assert_eq!(usable_disk_mib(10 * 1024 * 1024 * 1024), 10 * 1024 - 2);
+    }
+
+    #[test]
+    fn test_partition_plan_uefi_gpt_script() {
+        let config = PartitionConfig::new(
+            "/dev/sda".to_string(),
+            512,
+            2048,
+            true,
+            Filesystem::Ext4,
+        ).with_firmware(FirmwareMode::Uefi { bits: 64 });
+        let sizes = PartitionSizes { boot_mib: 512, swap_mib: 2048, root_mib: 20_000 };
+
+        let plan = PartitionPlan::new(&config, &sizes, 1);
+        assert!(plan.new_table);
+        assert_eq!(plan.start_of(PartitionRole::Swap), Some(513));
+        assert_eq!(plan.start_of(PartitionRole::Root), Some(2561));
+
+        assert_eq!(
+            plan.to_sfdisk_script(),
+            "label: gpt\n\
+             start=1MiB, size=512MiB, type=C12A7328-F81F-11D2-BA4B-00A0C93EC93B, name=\"ASENOS_ESP\"\n\
+             start=513MiB, size=2048MiB, type=0657FD6D-A4AB-43C4-84E5-0933C84B4F4F, name=\"ASENOS_SWAP\"\n\
+             start=2561MiB, size=20000MiB, type=4F68BCE3-E8CD-4DB1-96E7-FBCAF984B709, name=\"ASENOS_ROOT\"\n"
+        );
+    }
+
+    #[test]
+    fn test_partition_plan_bios_gpt_has_bios_grub() {
+        let config = PartitionConfig::new(
+            "/dev/sda".to_string(),
+            512,
+            2048,
+            true,
+            Filesystem::Ext4,
+        ).with_firmware(FirmwareMode::Bios);
+        let sizes = config.resolve_sizes(30_000, false).unwrap();
+
+        let plan = PartitionPlan::new(&config, &sizes, 1);
+        assert_eq!(plan.partitions[0].role, PartitionRole::BiosGrub);
+        assert_eq!(plan.partitions[0].size_mib, BIOS_GRUB_SIZE_MB);
+        assert!(plan.to_sfdisk_script().contains("type=21686148-6449-6E6F-744E-656564454649"));
+    }
+
+    #[test]
+    fn test_partition_plan_bios_mbr_bootable_root() {
+        let config = PartitionConfig::new(
+            "/dev/sda".to_string(),
+            512,
+            2048,
+            false,
+            Filesystem::Ext4,
+        ).with_firmware(FirmwareMode::Bios);
+        let sizes = config.resolve_sizes(30_000, false).unwrap();
+
+        let script = PartitionPlan::new(&config, &sizes, 1).to_sfdisk_script();
+        let lines: Vec<&str> = script.lines().collect();
+        assert_eq!(lines[0], "label: dos");
+        assert_eq!(lines.len(), 3);
+        assert!(lines[1].ends_with("type=82"));
+        assert!(lines[2].ends_with("type=83, bootable"));
+    }
+
+    #[test]
+    fn test_partition_plan_free_space_appends() {
+        let config = PartitionConfig::new(
+            "/dev/sda".to_string(),
+            512,
+            2048,
+            true,
+            Filesystem::Ext4,
+        )
+        .with_firmware(FirmwareMode::Uefi { bits: 64 })
+        .with_mode(InstallMode::FreeSpace(None));
+
+        // Existing ESP reused: no boot partition, no new label
+        let sizes = config.resolve_sizes(42_400, true).unwrap();
+        let plan = PartitionPlan::new(&config, &sizes, 60_000);
+        let script = plan.to_sfdisk_script();
+
+        assert!(!plan.new_table);
+        assert!(!script.contains("label:"));
+        assert_eq!(plan.partitions.len(), 2);
+        assert!(script.starts_with("start=60000MiB, size=2048MiB"));
+        assert_eq!(plan.start_of(PartitionRole::Esp), None);
+    }
 }
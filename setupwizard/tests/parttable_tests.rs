@@ -0,0 +1,333 @@
+use setupwizard::parttable::{
+    crc32, read_partition_table_from, Guid, PartitionTable, GUID_ESP, GUID_LINUX_ROOT_X86_64,
+    GUID_LINUX_SWAP, GUID_MS_BASIC_DATA,
+};
+use setupwizard::partition::{layout_from_table, PartitionConfig, PartitionPlan, PartitionSizes};
+use setupwizard::{Filesystem, FirmwareMode, SetupError};
+use std::fs::OpenOptions;
+use std::io::{Seek, SeekFrom, Write};
+use std::path::Path;
+
+#[cfg(test)]
+mod parttable_tests {
+    use super::*;
+
+    const MIB: u64 = 1024 * 1024;
+
+    struct Part {
+        type_guid: &'static str,
+        first_lba: u64,
+        last_lba: u64,
+        name: &'static str,
+        attributes: u64,
+    }
+
+    fn write_at(path: &Path, offset: u64, data: &[u8]) {
+        let mut file = OpenOptions::new().write(true).open(path).unwrap();
+        file.seek(SeekFrom::Start(offset)).unwrap();
+        file.write_all(data).unwrap();
+    }
+
+    fn mbr_entry(slot: usize, bootable: bool, type_id: u8, first_lba: u32, sectors: u32) -> (usize, [u8; 16]) {
+        let mut entry = [0u8; 16];
+        entry[0] = if bootable { 0x80 } else { 0 };
+        entry[4] = type_id;
+        entry[8..12].copy_from_slice(&first_lba.to_le_bytes());
+        entry[12..16].copy_from_slice(&sectors.to_le_bytes());
+        (446 + slot * 16, entry)
+    }
+
+    fn boot_sector(entries: &[(usize, [u8; 16])]) -> Vec<u8> {
+        let mut sector = vec![0u8; 512];
+        for (offset, entry) in entries {
+            sector[*offset..*offset + 16].copy_from_slice(entry);
+        }
+        sector[510] = 0x55;
+        sector[511] = 0xaa;
+        sector
+    }
+
+    /// Sparse image of `total_sectors` with a protective MBR and both GPT copies
+    fn gpt_image(path: &Path, sector_size: u64, total_sectors: u64, parts: &[Part]) {
+        let file = OpenOptions::new().create(true).truncate(true).write(true).open(path).unwrap();
+        file.set_len(sector_size * total_sectors).unwrap();
+        drop(file);
+
+        let protective = mbr_entry(0, false, 0xee, 1, (total_sectors - 1).min(u32::MAX as u64) as u32);
+        write_at(path, 0, &boot_sector(&[protective]));
+
+        let mut array = vec![0u8; 128 * 128];
+        for (i, part) in parts.iter().enumerate() {
+            let e = &mut array[i * 128..(i + 1) * 128];
+            e[0..16].copy_from_slice(&part.type_guid.parse::<Guid>().unwrap().0);
+            e[16..32].copy_from_slice(&[i as u8 + 1; 16]);
+            e[32..40].copy_from_slice(&part.first_lba.to_le_bytes());
+            e[40..48].copy_from_slice(&part.last_lba.to_le_bytes());
+            e[48..56].copy_from_slice(&part.attributes.to_le_bytes());
+            for (j, unit) in part.name.encode_utf16().enumerate() {
+                e[56 + j * 2..58 + j * 2].copy_from_slice(&unit.to_le_bytes());
+            }
+        }
+        let array_sectors = array.len() as u64 / sector_size;
+        let array_sectors = array_sectors.max(1);
+        let array_crc = crc32(&array);
+
+        let header = |current: u64, backup: u64, entries_lba: u64| {
+            let mut h = vec![0u8; 92];
+            h[0..8].copy_from_slice(b"EFI PART");
+            h[8..12].copy_from_slice(&0x0001_0000u32.to_le_bytes());
+            h[12..16].copy_from_slice(&92u32.to_le_bytes());
+            h[24..32].copy_from_slice(&current.to_le_bytes());
+            h[32..40].copy_from_slice(&backup.to_le_bytes());
+            h[40..48].copy_from_slice(&(2 + array_sectors).to_le_bytes());
+            h[48..56].copy_from_slice(&(total_sectors - 2 - array_sectors).to_le_bytes());
+            h[56..72].copy_from_slice(&[0xab; 16]);
+            h[72..80].copy_from_slice(&entries_lba.to_le_bytes());
+            h[80..84].copy_from_slice(&128u32.to_le_bytes());
+            h[84..88].copy_from_slice(&128u32.to_le_bytes());
+            h[88..92].copy_from_slice(&array_crc.to_le_bytes());
+            let crc = crc32(&h);
+            h[16..20].copy_from_slice(&crc.to_le_bytes());
+            h
+        };
+
+        let last = total_sectors - 1;
+        write_at(path, sector_size, &header(1, last, 2));
+        write_at(path, 2 * sector_size, &array);
+        write_at(path, (last - array_sectors) * sector_size, &array);
+        write_at(path, last * sector_size, &header(last, 1, last - array_sectors));
+    }
+
+    fn dual_boot_parts() -> Vec<Part> {
+        vec![
+            Part { type_guid: GUID_ESP, first_lba: 2048, last_lba: 206847, name: "EFI system partition", attributes: 0 },
+            Part { type_guid: GUID_MS_BASIC_DATA, first_lba: 206848, last_lba: 1050623, name: "Basic data partition", attributes: 0 },
+        ]
+    }
+
+    /// Change header bytes at `offset` of the GPT header at `lba` and fix up
+    /// its CRC, as a crafted table would
+    fn patch_header(path: &Path, sector_size: u64, lba: u64, offset: usize, data: &[u8]) {
+        let mut header = std::fs::read(path).unwrap()[(lba * sector_size) as usize..][..92].to_vec();
+        header[offset..offset + data.len()].copy_from_slice(data);
+        header[16..20].fill(0);
+        let crc = crc32(&header);
+        header[16..20].copy_from_slice(&crc.to_le_bytes());
+        write_at(path, lba * sector_size, &header);
+    }
+
+    /// 1 GiB disk with 512-byte sectors
+    const SECTORS_1G: u64 = 2 * 1024 * 1024;
+
+    #[test]
+    fn test_read_gpt_entries() {
+        let dir = tempfile::tempdir().unwrap();
+        let image = dir.path().join("disk.img");
+        gpt_image(&image, 512, SECTORS_1G, &dual_boot_parts());
+
+        let table = read_partition_table_from(&image).unwrap().unwrap();
+        let PartitionTable::Gpt { entries, health, header, .. } = &table else {
+            panic!("Expected GPT, got {:?}", table);
+        };
+        assert!(health.protective_mbr && health.primary_valid && health.backup_valid);
+        assert_eq!(header.first_usable_lba, 34);
+        assert_eq!(entries.len(), 2);
+        assert_eq!(entries[0].type_guid.to_string(), GUID_ESP);
+        assert_eq!(entries[0].name, "EFI system partition");
+        assert_eq!(entries[1].number, 2);
+        assert_eq!(table.detected_systems(), vec!["Windows"]);
+    }
+
+    #[test]
+    fn test_corrupt_primary_falls_back_to_backup() {
+        let dir = tempfile::tempdir().unwrap();
+        let image = dir.path().join("disk.img");
+        gpt_image(&image, 512, SECTORS_1G, &dual_boot_parts());
+        write_at(&image, 512 + 40, &[0xff; 4]);
+
+        let table = read_partition_table_from(&image).unwrap().unwrap();
+        let PartitionTable::Gpt { entries, health, header, .. } = &table else {
+            panic!("Expected GPT, got {:?}", table);
+        };
+        assert!(!health.primary_valid && health.backup_valid);
+        assert_eq!(header.current_lba, SECTORS_1G - 1);
+        assert_eq!(entries.len(), 2);
+    }
+
+    #[test]
+    fn test_corrupt_entry_array_detected() {
+        let dir = tempfile::tempdir().unwrap();
+        let image = dir.path().join("disk.img");
+        gpt_image(&image, 512, SECTORS_1G, &dual_boot_parts());
+        write_at(&image, 2 * 512 + 56, b"x");
+
+        let table = read_partition_table_from(&image).unwrap().unwrap();
+        assert!(matches!(table, PartitionTable::Gpt { health, .. } if !health.primary_valid && health.backup_valid));
+
+        // With both copies damaged there is nothing trustworthy left
+        write_at(&image, (SECTORS_1G - 1) * 512 + 40, &[0xff; 4]);
+        let result = read_partition_table_from(&image);
+        assert!(matches!(result, Err(SetupError::System(msg)) if msg.contains("corrupt")));
+    }
+
+    #[test]
+    fn test_crafted_entry_size_rejected() {
+        let dir = tempfile::tempdir().unwrap();
+        let image = dir.path().join("disk.img");
+        gpt_image(&image, 512, 8192, &dual_boot_parts()[..1]);
+
+        // A valid CRC does not make a 4 GiB entry array plausible
+        patch_header(&image, 512, 1, 84, &0x1000_0000u32.to_le_bytes());
+        let table = read_partition_table_from(&image).unwrap().unwrap();
+        assert!(matches!(table, PartitionTable::Gpt { health, .. } if !health.primary_valid && health.backup_valid));
+
+        patch_header(&image, 512, 1, 84, &192u32.to_le_bytes());
+        let table = read_partition_table_from(&image).unwrap().unwrap();
+        assert!(matches!(table, PartitionTable::Gpt { health, .. } if !health.primary_valid));
+
+        patch_header(&image, 512, 1, 84, &128u32.to_le_bytes());
+        patch_header(&image, 512, 1, 72, &u64::MAX.to_le_bytes());
+        let table = read_partition_table_from(&image).unwrap().unwrap();
+        assert!(matches!(table, PartitionTable::Gpt { health, .. } if !health.primary_valid));
+    }
+
+    #[test]
+    fn test_inverted_entry_rejected() {
+        let dir = tempfile::tempdir().unwrap();
+        let image = dir.path().join("disk.img");
+        let parts = [Part { type_guid: GUID_LINUX_SWAP, first_lba: 4096, last_lba: 2048, name: "swap", attributes: 0 }];
+        gpt_image(&image, 512, 8192, &parts);
+
+        let result = read_partition_table_from(&image);
+        assert!(matches!(result, Err(SetupError::System(msg)) if msg.contains("corrupt")));
+    }
+
+    #[test]
+    fn test_read_gpt_4k_sectors() {
+        let dir = tempfile::tempdir().unwrap();
+        let image = dir.path().join("disk.img");
+        let parts = [Part { type_guid: GUID_LINUX_ROOT_X86_64, first_lba: 256, last_lba: 2815, name: "root", attributes: 1 << 2 }];
+        gpt_image(&image, 4096, 262144, &parts);
+
+        let table = read_partition_table_from(&image).unwrap().unwrap();
+        assert_eq!(table.sector_size(), 4096);
+        let partitions = table.partitions();
+        assert_eq!(partitions[0].first_lba, 256);
+        assert!(partitions[0].flags.contains(&"legacy_boot".to_string()));
+        assert_eq!(table.detected_systems(), vec!["Linux"]);
+    }
+
+    #[test]
+    fn test_read_mbr_with_logical_partitions() {
+        let dir = tempfile::tempdir().unwrap();
+        let image = dir.path().join("disk.img");
+        let file = OpenOptions::new().create(true).truncate(true).write(true).open(&image).unwrap();
+        file.set_len(SECTORS_1G * 512).unwrap();
+        drop(file);
+
+        write_at(&image, 0, &boot_sector(&[
+            mbr_entry(0, true, 0x83, 2048, 204800),
+            mbr_entry(1, false, 0x05, 206848, 409600),
+        ]));
+        // First EBR: a logical swap partition and a link to the next EBR
+        write_at(&image, 206848 * 512, &boot_sector(&[
+            mbr_entry(0, false, 0x82, 2048, 100000),
+            mbr_entry(1, false, 0x05, 204800, 204800),
+        ]));
+        write_at(&image, (206848 + 204800) * 512, &boot_sector(&[mbr_entry(0, false, 0x07, 2048, 100000)]));
+
+        let table = read_partition_table_from(&image).unwrap().unwrap();
+        assert_eq!(table.label(), "msdos");
+        let partitions = table.partitions();
+        let numbers: Vec<u32> = partitions.iter().map(|p| p.number).collect();
+        assert_eq!(numbers, vec![1, 5, 6]);
+        assert_eq!(partitions[0].flags, vec!["boot".to_string()]);
+        assert_eq!(partitions[1].first_lba, 206848 + 2048);
+        assert_eq!(partitions[2].type_code, "07");
+        assert_eq!(table.detected_systems(), vec!["Windows", "Linux"]);
+
+        // Space inside the extended partition is not free for new primaries
+        let free = table.free_ranges();
+        assert!(free.iter().all(|(start, end)| *end <= 206848 * 512 || *start >= (206848 + 409600) * 512));
+    }
+
+    #[test]
+    fn test_free_space_after_extended_partition() {
+        let dir = tempfile::tempdir().unwrap();
+        let image = dir.path().join("disk.img");
+        let file = OpenOptions::new().create(true).truncate(true).write(true).open(&image).unwrap();
+        file.set_len(SECTORS_1G * 512).unwrap();
+        drop(file);
+
+        write_at(&image, 0, &boot_sector(&[
+            mbr_entry(0, false, 0x83, 2048, 204800),
+            mbr_entry(1, false, 0x05, 206848, 409600),
+        ]));
+        write_at(&image, 206848 * 512, &boot_sector(&[mbr_entry(0, false, 0x83, 2048, 100000)]));
+
+        // Only the extended partition itself is cut out of the free space
+        let expected = vec![(512, 2048 * 512), ((206848 + 409600) * 512, SECTORS_1G * 512)];
+        let table = read_partition_table_from(&image).unwrap().unwrap();
+        assert_eq!(table.free_ranges(), expected);
+
+        // Also when it holds no logical partitions yet
+        write_at(&image, 206848 * 512, &boot_sector(&[]));
+        let table = read_partition_table_from(&image).unwrap().unwrap();
+        assert_eq!(table.partitions().len(), 1);
+        assert_eq!(table.free_ranges(), expected);
+    }
+
+    #[test]
+    fn test_blank_image_has_no_table() {
+        let dir = tempfile::tempdir().unwrap();
+        let image = dir.path().join("blank.img");
+        let file = OpenOptions::new().create(true).truncate(true).write(true).open(&image).unwrap();
+        file.set_len(MIB).unwrap();
+        drop(file);
+
+        assert!(read_partition_table_from(&image).unwrap().is_none());
+    }
+
+    #[test]
+    fn test_layout_from_gpt_image() {
+        let dir = tempfile::tempdir().unwrap();
+        let image = dir.path().join("disk.img");
+        gpt_image(&image, 512, SECTORS_1G, &dual_boot_parts());
+
+        let layout = layout_from_table(&read_partition_table_from(&image).unwrap().unwrap());
+        assert_eq!(layout.table, "gpt");
+        assert_eq!(layout.size_mib, 1024);
+        assert_eq!(layout.esp().map(|p| p.number), Some(1));
+        assert_eq!(layout.partitions[1].start_mib, 101);
+        assert_eq!(layout.partitions[1].end_mib, 513);
+
+        // The tail region ends where the backup entry array begins
+        let largest = layout.free_regions(1)[0];
+        assert_eq!((largest.start_mib, largest.end_mib), (513, 1023));
+    }
+
+    #[test]
+    fn test_plan_verified_against_written_table() {
+        let dir = tempfile::tempdir().unwrap();
+        let image = dir.path().join("disk.img");
+        gpt_image(&image, 512, SECTORS_1G, &[
+            Part { type_guid: GUID_ESP, first_lba: 2048, last_lba: 1050623, name: "", attributes: 0 },
+            Part { type_guid: GUID_LINUX_SWAP, first_lba: 1050624, last_lba: 2099199, name: "", attributes: 0 },
+        ]);
+
+        let config = PartitionConfig::new(image.to_string_lossy().into_owned(), 512, 512, true, Filesystem::Ext4)
+            .with_firmware(FirmwareMode::Uefi { bits: 64 });
+        let table = read_partition_table_from(&image).unwrap().unwrap();
+
+        let matching = PartitionPlan::new(&config, &PartitionSizes { boot_mib: 512, swap_mib: 512, root_mib: 0 }, 1);
+        let mut plan = matching.clone();
+        plan.partitions.pop();
+        assert!(plan.verify(&table).is_ok());
+
+        let result = matching.verify(&table);
+        assert!(matches!(result, Err(SetupError::System(msg)) if msg.contains("no partition at 1025 MiB")));
+
+        let wrong_size = PartitionPlan::new(&config, &PartitionSizes { boot_mib: 256, swap_mib: 512, root_mib: 512 }, 1);
+        assert!(matches!(wrong_size.verify(&table), Err(SetupError::System(msg)) if msg.contains("not the planned 256 MiB")));
+    }
+}
@@ -0,0 +1,152 @@
+use setupwizard::common::SetupError;
+use setupwizard::filesystem::{FormatSpec, ROOT_LABEL};
+use setupwizard::parttable::{GUID_ESP, GUID_LINUX_HOME, GUID_LINUX_RAID, GUID_LINUX_ROOT_X86_64};
+use setupwizard::partition::{InstallMode, InstallPartitions, PartitionConfig, PartitionPlan, PartitionRole, PartitionSizes};
+use setupwizard::raid::*;
+use setupwizard::target::Target;
+use setupwizard::{Filesystem, FirmwareMode};
+use std::fs;
+
+#[cfg(test)]
+mod raid_tests {
+    use super::*;
+
+    const SIZES: PartitionSizes = PartitionSizes { boot_mib: 512, swap_mib: 4096, root_mib: 100_000 };
+
+    fn base(filesystem: Filesystem, firmware: FirmwareMode, use_gpt: bool) -> PartitionConfig {
+        PartitionConfig::new("/dev/sda".to_string(), 512, 4096, use_gpt, filesystem).with_firmware(firmware)
+    }
+
+    fn uefi(level: RaidLevel, filesystem: Filesystem) -> RaidConfig {
+        RaidConfig::new(base(filesystem, FirmwareMode::Uefi { bits: 64 }, true), vec!["/dev/sdb".to_string()], level)
+    }
+
+    fn members(disks: &[&str]) -> Vec<InstallPartitions> {
+        disks
+            .iter()
+            .map(|d| InstallPartitions {
+                boot: Some(format!("{}1", d)),
+                swap: format!("{}2", d),
+                root: format!("{}3", d),
+                format_boot: true,
+            })
+            .collect()
+    }
+
+    #[test]
+    fn test_members_typed_as_linux_raid() {
+        let config = uefi(RaidLevel::Raid1, Filesystem::Ext4);
+        let plan = PartitionPlan::new(&config.member_config("/dev/sdb"), &SIZES, 1);
+
+        assert_eq!(plan.disk, "/dev/sdb");
+        assert_eq!(plan.type_code(PartitionRole::Esp), GUID_ESP);
+        assert_eq!(plan.type_code(PartitionRole::Swap), GUID_LINUX_RAID);
+        assert_eq!(plan.type_code(PartitionRole::Root), GUID_LINUX_RAID);
+        assert!(plan.to_sfdisk_script().contains(&format!("type={}, name=\"ASENOS_ROOT\"", GUID_LINUX_RAID)));
+
+        // btrfs mirrors root itself, so only swap is an md member
+        let btrfs = uefi(RaidLevel::BtrfsRaid1, Filesystem::Btrfs);
+        let plan = PartitionPlan::new(&btrfs.member_config("/dev/sdb"), &SIZES, 1);
+        assert_eq!(plan.type_code(PartitionRole::Root), GUID_LINUX_ROOT_X86_64);
+        assert_eq!(plan.type_code(PartitionRole::Swap), GUID_LINUX_RAID);
+
+        let mbr = RaidConfig::new(base(Filesystem::Ext4, FirmwareMode::Bios, false), vec!["/dev/sdb".to_string()], RaidLevel::Raid1);
+        let plan = PartitionPlan::new(&mbr.member_config("/dev/sdb"), &SIZES, 1);
+        assert_eq!(plan.type_code(PartitionRole::Root), "fd");
+    }
+
+    #[test]
+    fn test_arrays_for_raid1_with_esp() {
+        let config = uefi(RaidLevel::Raid1, Filesystem::Ext4);
+        let arrays = config.arrays(&members(&["/dev/sda", "/dev/sdb"]));
+
+        let names: Vec<&str> = arrays.iter().map(|a| a.name).collect();
+        assert_eq!(names, vec![ESP_ARRAY, SWAP_ARRAY, ROOT_ARRAY]);
+        assert_eq!(
+            arrays[0].create_command(),
+            vec![
+                "mdadm", "--create", "/dev/md/asenos-esp", "--run", "--homehost=any",
+                "--level=1", "--metadata=1.0", "--raid-devices=2", "/dev/sda1", "/dev/sdb1",
+            ]
+        );
+        assert_eq!(arrays[2].metadata, "1.2");
+        assert_eq!(arrays[2].members, vec!["/dev/sda3", "/dev/sdb3"]);
+
+        let assembled = config.assembled(&members(&["/dev/sda", "/dev/sdb"]));
+        assert_eq!(assembled.boot.as_deref(), Some("/dev/md/asenos-esp"));
+        assert_eq!(assembled.root, "/dev/md/asenos-root");
+    }
+
+    #[test]
+    fn test_arrays_for_raid10_and_btrfs() {
+        let disks = ["/dev/sda", "/dev/sdb", "/dev/sdc", "/dev/sdd"];
+        let others = disks[1..].iter().map(|d| d.to_string()).collect();
+        let raid10 = RaidConfig::new(base(Filesystem::Xfs, FirmwareMode::Bios, true), others, RaidLevel::Raid10);
+        let arrays = raid10.arrays(&members(&disks));
+        // bios_grub partitions hold no data and are not mirrored
+        assert_eq!(arrays.iter().map(|a| a.name).collect::<Vec<_>>(), vec![SWAP_ARRAY, ROOT_ARRAY]);
+        assert!(arrays.iter().all(|a| a.level == "10" && a.members.len() == 4));
+
+        let btrfs = uefi(RaidLevel::BtrfsRaid1, Filesystem::Btrfs);
+        let arrays = btrfs.arrays(&members(&["/dev/sda", "/dev/sdb"]));
+        assert!(arrays.iter().all(|a| a.name != ROOT_ARRAY));
+        assert_eq!(btrfs.assembled(&members(&["/dev/sda", "/dev/sdb"])).root, "/dev/sda3");
+    }
+
+    #[test]
+    fn test_btrfs_raid1_command() {
+        let spec = FormatSpec::new(Filesystem::Btrfs, ROOT_LABEL);
+        let command = btrfs_raid1_command(&spec, &["/dev/sda3".to_string(), "/dev/sdb3".to_string()]);
+        assert_eq!(
+            command,
+            vec!["mkfs.btrfs", "-f", "-L", "ASENOS_ROOT", "-d", "raid1", "-m", "raid1", "/dev/sda3", "/dev/sdb3"]
+        );
+    }
+
+    #[test]
+    fn test_validate_layout() {
+        let result = RaidConfig::new(
+            base(Filesystem::Ext4, FirmwareMode::Uefi { bits: 64 }, true),
+            vec!["/dev/sdb".to_string()],
+            RaidLevel::Raid10,
+        )
+        .validate();
+        assert!(matches!(result, Err(SetupError::InvalidInput(msg)) if msg.contains("at least 4 disks")));
+
+        let result = uefi(RaidLevel::BtrfsRaid1, Filesystem::Ext4).validate();
+        assert!(matches!(result, Err(SetupError::InvalidInput(msg)) if msg.contains("btrfs root")));
+
+        let mut twice = uefi(RaidLevel::Raid1, Filesystem::Ext4);
+        twice.disks.push("/dev/sdb".to_string());
+        assert!(matches!(twice.validate(), Err(SetupError::InvalidInput(msg)) if msg.contains("listed twice")));
+
+        let mut free = uefi(RaidLevel::Raid1, Filesystem::Ext4);
+        free.base = free.base.with_mode(InstallMode::FreeSpace(None));
+        assert!(matches!(free.validate(), Err(SetupError::InvalidInput(msg)) if msg.contains("whole disks")));
+    }
+
+    #[test]
+    fn test_home_partition_type() {
+        assert_eq!(PartitionRole::Home.type_code(true), GUID_LINUX_HOME);
+        assert_eq!(PartitionRole::Home.type_code(false), "83");
+        assert_eq!(PartitionRole::Home.label(), "ASENOS_HOME");
+    }
+
+    #[test]
+    fn test_configure_raid_in_target() {
+        let dir = tempfile::tempdir().unwrap();
+        let target = Target::new(dir.path());
+        target.write_file("etc/mkinitcpio.conf", "HOOKS=(base udev autodetect block filesystems fsck)\n").unwrap();
+
+        let scan = "ARRAY /dev/md/asenos-swap metadata=1.2 name=any:asenos-swap UUID=aa:bb:cc:dd\n\
+                    ARRAY /dev/md/asenos-root metadata=1.2 name=any:asenos-root UUID=ee:ff:00:11\n";
+        configure_raid(&target, scan).unwrap();
+
+        let conf = fs::read_to_string(target.path("etc/mdadm.conf")).unwrap();
+        assert_eq!(conf.lines().filter(|l| l.starts_with("ARRAY")).count(), 2);
+        assert_eq!(
+            target.initramfs_hooks().unwrap(),
+            vec!["base", "udev", "autodetect", "block", "mdadm_udev", "filesystems", "fsck"]
+        );
+    }
+}
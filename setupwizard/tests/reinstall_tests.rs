@@ -0,0 +1,276 @@
+use setupwizard::common::SetupError;
+use setupwizard::disk::StorageMedia;
+use setupwizard::filesystem::FormatSpec;
+use setupwizard::partition::{PartitionRole, Volume};
+use setupwizard::reinstall::*;
+use setupwizard::target::fstab;
+use setupwizard::Filesystem;
+use std::fs;
+use std::os::unix::fs::symlink;
+use std::path::Path;
+
+#[cfg(test)]
+mod reinstall_tests {
+    use super::*;
+
+    const ROOT_UUID: &str = "0f3c9a4e-5b6d-4e7f-8a9b-0c1d2e3f4a5b";
+    const HOME_UUID: &str = "5e6f7a8b-9c0d-4e1f-8a2b-3c4d5e6f7a8b";
+    const SWAP_UUID: &str = "7a1b2c3d-4e5f-4a6b-8c7d-9e0f1a2b3c4d";
+    const ESP_UUID: &str = "1234-ABCD";
+
+    const PASSWD: &str = "\
+root:x:0:0::/root:/usr/bin/bash
+systemd-network:x:192:192:systemd Network Management:/:/usr/bin/nologin
+alice:x:1000:1000:Alice:/home/alice:/usr/bin/zsh
+bob:x:1001:100::/home/bob:/usr/bin/bash
+nobody:x:65534:65534:Kernel Overflow User:/:/usr/bin/nologin
+";
+    const GROUP: &str = "\
+root:x:0:root
+wheel:x:998:alice
+users:x:100:
+audio:x:995:alice,bob
+docker:x:960:bob
+alice:x:1000:
+";
+    const SHADOW: &str = "\
+root:*:19000::::::
+alice:$6$salt$hashedpassword:19000:0:99999:7:::
+bob:!:19000:0:99999:7:::
+";
+
+    /// sysfs and udev links for sda with ESP, swap, root and home
+    /// partitions, the root one named ASENOS_ROOT
+    fn fixture(root: &Path, gpt_names: bool) {
+        let block = root.join("sys/class/block");
+        fs::create_dir_all(root.join("dev/disk/by-uuid")).unwrap();
+        fs::create_dir_all(root.join("dev/disk/by-label")).unwrap();
+        for (i, (part, name)) in [("sda1", "ASENOS_ESP"), ("sda2", "ASENOS_SWAP"), ("sda3", "ASENOS_ROOT"), ("sda4", "")]
+            .iter()
+            .enumerate()
+        {
+            for dir in [block.join("sda").join(part), block.join(part)] {
+                fs::create_dir_all(&dir).unwrap();
+                fs::write(dir.join("partition"), (i + 1).to_string()).unwrap();
+                let partname = if gpt_names && !name.is_empty() { format!("PARTNAME={}\n", name) } else { String::new() };
+                fs::write(dir.join("uevent"), format!("DEVTYPE=partition\nPARTN={}\n{}", i + 1, partname)).unwrap();
+            }
+            fs::write(root.join("dev").join(part), "").unwrap();
+        }
+        for (uuid, part) in [(ESP_UUID, "sda1"), (SWAP_UUID, "sda2"), (ROOT_UUID, "sda3"), (HOME_UUID, "sda4")] {
+            symlink(format!("../../{}", part), root.join("dev/disk/by-uuid").join(uuid)).unwrap();
+        }
+        symlink("../../sda1", root.join("dev/disk/by-label/ASENOS_ESP")).unwrap();
+        symlink("../../sda3", root.join("dev/disk/by-label/ASENOS_ROOT")).unwrap();
+    }
+
+    fn old_system(dir: &Path, fstab: &str) {
+        fs::create_dir_all(dir.join("etc")).unwrap();
+        fs::write(dir.join("etc/fstab"), fstab).unwrap();
+        fs::write(dir.join("etc/passwd"), PASSWD).unwrap();
+        fs::write(dir.join("etc/group"), GROUP).unwrap();
+        fs::write(dir.join("etc/shadow"), SHADOW).unwrap();
+    }
+
+    #[test]
+    fn test_find_old_root() {
+        let dir = tempfile::tempdir().unwrap();
+        fixture(dir.path(), true);
+        assert_eq!(find_old_root_in(dir.path(), "/dev/sda").unwrap(), "/dev/sda3");
+
+        // MBR disks have no partition names, so the filesystem label decides
+        let dir = tempfile::tempdir().unwrap();
+        fixture(dir.path(), false);
+        assert_eq!(find_old_root_in(dir.path(), "/dev/sda").unwrap(), "/dev/sda3");
+
+        fs::remove_file(dir.path().join("dev/disk/by-label/ASENOS_ROOT")).unwrap();
+        let result = find_old_root_in(dir.path(), "/dev/sda");
+        assert!(matches!(result, Err(SetupError::InvalidInput(msg)) if msg.contains("No Asenos installation")));
+    }
+
+    #[test]
+    fn test_inspect_separate_home_partition() {
+        let dir = tempfile::tempdir().unwrap();
+        fixture(dir.path(), true);
+        let old_root = dir.path().join("mnt");
+        old_system(
+            &old_root,
+            &format!(
+                "# Written by the Asenos installer\n\
+                 UUID={}\t/\text4\tdefaults,noatime\t0 1\n\
+                 UUID={}\t/boot\tvfat\tdefaults,umask=0077\t0 2\n\
+                 UUID={}\t/home\text4\tdefaults,noatime\t0 2\n\
+                 LABEL=ASENOS_SWAP\tnone\tswap\tdefaults\t0 0\n",
+                ROOT_UUID, ESP_UUID, HOME_UUID
+            ),
+        );
+        symlink("../../sda2", dir.path().join("dev/disk/by-label/ASENOS_SWAP")).unwrap();
+
+        let existing = inspect_old_root_in(dir.path(), "/dev/sda3", &old_root).unwrap();
+        assert_eq!(existing.home, "/dev/sda4");
+        assert_eq!(existing.home_filesystem, Filesystem::Ext4);
+        assert_eq!(existing.home_subvolume, None);
+        assert_eq!(existing.esp.as_deref(), Some("/dev/sda1"));
+        assert!(existing.reformat_esp);
+        assert_eq!(existing.swap.as_deref(), Some("/dev/sda2"));
+
+        let names: Vec<&str> = existing.users.iter().map(|u| u.name.as_str()).collect();
+        assert_eq!(names, vec!["alice", "bob"]);
+        let alice = &existing.users[0];
+        assert_eq!((alice.uid, alice.gid, alice.group.as_str()), (1000, 1000, "alice"));
+        assert_eq!(alice.groups, vec!["wheel", "audio"]);
+        assert_eq!(alice.password_hash.as_deref(), Some("$6$salt$hashedpassword"));
+        // A locked account keeps no hash
+        assert_eq!(existing.users[1].group, "users");
+        assert_eq!(existing.users[1].password_hash, None);
+    }
+
+    #[test]
+    fn test_inspect_btrfs_home_subvolume() {
+        let dir = tempfile::tempdir().unwrap();
+        fixture(dir.path(), true);
+        let old_root = dir.path().join("mnt");
+        // The top level of the filesystem is mounted, the system lives in @
+        old_system(
+            &old_root.join("@"),
+            &format!(
+                "UUID={0} / btrfs rw,noatime,compress=zstd,subvol=/@ 0 0\n\
+                 UUID={0} /home btrfs rw,noatime,compress=zstd,subvol=/@home 0 0\n",
+                ROOT_UUID
+            ),
+        );
+
+        let existing = inspect_old_root_in(dir.path(), "/dev/sda3", &old_root).unwrap();
+        assert_eq!(existing.home, "/dev/sda3");
+        assert_eq!(existing.home_subvolume.as_deref(), Some("@home"));
+        assert_eq!(existing.root_subvolume.as_deref(), Some("@"));
+        assert!(existing.home_shares_root());
+        assert_eq!(existing.esp, None);
+    }
+
+    #[test]
+    fn test_inspect_btrfs_home_on_other_device() {
+        let dir = tempfile::tempdir().unwrap();
+        fixture(dir.path(), true);
+        let old_root = dir.path().join("mnt");
+        // An ext4 root with /home in a subvolume of its own btrfs partition
+        old_system(
+            &old_root,
+            &format!(
+                "UUID={} / ext4 defaults 0 1\n\
+                 UUID={} /home btrfs rw,noatime,subvol=/@home 0 0\n",
+                ROOT_UUID, HOME_UUID
+            ),
+        );
+
+        let existing = inspect_old_root_in(dir.path(), "/dev/sda3", &old_root).unwrap();
+        assert_eq!(existing.home, "/dev/sda4");
+        assert_eq!(existing.home_subvolume.as_deref(), Some("@home"));
+        assert_eq!(existing.root_subvolume, None);
+        // The root is reformatted whole, with any filesystem
+        assert!(!existing.home_shares_root());
+    }
+
+    #[test]
+    fn test_inspect_refuses_btrfs_top_level_root() {
+        let dir = tempfile::tempdir().unwrap();
+        fixture(dir.path(), true);
+        let old_root = dir.path().join("mnt");
+        // The system sits in the top level, with only /home in a subvolume
+        old_system(
+            &old_root,
+            &format!(
+                "UUID={0} / btrfs rw,noatime 0 0\n\
+                 UUID={0} /home btrfs rw,noatime,subvol=/@home 0 0\n",
+                ROOT_UUID
+            ),
+        );
+
+        let result = inspect_old_root_in(dir.path(), "/dev/sda3", &old_root);
+        assert!(matches!(result, Err(SetupError::InvalidInput(msg)) if msg.contains("top level")));
+    }
+
+    #[test]
+    fn test_inspect_refuses_home_inside_root() {
+        let dir = tempfile::tempdir().unwrap();
+        fixture(dir.path(), true);
+        let old_root = dir.path().join("mnt");
+        old_system(&old_root, &format!("UUID={} / ext4 defaults 0 1\n", ROOT_UUID));
+
+        let result = inspect_old_root_in(dir.path(), "/dev/sda3", &old_root);
+        assert!(matches!(result, Err(SetupError::InvalidInput(msg)) if msg.contains("cannot be preserved")));
+    }
+
+    #[test]
+    fn test_useradd_keeps_ids_and_known_groups() {
+        let users = parse_users(PASSWD, GROUP, SHADOW);
+        let known = vec!["root".to_string(), "wheel".to_string(), "users".to_string()];
+
+        let command = useradd_command(Path::new("/mnt"), &users[0], &known);
+        assert_eq!(
+            command,
+            vec![
+                "useradd", "--root", "/mnt", "--uid", "1000", "--gid", "1000", "--no-create-home",
+                "--home-dir", "/home/alice", "--shell", "/usr/bin/zsh", "--comment", "Alice",
+                "--groups", "wheel", "alice",
+            ]
+        );
+        // Neither audio nor docker exist yet in the new system
+        assert!(!useradd_command(Path::new("/mnt"), &users[1], &known).contains(&"--groups".to_string()));
+    }
+
+    #[test]
+    fn test_group_id_conflicts() {
+        let users = parse_users(PASSWD, GROUP, SHADOW);
+        assert!(check_group_ids("root:x:0:\nwheel:x:998:\nusers:x:100:\n", &users).is_ok());
+        // alice's group exists already, as it does in the old system
+        assert!(check_group_ids("alice:x:1000:\nusers:x:100:\n", &users).is_ok());
+
+        let result = check_group_ids("users:x:984:\n", &users);
+        assert!(matches!(result, Err(SetupError::InvalidInput(msg)) if msg.contains("'users' of bob has GID 984")));
+        let result = check_group_ids("users:x:100:\nsystemd-journal:x:1000:\n", &users);
+        assert!(matches!(result, Err(SetupError::InvalidInput(msg)) if msg.contains("already belongs to 'systemd-journal'")));
+    }
+
+    #[test]
+    fn test_fstab_mounts_kept_subvolume() {
+        let volume = Volume {
+            role: PartitionRole::Home,
+            device: "/dev/sda3".to_string(),
+            format: FormatSpec::new(Filesystem::Btrfs, "").with_uuid(ROOT_UUID.to_string()),
+            formatted: false,
+            subvolume: Some("@home".to_string()),
+        };
+        let fstab = fstab(&[volume], StorageMedia::Rotational);
+        assert!(fstab.contains(&format!("UUID={}\t/home\tbtrfs\t", ROOT_UUID)));
+        assert!(fstab.contains(",subvol=/@home\t0 2"));
+    }
+
+    #[test]
+    fn test_hibernation_checks_kept_swap() {
+        let dir = tempfile::tempdir().unwrap();
+        fixture(dir.path(), true);
+        // 2 GiB of swap in 512-byte sectors
+        fs::write(dir.path().join("sys/class/block/sda2/size"), "4194304\n").unwrap();
+        let old_root = dir.path().join("mnt");
+        old_system(
+            &old_root,
+            &format!(
+                "UUID={}\t/\text4\tdefaults\t0 1\n\
+                 UUID={}\t/home\text4\tdefaults\t0 2\n\
+                 UUID={}\tnone\tswap\tdefaults\t0 0\n",
+                ROOT_UUID, HOME_UUID, SWAP_UUID
+            ),
+        );
+        let mut existing = inspect_old_root_in(dir.path(), "/dev/sda3", &old_root).unwrap();
+
+        assert_eq!(partition_mib_in(dir.path(), "/dev/sda2").unwrap(), 2048);
+        assert!(check_hibernation_swap_in(dir.path(), &existing, 2048).is_ok());
+        let result = check_hibernation_swap_in(dir.path(), &existing, 4096);
+        assert!(matches!(result, Err(SetupError::InvalidInput(msg)) if msg.contains("smaller than the 4096 MiB")));
+
+        existing.swap = None;
+        let result = check_hibernation_swap_in(dir.path(), &existing, 2048);
+        assert!(matches!(result, Err(SetupError::InvalidInput(msg)) if msg.contains("needs a swap partition")));
+    }
+}
@@ -0,0 +1,82 @@
+use setupwizard::common::SetupError;
+use setupwizard::size::{resolve_sizes, SizeSpec, MIB};
+
+#[cfg(test)]
+mod size_tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_binary_and_decimal_units() {
+        let cases = vec![
+            ("1024", SizeSpec::Bytes(1024 * MIB)),
+            ("512M", SizeSpec::Bytes(512 * MIB)),
+            ("512 MiB", SizeSpec::Bytes(512 * MIB)),
+            ("1.5G", SizeSpec::Bytes(1536 * MIB)),
+            ("2GiB", SizeSpec::Bytes(2048 * MIB)),
+            ("1T", SizeSpec::Bytes(1024 * 1024 * MIB)),
+            ("64K", SizeSpec::Bytes(64 * 1024)),
+            ("1GB", SizeSpec::Bytes(1_000_000_000)),
+            ("500MB", SizeSpec::Bytes(500_000_000)),
+            ("4096B", SizeSpec::Bytes(4096)),
+        ];
+
+        for (input, expected) in cases {
+            assert_eq!(input.parse::<SizeSpec>().unwrap(), expected, "Failed for {}", input);
+        }
+    }
+
+    #[test]
+    fn test_parse_percent_and_rest() {
+        assert_eq!("20%".parse::<SizeSpec>().unwrap(), SizeSpec::Percent(20));
+        assert_eq!("100%".parse::<SizeSpec>().unwrap(), SizeSpec::Percent(100));
+        assert_eq!("rest".parse::<SizeSpec>().unwrap(), SizeSpec::Rest);
+    }
+
+    #[test]
+    fn test_parse_invalid() {
+        let invalid = vec!["", "abc", "-1", "0%", "101%", "12X", "1.2.3G", "%"];
+
+        for input in invalid {
+            let result = input.parse::<SizeSpec>();
+            assert!(matches!(result, Err(SetupError::InvalidInput(_))), "Should fail for: {:?}", input);
+        }
+    }
+
+    #[test]
+    fn test_fixed_mib_rounds_up_to_boundary() {
+        assert_eq!(SizeSpec::Bytes(MIB).fixed_mib(), Some(1));
+        assert_eq!(SizeSpec::Bytes(MIB + 1).fixed_mib(), Some(2));
+        assert_eq!("1GB".parse::<SizeSpec>().unwrap().fixed_mib(), Some(954));
+        assert_eq!(SizeSpec::Percent(50).fixed_mib(), None);
+    }
+
+    #[test]
+    fn test_display_round_trip() {
+        for input in ["512MiB", "2GiB", "20%", "rest"] {
+            let spec = input.parse::<SizeSpec>().unwrap();
+            assert_eq!(spec.to_string(), input);
+            assert_eq!(spec.to_string().parse::<SizeSpec>().unwrap(), spec);
+        }
+    }
+
+    #[test]
+    fn test_resolve_sizes() {
+        let sizes = resolve_sizes(&[SizeSpec::mib(512), SizeSpec::Percent(25), SizeSpec::Rest], 20_000).unwrap();
+        assert_eq!(sizes, vec![512, 5000, 14_488]);
+
+        let sizes = resolve_sizes(&[SizeSpec::mib(512), SizeSpec::mib(1024)], 20_000).unwrap();
+        assert_eq!(sizes, vec![512, 1024]);
+    }
+
+    #[test]
+    fn test_resolve_sizes_errors() {
+        let result = resolve_sizes(&[SizeSpec::Rest, SizeSpec::Rest], 1000);
+        assert!(matches!(result, Err(SetupError::InvalidInput(msg)) if msg.contains("Only one")));
+
+        let result = resolve_sizes(&[SizeSpec::mib(800), SizeSpec::Percent(30)], 1000);
+        assert!(matches!(result, Err(SetupError::InvalidInput(msg)) if msg.contains("1100 MiB")));
+
+        let result = resolve_sizes(&[SizeSpec::Percent(100), SizeSpec::Rest], 1000);
+        assert!(matches!(result, Err(SetupError::InvalidInput(msg)) if msg.contains("No space left")));
+    }
+}
@@ -0,0 +1,98 @@
+use setupwizard::smart::*;
+
+#[cfg(test)]
+mod smart_tests {
+    use super::*;
+
+    /// Trimmed `smartctl -j -H -A -i` output of a SATA disk with bad sectors
+    const ATA_FAILING: &str = r#"{
+        "json_format_version": [1, 0],
+        "smartctl": {"version": [7, 4], "exit_status": 24},
+        "device": {"name": "/dev/sda", "type": "sat", "protocol": "ATA"},
+        "model_name": "WDC WD20EZRZ-00Z5HB0",
+        "smart_status": {"passed": false},
+        "ata_smart_attributes": {
+            "revision": 16,
+            "table": [
+                {"id": 1, "name": "Raw_Read_Error_Rate", "raw": {"value": 12, "string": "12"}},
+                {"id": 5, "name": "Reallocated_Sector_Ct", "raw": {"value": 1432, "string": "1432"}},
+                {"id": 9, "name": "Power_On_Hours", "raw": {"value": 41234, "string": "41234"}},
+                {"id": 197, "name": "Current_Pending_Sector", "raw": {"value": 16, "string": "16"}}
+            ]
+        },
+        "power_on_time": {"hours": 41234}
+    }"#;
+
+    const NVME_HEALTHY: &str = r#"{
+        "device": {"name": "/dev/nvme0", "type": "nvme", "protocol": "NVMe"},
+        "model_name": "Samsung SSD 980 PRO 1TB",
+        "smart_status": {"passed": true, "nvme": {"value": 0}},
+        "nvme_smart_health_information_log": {
+            "critical_warning": 0,
+            "temperature": 38,
+            "available_spare": 100,
+            "percentage_used": 3,
+            "power_on_hours": 5120,
+            "media_errors": 0
+        }
+    }"#;
+
+    #[test]
+    fn test_parse_failing_ata_disk() {
+        let health = parse_smartctl_json(ATA_FAILING).unwrap();
+        assert_eq!(health.model.as_deref(), Some("WDC WD20EZRZ-00Z5HB0"));
+        assert_eq!(health.passed, Some(false));
+        assert_eq!(health.reallocated_sectors, Some(1432));
+        assert_eq!(health.pending_sectors, Some(16));
+        assert_eq!(health.power_on_hours, Some(41234));
+        assert!(health.is_failing());
+
+        let warnings = health.warnings();
+        assert_eq!(warnings.len(), 3);
+        assert!(warnings[0].contains("FAILED"));
+        assert!(warnings.iter().any(|w| w == "1432 reallocated sectors"));
+        assert!(warnings.iter().any(|w| w == "16 sectors pending reallocation"));
+        assert_eq!(health.summary(), "FAILED, 41234 h");
+    }
+
+    #[test]
+    fn test_parse_healthy_nvme_disk() {
+        let health = parse_smartctl_json(NVME_HEALTHY).unwrap();
+        assert_eq!(health.nvme_percentage_used, Some(3));
+        assert_eq!(health.power_on_hours, Some(5120));
+        assert_eq!(health.reallocated_sectors, None);
+        assert!(!health.is_failing());
+        assert!(health.warnings().is_empty());
+        assert_eq!(health.summary(), "PASSED, 5120 h, 3% used");
+    }
+
+    #[test]
+    fn test_nvme_wear_and_critical_warning() {
+        let worn = NVME_HEALTHY
+            .replace("\"percentage_used\": 3", "\"percentage_used\": 97")
+            .replace("\"media_errors\": 0", "\"media_errors\": 4");
+        let health = parse_smartctl_json(&worn).unwrap();
+        // Wear and media errors are warnings, not failures
+        assert!(!health.is_failing());
+        assert_eq!(health.warnings(), vec!["4 media errors", "97% of rated endurance used"]);
+
+        let critical = NVME_HEALTHY.replace("\"critical_warning\": 0", "\"critical_warning\": 4");
+        let health = parse_smartctl_json(&critical).unwrap();
+        assert!(health.is_failing());
+        assert!(health.warnings()[0].contains("0x04"));
+    }
+
+    #[test]
+    fn test_missing_status_is_unknown() {
+        let health = parse_smartctl_json(r#"{"device": {"name": "/dev/sdc", "protocol": "SCSI"}}"#).unwrap();
+        assert_eq!(health, SmartHealth::default());
+        assert!(!health.is_failing());
+        assert_eq!(health.summary(), "status unknown");
+    }
+
+    #[test]
+    fn test_check_disk_health_without_data_passes() {
+        // Neither a missing smartctl nor an unreadable device blocks the install
+        assert!(check_disk_health("/dev/nonexistent_disk_12345", false).is_ok());
+    }
+}
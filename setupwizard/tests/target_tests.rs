@@ -0,0 +1,105 @@
+use setupwizard::disk::StorageMedia;
+use setupwizard::filesystem::{FormatSpec, ESP_LABEL, ROOT_LABEL, SWAP_LABEL};
+use setupwizard::partition::{PartitionRole, Volume};
+use setupwizard::target::*;
+use setupwizard::Filesystem;
+use std::fs;
+
+#[cfg(test)]
+mod target_tests {
+    use super::*;
+
+    const ROOT_UUID: &str = "0f3c9a4e-5b6d-4e7f-8a9b-0c1d2e3f4a5b";
+    const SWAP_UUID: &str = "7a1b2c3d-4e5f-4a6b-8c7d-9e0f1a2b3c4d";
+
+    fn volume(role: PartitionRole, device: &str, format: FormatSpec) -> Volume {
+        Volume { role, device: device.to_string(), format, formatted: true, subvolume: None }
+    }
+
+    fn volumes(root: Filesystem) -> Vec<Volume> {
+        vec![
+            volume(PartitionRole::Esp, "/dev/sda1", FormatSpec::new(Filesystem::Vfat, ESP_LABEL)),
+            volume(
+                PartitionRole::Swap,
+                "/dev/sda2",
+                FormatSpec::new(Filesystem::Swap, SWAP_LABEL).with_uuid(SWAP_UUID.to_string()),
+            ),
+            volume(
+                PartitionRole::Root,
+                "/dev/sda3",
+                FormatSpec::new(root, ROOT_LABEL).with_uuid(ROOT_UUID.to_string()),
+            ),
+        ]
+    }
+
+    #[test]
+    fn test_fstab_on_ssd() {
+        let fstab = fstab(&volumes(Filesystem::Btrfs), StorageMedia::SolidState { trim: true });
+        let lines: Vec<&str> = fstab.lines().collect();
+
+        assert_eq!(lines[0], "# Written by the Asenos installer");
+        assert_eq!(
+            lines[1],
+            format!("UUID={}\t/\tbtrfs\tdefaults,noatime,compress=zstd,ssd,discard=async\t0 1", ROOT_UUID)
+        );
+        assert_eq!(lines[2], "LABEL=ASENOS_ESP\t/boot\tvfat\tdefaults,umask=0077\t0 2");
+        assert_eq!(lines[3], format!("UUID={}\tnone\tswap\tdefaults,discard=once\t0 0", SWAP_UUID));
+        assert_eq!(lines.len(), 4);
+    }
+
+    #[test]
+    fn test_fstab_esp_by_fat_volume_id() {
+        let mut volumes = volumes(Filesystem::Ext4);
+        volumes[0].format = FormatSpec::new(Filesystem::Vfat, ESP_LABEL).with_uuid(ROOT_UUID.to_string());
+        let fstab = fstab(&volumes, StorageMedia::Rotational);
+
+        // blkid shows a FAT volume ID, never the full UUID it came from
+        assert!(fstab.contains("UUID=0F3C-9A4E	/boot	vfat	defaults,umask=0077	0 2"), "{}", fstab);
+        assert!(!fstab.contains(&format!("UUID={}	/boot", ROOT_UUID)));
+    }
+
+    #[test]
+    fn test_fstab_on_hdd() {
+        let fstab = fstab(&volumes(Filesystem::Ext4), StorageMedia::Rotational);
+
+        assert!(fstab.contains(&format!("UUID={}\t/\text4\tdefaults,relatime\t0 1", ROOT_UUID)));
+        assert!(fstab.contains(&format!("UUID={}\tnone\tswap\tdefaults\t0 0", SWAP_UUID)));
+        assert!(!fstab.contains("noatime"));
+    }
+
+    #[test]
+    fn test_fstab_skips_bios_boot_and_falls_back_to_device() {
+        let volumes = vec![
+            volume(PartitionRole::BiosGrub, "/dev/sda1", FormatSpec::new(Filesystem::Vfat, "")),
+            volume(PartitionRole::Root, "/dev/sda3", FormatSpec::new(Filesystem::Jfs, "")),
+        ];
+        let fstab = fstab(&volumes, StorageMedia::Rotational);
+
+        assert!(!fstab.contains("/dev/sda1"));
+        assert!(fstab.contains("/dev/sda3\t/\tjfs\tdefaults,relatime\t0 1"));
+    }
+
+    #[test]
+    fn test_configure_storage_enables_trim_only_when_supported() {
+        let ssd = tempfile::tempdir().unwrap();
+        let target = Target::new(ssd.path());
+        target.configure_storage(&volumes(Filesystem::Ext4), StorageMedia::SolidState { trim: true }).unwrap();
+
+        assert!(target.unit_enabled("fstrim.timer", "timers.target"));
+        let link = fs::read_link(target.path("etc/systemd/system/timers.target.wants/fstrim.timer")).unwrap();
+        assert_eq!(link.to_str(), Some("/usr/lib/systemd/system/fstrim.timer"));
+        assert!(fs::read_to_string(target.path("etc/fstab")).unwrap().contains("noatime"));
+        assert_eq!(
+            fs::read_to_string(target.path("etc/udev/rules.d/60-ioschedulers.rules")).unwrap(),
+            IO_SCHEDULER_RULES
+        );
+
+        for media in [StorageMedia::SolidState { trim: false }, StorageMedia::Rotational] {
+            let dir = tempfile::tempdir().unwrap();
+            let target = Target::new(dir.path());
+            target.configure_storage(&volumes(Filesystem::Ext4), media).unwrap();
+            assert!(!target.unit_enabled("fstrim.timer", "timers.target"));
+            assert!(target.path("etc/udev/rules.d/60-ioschedulers.rules").exists());
+        }
+    }
+}
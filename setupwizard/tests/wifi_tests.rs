@@ -1,5 +1,14 @@
-use setupwizard::wifi::{list_networks, connect_network};
-use setupwizard::common::SetupError;
+use setupwizard::wifi::{
+    connect_configured_in, connect_enterprise_in, connect_network, connect_network_in, list_networks_with, parse_connect_spec, saved_profile_in, parse_device_list, parse_profile_file_name, preparation_commands,
+    profile_file_name, select_device, write_enterprise_profile_in, write_profile_in, DeviceMode, EapMethod,
+    EnterpriseNetwork, Network, Phase2Method, Security, WirelessDevice, IWD_STATE_DIR,
+};
+use std::cell::RefCell;
+use std::fs;
+use std::os::unix::fs::PermissionsExt;
+use std::path::Path;
+use setupwizard::common::{CommandResult, SetupError};
+use setupwizard::secret::{read_secret_file, take_env_secret, REDACTED};
 
 #[cfg(test)]
 mod wifi_tests {
@@ -19,70 +28,155 @@ mod wifi_tests {
         matches!(result.unwrap_err(), SetupError::InvalidInput(_));
     }
 
+    /// Stands in for iwctl with the devices of DEVICE_LIST, logging every
+    /// command it is asked to run. Connecting fails unless `connects`.
+    fn fake_iwctl(log: &RefCell<Vec<String>>, connects: bool) -> impl Fn(&[&str]) -> CommandResult<String> + '_ {
+        move |args| {
+            log.borrow_mut().push(args.join(" "));
+            match args {
+                ["iwctl", "device", "list"] => Ok(DEVICE_LIST.to_string()),
+                ["iwctl", "station", _, "connect" | "connect-hidden", _] if !connects => {
+                    Err(SetupError::CommandFailed("Operation failed".to_string()))
+                }
+                _ => Ok(String::new()),
+            }
+        }
+    }
+
+    /// Connect through the fake iwctl with profiles under `root`
+    fn connect_fake(root: &Path, ssid: &str, password: Option<&str>) -> (CommandResult<String>, Vec<String>) {
+        let log = RefCell::new(Vec::new());
+        let result = connect_network_in(root, ssid, password, Some("wlan0"), false, &fake_iwctl(&log, true));
+        (result, log.into_inner())
+    }
+
     #[test]
     fn test_connect_network_newline_ssid() {
-        let result = connect_network("ssid\nwith\nnewlines", None);
+        let dir = tempfile::tempdir().unwrap();
+        let (result, _) = connect_fake(dir.path(), "ssid\nwith\nnewlines", None);
         // Should be treated as invalid or potentially dangerous
         // This tests that we handle special characters appropriately
-        match result {
-            Ok(_) => println!("SSID with newlines was accepted"),
-            Err(_) => {}, // This is also acceptable
+        // An error is also acceptable
+        if result.is_ok() {
+            println!("SSID with newlines was accepted");
         }
     }
 
     #[test]
     fn test_list_networks() {
-        let result = list_networks();
-        
-        match result {
-            Ok(networks) => {
-                // If successful, should return some output
-                println!("WiFi networks found: {}", networks.len());
-            }
-            Err(SetupError::System(msg)) => {
-                // Expected if iwctl is not available or no WiFi hardware
-                assert!(msg.contains("iwctl") || msg.contains("wireless"));
-                println!("WiFi not available in test environment: {}", msg);
-            }
-            Err(SetupError::CommandFailed(_)) => {
-                // Also acceptable - command might exist but fail
-                println!("WiFi command failed (expected in test environment)");
-            }
-            Err(e) => panic!("Unexpected error type: {:?}", e),
-        }
+        let log = RefCell::new(Vec::new());
+        list_networks_with(Some("wlan1"), &fake_iwctl(&log, true)).unwrap();
+        // The powered-off access point is made a station before scanning
+        assert_eq!(
+            log.into_inner(),
+            vec![
+                "iwctl device list",
+                "iwctl adapter phy1 set-property Powered on",
+                "iwctl device wlan1 set-property Powered on",
+                "iwctl device wlan1 set-property Mode station",
+                "iwctl station wlan1 scan",
+                "iwctl station wlan1 get-networks",
+            ]
+        );
     }
 
     #[test]
     fn test_connect_network_with_password() {
-        let result = connect_network("TestSSID", Some("password123"));
-        
-        match result {
-            Ok(_) => {
-                // Unlikely to succeed in test environment but not an error
-                println!("WiFi connection unexpectedly succeeded");
-            }
-            Err(SetupError::System(_)) => {
-                // Expected if iwctl not available
-                println!("WiFi system not available (expected)");
-            }
-            Err(SetupError::CommandFailed(_)) => {
-                // Expected if command fails (no such SSID, etc.)
-                println!("WiFi connection failed (expected)");
-            }
-            Err(e) => panic!("Unexpected error type: {:?}", e),
-        }
+        let dir = tempfile::tempdir().unwrap();
+        let (result, log) = connect_fake(dir.path(), "TestSSID", Some("password123"));
+        assert!(result.is_ok());
+
+        let profile = dir.path().join(IWD_STATE_DIR).join("TestSSID.psk");
+        assert_eq!(fs::read_to_string(&profile).unwrap(), "[Security]\nPassphrase=password123\n\n[Settings]\nAutoConnect=true\n");
+        assert_eq!(fs::metadata(&profile).unwrap().permissions().mode() & 0o777, 0o600);
+        assert_eq!(log.last().unwrap(), "iwctl station wlan0 connect TestSSID");
+        assert!(log.iter().all(|command| !command.contains("password123")));
+    }
+
+    #[test]
+    fn test_failed_connect_removes_profile() {
+        let dir = tempfile::tempdir().unwrap();
+        let log = RefCell::new(Vec::new());
+        let result = connect_network_in(dir.path(), "TestSSID", Some("wrong pass"), Some("wlan0"), false, &fake_iwctl(&log, false));
+        assert!(matches!(result, Err(SetupError::CommandFailed(_))));
+        assert_eq!(saved_profile_in(dir.path(), "TestSSID"), None);
+    }
+
+    #[test]
+    fn test_saved_profile_is_kept() {
+        let dir = tempfile::tempdir().unwrap();
+        let old = Network::new("TestSSID", Security::Psk, Some("old password"));
+        let path = write_profile_in(dir.path(), &old).unwrap();
+        assert_eq!(saved_profile_in(dir.path(), "TestSSID"), Some(path.clone()));
+
+        // Not replaced without asking, and nothing is run
+        let log = RefCell::new(Vec::new());
+        let result = connect_network_in(dir.path(), "TestSSID", Some("new password"), None, false, &fake_iwctl(&log, true));
+        assert!(matches!(result, Err(SetupError::InvalidInput(msg)) if msg.contains("already saved")));
+        assert!(log.borrow().is_empty());
+
+        // A replacement that cannot connect brings the old profile back
+        let result = connect_network_in(dir.path(), "TestSSID", Some("new password"), Some("wlan0"), true, &fake_iwctl(&log, false));
+        assert!(result.is_err());
+        assert_eq!(fs::read_to_string(&path).unwrap(), old.profile());
+
+        let result = connect_network_in(dir.path(), "TestSSID", Some("new password"), Some("wlan0"), true, &fake_iwctl(&log, true));
+        assert!(result.is_ok());
+        assert!(fs::read_to_string(&path).unwrap().contains("Passphrase=new password\n"));
+    }
+
+    #[test]
+    fn test_hidden_network_profile_kept_only_on_success() {
+        let dir = tempfile::tempdir().unwrap();
+        let network = Network::new("Lab", Security::Psk, Some("correct horse")).with_hidden(true);
+        let path = dir.path().join(IWD_STATE_DIR).join("Lab.psk");
+
+        let log = RefCell::new(Vec::new());
+        let result = connect_configured_in(dir.path(), &network, Some("wlan0"), false, &fake_iwctl(&log, false));
+        assert!(matches!(result, Err(SetupError::CommandFailed(_))));
+        assert!(!path.exists());
+
+        connect_configured_in(dir.path(), &network, Some("wlan0"), false, &fake_iwctl(&log, true)).unwrap();
+        assert_eq!(log.borrow().last().unwrap(), "iwctl station wlan0 connect-hidden Lab");
+        assert_eq!(fs::read_to_string(&path).unwrap(), network.profile());
+
+        // A second connect does not overwrite the saved profile unasked
+        let other = Network::new("Lab", Security::Psk, Some("other password")).with_hidden(true);
+        let result = connect_configured_in(dir.path(), &other, Some("wlan0"), false, &fake_iwctl(&log, true));
+        assert!(matches!(result, Err(SetupError::InvalidInput(msg)) if msg.contains("--wifi-replace-profile")));
+        assert_eq!(fs::read_to_string(&path).unwrap(), network.profile());
+    }
+
+    #[test]
+    fn test_failed_enterprise_connect_restores_profile() {
+        let dir = tempfile::tempdir().unwrap();
+        let old = EnterpriseNetwork::new("eduroam", EapMethod::Peap, "jdoe").with_password(Some("old password"));
+        let path = write_enterprise_profile_in(dir.path(), &old).unwrap();
+        let new = EnterpriseNetwork::new("eduroam", EapMethod::Peap, "jdoe").with_password(Some("new password"));
+
+        let log = RefCell::new(Vec::new());
+        let result = connect_enterprise_in(dir.path(), &new, Some("wlan0"), false, &fake_iwctl(&log, true));
+        assert!(matches!(result, Err(SetupError::InvalidInput(msg)) if msg.contains("already saved")));
+        assert!(log.borrow().is_empty());
+
+        // The new password does not stay behind when it fails
+        let result = connect_enterprise_in(dir.path(), &new, Some("wlan0"), true, &fake_iwctl(&log, false));
+        assert!(result.is_err());
+        assert_eq!(fs::read_to_string(&path).unwrap(), old.profile());
+
+        fs::remove_file(&path).unwrap();
+        let result = connect_enterprise_in(dir.path(), &new, Some("wlan0"), false, &fake_iwctl(&log, false));
+        assert!(result.is_err());
+        assert!(!path.exists());
     }
 
     #[test]
     fn test_connect_network_without_password() {
-        let result = connect_network("OpenNetwork", None);
-        
-        match result {
-            Ok(_) => println!("Open network connection unexpectedly succeeded"),
-            Err(SetupError::System(_)) => println!("WiFi system not available (expected)"),
-            Err(SetupError::CommandFailed(_)) => println!("WiFi connection failed (expected)"),
-            Err(e) => panic!("Unexpected error type: {:?}", e),
-        }
+        let dir = tempfile::tempdir().unwrap();
+        let (result, log) = connect_fake(dir.path(), "OpenNetwork", None);
+        assert!(result.is_ok());
+        assert!(!dir.path().join(IWD_STATE_DIR).exists());
+        assert_eq!(log.last().unwrap(), "iwctl station wlan0 connect OpenNetwork");
     }
 
     #[test]
@@ -96,19 +190,10 @@ mod wifi_tests {
             "家のWiFi", // Unicode SSID
         ];
 
+        let dir = tempfile::tempdir().unwrap();
         for ssid in valid_ssids {
-            if ssid.trim().is_empty() {
-                continue;
-            }
-            
-            let result = connect_network(ssid, None);
-            match result {
-                Ok(_) => println!("Connection to '{}' unexpectedly succeeded", ssid),
-                Err(SetupError::InvalidInput(_)) => {
-                    panic!("Valid SSID '{}' was rejected as invalid", ssid);
-                }
-                Err(_) => {}, // Other errors are fine (system not available, etc.)
-            }
+            let (result, _) = connect_fake(dir.path(), ssid, None);
+            assert!(result.is_ok(), "Valid SSID '{}' was rejected: {:?}", ssid, result);
         }
     }
 
@@ -123,15 +208,9 @@ mod wifi_tests {
         ];
 
         for (ssid, password) in test_cases {
-            let result = connect_network(ssid, password);
-            match result {
-                Ok(_) => println!("Connection unexpectedly succeeded"),
-                Err(SetupError::InvalidInput(msg)) => {
-                    // Should only fail for empty SSID, not password issues
-                    assert!(msg.contains("SSID"), "Should not reject based on password: {}", msg);
-                }
-                Err(_) => {}, // Other errors are expected
-            }
+            let dir = tempfile::tempdir().unwrap();
+            let (result, _) = connect_fake(dir.path(), ssid, password);
+            assert!(result.is_ok(), "Should not reject password {:?}: {:?}", password, result);
         }
     }
 
@@ -146,16 +225,12 @@ mod wifi_tests {
             "My WiFi @ Home",
         ];
 
+        let dir = tempfile::tempdir().unwrap();
         for ssid in special_ssids {
-            let result = connect_network(ssid, None);
-            match result {
-                Ok(_) => println!("Connection to '{}' unexpectedly succeeded", ssid),
-                Err(SetupError::InvalidInput(_)) => {
-                    // Should not reject based on special characters in SSID
-                    println!("Warning: SSID '{}' was rejected as invalid", ssid);
-                }
-                Err(_) => {}, // Other errors are fine
-            }
+            // Should not reject based on special characters in SSID
+            let (result, log) = connect_fake(dir.path(), ssid, None);
+            assert!(result.is_ok(), "SSID '{}' was rejected: {:?}", ssid, result);
+            assert_eq!(log.last().unwrap(), &format!("iwctl station wlan0 connect {}", ssid));
         }
     }
 
@@ -193,4 +268,234 @@ mod wifi_tests {
             }
         }
     }
+
+    /// `iwctl device list` of iwd 2.x with a USB dongle next to the
+    /// internal card, colours included
+    const DEVICE_LIST: &str = "\
+                                    Devices                                   *
+--------------------------------------------------------------------------------
+  \u{1b}[1;90mName                  Address               Powered     Adapter     Mode      \u{1b}[0m
+--------------------------------------------------------------------------------
+  wlan0                 3c:a9:f4:12:34:56     on          phy0        station   
+  wlan1                 00:c0:ca:ab:cd:ef     off         phy1        ap        
+
+";
+
+    #[test]
+    fn test_parse_iwd_device_list() {
+        let devices = parse_device_list(DEVICE_LIST);
+        assert_eq!(
+            devices,
+            vec![
+                WirelessDevice {
+                    name: "wlan0".to_string(),
+                    adapter: "phy0".to_string(),
+                    mode: DeviceMode::Station,
+                    powered: true,
+                    address: "3c:a9:f4:12:34:56".to_string(),
+                },
+                WirelessDevice {
+                    name: "wlan1".to_string(),
+                    adapter: "phy1".to_string(),
+                    mode: DeviceMode::AccessPoint,
+                    powered: false,
+                    address: "00:c0:ca:ab:cd:ef".to_string(),
+                },
+            ]
+        );
+        assert!(parse_device_list("No devices found\n").is_empty());
+    }
+
+    #[test]
+    fn test_select_device() {
+        let devices = parse_device_list(DEVICE_LIST);
+        assert_eq!(select_device(&devices, Some("wlan1")).unwrap().adapter, "phy1");
+        assert_eq!(select_device(&devices[..1], None).unwrap().name, "wlan0");
+
+        let result = select_device(&devices, None);
+        assert!(matches!(result, Err(SetupError::InvalidInput(msg)) if msg.contains("--wifi-device") && msg.contains("wlan1 (phy1")));
+        let result = select_device(&devices, Some("wlan2"));
+        assert!(matches!(result, Err(SetupError::InvalidInput(msg)) if msg.contains("No wireless device 'wlan2'")));
+        let result = select_device(&[], None);
+        assert!(matches!(result, Err(SetupError::System(msg)) if msg.contains("No wireless device")));
+    }
+
+    #[test]
+    fn test_prepare_powered_off_access_point() {
+        let devices = parse_device_list(DEVICE_LIST);
+        assert!(preparation_commands(&devices[0]).is_empty());
+        assert_eq!(
+            preparation_commands(&devices[1]),
+            vec![
+                vec!["iwctl", "adapter", "phy1", "set-property", "Powered", "on"],
+                vec!["iwctl", "device", "wlan1", "set-property", "Powered", "on"],
+                vec!["iwctl", "device", "wlan1", "set-property", "Mode", "station"],
+            ]
+        );
+    }
+
+    #[test]
+    fn test_profile_names_round_trip() {
+        assert_eq!(profile_file_name("Home-Network_5G", Security::Psk), "Home-Network_5G.psk");
+        assert_eq!(profile_file_name("Guest Network", Security::Open), "Guest Network.open");
+        assert_eq!(profile_file_name("My WiFi @ Home", Security::Sae), "=4d792057694669204020486f6d65.psk");
+
+        for ssid in ["Café WiFi", "a:b\\c", "Network (2.4GHz)", "家のWiFi", "../etc", "x=y.psk"] {
+            let name = profile_file_name(ssid, Security::Psk);
+            assert!(!name.contains('/'), "{}", name);
+            assert_eq!(parse_profile_file_name(&name), Some((ssid.to_string(), "psk")));
+        }
+        assert_eq!(parse_profile_file_name("=4g.psk"), None);
+    }
+
+    #[test]
+    fn test_hidden_profile() {
+        let dir = tempfile::tempdir().unwrap();
+        let network = Network::new("Lab:Hidden", Security::Sae, Some("short")).with_hidden(true);
+
+        let path = write_profile_in(dir.path(), &network).unwrap();
+        assert_eq!(path.parent().unwrap(), dir.path().join(IWD_STATE_DIR));
+        assert_eq!(
+            fs::read_to_string(&path).unwrap(),
+            "[Security]\nPassphrase=short\n\n[Settings]\nAutoConnect=true\nHidden=true\n"
+        );
+        assert_eq!(fs::metadata(&path).unwrap().permissions().mode() & 0o777, 0o600);
+
+        let open = Network::new("Cafe", Security::Open, None);
+        assert_eq!(open.profile(), "[Settings]\nAutoConnect=true\n");
+    }
+
+    #[test]
+    fn test_profile_values_escaped() {
+        // iwd trims spaces at either end and reads backslashes as escapes
+        let network = Network::new("Home", Security::Psk, Some("  back\\slash pass "));
+        assert!(network.profile().starts_with("[Security]\nPassphrase=\\s\\sback\\\\slash pass\\s\n"));
+
+        let peap = EnterpriseNetwork::new("Corp", EapMethod::Peap, "CORP\\jdoe").with_password(Some(" pw"));
+        let profile = peap.profile();
+        assert!(profile.contains("EAP-Identity=CORP\\\\jdoe\n"));
+        assert!(profile.contains("EAP-PEAP-Phase2-Identity=CORP\\\\jdoe\n"));
+        assert!(profile.contains("EAP-PEAP-Phase2-Password=\\spw\n"));
+    }
+
+    #[test]
+    fn test_existing_profile_made_private() {
+        let dir = tempfile::tempdir().unwrap();
+        let network = Network::new("Home", Security::Psk, Some("correct horse"));
+        let path = dir.path().join(IWD_STATE_DIR).join(network.profile_file_name());
+        fs::create_dir_all(path.parent().unwrap()).unwrap();
+        fs::write(&path, "[Settings]\n").unwrap();
+        fs::set_permissions(&path, fs::Permissions::from_mode(0o644)).unwrap();
+
+        write_profile_in(dir.path(), &network).unwrap();
+        assert_eq!(fs::metadata(&path).unwrap().permissions().mode() & 0o777, 0o600);
+    }
+
+    #[test]
+    fn test_security_checks_passphrase() {
+        let check = |security: Security, passphrase: Option<&str>| Network::new("Net", security, passphrase).validate();
+        assert!(check(Security::Open, None).is_ok());
+        assert!(check(Security::Psk, Some("correct horse")).is_ok());
+        // SAE has no minimum length, WPA2 does
+        assert!(check(Security::Sae, Some("short")).is_ok());
+        let result = check(Security::Psk, Some("short"));
+        assert!(matches!(result, Err(SetupError::InvalidInput(msg)) if msg.contains("8 to 63")));
+        let result = check(Security::Open, Some("secret12"));
+        assert!(matches!(result, Err(SetupError::InvalidInput(msg)) if msg.contains("no passphrase")));
+        let result = check(Security::Sae, None);
+        assert!(matches!(result, Err(SetupError::InvalidInput(msg)) if msg.contains("need a passphrase")));
+        let result = check(Security::Psk, Some("line\nbreak!"));
+        assert!(result.is_err());
+
+        let result = Network::new(&"x".repeat(33), Security::Open, None).validate();
+        assert!(matches!(result, Err(SetupError::InvalidInput(msg)) if msg.contains("at most 32")));
+        assert_eq!("SAE".parse::<Security>().unwrap(), Security::Sae);
+        assert!("wep".parse::<Security>().is_err());
+    }
+
+    #[test]
+    fn test_connect_spec_escapes() {
+        assert_eq!(parse_connect_spec("Home"), ("Home".to_string(), None));
+        assert_eq!(parse_connect_spec("Home:pa:ss"), ("Home".to_string(), Some("pa:ss")));
+        assert_eq!(parse_connect_spec(r"a\:b\\c:pw"), (r"a:b\c".to_string(), Some("pw")));
+        assert_eq!(parse_connect_spec(r"trailing\"), (r"trailing\".to_string(), None));
+    }
+
+    #[test]
+    fn test_tls_profile_written() {
+        let dir = tempfile::tempdir().unwrap();
+        let certs = dir.path().join("certs");
+        fs::create_dir_all(&certs).unwrap();
+        for file in ["ca.pem", "client.pem", "client.key"] {
+            fs::write(certs.join(file), "-----BEGIN-----\n").unwrap();
+        }
+
+        let network = EnterpriseNetwork::new("Lab: 802.1X", EapMethod::Tls, "host/lab01")
+            .with_ca_cert(Some(&certs.join("ca.pem")))
+            .with_client_cert(Some(&certs.join("client.pem")), Some(&certs.join("client.key")))
+            .with_password(Some("key passphrase"));
+        let path = write_enterprise_profile_in(dir.path(), &network).unwrap();
+
+        let file_name = path.file_name().unwrap().to_string_lossy().to_string();
+        assert_eq!(parse_profile_file_name(&file_name), Some(("Lab: 802.1X".to_string(), "8021x")));
+        assert_eq!(path.parent().unwrap(), dir.path().join(IWD_STATE_DIR));
+        assert_eq!(fs::metadata(&path).unwrap().permissions().mode() & 0o777, 0o600);
+        let profile = fs::read_to_string(&path).unwrap();
+        assert!(profile.starts_with("[Security]\nEAP-Method=TLS\nEAP-Identity=host/lab01\n"));
+        assert!(profile.contains(&format!("EAP-TLS-ClientKey={}\n", certs.join("client.key").display())));
+        assert!(profile.contains("EAP-TLS-ClientKeyPassphrase=key passphrase\n"));
+        assert!(!profile.contains("Phase2"));
+    }
+
+    #[test]
+    fn test_enterprise_validation() {
+        let peap = || EnterpriseNetwork::new("eduroam", EapMethod::Peap, "jdoe").with_password(Some("pw"));
+        assert!(peap().validate().is_ok());
+
+        let result = peap().with_password(None).validate();
+        assert!(matches!(result, Err(SetupError::InvalidInput(msg)) if msg.contains("peap needs a password")));
+        let result = peap().with_phase2(Some(Phase2Method::Pap)).validate();
+        assert!(matches!(result, Err(SetupError::InvalidInput(msg)) if msg.contains("cannot use phase 2 method pap")));
+        let result = peap().with_ca_cert(Some(std::path::Path::new("/nonexistent/ca.pem"))).validate();
+        assert!(matches!(result, Err(SetupError::InvalidInput(msg)) if msg.contains("/nonexistent/ca.pem")));
+        let result = peap().with_ca_cert(Some(std::path::Path::new("ca.pem"))).validate();
+        assert!(matches!(result, Err(SetupError::InvalidInput(msg)) if msg.contains("absolute path")));
+        let result = EnterpriseNetwork::new("eduroam", EapMethod::Peap, "jdoe\nEAP-Method=TLS").with_password(Some("pw")).validate();
+        assert!(matches!(result, Err(SetupError::InvalidInput(msg)) if msg.contains("control characters")));
+
+        let result = EnterpriseNetwork::new("eduroam", EapMethod::Tls, "jdoe").validate();
+        assert!(matches!(result, Err(SetupError::InvalidInput(msg)) if msg.contains("client certificate and key")));
+        assert_eq!("TTLS".parse::<EapMethod>().unwrap(), EapMethod::Ttls);
+        assert!("leap".parse::<EapMethod>().is_err());
+    }
+
+    #[test]
+    fn test_password_file() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("psk");
+        fs::write(&path, "correct horse: battery\nsecond line\n").unwrap();
+        assert_eq!(read_secret_file(&path).unwrap(), "correct horse: battery");
+
+        fs::write(&path, "\n").unwrap();
+        let result = read_secret_file(&path);
+        assert!(matches!(result, Err(SetupError::InvalidInput(msg)) if msg.contains("is empty")));
+        let result = read_secret_file(&dir.path().join("missing"));
+        assert!(matches!(result, Err(SetupError::InvalidInput(msg)) if msg.contains("Cannot read password file")));
+    }
+
+    #[test]
+    fn test_env_password_is_taken_once() {
+        std::env::set_var("ASENOS_TEST_WIFI_PASSWORD", "hunter22");
+        assert_eq!(take_env_secret("ASENOS_TEST_WIFI_PASSWORD").as_deref(), Some("hunter22"));
+        assert!(std::env::var("ASENOS_TEST_WIFI_PASSWORD").is_err());
+        assert_eq!(take_env_secret("ASENOS_TEST_WIFI_PASSWORD"), None);
+    }
+
+    #[test]
+    fn test_errors_hide_password() {
+        let error = SetupError::CommandFailed("Command iwctl failed: bad passphrase hunter22".to_string());
+        let message = error.redact("hunter22").to_string();
+        assert!(!message.contains("hunter22"));
+        assert!(message.contains(REDACTED));
+    }
 }
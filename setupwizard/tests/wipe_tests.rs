@@ -0,0 +1,152 @@
+use setupwizard::common::SetupError;
+use setupwizard::partition::{InstallMode, PartitionConfig};
+use setupwizard::wipe::*;
+use setupwizard::Filesystem;
+use std::fs;
+use std::path::Path;
+use std::time::Duration;
+
+#[cfg(test)]
+mod wipe_tests {
+    use super::*;
+
+    const HDPARM_SECURITY: &str = "\
+ATA device, with non-removable media
+\tModel Number:       Samsung SSD 870 EVO 1TB
+Security:
+\tMaster password revision code = 65534
+\t\tsupported
+\tnot\tenabled
+\tnot\tlocked
+\tnot\tfrozen
+\tnot\texpired: security count
+\t\tsupported: enhanced erase
+\t2min for SECURITY ERASE UNIT. 8min for ENHANCED SECURITY ERASE UNIT.
+Logical Unit WWN Device Identifier: 5002538f4150e5d2
+";
+
+    /// sysfs with a 1 TB SATA SSD sda (two partitions), a 2 TB hard disk sdb
+    /// and a 1 TB NVMe drive
+    fn fixture(root: &Path) {
+        let block = root.join("sys/class/block");
+        for (disk, sectors, rotational, discard, parts) in [
+            ("sda", "1953525168", "0", "2147450880", vec!["sda1", "sda2"]),
+            ("sdb", "3907029168", "1", "0", vec![]),
+            ("nvme0n1", "1953525168", "0", "2199023255040", vec![]),
+        ] {
+            fs::create_dir_all(block.join(disk).join("queue")).unwrap();
+            fs::write(block.join(disk).join("size"), sectors).unwrap();
+            fs::write(block.join(disk).join("queue/rotational"), rotational).unwrap();
+            fs::write(block.join(disk).join("queue/discard_max_bytes"), discard).unwrap();
+            for (i, part) in parts.iter().enumerate() {
+                fs::create_dir_all(block.join(disk).join(part)).unwrap();
+                fs::write(block.join(disk).join(part).join("partition"), (i + 1).to_string()).unwrap();
+            }
+        }
+    }
+
+    #[test]
+    fn test_parse_hdparm_security() {
+        let security = parse_hdparm_security(HDPARM_SECURITY);
+        assert_eq!(security, AtaSecurity { supported: true, frozen: false, erase_minutes: Some(2) });
+
+        let frozen = parse_hdparm_security(&HDPARM_SECURITY.replace("\tnot\tfrozen", "\t\tfrozen"));
+        assert!(frozen.frozen);
+
+        assert_eq!(parse_hdparm_security("no security section"), AtaSecurity::default());
+    }
+
+    #[test]
+    fn test_signature_wipe_covers_partitions_first() {
+        let dir = tempfile::tempdir().unwrap();
+        fixture(dir.path());
+
+        let plan = plan_wipe_in(dir.path(), "/dev/sda", WipeMode::Signatures, None).unwrap();
+        let devices: Vec<&str> = plan.commands().iter().map(|c| c[2].as_str()).collect();
+        assert_eq!(devices, vec!["/dev/sda1", "/dev/sda2", "/dev/sda"]);
+        assert!(plan.commands().iter().all(|c| c[..2] == ["wipefs", "--all"]));
+        assert_eq!(plan.estimate, Duration::from_secs(3));
+    }
+
+    #[test]
+    fn test_discard_needs_support() {
+        let dir = tempfile::tempdir().unwrap();
+        fixture(dir.path());
+
+        let plan = plan_wipe_in(dir.path(), "/dev/sda", WipeMode::Discard, None).unwrap();
+        assert_eq!(plan.commands(), &[vec!["blkdiscard", "--force", "/dev/sda"]]);
+        assert_eq!(plan.estimate, Duration::from_secs(20));
+
+        let result = plan_wipe_in(dir.path(), "/dev/sdb", WipeMode::Discard, None);
+        assert!(matches!(result, Err(SetupError::InvalidInput(msg)) if msg.contains("does not support discard")));
+    }
+
+    #[test]
+    fn test_secure_erase_per_interface() {
+        let dir = tempfile::tempdir().unwrap();
+        fixture(dir.path());
+
+        let nvme = plan_wipe_in(dir.path(), "/dev/nvme0n1", WipeMode::SecureErase, None).unwrap();
+        assert_eq!(nvme.commands()[0][..2], ["nvme", "format"]);
+
+        let ata = plan_wipe_in(dir.path(), "/dev/sda", WipeMode::SecureErase, Some(parse_hdparm_security(HDPARM_SECURITY))).unwrap();
+        assert_eq!(ata.commands().len(), 2);
+        assert!(ata.commands()[1].contains(&"--security-erase".to_string()));
+        assert_eq!(ata.estimate, Duration::from_secs(120));
+        // A failed erase must not leave the drive locked
+        assert_eq!(ata.recovery_commands().len(), 1);
+        assert!(ata.recovery_commands()[0].contains(&"--security-disable".to_string()));
+        assert!(nvme.recovery_commands().is_empty());
+
+        let frozen = AtaSecurity { supported: true, frozen: true, erase_minutes: None };
+        let result = plan_wipe_in(dir.path(), "/dev/sda", WipeMode::SecureErase, Some(frozen));
+        assert!(matches!(result, Err(SetupError::InvalidInput(msg)) if msg.contains("frozen")));
+
+        let result = plan_wipe_in(dir.path(), "/dev/sdb", WipeMode::SecureErase, None);
+        assert!(matches!(result, Err(SetupError::InvalidInput(msg)) if msg.contains("does not support")));
+    }
+
+    #[test]
+    fn test_random_estimate_depends_on_media() {
+        let dir = tempfile::tempdir().unwrap();
+        fixture(dir.path());
+
+        let hdd = plan_wipe_in(dir.path(), "/dev/sdb", WipeMode::Random, None).unwrap();
+        let ssd = plan_wipe_in(dir.path(), "/dev/sda", WipeMode::Random, None).unwrap();
+        let nvme = plan_wipe_in(dir.path(), "/dev/nvme0n1", WipeMode::Random, None).unwrap();
+
+        assert_eq!(hdd.commands(), &[vec!["shred", "--iterations=1", "/dev/sdb"]]);
+        assert_eq!(format_estimate(hdd.estimate), "about 3 hours 42 minutes");
+        assert!(ssd.estimate > nvme.estimate);
+    }
+
+    #[test]
+    fn test_each_mode_has_its_own_confirmation() {
+        let dir = tempfile::tempdir().unwrap();
+        fixture(dir.path());
+
+        let words: Vec<&str> = WipeMode::ALL.iter().filter_map(|m| m.confirmation_word()).collect();
+        let mut unique = words.clone();
+        unique.dedup();
+        assert_eq!(words.len(), 4);
+        assert_eq!(unique, words);
+
+        let plan = plan_wipe_in(dir.path(), "/dev/sdb", WipeMode::Random, None).unwrap();
+        assert!(plan.confirm_prompt().contains("about 3 hours"));
+        assert!(plan.confirm_prompt().contains("'overwrite'"));
+        assert!(plan.check_confirmation("overwrite").is_ok());
+
+        let result = plan.check_confirmation("yes");
+        assert!(matches!(result, Err(SetupError::InvalidInput(msg)) if msg.contains("not confirmed")));
+    }
+
+    #[test]
+    fn test_wipe_refused_for_free_space_install() {
+        let config = PartitionConfig::new("/dev/sda".to_string(), 512, 2048, true, Filesystem::Ext4)
+            .with_mode(InstallMode::FreeSpace(None))
+            .with_wipe(WipeMode::Signatures);
+
+        let result = config.validate();
+        assert!(matches!(result, Err(SetupError::InvalidInput(msg)) if msg.contains("whole-disk install")));
+    }
+}